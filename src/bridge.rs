@@ -1,31 +1,195 @@
-use crate::protocol::ProtocolEvent;
+use crate::chunking::{ChunkLimits, forward_chunks};
+use crate::event_log::EventLog;
+use crate::fallback::{FallbackPolicy, parse_provider_list, run_with_fallback};
+use crate::logging::truncate_for_log;
+use crate::memory_log::{ConversationRecord, log_conversation, memory_log_enabled};
+use crate::metrics::BridgeMetrics;
+use crate::offline::{OFFLINE_CHUNK_DELAY, offline_mode, offline_reply_chunks};
+use crate::prompt_queue::{Admission, DEFAULT_DEDUP_WINDOW, Duplicate, PromptDedup, PromptQueue};
+use crate::rate_limit::{Quota, RateDecision, quota_exceeded_message};
+use crate::schedule::{Delivery, SCHEDULES_FILE, Schedule, ScheduleStore, schedule_channel};
+use crate::templates::{TemplateStore, is_template_name, templates_path};
+use crate::protocol::{
+    AGENT_FAILURE_PREFIX, PROTOCOL_VERSION, ProtocolEvent, compare_channel, default_model_for_provider, models_for_provider, provider_from_command_name,
+    split_compare_channel, version_mismatch_message,
+};
+use acomm::client::DEFAULT_SOCKET_PATH;
 use acore::{AgentExecutor, AgentProvider, SessionManager};
-use std::{collections::VecDeque, error::Error, path::Path, sync::Arc};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::{broadcast, Mutex};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::UnixListener;
+use tokio::sync::{broadcast, Mutex, Semaphore};
+use tracing::{Instrument, debug, error, info, info_span, warn};
 
-const SOCKET_PATH: &str = "/tmp/acomm.sock";
+/// Written once the socket is bound, so clients can tell a stale socket from a busy bridge.
+pub const BRIDGE_PID_PATH: &str = "/tmp/acomm.pid";
 const MAX_BACKLOG: usize = 100;
+/// Prompt text is cut to this many characters in log lines.
+const LOG_PROMPT_CHARS: usize = 80;
+const DEFAULT_BROADCAST_CAP: usize = 100;
+const DEFAULT_AGENT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Providers `/compare` runs a prompt on when `ACOMM_COMPARE_PROVIDERS` is unset.
+const DEFAULT_COMPARE_PROVIDERS: &str = "gemini,claude";
 const DEFAULT_PROVIDER: AgentProvider = AgentProvider::Gemini;
-const DEFAULT_GEMINI_MODEL: &str = "auto-gemini-3";
-const DEFAULT_CLAUDE_MODEL: &str = "claude-sonnet-4-6";
-const DEFAULT_CODEX_MODEL: &str = "gpt-5.3-codex";
+/// Ollama is recognised by name but needs an `AgentProvider::Ollama` executor in
+/// `acore` before it can be selected; until then `/provider ollama` explains why.
+const OLLAMA_PROVIDER_NAME: &str = "ollama";
+const DEFAULT_OLLAMA_MODEL: &str = "llama3";
+const PERSISTED_BACKLOG_FILE: &str = "backlog.jsonl";
+const PERSISTED_MACROS_FILE: &str = "macros.json";
+const PERSISTED_PERSONAS_FILE: &str = "personas.json";
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct ProviderPreset {
     provider: AgentProvider,
-    model: &'static str,
+    model: Option<&'static str>,
+}
+
+/// Commands handled by `handle_command` (without the leading slash).
+pub const BRIDGE_COMMANDS: &[&str] = &[
+    "cancel", "clear", "compare", "fallback", "history", "macro", "model", "persona", "provider", "quota", "schedule", "search", "status", "template", "today",
+];
+
+/// Provider names accepted by `/provider`.
+pub const PROVIDER_NAMES: &[&str] = &["gemini", "claude", "codex", "opencode", "dummy", "mock"];
+
+/// Models the bridge knows about: every known model of every provider.
+pub fn known_models() -> Vec<&'static str> {
+    PROVIDER_NAMES
+        .iter()
+        .filter_map(|name| provider_from_command_name(name))
+        .flat_map(|provider| models_for_provider(&provider).iter().copied())
+        .collect()
+}
+
+/// Resolve the startup provider/model from `ACOMM_DEFAULT_PROVIDER` / `ACOMM_DEFAULT_MODEL`.
+///
+/// An unknown provider falls back to `DEFAULT_PROVIDER` (and its default model) with a
+/// warning; an unset or blank model uses the provider's default model.
+fn resolve_default_provider(
+    provider_env: Option<&str>,
+    model_env: Option<&str>,
+) -> (AgentProvider, Option<String>, Option<String>) {
+    let requested = provider_env.map(|p| p.trim().to_ascii_lowercase()).filter(|p| !p.is_empty());
+    let (provider, warning) = match requested {
+        None => (DEFAULT_PROVIDER, None),
+        Some(name) => match provider_from_command_name(&name) {
+            Some(provider) => (provider, None),
+            None => (
+                DEFAULT_PROVIDER,
+                Some(format!(
+                    "Unknown ACOMM_DEFAULT_PROVIDER '{}'; falling back to {}",
+                    name,
+                    DEFAULT_PROVIDER.command_name()
+                )),
+            ),
+        },
+    };
+    let model = match model_env.map(str::trim).filter(|m| !m.is_empty()) {
+        Some(model) if warning.is_none() => Some(model.to_string()),
+        _ => default_model_for_provider(&provider).map(str::to_string),
+    };
+    (provider, model, warning)
+}
+
+/// Resolve the broadcast channel capacity from `ACOMM_BROADCAST_CAP`.
+///
+/// Subscribers that fall more than this many events behind skip ahead and are told how
+/// many events they missed. Unset, zero, or unparsable values use `DEFAULT_BROADCAST_CAP`.
+fn broadcast_capacity(cap_env: Option<&str>) -> (usize, Option<String>) {
+    match cap_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => (DEFAULT_BROADCAST_CAP, None),
+        Some(raw) => match raw.parse::<usize>() {
+            Ok(cap) if cap > 0 => (cap, None),
+            _ => (
+                DEFAULT_BROADCAST_CAP,
+                Some(format!(
+                    "Invalid ACOMM_BROADCAST_CAP '{}'; using {}",
+                    raw, DEFAULT_BROADCAST_CAP
+                )),
+            ),
+        },
+    }
+}
+
+/// Resolve from `ACOMM_DEDUP_WINDOW_SECS` how long a finished prompt still counts as a
+/// duplicate. `0` only drops repeats of prompts still queued or running; unset or
+/// unparsable values use `DEFAULT_DEDUP_WINDOW`.
+fn resolve_dedup_window(window_env: Option<&str>) -> (Duration, Option<String>) {
+    match window_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => (DEFAULT_DEDUP_WINDOW, None),
+        Some(raw) => match raw.parse::<u64>() {
+            Ok(secs) => (Duration::from_secs(secs), None),
+            Err(_) => (
+                DEFAULT_DEDUP_WINDOW,
+                Some(format!(
+                    "Invalid ACOMM_DEDUP_WINDOW_SECS '{}'; using {}",
+                    raw,
+                    DEFAULT_DEDUP_WINDOW.as_secs()
+                )),
+            ),
+        },
+    }
+}
+
+/// Resolve the agent run timeout from `ACOMM_AGENT_TIMEOUT_SECS`.
+///
+/// `0` disables the timeout. Unset or unparsable values use `DEFAULT_AGENT_TIMEOUT`.
+fn resolve_agent_timeout(timeout_env: Option<&str>) -> (Option<Duration>, Option<String>) {
+    match timeout_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => (Some(DEFAULT_AGENT_TIMEOUT), None),
+        Some(raw) => match raw.parse::<u64>() {
+            Ok(0) => (None, None),
+            Ok(secs) => (Some(Duration::from_secs(secs)), None),
+            Err(_) => (
+                Some(DEFAULT_AGENT_TIMEOUT),
+                Some(format!(
+                    "Invalid ACOMM_AGENT_TIMEOUT_SECS '{}'; using {}",
+                    raw,
+                    DEFAULT_AGENT_TIMEOUT.as_secs()
+                )),
+            ),
+        },
+    }
 }
 
-fn default_model_for_provider(provider: &AgentProvider) -> Option<&'static str> {
-    match provider {
-        AgentProvider::Gemini => Some(DEFAULT_GEMINI_MODEL),
-        AgentProvider::Claude => Some(DEFAULT_CLAUDE_MODEL),
-        AgentProvider::Codex => Some(DEFAULT_CODEX_MODEL),
-        AgentProvider::Dummy => Some("echo"),
-        AgentProvider::Mock => Some("mock-model"),
-        AgentProvider::OpenCode => None,
+/// Resolve the global limit on concurrently running agents from `ACOMM_MAX_CONCURRENT_AGENTS`.
+///
+/// Unset or `0` means no limit; unparsable values also mean no limit, with a warning.
+fn resolve_max_concurrent_agents(limit_env: Option<&str>) -> (Option<usize>, Option<String>) {
+    match limit_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => (None, None),
+        Some(raw) => match raw.parse::<usize>() {
+            Ok(0) => (None, None),
+            Ok(limit) => (Some(limit), None),
+            Err(_) => (None, Some(format!("Invalid ACOMM_MAX_CONCURRENT_AGENTS '{}'; not limiting agents", raw))),
+        },
+    }
+}
+
+/// Resolve the providers `/compare` fans out to from `ACOMM_COMPARE_PROVIDERS`.
+///
+/// Unset or invalid lists use `DEFAULT_COMPARE_PROVIDERS`, the latter with a warning.
+fn resolve_compare_providers(providers_env: Option<&str>) -> (Vec<AgentProvider>, Option<String>) {
+    let default = || parse_provider_list(DEFAULT_COMPARE_PROVIDERS).unwrap_or_default();
+    match providers_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => (default(), None),
+        Some(raw) => match parse_provider_list(raw) {
+            Ok(providers) => (providers, None),
+            Err(e) => (
+                default(),
+                Some(format!("Invalid ACOMM_COMPARE_PROVIDERS '{}': {}; using {}", raw, e, DEFAULT_COMPARE_PROVIDERS)),
+            ),
+        },
     }
 }
 
@@ -34,145 +198,609 @@ fn discord_magic_provider_preset(text: &str, channel: Option<&str>) -> Option<Pr
         return None;
     }
 
-    match text.trim() {
-        "p-gemini" => Some(ProviderPreset {
-            provider: AgentProvider::Gemini,
-            model: DEFAULT_GEMINI_MODEL,
-        }),
-        "p-codex" => Some(ProviderPreset {
-            provider: AgentProvider::Codex,
-            model: DEFAULT_CODEX_MODEL,
-        }),
-        "p-claude" => Some(ProviderPreset {
-            provider: AgentProvider::Claude,
-            model: DEFAULT_CLAUDE_MODEL,
-        }),
-        _ => None,
-    }
+    let provider = provider_from_command_name(text.trim().strip_prefix("p-")?)?;
+    Some(ProviderPreset {
+        model: default_model_for_provider(&provider),
+        provider,
+    })
 }
 
 fn apply_provider_preset(
-    tx: &Arc<broadcast::Sender<ProtocolEvent>>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
     channel: Option<String>,
     preset: ProviderPreset,
 ) {
     let provider_name = preset.provider.command_name().to_string();
-    let _ = tx.send(ProtocolEvent::ProviderSwitched {
+    let _ = tx.send(BusEvent::new(ProtocolEvent::ProviderSwitched {
         provider: preset.provider.clone(),
-    });
-    let _ = tx.send(ProtocolEvent::ModelSwitched {
-        model: preset.model.to_string(),
-    });
-    let _ = tx.send(ProtocolEvent::SystemMessage {
-        msg: format!("Switched to {}:{}.", provider_name, preset.model),
-        channel,
-    });
+    }));
+    let msg = match preset.model {
+        Some(model) => {
+            let _ = tx.send(BusEvent::new(ProtocolEvent::ModelSwitched {
+                model: model.to_string(),
+            }));
+            format!("Switched to {}:{}.", provider_name, model)
+        }
+        None => format!("Switched to {}.", provider_name),
+    };
+    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel }));
+}
+
+/// Directory holding acomm's on-disk state (`~/.cache/acomm`).
+pub fn persisted_state_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|p| p.join("acomm"))
+}
+
+/// Location of the persisted bridge backlog.
+pub fn backlog_path() -> Option<PathBuf> {
+    persisted_state_dir().map(|p| p.join(PERSISTED_BACKLOG_FILE))
+}
+
+/// Remove acomm's persisted state from disk and return the paths actually deleted.
+///
+/// The bridge backlog is always removed; the TUI input history is removed only
+/// when `include_tui` is set. Missing files are skipped silently.
+pub fn purge_persisted_state(include_tui: bool) -> std::io::Result<Vec<PathBuf>> {
+    let mut targets: Vec<PathBuf> = backlog_path().into_iter().collect();
+    if include_tui {
+        targets.extend(crate::tui::InputState::history_path());
+    }
+    remove_persisted_files(&targets)
+}
+
+fn remove_persisted_files(paths: &[PathBuf]) -> std::io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    for path in paths {
+        match std::fs::remove_file(path) {
+            Ok(()) => removed.push(path.clone()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
+
+/// Persona text injected ahead of prompts, per channel scope with a global fallback.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PersonaStore {
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub channels: HashMap<String, String>,
+}
+
+impl PersonaStore {
+    /// The persona of `channel`'s scope, falling back to the default. Channel-less prompts
+    /// and commands share the `bridge` scope, like `/persona set` without a channel.
+    pub fn for_channel(&self, channel: Option<&str>) -> Option<&str> {
+        self.channels
+            .get(&session_scope(channel))
+            .or(self.default.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// Stable scope for a bridge channel.
+///
+/// Adapter channels carry per-message ids (`discord:<channel>:<message>`,
+/// `slack:<user>:<channel>`, `slack:dm:<user>:<channel>`, `ntfy:<message>`,
+/// `matrix:<room>:<event>`); this strips them so settings and agent sessions apply to
+/// the conversation rather than a single message.
+pub fn channel_scope(channel: &str) -> String {
+    let parts: Vec<&str> = channel.splitn(3, ':').collect();
+    match parts.as_slice() {
+        ["discord", channel_id, _] => format!("discord:{}", channel_id),
+        ["slack", "dm", rest] => format!("slack:{}", rest.rsplit(':').next().unwrap_or(rest)),
+        ["slack", _, channel_id] => format!("slack:{}", channel_id),
+        ["ntfy", ..] => "ntfy".to_string(),
+        // Room ids contain ':' themselves (`!room:server`), so drop only the trailing event id.
+        ["matrix", _, rest] if rest.contains(':') => {
+            channel.rsplit_once(':').map(|(room, _)| room.to_string()).unwrap_or_else(|| channel.to_string())
+        }
+        _ => channel.to_string(),
+    }
+}
+
+/// Who a prompt's quota is charged to: the Slack user encoded in its channel, the
+/// `author` the adapter sent (Discord: `discord:user:<id>`), otherwise the channel scope.
+fn quota_user(channel: Option<&str>, author: Option<&str>) -> String {
+    let parts: Vec<&str> = channel.unwrap_or("").splitn(4, ':').collect();
+    match (parts.as_slice(), author) {
+        (["slack", "dm", user, _], _) => format!("slack:{}", user),
+        (["slack", user, _], _) if *user != "dm" => format!("slack:{}", user),
+        ([platform, _, ..], Some(author)) => format!("{}:user:{}", platform, author),
+        _ => session_scope(channel),
+    }
+}
+
+/// Prompts that run an agent: plain text and `/compare`. Other commands are free.
+fn charges_quota(text: &str) -> bool {
+    !text.starts_with('/') || split_first_word(&text[1..]).0 == "compare"
+}
+
+/// Take one prompt from the sender's quota. Over the limit, tells the channel when to
+/// retry and returns `false` so the prompt is dropped.
+async fn admit_prompt(
+    channel: Option<&str>,
+    author: Option<&str>,
+    tx: &broadcast::Sender<SharedEvent>,
+    state: &Mutex<BridgeState>,
+) -> bool {
+    let user = quota_user(channel, author);
+    let decision = match state.lock().await.quota.as_mut() {
+        Some(quota) => quota.check(&user, &session_scope(channel), Instant::now()),
+        None => return true,
+    };
+    match decision {
+        RateDecision::Allow => true,
+        RateDecision::Throttled { retry_after, .. } => {
+            info!(user = %user, "prompt rejected over quota");
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                msg: quota_exceeded_message(retry_after),
+                channel: Some(channel.unwrap_or("bridge").to_string()),
+            }));
+            false
+        }
+    }
+}
+
+/// Give back the quota `admit_prompt` took for a prompt that was dropped before it ran.
+async fn refund_prompt(channel: Option<&str>, author: Option<&str>, state: &Mutex<BridgeState>) {
+    if let Some(quota) = state.lock().await.quota.as_mut() {
+        quota.refund(&quota_user(channel, author), &session_scope(channel));
+    }
+}
+
+/// Session key of a prompt's channel; channel-less prompts share the `bridge` scope.
+fn session_scope(channel: Option<&str>) -> String {
+    channel.map(channel_scope).unwrap_or_else(|| "bridge".to_string())
+}
+
+/// Agent session key of a prompt: its channel scope, narrowed to the thread when the prompt
+/// replies to an earlier message so every prompt in that thread resumes the same session.
+fn session_key(channel: Option<&str>, reply_to: Option<&str>) -> String {
+    let scope = session_scope(channel);
+    match reply_to.filter(|id| !id.is_empty()) {
+        Some(thread) => format!("{}#{}", scope, thread),
+        None => scope,
+    }
+}
+
+fn apply_persona(persona: Option<&str>, text: &str) -> String {
+    match persona.map(str::trim).filter(|p| !p.is_empty()) {
+        Some(persona) => format!("{}\n\n{}", persona, text),
+        None => text.to_string(),
+    }
+}
+
+/// An event as fanned out over the broadcast channel. Every subscriber and the backlog share
+/// one allocation, and the JSON line is serialized once, by whichever connection writes it first.
+#[derive(Debug)]
+pub struct BusEvent {
+    event: ProtocolEvent,
+    line: OnceLock<String>,
+}
+
+/// What the broadcast channel carries.
+pub type SharedEvent = Arc<BusEvent>;
+
+impl BusEvent {
+    pub fn new(event: ProtocolEvent) -> SharedEvent {
+        Arc::new(BusEvent { event, line: OnceLock::new() })
+    }
+
+    /// The event as one newline-terminated JSON line, serialized on first use.
+    pub fn line(&self) -> &str {
+        self.line.get_or_init(|| {
+            let json = serde_json::to_string(&self.event).expect("ProtocolEvent always serializes to JSON");
+            format!("{}\n", json)
+        })
+    }
+}
+
+impl std::ops::Deref for BusEvent {
+    type Target = ProtocolEvent;
+
+    fn deref(&self) -> &ProtocolEvent {
+        &self.event
+    }
 }
 
 pub struct BridgeState {
     pub active_provider: AgentProvider,
     pub active_model: Option<String>,
-    pub backlog: VecDeque<ProtocolEvent>,
-    pub session_manager: SessionManager,
+    pub backlog: VecDeque<SharedEvent>,
+    /// Agent sessions per channel scope (`session_scope`), so clearing one conversation
+    /// leaves the others' context alone.
+    pub sessions: HashMap<String, SessionManager>,
+    pub macros: HashMap<String, String>,
+    pub personas: PersonaStore,
+    /// Provider and model set for one channel scope with `/provider channel <name>`;
+    /// prompts from that scope that don't name a provider run there instead of the active one.
+    pub channel_providers: HashMap<String, (AgentProvider, Option<String>)>,
+    /// Prompt templates for `@name` (`~/.config/acomm/templates.toml`).
+    pub templates: TemplateStore,
+    /// Scheduled and delayed prompts (`schedules.json` in `state_dir`).
+    pub schedules: ScheduleStore,
+    /// Where bridge state is persisted; `None` keeps everything in memory.
+    pub state_dir: Option<PathBuf>,
+    /// Memory backend behind `/search` and `/today` (`ACOMM_MEMORY_CMD`), split into words.
+    pub memory_cmd: Vec<String>,
+    /// Whether finished conversations are added to `memory_cmd` (`ACOMM_AMEM_LOG`).
+    pub memory_log: bool,
+    /// How agent output is split and coalesced into `AgentChunk`s (`ACOMM_MAX_CHUNK_BYTES`).
+    pub chunk_limits: ChunkLimits,
+    /// How long an agent run may take before it is abandoned (`ACOMM_AGENT_TIMEOUT_SECS`);
+    /// `None` waits forever.
+    pub agent_timeout: Option<Duration>,
+    /// Providers a prompt is retried on after a quota/overload failure (`ACOMM_FALLBACK_PROVIDERS`).
+    pub fallback: FallbackPolicy,
+    /// Providers `/compare` runs a prompt on (`ACOMM_COMPARE_PROVIDERS`).
+    pub compare_providers: Vec<AgentProvider>,
+    /// Slots shared by every agent run (`ACOMM_MAX_CONCURRENT_AGENTS`); `None` runs them all at once.
+    pub agent_slots: Option<Arc<Semaphore>>,
+    /// Prompts queued, running or just finished, to drop accidental repeats (`ACOMM_DEDUP_WINDOW_SECS`).
+    pub dedup: SharedPromptDedup,
+    /// Agent runs in flight, for `/cancel`.
+    pub running: SharedRunningPrompts,
+    /// Hourly prompt quota per user (`ACOMM_QUOTA_PER_HOUR`); `None` leaves prompts unlimited.
+    pub quota: Option<Quota>,
+    /// Prompts waiting for the running one in the same session, keyed like `sessions`.
+    queues: HashMap<String, SharedPromptQueue>,
+}
+
+/// Events kept in the backlog and replayed to new clients; status updates, acks and
+/// handshake events are live-only.
+pub fn is_backlog_event(event: &ProtocolEvent) -> bool {
+    matches!(
+        event,
+        ProtocolEvent::Prompt { .. }
+            | ProtocolEvent::AgentChunk { .. }
+            | ProtocolEvent::AgentDone { .. }
+            | ProtocolEvent::ChannelClosed { .. }
+            | ProtocolEvent::SystemMessage { .. }
+            | ProtocolEvent::ProviderSwitched { .. }
+            | ProtocolEvent::ModelSwitched { .. }
+    )
+}
+
+/// The PID a bridge wrote to `path` when it started.
+pub fn read_bridge_pid(path: &Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Whether a process with `pid` exists. Reads `/proc` rather than spawning `kill -0`,
+/// since clients check this on every connect retry.
+pub fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+/// Free `socket_path` for this bridge. Refuses while another bridge accepts on it or the
+/// bridge process recorded in `pid_path` is still alive (starting up or busy); only a
+/// socket nobody owns any more is removed.
+fn claim_socket(socket_path: &Path, pid_path: &Path) -> Result<(), String> {
+    if !socket_path.exists() {
+        return Ok(());
+    }
+    if std::os::unix::net::UnixStream::connect(socket_path).is_ok() {
+        return Err(format!("another acomm bridge is already listening on {}", socket_path.display()));
+    }
+    let owner = read_bridge_pid(pid_path).filter(|pid| *pid != std::process::id() && process_alive(*pid));
+    if let Some(pid) = owner {
+        return Err(format!("bridge process {} still owns {}; stop it before starting another", pid, socket_path.display()));
+    }
+    warn!("Removing stale bridge socket {}", socket_path.display());
+    let _ = std::fs::remove_file(socket_path);
+    Ok(())
+}
+
+/// Remove the socket and, if it is still ours, the PID file, so a later bridge neither
+/// trips over the socket nor mistakes a reused PID for a live bridge.
+fn release_socket(socket_path: &Path, pid_path: &Path) {
+    if read_bridge_pid(pid_path) == Some(std::process::id()) {
+        let _ = std::fs::remove_file(pid_path);
+    }
+    let _ = std::fs::remove_file(socket_path);
+}
+
+/// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).ok();
+    let terminated = async {
+        match terminate.as_mut() {
+            Some(signal) => {
+                signal.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminated => {}
+    }
 }
 
-pub async fn start_bridge() -> Result<(), Box<dyn Error>> {
-    if Path::new(SOCKET_PATH).exists() {
-        let _ = std::fs::remove_file(SOCKET_PATH);
+/// Run the bridge; with `metrics_addr`, also serve Prometheus metrics on that address.
+pub async fn start_bridge(metrics_addr: Option<String>) -> Result<(), Box<dyn Error>> {
+    claim_socket(Path::new(DEFAULT_SOCKET_PATH), Path::new(BRIDGE_PID_PATH))?;
+    let listener = UnixListener::bind(DEFAULT_SOCKET_PATH)?;
+    if let Err(e) = std::fs::write(BRIDGE_PID_PATH, std::process::id().to_string()) {
+        warn!("Could not write {}: {}", BRIDGE_PID_PATH, e);
     }
-    let listener = UnixListener::bind(SOCKET_PATH)?;
     
-    let (tx, _rx) = broadcast::channel(100);
+    let (capacity, cap_warning) = broadcast_capacity(std::env::var("ACOMM_BROADCAST_CAP").ok().as_deref());
+    if let Some(warning) = cap_warning {
+        warn!("{}", warning);
+    }
+    let (tx, _rx) = broadcast::channel(capacity);
     let tx = Arc::new(tx);
     
+    let (default_provider, default_model) = if offline_mode(std::env::var("ACOMM_OFFLINE").ok().as_deref()) {
+        info!("ACOMM_OFFLINE is set; answering prompts with the offline demo provider (dummy:echo)");
+        (AgentProvider::Dummy, default_model_for_provider(&AgentProvider::Dummy).map(str::to_string))
+    } else {
+        let (provider, model, warning) = resolve_default_provider(
+            std::env::var("ACOMM_DEFAULT_PROVIDER").ok().as_deref(),
+            std::env::var("ACOMM_DEFAULT_MODEL").ok().as_deref(),
+        );
+        if let Some(warning) = warning {
+            warn!("{}", warning);
+        }
+        (provider, model)
+    };
+
+    let (chunk_limits, chunk_warning) = ChunkLimits::from_env_value(std::env::var("ACOMM_MAX_CHUNK_BYTES").ok().as_deref());
+    if let Some(warning) = chunk_warning {
+        warn!("{}", warning);
+    }
+
+    let (agent_timeout, timeout_warning) = resolve_agent_timeout(std::env::var("ACOMM_AGENT_TIMEOUT_SECS").ok().as_deref());
+    if let Some(warning) = timeout_warning {
+        warn!("{}", warning);
+    }
+
+    let (fallback, fallback_warnings) = FallbackPolicy::from_env();
+    for warning in fallback_warnings {
+        warn!("{}", warning);
+    }
+
+    let (compare_providers, compare_warning) = resolve_compare_providers(std::env::var("ACOMM_COMPARE_PROVIDERS").ok().as_deref());
+    if let Some(warning) = compare_warning {
+        warn!("{}", warning);
+    }
+
+    let (agent_limit, limit_warning) = resolve_max_concurrent_agents(std::env::var("ACOMM_MAX_CONCURRENT_AGENTS").ok().as_deref());
+    if let Some(warning) = limit_warning {
+        warn!("{}", warning);
+    }
+
+    let (dedup_window, dedup_warning) = resolve_dedup_window(std::env::var("ACOMM_DEDUP_WINDOW_SECS").ok().as_deref());
+    if let Some(warning) = dedup_warning {
+        warn!("{}", warning);
+    }
+
     let state = Arc::new(Mutex::new(BridgeState {
-        active_provider: DEFAULT_PROVIDER,
-        active_model: default_model_for_provider(&DEFAULT_PROVIDER).map(str::to_string),
+        active_provider: default_provider,
+        active_model: default_model,
         backlog: VecDeque::new(),
-        session_manager: SessionManager::new(),
+        sessions: HashMap::new(),
+        macros: load_macros(persisted_state_dir().as_deref()),
+        personas: load_personas(persisted_state_dir().as_deref()),
+        channel_providers: HashMap::new(),
+        templates: TemplateStore::load(templates_path()),
+        schedules: ScheduleStore::load(persisted_state_dir().map(|dir| dir.join(SCHEDULES_FILE))),
+        state_dir: persisted_state_dir(),
+        memory_cmd: memory_command(std::env::var("ACOMM_MEMORY_CMD").ok().as_deref()),
+        memory_log: memory_log_enabled(std::env::var("ACOMM_AMEM_LOG").ok().as_deref()),
+        chunk_limits,
+        agent_timeout,
+        fallback,
+        compare_providers,
+        agent_slots: agent_limit.map(|limit| Arc::new(Semaphore::new(limit))),
+        quota: Quota::from_env(),
+        dedup: Arc::new(std::sync::Mutex::new(PromptDedup::new(dedup_window))),
+        running: Default::default(),
+        queues: HashMap::new(),
     }));
 
-    let mut manager_rx = tx.subscribe();
-    let state_for_manager = Arc::clone(&state);
-    tokio::spawn(async move {
-        while let Ok(event) = manager_rx.recv().await {
-            let mut s = state_for_manager.lock().await;
-            if matches!(event,
-                ProtocolEvent::Prompt { .. }
-                | ProtocolEvent::AgentChunk { .. }
-                | ProtocolEvent::AgentDone { .. }
-                | ProtocolEvent::SystemMessage { .. }
-                | ProtocolEvent::ProviderSwitched { .. }
-                | ProtocolEvent::ModelSwitched { .. }
-            ) {
-                s.backlog.push_back(event.clone());
-                if s.backlog.len() > MAX_BACKLOG {
-                    s.backlog.pop_front();
+    let metrics = match metrics_addr {
+        Some(addr) => {
+            let metrics = Arc::new(BridgeMetrics::default());
+            let served = Arc::clone(&metrics);
+            tokio::spawn(async move {
+                if let Err(e) = crate::metrics::serve_metrics(&addr, served).await {
+                    error!("Metrics endpoint error: {}", e);
                 }
-            }
-            if let ProtocolEvent::ProviderSwitched { ref provider } = event {
-                s.active_provider = provider.clone();
-                // Reset model selection when provider changes
-                s.active_model = default_model_for_provider(provider).map(str::to_string);
-            }
-            if let ProtocolEvent::ModelSwitched { ref model } = event {
-                s.active_model = Some(model.clone());
-            }
+            });
+            Some(metrics)
         }
-    });
+        None => None,
+    };
+
+    let event_log = EventLog::from_env();
+    if let Some(ref log) = event_log {
+        info!("Appending every broadcast event to {}", log.path().display());
+    }
+    #[cfg(feature = "sqlite")]
+    let history = crate::history_store::HistoryStore::from_env();
+    #[cfg(feature = "sqlite")]
+    if let Some(ref store) = history {
+        match store.prune(crate::history_store::retention_from_env(), chrono::Utc::now()) {
+            Ok(0) => {}
+            Ok(deleted) => info!("Pruned {} old rows from the history store", deleted),
+            Err(e) => warn!("History store pruning failed: {}", e),
+        }
+    }
+    let sinks = EventSinks {
+        event_log,
+        #[cfg(feature = "sqlite")]
+        history,
+    };
+    tokio::spawn(manage_state(tx.subscribe(), Arc::clone(&state), metrics.clone(), sinks));
+    tokio::spawn(run_scheduler(Arc::clone(&tx), Arc::clone(&state)));
 
-    println!("acomm bridge started at {}", SOCKET_PATH);
+    info!("acomm bridge started at {}", DEFAULT_SOCKET_PATH);
 
-    loop {
-        let (stream, _) = listener.accept().await?;
+    let next_connection_id = AtomicU64::new(1);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let result: Result<(), Box<dyn Error>> = loop {
+        let (stream, _) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => break Err(e.into()),
+            },
+            _ = &mut shutdown => {
+                info!("acomm bridge shutting down");
+                break Ok(());
+            }
+        };
         let tx = Arc::clone(&tx);
         let state = Arc::clone(&state);
-        tokio::spawn(async move {
-            if let Err(e) = handle_bridge_connection(stream, tx, state).await {
-                let msg = e.to_string();
-                if !msg.contains("Broken pipe") {
-                    eprintln!("Bridge connection error: {}", e);
+        let metrics = metrics.clone();
+        let span = info_span!("connection", id = next_connection_id.fetch_add(1, Ordering::Relaxed));
+        tokio::spawn(
+            async move {
+                info!("client connected");
+                match handle_bridge_connection(stream, tx, state, metrics).await {
+                    Err(e) if !e.to_string().contains("Broken pipe") => error!("Bridge connection error: {}", e),
+                    _ => {}
                 }
+                info!("client disconnected");
             }
-        });
+            .instrument(span),
+        );
+    };
+    release_socket(Path::new(DEFAULT_SOCKET_PATH), Path::new(BRIDGE_PID_PATH));
+    result
+}
+
+/// Optional per-event recorders fed by `manage_state` besides the backlog.
+#[derive(Default)]
+struct EventSinks {
+    event_log: Option<EventLog>,
+    #[cfg(feature = "sqlite")]
+    history: Option<crate::history_store::HistoryStore>,
+}
+
+/// Follow the broadcast channel and keep `BridgeState` in sync with it: the backlog replayed
+/// to new clients, the active provider/model, metrics and any configured event sinks.
+async fn manage_state(
+    mut rx: broadcast::Receiver<SharedEvent>,
+    state: Arc<Mutex<BridgeState>>,
+    metrics: Option<Arc<BridgeMetrics>>,
+    mut sinks: EventSinks,
+) {
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Bridge manager lagged behind by {} events; raise ACOMM_BROADCAST_CAP", skipped);
+                if let Some(ref metrics) = metrics {
+                    metrics.record_lag(skipped);
+                }
+                continue;
+            }
+            Err(_) => break,
+        };
+        if let Some(ref metrics) = metrics {
+            metrics.observe(&event);
+        }
+        if let Some(ref mut log) = sinks.event_log {
+            if let Err(e) = log.append(&event) {
+                warn!("Event log write to {} failed: {}", log.path().display(), e);
+            }
+        }
+        let mut s = state.lock().await;
+        #[cfg(feature = "sqlite")]
+        if let Some(ref store) = sinks.history {
+            if let Err(e) = store.record(&event, &s.active_provider) {
+                warn!("History store write failed: {}", e);
+            }
+        }
+        if is_backlog_event(&event) {
+            // Serialize here, once, so replaying the backlog to new clients is just copying lines.
+            event.line();
+            s.backlog.push_back(event.clone());
+            if s.backlog.len() > MAX_BACKLOG {
+                s.backlog.pop_front();
+            }
+        }
+        if let ProtocolEvent::ProviderSwitched { ref provider } = **event {
+            info!(provider = provider.command_name(), "provider switched");
+            s.active_provider = provider.clone();
+            // Reset model selection when provider changes
+            s.active_model = default_model_for_provider(provider).map(str::to_string);
+        }
+        if let ProtocolEvent::ModelSwitched { ref model } = **event {
+            info!(model = %model, "model switched");
+            s.active_model = Some(model.clone());
+        }
+    }
+}
+
+/// The part of `BridgeState` a new connection replays. Taking it only clones the backlog's
+/// `Arc`s, so the state lock is released before the payload is built and written.
+struct SyncSnapshot {
+    provider: AgentProvider,
+    model: Option<String>,
+    backlog: Vec<SharedEvent>,
+}
+
+impl BridgeState {
+    fn sync_snapshot(&self) -> SyncSnapshot {
+        SyncSnapshot {
+            provider: self.active_provider.clone(),
+            model: self.active_model.clone(),
+            backlog: self.backlog.iter().cloned().collect(),
+        }
     }
 }
 
-async fn handle_bridge_connection(
-    mut stream: UnixStream,
-    broadcast_tx: Arc<broadcast::Sender<ProtocolEvent>>,
+/// Everything a client receives on connect, up to and including `BridgeSyncDone`.
+/// Backlog entries are already serialized, so they are only copied.
+fn initial_sync_payload(snapshot: &SyncSnapshot, context: String) -> Result<String, serde_json::Error> {
+    let mut events = vec![ProtocolEvent::Hello { version: PROTOCOL_VERSION }];
+    if !context.is_empty() {
+        events.push(ProtocolEvent::SyncContext { context });
+    }
+    events.push(ProtocolEvent::ProviderSwitched { provider: snapshot.provider.clone() });
+    if let Some(ref model) = snapshot.model {
+        events.push(ProtocolEvent::ModelSwitched { model: model.clone() });
+    }
+    let mut head = String::new();
+    for event in &events {
+        head.push_str(&serde_json::to_string(event)?);
+        head.push('\n');
+    }
+    let sync_done = format!("{}\n", serde_json::to_string(&ProtocolEvent::BridgeSyncDone {})?);
+    let backlog_bytes: usize = snapshot.backlog.iter().map(|event| event.line().len()).sum();
+    let mut payload = String::with_capacity(head.len() + backlog_bytes + sync_done.len());
+    payload.push_str(&head);
+    for event in &snapshot.backlog {
+        payload.push_str(event.line());
+    }
+    payload.push_str(&sync_done);
+    Ok(payload)
+}
+
+/// Serve one client connection. Generic over the transport so tests can drive it through
+/// `tokio::io::duplex` instead of the shared socket path.
+async fn handle_bridge_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    broadcast_tx: Arc<broadcast::Sender<SharedEvent>>,
     state: Arc<Mutex<BridgeState>>,
+    metrics: Option<Arc<BridgeMetrics>>,
 ) -> Result<(), Box<dyn Error>> {
     let mut broadcast_rx = broadcast_tx.subscribe();
-    let (reader, mut writer) = stream.split();
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut prompt_seq: u64 = 0;
     let mut lines = BufReader::new(reader).lines();
 
-    {
-        let s = state.lock().await;
-        let context = AgentExecutor::fetch_context().await;
-        let mut initial_payload = String::new();
-        if !context.is_empty() {
-            let event = ProtocolEvent::SyncContext { context };
-            initial_payload.push_str(&serde_json::to_string(&event)?);
-            initial_payload.push('\n');
-        }
-        let provider_event = ProtocolEvent::ProviderSwitched { provider: s.active_provider.clone() };
-        initial_payload.push_str(&serde_json::to_string(&provider_event)?);
-        initial_payload.push('\n');
-        if let Some(ref model) = s.active_model {
-            let model_event = ProtocolEvent::ModelSwitched { model: model.clone() };
-            initial_payload.push_str(&serde_json::to_string(&model_event)?);
-            initial_payload.push('\n');
-        }
-        for event in &s.backlog {
-            initial_payload.push_str(&serde_json::to_string(event)?);
-            initial_payload.push('\n');
-        }
-        let sync_done = ProtocolEvent::BridgeSyncDone {};
-        initial_payload.push_str(&serde_json::to_string(&sync_done)?);
-        initial_payload.push('\n');
-        let _ = writer.write_all(initial_payload.as_bytes()).await;
-    }
+    let snapshot = state.lock().await.sync_snapshot();
+    let context = AgentExecutor::fetch_context().await;
+    let initial_payload = initial_sync_payload(&snapshot, context)?;
+    let _ = writer.write_all(initial_payload.as_bytes()).await;
 
     loop {
         let tx_loop = Arc::clone(&broadcast_tx);
@@ -184,66 +812,45 @@ async fn handle_bridge_connection(
                 };
                 if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
                     match event {
-                        ProtocolEvent::Prompt { ref text, ref provider, .. } => {
+                        ProtocolEvent::Hello { version } => {
+                            if let Some(msg) = version_mismatch_message("client", version) {
+                                warn!("Rejecting client: {}", msg);
+                                let rejection = ProtocolEvent::SystemMessage { msg, channel: None };
+                                let _ = writer.write_all(format!("{}\n", serde_json::to_string(&rejection)?).as_bytes()).await;
+                                let _ = writer.shutdown().await;
+                                break;
+                            }
+                        }
+                        ProtocolEvent::Prompt { ref text, ref provider, ref reply_to, priority, ref author, .. } => {
                             let channel = event.clone_channel();
+                            info!(
+                                channel = channel.as_deref().unwrap_or("none"),
+                                text = %truncate_for_log(text, LOG_PROMPT_CHARS),
+                                "prompt received"
+                            );
+                            prompt_seq += 1;
+                            let ack = ProtocolEvent::PromptAck { channel: channel.clone(), seq: prompt_seq };
                             if let Some(preset) = discord_magic_provider_preset(text, channel.as_deref()) {
                                 apply_provider_preset(&tx_loop, channel, preset);
-                                continue;
-                            }
-                            if text.starts_with('/') {
-                                handle_command(text, &tx_loop, &state).await?;
+                            } else if charges_quota(text) && !admit_prompt(channel.as_deref(), author.as_deref(), &tx_loop, &state).await {
+                                // Over quota: the rejection was sent to the channel instead.
+                            } else if text.starts_with('/') {
+                                handle_command(text, channel, &tx_loop, &state).await?;
                             } else {
-                                let (active_provider, active_model, manager) = {
-                                    let s = state.lock().await;
-                                    let selected_provider = match provider {
-                                        Some(t) => t.clone(),
-                                        None => s.active_provider.clone(),
-                                    };
-                                    let selected_model = if selected_provider == s.active_provider {
-                                        s.active_model.clone()
-                                    } else {
-                                        default_model_for_provider(&selected_provider).map(str::to_string)
-                                    };
-                                    (selected_provider, selected_model, s.session_manager.clone())
-                                };
-                                let _ = tx_loop.send(ProtocolEvent::Prompt { 
-                                    text: text.clone(), 
-                                    provider: Some(active_provider.clone()), 
-                                    channel: channel.clone()
-                                });
-                                let _ = tx_loop.send(ProtocolEvent::StatusUpdate { is_processing: true, channel: channel.clone() });
-                                
-                                let tx_inner = Arc::clone(&tx_loop);
-                                let text_inner = text.clone();
-                                let channel_inner = channel.clone();
-                                let active_model_inner = active_model.clone();
-                                
-                                tokio::spawn(async move {
-                                    let tx_chunk = Arc::clone(&tx_inner);
-                                    let tx_err = Arc::clone(&tx_inner);
-                                    let ch_chunk = channel_inner.clone();
-                                    match manager.execute_with_resume_with_model(
-                                        active_provider,
-                                        active_model_inner,
-                                        &text_inner,
-                                        move |chunk| {
-                                        let _ = tx_chunk.send(ProtocolEvent::AgentChunk { chunk, channel: ch_chunk.clone() });
-                                    }).await {
-                                        Ok(_) => {},
-                                        Err(e) => {
-                                            let _ = tx_err.send(ProtocolEvent::SystemMessage { 
-                                                msg: format!("Agent execution failed: {}", e), 
-                                                channel: channel_inner.clone()
-                                            });
-                                        }
-                                    }
-                                    let _ = tx_inner.send(ProtocolEvent::AgentDone { channel: channel_inner.clone() });
-                                    let _ = tx_inner.send(ProtocolEvent::StatusUpdate { is_processing: false, channel: channel_inner });
-                                });
+                                let admitted =
+                                    run_prompt(text.clone(), provider.clone(), channel.clone(), reply_to.clone(), priority, &tx_loop, &state).await;
+                                if !admitted {
+                                    // Dropped as a duplicate or a bad template: it does not use up the quota.
+                                    refund_prompt(channel.as_deref(), author.as_deref(), &state).await;
+                                }
+                            }
+                            // Acknowledge on this connection only, once the prompt has been dispatched.
+                            if writer.write_all(format!("{}\n", serde_json::to_string(&ack)?).as_bytes()).await.is_err() {
+                                break;
                             }
                         }
                         ProtocolEvent::SystemMessage { .. } => {
-                            let _ = tx_loop.send(event);
+                            let _ = tx_loop.send(BusEvent::new(event));
                         }
                         _ => {}
                     }
@@ -252,13 +859,22 @@ async fn handle_bridge_connection(
             event_res = broadcast_rx.recv() => {
                 match event_res {
                     Ok(event) => {
-                        if let Ok(j) = serde_json::to_string(&event) {
-                            if let Err(_) = writer.write_all(format!("{}\n", j).as_bytes()).await {
-                                break;
-                            }
+                        if writer.write_all(event.line().as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        if let Some(ref metrics) = metrics {
+                            metrics.record_lag(skipped);
+                        }
+                        let payload = {
+                            let s = state.lock().await;
+                            lag_resync_payload(skipped, &s)?
+                        };
+                        if writer.write_all(payload.as_bytes()).await.is_err() {
+                            break;
                         }
                     }
-                    Err(broadcast::error::RecvError::Lagged(_)) => { continue; }
                     Err(_) => break,
                 }
             }
@@ -267,210 +883,2720 @@ async fn handle_bridge_connection(
     Ok(())
 }
 
-async fn handle_command(
-    text: &str,
-    tx: &Arc<broadcast::Sender<ProtocolEvent>>,
+/// Events sent to a client that fell behind the broadcast buffer: a notice with the number
+/// of skipped events, followed by the current provider/model so its header state re-syncs.
+fn lag_resync_payload(skipped: u64, state: &BridgeState) -> Result<String, serde_json::Error> {
+    let mut events = vec![
+        ProtocolEvent::SystemMessage {
+            msg: format!(
+                "Client fell behind and missed {} events (raise ACOMM_BROADCAST_CAP if this repeats)",
+                skipped
+            ),
+            channel: None,
+        },
+        ProtocolEvent::ProviderSwitched { provider: state.active_provider.clone() },
+    ];
+    if let Some(ref model) = state.active_model {
+        events.push(ProtocolEvent::ModelSwitched { model: model.clone() });
+    }
+    let mut payload = String::new();
+    for event in &events {
+        payload.push_str(&serde_json::to_string(event)?);
+        payload.push('\n');
+    }
+    Ok(payload)
+}
+
+/// A prompt resolved against the bridge state when it arrived (provider, model, persona
+/// and session), so it can wait in its session's queue and run later as submitted.
+struct PromptJob {
+    text: String,
+    channel: Option<String>,
+    reply_to: Option<String>,
+    priority: bool,
+    provider: AgentProvider,
+    model: Option<String>,
+    agent_text: String,
+    manager: SessionManager,
+    chunk_limits: ChunkLimits,
+    timeout: Option<Duration>,
+    fallback: FallbackPolicy,
+    slots: Option<Arc<Semaphore>>,
+    running: SharedRunningPrompts,
+    /// Memory backend the finished conversation is added to, when `ACOMM_AMEM_LOG` is on.
+    memory_log: Option<Vec<String>>,
+}
+
+type SharedPromptQueue = Arc<std::sync::Mutex<PromptQueue<PromptJob>>>;
+type SharedPromptDedup = Arc<std::sync::Mutex<PromptDedup>>;
+/// Agent tasks in flight by task id, with the channel each one answers on.
+type SharedRunningPrompts = Arc<std::sync::Mutex<HashMap<tokio::task::Id, (Option<String>, tokio::task::AbortHandle)>>>;
+
+/// The notice sent instead of running a prompt that `PromptDedup` turned away.
+fn duplicate_prompt_message(duplicate: &Duplicate) -> String {
+    match duplicate {
+        Duplicate::InFlight => "Ignored a duplicate prompt: the same prompt is already queued or running.".to_string(),
+        Duplicate::Recent(ago) => format!(
+            "Ignored a duplicate prompt: the same prompt finished {}s ago.",
+            ago.as_secs_f64().ceil().max(1.0) as u64
+        ),
+    }
+}
+
+/// Run the prompt on the selected provider in the background, or queue it behind the
+/// prompt already running in the same session (at the front when `priority` is set).
+/// A leading `@name` is expanded from the templates first, so the echoed `Prompt`
+/// carries the text that actually runs. Returns `false` when the prompt was dropped
+/// instead (unknown template or duplicate).
+async fn run_prompt(
+    text: String,
+    provider: Option<AgentProvider>,
+    channel: Option<String>,
+    reply_to: Option<String>,
+    priority: bool,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
     state: &Mutex<BridgeState>,
-) -> Result<(), Box<dyn Error>> {
-    let parts: Vec<&str> = text[1..].split_whitespace().collect();
-    let cmd = parts.get(0).unwrap_or(&"");
-    match *cmd {
-        "search" => {
-            let query = parts[1..].join(" ");
-            let output = std::process::Command::new("amem").arg("search").arg(&query).output()?;
-            let result = String::from_utf8_lossy(&output.stdout).to_string();
-            let _ = tx.send(ProtocolEvent::SystemMessage { msg: format!("Search results:\n{result}"), channel: Some("bridge".into()) });
-        }
-        "today" => {
-            let output = std::process::Command::new("amem").arg("today").output()?;
-            let result = String::from_utf8_lossy(&output.stdout).to_string();
-            let _ = tx.send(ProtocolEvent::SystemMessage { msg: format!("Today:\n{result}"), channel: Some("bridge".into()) });
-        }
-        "provider" => {
-            if let Some(name) = parts.get(1) {
-                let provider = match *name {
-                    "gemini" => AgentProvider::Gemini,
-                    "claude" => AgentProvider::Claude,
-                    "codex" => AgentProvider::Codex,
-                    "opencode" => AgentProvider::OpenCode,
-                    "dummy" | "dummy-bot" | "dummybot" => AgentProvider::Dummy,
-                    "mock" => AgentProvider::Mock,
-                    _ => return Ok(()),
-                };
-                let default_model = default_model_for_provider(&provider).map(str::to_string);
-                let _ = tx.send(ProtocolEvent::ProviderSwitched { provider });
-                if let Some(model) = default_model {
-                    let _ = tx.send(ProtocolEvent::ModelSwitched { model });
-                }
+) -> bool {
+    let (job, queue, dedup) = {
+        let mut s = state.lock().await;
+        let text = match s.templates.expand(&text) {
+            Ok(expanded) => expanded.unwrap_or(text),
+            Err(msg) => {
+                let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: channel.or_else(|| Some("bridge".into())) }));
+                return false;
             }
+        };
+        let key = session_key(channel.as_deref(), reply_to.as_deref());
+        let admitted = s.dedup.lock().unwrap_or_else(std::sync::PoisonError::into_inner).admit(&key, &text, Instant::now());
+        if let Err(duplicate) = admitted {
+            info!(channel = channel.as_deref().unwrap_or("none"), ?duplicate, "duplicate prompt dropped");
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                msg: duplicate_prompt_message(&duplicate),
+                channel: channel.or_else(|| Some("bridge".into())),
+            }));
+            return false;
         }
-        "model" => {
-            if let Some(model_name) = parts.get(1) {
-                let _ = tx.send(ProtocolEvent::ModelSwitched { model: model_name.to_string() });
+        let (selected_provider, selected_model) = match (provider, s.channel_providers.get(&session_scope(channel.as_deref()))) {
+            (Some(t), _) if t != s.active_provider => {
+                let model = default_model_for_provider(&t).map(str::to_string);
+                (t, model)
             }
+            (None, Some(chosen)) => chosen.clone(),
+            _ => (s.active_provider.clone(), s.active_model.clone()),
+        };
+        let agent_text = apply_persona(s.personas.for_channel(channel.as_deref()), &text);
+        let manager = s.sessions.entry(key.clone()).or_insert_with(SessionManager::new).clone();
+        let queue = Arc::clone(s.queues.entry(key).or_default());
+        let job = PromptJob {
+            text,
+            channel,
+            reply_to,
+            priority,
+            provider: selected_provider,
+            model: selected_model,
+            agent_text,
+            manager,
+            chunk_limits: s.chunk_limits,
+            timeout: s.agent_timeout,
+            fallback: s.fallback.clone(),
+            slots: s.agent_slots.clone(),
+            running: Arc::clone(&s.running),
+            memory_log: s.memory_log.then(|| s.memory_cmd.clone()),
+        };
+        (job, queue, Arc::clone(&s.dedup))
+    };
+    let channel = job.channel.clone();
+    let admission = queue.lock().unwrap_or_else(std::sync::PoisonError::into_inner).admit(job, priority);
+    match admission {
+        Admission::Start(job) => {
+            announce_prompt(&job, tx);
+            tokio::spawn(run_prompt_queue(job, queue, dedup, Arc::clone(tx)));
         }
-        "clear" => {
-            let mut s = state.lock().await;
-            s.backlog.clear();
-            s.session_manager = SessionManager::new();
-            s.active_model = default_model_for_provider(&s.active_provider).map(str::to_string);
-            let cleared_model = s.active_model.clone();
-            let _ = tx.send(ProtocolEvent::SystemMessage { msg: "Cleared.".into(), channel: Some("bridge".into()) });
-            if let Some(model) = cleared_model {
-                let _ = tx.send(ProtocolEvent::ModelSwitched { model });
-            }
+        Admission::Queued { position } => {
+            info!(channel = channel.as_deref().unwrap_or("none"), position, priority, "prompt queued");
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                msg: format!("Queued behind the running prompt (position {})", position),
+                channel,
+            }));
         }
-        _ => {}
     }
-    Ok(())
+    true
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::protocol::ProtocolEvent;
-    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-    use tokio::net::UnixStream;
-    use std::time::Duration;
+/// Echo the prompt to all subscribers as it starts running.
+fn announce_prompt(job: &PromptJob, tx: &broadcast::Sender<SharedEvent>) {
+    let _ = tx.send(BusEvent::new(ProtocolEvent::Prompt {
+        text: job.text.clone(),
+        provider: Some(job.provider.clone()),
+        channel: job.channel.clone(),
+        reply_to: job.reply_to.clone(),
+        priority: job.priority,
+        author: None,
+    }));
+    let _ = tx.send(BusEvent::new(ProtocolEvent::StatusUpdate { is_processing: true, channel: job.channel.clone() }));
+}
 
-    // ブリッジテストは同じソケットパスを使うため並列実行すると競合する。
-    // static Mutex で排他制御し、常に1テストずつ実行する。
-    static BRIDGE_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+/// Run `first`, then every prompt that queued up behind it in the same session.
+async fn run_prompt_queue(
+    first: PromptJob,
+    queue: SharedPromptQueue,
+    dedup: SharedPromptDedup,
+    tx: Arc<broadcast::Sender<SharedEvent>>,
+) {
+    let mut job = first;
+    loop {
+        let (key, text) = (session_key(job.channel.as_deref(), job.reply_to.as_deref()), job.text.clone());
+        let _ = spawn_prompt_job(job, &tx).await;
+        dedup.lock().unwrap_or_else(std::sync::PoisonError::into_inner).finish(&key, &text, Instant::now());
+        let next = queue.lock().unwrap_or_else(std::sync::PoisonError::into_inner).finish();
+        let Some(next) = next else { break };
+        announce_prompt(&next, &tx);
+        job = next;
+    }
+}
 
-    #[tokio::test]
-    async fn test_bridge_mock_flow() {
-        let _guard = BRIDGE_TEST_LOCK.lock().unwrap();
-        let _ = std::fs::remove_file(SOCKET_PATH);
-        tokio::spawn(async { let _ = start_bridge().await; });
-        tokio::time::sleep(Duration::from_millis(500)).await;
-        
-        let stream = UnixStream::connect(SOCKET_PATH).await.expect("Failed to connect");
-        let (reader, mut writer) = tokio::io::split(stream);
-        let mut lines = BufReader::new(reader).lines();
-        
-        while let Ok(Ok(Some(line))) = tokio::time::timeout(Duration::from_millis(200), lines.next_line()).await {
-            let _ = serde_json::from_str::<ProtocolEvent>(&line);
-        }
-
-        let prompt = ProtocolEvent::Prompt { 
-            text: "hello mock".into(), 
-            provider: Some(AgentProvider::Mock), 
-            channel: Some("test_channel".into()) 
+/// Run one prompt on its provider, falling back along the job's chain on retryable
+/// failures; the returned task ends after its terminal events.
+fn spawn_prompt_job(job: PromptJob, tx: &Arc<broadcast::Sender<SharedEvent>>) -> tokio::task::JoinHandle<()> {
+    let PromptJob {
+        text, channel, provider: active_provider, model: active_model, agent_text, manager, chunk_limits, timeout, fallback, slots, running, memory_log, ..
+    } = job;
+    let tx_inner = Arc::clone(tx);
+    let run_channel = channel.clone();
+    let span = info_span!(
+        "agent",
+        channel = channel.as_deref().unwrap_or("none"),
+        provider = active_provider.command_name(),
+        model = active_model.as_deref().unwrap_or("default"),
+    );
+    spawn_prompt_task(channel, tx, Some(&running), async move {
+        // Wait for a free slot under ACOMM_MAX_CONCURRENT_AGENTS; it is held through any fallback retries.
+        let _slot = match slots {
+            Some(slots) => slots.acquire_owned().await.ok(),
+            None => None,
         };
-        writer.write_all(format!("{}\n", serde_json::to_string(&prompt).unwrap()).as_bytes()).await.unwrap();
-        
-        let mut received = Vec::new();
-        let start = std::time::Instant::now();
-        while start.elapsed() < Duration::from_secs(5) {
-            if let Ok(Ok(Some(line))) = tokio::time::timeout(Duration::from_millis(500), lines.next_line()).await {
-                let ev: ProtocolEvent = serde_json::from_str(&line).unwrap();
-                received.push(ev);
+        // Agent output goes through `forward_chunks`, which splits oversized chunks and
+        // coalesces tiny ones before they are broadcast.
+        let (chunk_tx, chunk_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let tx_chunk = Arc::clone(&tx_inner);
+        let ch_chunk = run_channel.clone();
+        // The reply is only collected when it is going to be logged.
+        let reply = memory_log.as_ref().map(|_| Arc::new(std::sync::Mutex::new(String::new())));
+        let reply_sink = reply.clone();
+        let forwarder = tokio::spawn(forward_chunks(chunk_rx, chunk_limits, move |chunk| {
+            if let Some(reply) = &reply_sink {
+                reply.lock().unwrap_or_else(std::sync::PoisonError::into_inner).push_str(&chunk);
+            }
+            let _ = tx_chunk.send(BusEvent::new(ProtocolEvent::AgentChunk { chunk, channel: ch_chunk.clone() }));
+        }));
+        let started = Instant::now();
+        debug!("agent started");
+        let outcome = run_with_fallback(
+            &fallback,
+            active_provider,
+            active_model,
+            |provider, model| execute_agent(&manager, provider, model, &agent_text, chunk_tx.clone(), timeout),
+            |failed, error, next| {
+                warn!("{} failed ({}); retrying with {}", failed.command_name(), error, next.command_name());
+                let _ = tx_inner.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                    msg: format!("{} failed ({}), retrying with {}…", failed.command_name(), error, next.command_name()),
+                    channel: run_channel.clone(),
+                }));
+            },
+        )
+        .await;
+        // Every chunk is out before the error message and AgentDone.
+        drop(chunk_tx);
+        let _ = forwarder.await;
+        let succeeded = outcome.result.is_ok();
+        match outcome.result {
+            Ok(()) => info!(elapsed_ms = started.elapsed().as_millis() as u64, provider = outcome.provider.command_name(), "agent finished"),
+            Err(e) => {
+                error!(elapsed_ms = started.elapsed().as_millis() as u64, "agent failed: {}", e);
+                let _ = tx_inner.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                    msg: format!("{}: {}", AGENT_FAILURE_PREFIX, e),
+                    channel: run_channel.clone()
+                }));
             }
         }
-        
-        assert!(received.iter().any(|e| matches!(e, ProtocolEvent::StatusUpdate { channel: Some(c), .. } if c == "test_channel")));
-        assert!(received.iter().any(|e| matches!(e, ProtocolEvent::AgentChunk { channel: Some(c), .. } if c == "test_channel")));
-        assert!(received.iter().any(|e| matches!(e, ProtocolEvent::AgentDone { channel: Some(c), .. } if c == "test_channel")));
-    }
-
-    #[tokio::test]
-    async fn test_bridge_initial_sync_emits_completion_marker() {
-        let _guard = BRIDGE_TEST_LOCK.lock().unwrap();
-        let _ = std::fs::remove_file(SOCKET_PATH);
-        tokio::spawn(async { let _ = start_bridge().await; });
-        tokio::time::sleep(Duration::from_millis(500)).await;
-
-        let stream = UnixStream::connect(SOCKET_PATH).await.expect("Failed to connect");
-        let (reader, _) = tokio::io::split(stream);
-        let mut lines = BufReader::new(reader).lines();
-
-        let mut saw_marker = false;
-        let start = std::time::Instant::now();
-        while start.elapsed() < Duration::from_secs(2) {
-            if let Ok(Ok(Some(line))) = tokio::time::timeout(Duration::from_millis(200), lines.next_line()).await {
-                if line.contains("\"BridgeSyncDone\"") {
-                    saw_marker = true;
-                    break;
+        let record = match (succeeded, memory_log, reply) {
+            (true, Some(memory_cmd), Some(reply)) => Some((memory_cmd, ConversationRecord {
+                timestamp: chrono::Local::now().to_rfc3339(),
+                channel: run_channel.clone(),
+                provider: outcome.provider.command_name().to_string(),
+                model: outcome.model.clone(),
+                prompt: text,
+                reply: std::mem::take(&mut *reply.lock().unwrap_or_else(std::sync::PoisonError::into_inner)),
+            })),
+            _ => None,
+        };
+        let _ = tx_inner.send(BusEvent::new(ProtocolEvent::AgentDone {
+            channel: run_channel,
+            provider: Some(outcome.provider),
+            model: outcome.model,
+        }));
+        // Logging happens off the reply path; a failing backend only costs a warning.
+        if let Some((memory_cmd, record)) = record {
+            tokio::spawn(async move {
+                if let Err(e) = log_conversation(&memory_cmd, &record).await {
+                    warn!("could not log the conversation to memory: {}", e);
                 }
-            } else {
-                break;
-            }
+            }.in_current_span());
         }
+    }.instrument(span))
+}
 
-        assert!(saw_marker, "bridge should emit BridgeSyncDone after initial sync payload");
-    }
-
-    #[tokio::test]
-    async fn test_bridge_initial_sync_emits_gemini_default_provider_and_model() {
-        let _guard = BRIDGE_TEST_LOCK.lock().unwrap();
-        let _ = std::fs::remove_file(SOCKET_PATH);
-        tokio::spawn(async { let _ = start_bridge().await; });
-        tokio::time::sleep(Duration::from_millis(500)).await;
-
-        let stream = UnixStream::connect(SOCKET_PATH).await.expect("Failed to connect");
-        let (reader, _) = tokio::io::split(stream);
-        let mut lines = BufReader::new(reader).lines();
-
-        let mut saw_provider = false;
-        let mut saw_model = false;
-        let start = std::time::Instant::now();
-        while start.elapsed() < Duration::from_secs(2) {
-            let line = match tokio::time::timeout(Duration::from_millis(200), lines.next_line()).await {
-                Ok(Ok(Some(line))) => line,
-                _ => break,
-            };
-
-            let ev: ProtocolEvent = match serde_json::from_str(&line) {
-                Ok(ev) => ev,
-                Err(_) => continue,
-            };
-
-            match ev {
-                ProtocolEvent::ProviderSwitched { provider } if provider == AgentProvider::Gemini => saw_provider = true,
-                ProtocolEvent::ModelSwitched { model } if model == "auto-gemini-3" => saw_model = true,
-                ProtocolEvent::BridgeSyncDone {} => break,
-                _ => {}
+/// One attempt at running `agent_text` on `provider`, streaming its output into
+/// `chunk_tx`. Also returns whether any output was produced, so a run that failed
+/// halfway through an answer is not retried on another provider.
+async fn execute_agent(
+    manager: &SessionManager,
+    provider: AgentProvider,
+    model: Option<String>,
+    agent_text: &str,
+    chunk_tx: tokio::sync::mpsc::UnboundedSender<String>,
+    timeout: Option<Duration>,
+) -> (Result<(), String>, bool) {
+    let produced = Arc::new(AtomicBool::new(false));
+    let seen = Arc::clone(&produced);
+    let send = move |chunk: String| {
+        seen.store(true, Ordering::Relaxed);
+        let _ = chunk_tx.send(chunk);
+    };
+    let execution = async move {
+        if provider == AgentProvider::Dummy {
+            // The offline demo provider answers without spawning an agent CLI.
+            for chunk in offline_reply_chunks(agent_text) {
+                send(chunk);
+                tokio::time::sleep(OFFLINE_CHUNK_DELAY).await;
             }
+            Ok(())
+        } else {
+            manager
+                .execute_with_resume_with_model(provider, model, agent_text, send)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string())
         }
+    };
+    // A stalled provider is dropped (closing its end of the chunk channel) once the
+    // timeout passes, so the prompt still ends with AgentDone.
+    let result = match timeout {
+        Some(limit) => tokio::time::timeout(limit, execution)
+            .await
+            .unwrap_or_else(|_| Err(format!("timed out after {:?}", limit))),
+        None => execution.await,
+    };
+    (result, produced.load(Ordering::Relaxed))
+}
 
-        assert!(saw_provider, "initial sync should include Gemini default provider");
-        assert!(saw_model, "initial sync should include auto-gemini-3 default model");
-    }
+/// Run a prompt's agent task so that every prompt ends with a terminal event:
+/// `run` sends `AgentDone` itself, and if it panics or is aborted first a
+/// `ChannelClosed` is sent instead so adapters can drop their per-channel state.
+/// While it runs the task is listed in `running`, so `/cancel` can abort it.
+fn spawn_prompt_task<F>(
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    running: Option<&SharedRunningPrompts>,
+    run: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: std::future::Future<Output = ()> + Send + 'static,
+{
+    let tx = Arc::clone(tx);
+    let task = tokio::spawn(run);
+    let registered = running.map(|running| {
+        let entry = (channel.clone(), task.abort_handle());
+        running.lock().unwrap_or_else(std::sync::PoisonError::into_inner).insert(task.id(), entry);
+        (Arc::clone(running), task.id())
+    });
+    tokio::spawn(async move {
+        let result = task.await;
+        if let Some((running, id)) = registered {
+            running.lock().unwrap_or_else(std::sync::PoisonError::into_inner).remove(&id);
+        }
+        if let Err(e) = result {
+            let reason = if e.is_panic() { "Agent task panicked" } else { "Agent task was cancelled" };
+            error!("{} (channel: {})", reason, channel.as_deref().unwrap_or("none"));
+            let _ = tx.send(BusEvent::new(ProtocolEvent::ChannelClosed { channel: channel.clone(), reason: reason.to_string() }));
+        }
+        let _ = tx.send(BusEvent::new(ProtocolEvent::StatusUpdate { is_processing: false, channel }));
+    })
+}
+
+const DEFAULT_MEMORY_CMD: &str = "amem";
+/// How often `/search` and `/today` send what the memory backend has printed so far.
+const MEMORY_PARTIAL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The memory backend command from `ACOMM_MEMORY_CMD` (program plus leading arguments),
+/// defaulting to `amem`.
+pub fn memory_command(raw: Option<&str>) -> Vec<String> {
+    let words: Vec<String> = raw.unwrap_or_default().split_whitespace().map(str::to_string).collect();
+    if words.is_empty() { vec![DEFAULT_MEMORY_CMD.to_string()] } else { words }
+}
+
+/// Run the memory backend with `args`, sending its output to `channel` as
+/// `SystemMessage`s titled `header`. Output is flushed every `partial_interval` while the backend is still
+/// running, so slow searches show results as they come. A missing binary or a failed
+/// run is reported as a message rather than an error.
+async fn stream_memory_command(
+    memory_cmd: &[String],
+    args: &[String],
+    header: &str,
+    partial_interval: Duration,
+    channel: Option<String>,
+    tx: &broadcast::Sender<SharedEvent>,
+) {
+    let send = |msg: String| {
+        let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: channel.clone() }));
+    };
+    let Some((program, base_args)) = memory_cmd.split_first() else {
+        send("memory backend is not configured".into());
+        return;
+    };
+    let spawned = tokio::process::Command::new(program)
+        .args(base_args)
+        .args(args)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            let msg = match e.kind() {
+                std::io::ErrorKind::NotFound => format!("memory backend '{}' not found", program),
+                _ => format!("memory backend '{}' failed to start: {}", program, e),
+            };
+            warn!("{}", msg);
+            send(msg);
+            return;
+        }
+    };
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = tokio::spawn(async move {
+        let mut buf = String::new();
+        let _ = tokio::io::AsyncReadExt::read_to_string(&mut stderr, &mut buf).await;
+        buf
+    });
+    let mut lines = BufReader::new(child.stdout.take().expect("stdout is piped")).lines();
+    let mut pending = String::new();
+    let mut sent_any = false;
+    let mut flush = tokio::time::interval(partial_interval);
+    flush.tick().await;
+    let mut reading = true;
+    while reading {
+        tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    pending.push_str(&line);
+                    pending.push('\n');
+                }
+                _ => reading = false,
+            },
+            _ = flush.tick() => {
+                if !pending.is_empty() {
+                    send(memory_message(header, sent_any, &std::mem::take(&mut pending)));
+                    sent_any = true;
+                }
+            }
+        }
+    }
+    let status = child.wait().await;
+    let stderr = stderr.await.unwrap_or_default();
+    let succeeded = status.as_ref().is_ok_and(|s| s.success());
+    if !pending.is_empty() || (!sent_any && succeeded) {
+        send(memory_message(header, sent_any, &pending));
+    }
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            let detail = stderr.trim();
+            let msg = match status.code() {
+                Some(code) => format!("memory backend '{}' exited with status {}", program, code),
+                None => format!("memory backend '{}' was terminated", program),
+            };
+            let msg = if detail.is_empty() { msg } else { format!("{}: {}", msg, detail) };
+            warn!("{}", msg);
+            send(msg);
+        }
+        Err(e) => send(format!("memory backend '{}' failed: {}", program, e)),
+    }
+}
+
+fn memory_message(header: &str, continued: bool, output: &str) -> String {
+    if continued {
+        format!("{} (continued):\n{}", header, output)
+    } else {
+        format!("{}:\n{}", header, output)
+    }
+}
+
+/// Split off the first whitespace-delimited word, returning it and the trimmed remainder.
+fn split_first_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.find(char::is_whitespace) {
+        Some(idx) => (&s[..idx], s[idx..].trim()),
+        None => (s, ""),
+    }
+}
+
+const DEFAULT_HISTORY_ENTRIES: usize = 10;
+
+/// Body of the `/history <channel> <n>` reply, read from the SQLite store off the async runtime.
+#[cfg(feature = "sqlite")]
+async fn history_message(channel: String, n: usize) -> String {
+    let lookup = tokio::task::spawn_blocking(move || -> Result<String, String> {
+        let path = crate::history_store::history_db_path(std::env::var("ACOMM_HISTORY_DB").ok().as_deref())
+            .ok_or("no cache directory for the history store")?;
+        let store = crate::history_store::HistoryStore::open(&path).map_err(|e| e.to_string())?;
+        let entries = store.recent_entries(&channel, n).map_err(|e| e.to_string())?;
+        if entries.is_empty() {
+            return Ok(format!("No history for {}.", channel));
+        }
+        let lines: Vec<String> = entries.iter().map(|e| e.render()).collect();
+        Ok(format!("History of {} (last {}):\n{}", channel, entries.len(), lines.join("\n")))
+    })
+    .await;
+    match lookup {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => format!("History lookup failed: {}", e),
+        Err(e) => format!("History lookup failed: {}", e),
+    }
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn history_message(_channel: String, _n: usize) -> String {
+    "History is not available: this acomm build was compiled without the `sqlite` feature.".into()
+}
+
+async fn handle_command(
+    text: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) -> Result<(), Box<dyn Error>> {
+    let parts: Vec<&str> = text[1..].split_whitespace().collect();
+    let cmd = parts.get(0).unwrap_or(&"");
+    match *cmd {
+        "search" | "today" => {
+            let memory_cmd = state.lock().await.memory_cmd.clone();
+            let (header, args) = if *cmd == "search" {
+                ("Search results", vec!["search".to_string(), parts[1..].join(" ")])
+            } else {
+                ("Today", vec!["today".to_string()])
+            };
+            // Run in the background so a slow backend doesn't hold up this connection.
+            let reply_channel = channel.or_else(|| Some("bridge".into()));
+            let tx = Arc::clone(tx);
+            tokio::spawn(async move {
+                stream_memory_command(&memory_cmd, &args, header, MEMORY_PARTIAL_INTERVAL, reply_channel, &tx).await;
+            });
+        }
+        "history" => {
+            let reply_channel = channel.clone().or_else(|| Some("bridge".into()));
+            let target = parts.get(1).map(|c| c.to_string()).or(channel).unwrap_or_else(|| "bridge".into());
+            let n = parts.get(2).and_then(|n| n.parse::<usize>().ok()).unwrap_or(DEFAULT_HISTORY_ENTRIES);
+            let tx = Arc::clone(tx);
+            tokio::spawn(async move {
+                let msg = history_message(target, n).await;
+                let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: reply_channel }));
+            });
+        }
+        "ping" => {
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg: "pong".into(), channel: channel.or_else(|| Some("bridge".into())) }));
+        }
+        "provider" if parts.get(1) == Some(&"channel") => {
+            handle_channel_provider_command(parts.get(2).copied(), channel, tx, state).await;
+        }
+        // `/tool` is the older name of `/provider`, still sent by some clients.
+        "provider" | "tool" => {
+            if let Some(name) = parts.get(1) {
+                let Some(provider) = provider_from_command_name(name) else {
+                    if name.eq_ignore_ascii_case(OLLAMA_PROVIDER_NAME) {
+                        let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                            msg: format!(
+                                "Provider '{}' (default model {}) is not available: this acore build has no Ollama executor.",
+                                OLLAMA_PROVIDER_NAME, DEFAULT_OLLAMA_MODEL
+                            ),
+                            channel: channel.or_else(|| Some("bridge".into())),
+                        }));
+                    }
+                    return Ok(());
+                };
+                let default_model = default_model_for_provider(&provider).map(str::to_string);
+                let _ = tx.send(BusEvent::new(ProtocolEvent::ProviderSwitched { provider }));
+                if let Some(model) = default_model {
+                    let _ = tx.send(BusEvent::new(ProtocolEvent::ModelSwitched { model }));
+                }
+            }
+        }
+        "model" => {
+            if let Some(model_name) = parts.get(1) {
+                let _ = tx.send(BusEvent::new(ProtocolEvent::ModelSwitched { model: model_name.to_string() }));
+            }
+        }
+        "status" => {
+            let s = state.lock().await;
+            let mut msg = format!(
+                "Provider: {} / Model: {}",
+                s.active_provider.command_name(),
+                s.active_model.as_deref().unwrap_or("(default)")
+            );
+            let scope = session_scope(channel.as_deref());
+            if let Some((provider, model)) = s.channel_providers.get(&scope) {
+                msg.push_str(&format!("\n{}: {} / Model: {}", scope, provider.command_name(), model.as_deref().unwrap_or("(default)")));
+            }
+            if let Some(ref quota) = s.quota {
+                msg.push('\n');
+                msg.push_str(&quota.describe(Instant::now()));
+            }
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: channel.or_else(|| Some("bridge".into())) }));
+        }
+        "macro" => {
+            handle_macro_command(&text[1..], channel, tx, state).await;
+        }
+        "persona" => {
+            handle_persona_command(&text[1..], channel, tx, state).await;
+        }
+        "template" => {
+            handle_template_command(&text[1..], channel, tx, state).await;
+        }
+        "fallback" => {
+            handle_fallback_command(&text[1..], channel, tx, state).await;
+        }
+        "compare" => {
+            handle_compare_command(&text[1..], channel, tx, state).await;
+        }
+        "schedule" => {
+            handle_schedule_command(&text[1..], channel, tx, state).await;
+        }
+        "quota" => {
+            handle_quota_command(&text[1..], channel, tx, state).await;
+        }
+        // `/cancel` stops what is running in this conversation; prompts queued behind it still run.
+        "cancel" => {
+            let scope = session_scope(channel.as_deref());
+            let running = Arc::clone(&state.lock().await.running);
+            let msg = match cancel_running_prompts(&running, &scope) {
+                0 => format!("Nothing is running in {}.", scope),
+                n => format!("Cancelled {} running prompt(s) in {}.", n, scope),
+            };
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: channel.or_else(|| Some("bridge".into())) }));
+        }
+        // `/clear <channel-prefix>` drops only that channel's backlog; other channels are untouched.
+        "clear" if parts.get(1) == Some(&"--all") => {
+            let mut s = state.lock().await;
+            s.backlog.clear();
+            s.sessions.clear();
+            s.active_model = default_model_for_provider(&s.active_provider).map(str::to_string);
+            let cleared_model = s.active_model.clone();
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg: "Cleared every channel.".into(), channel: channel.or_else(|| Some("bridge".into())) }));
+            if let Some(model) = cleared_model {
+                let _ = tx.send(BusEvent::new(ProtocolEvent::ModelSwitched { model }));
+            }
+        }
+        "clear" if parts.len() > 1 => {
+            let prefix = parts[1];
+            let mut s = state.lock().await;
+            let removed = clear_channel_backlog(&mut s.backlog, prefix);
+            let sessions_before = s.sessions.len();
+            s.sessions.retain(|scope, _| !scope.starts_with(prefix));
+            let sessions = sessions_before - s.sessions.len();
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                msg: format!("Cleared {} backlog event(s) for {} and reset {} agent session(s).", removed, prefix, sessions),
+                channel: channel.or_else(|| Some("bridge".into())),
+            }));
+        }
+        "clear" => {
+            let scope = session_scope(channel.as_deref());
+            let mut s = state.lock().await;
+            let removed = clear_scope_backlog(&mut s.backlog, &scope);
+            let thread_prefix = format!("{}#", scope);
+            s.sessions.retain(|key, _| *key != scope && !key.starts_with(&thread_prefix));
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                msg: format!(
+                    "Cleared {} backlog event(s) and the agent session for {}. Other channels are untouched; /clear --all resets everything.",
+                    removed, scope
+                ),
+                channel: channel.or_else(|| Some("bridge".into())),
+            }));
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Abort every agent run whose channel belongs to `scope`, including its `/compare`
+/// runs, and return how many were aborted. Each one ends with a `ChannelClosed`.
+fn cancel_running_prompts(running: &SharedRunningPrompts, scope: &str) -> usize {
+    let running = running.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+    running
+        .values()
+        .filter(|(channel, _)| {
+            let channel = channel.as_deref().map(|ch| split_compare_channel(ch).map_or(ch, |(base, _)| base));
+            session_scope(channel) == scope
+        })
+        .inspect(|(_, task)| task.abort())
+        .count()
+}
+
+/// Remove the backlog events whose channel starts with `prefix` and return how many were removed.
+/// Channel-less events (provider/model switches) are kept.
+fn clear_channel_backlog(backlog: &mut VecDeque<SharedEvent>, prefix: &str) -> usize {
+    let before = backlog.len();
+    backlog.retain(|event| !event.clone_channel().is_some_and(|ch| ch.starts_with(prefix)));
+    before - backlog.len()
+}
+
+/// Remove the backlog events of one conversation (`session_scope`) and return how many were removed.
+fn clear_scope_backlog(backlog: &mut VecDeque<SharedEvent>, scope: &str) -> usize {
+    let before = backlog.len();
+    backlog.retain(|event| event.clone_channel().is_none_or(|ch| channel_scope(&ch) != scope));
+    before - backlog.len()
+}
+
+/// `/macro set <name> <text...>`, `/macro list`, `/macro <name>`.
+///
+/// `command` is the command text without the leading slash.
+async fn handle_macro_command(
+    command: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let reply_channel = channel.clone().or_else(|| Some("bridge".into()));
+    let (_, args) = split_first_word(command);
+    let (sub, rest) = split_first_word(args);
+    match sub {
+        "" => {
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                msg: "Usage: /macro set <name> <text> | /macro list | /macro <name>".into(),
+                channel: reply_channel,
+            }));
+        }
+        "set" => {
+            let (name, body) = split_first_word(rest);
+            if name.is_empty() || body.is_empty() {
+                let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                    msg: "Usage: /macro set <name> <text>".into(),
+                    channel: reply_channel,
+                }));
+                return;
+            }
+            let mut s = state.lock().await;
+            s.macros.insert(name.to_string(), body.to_string());
+            save_macros(s.state_dir.as_deref(), &s.macros);
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                msg: format!("Macro '{}' saved.", name),
+                channel: reply_channel,
+            }));
+        }
+        "list" => {
+            let s = state.lock().await;
+            let mut names: Vec<&str> = s.macros.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            let msg = if names.is_empty() {
+                "No macros defined. Use /macro set <name> <text>.".to_string()
+            } else {
+                format!("Macros: {}", names.join(", "))
+            };
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: reply_channel }));
+        }
+        name => {
+            let expansion = state.lock().await.macros.get(name).cloned();
+            match expansion {
+                Some(text) => {
+                    run_prompt(text, None, channel, None, false, tx, state).await;
+                }
+                None => {
+                    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+                        msg: format!(
+                            "Unknown macro '{}'. Use /macro list to see defined macros.",
+                            name
+                        ),
+                        channel: reply_channel,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+/// `/template save <name> <text>`, `/template list`, `/template delete <name>`.
+async fn handle_template_command(
+    command: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let reply_channel = channel.or_else(|| Some("bridge".into()));
+    let (_, args) = split_first_word(command);
+    let (sub, rest) = split_first_word(args);
+    let (name, body) = split_first_word(rest);
+    let mut s = state.lock().await;
+    let msg = match sub {
+        "save" if is_template_name(name) && !body.is_empty() => match s.templates.save(name, body) {
+            Ok(()) => format!("Template '{}' saved. Start a prompt with @{} to use it.", name, name),
+            Err(e) => format!("Template '{}' saved for this session, but writing templates.toml failed: {}", name, e),
+        },
+        "save" if !name.is_empty() && !is_template_name(name) => {
+            format!("Invalid template name '{}': use letters, digits, '-' and '_'.", name)
+        }
+        "list" => {
+            let names = s.templates.names();
+            if names.is_empty() {
+                "No templates defined. Use /template save <name> <text>.".to_string()
+            } else {
+                format!("Templates: {}", names.join(", "))
+            }
+        }
+        "delete" if !name.is_empty() => match s.templates.delete(name) {
+            Ok(true) => format!("Template '{}' deleted.", name),
+            Ok(false) => format!("Unknown template '{}'. Use /template list to see defined templates.", name),
+            Err(e) => format!("Template '{}' deleted for this session, but writing templates.toml failed: {}", name, e),
+        },
+        _ => "Usage: /template save <name> <text> | /template list | /template delete <name>".to_string(),
+    };
+    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: reply_channel }));
+}
+
+/// Parse the arguments of `/schedule add`: a trigger (quoted when it contains spaces, as
+/// cron expressions do), then optional `--deliver <target>` / `--catch-up`, then the prompt.
+fn parse_schedule_add(args: &str) -> Result<(&str, Option<Delivery>, bool, &str), String> {
+    let args = args.trim_start();
+    let (spec, mut rest) = match args.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').ok_or("Missing closing quote after the schedule time")?,
+        None => split_first_word(args),
+    };
+    let (mut deliver, mut catch_up) = (None, false);
+    loop {
+        let (word, after) = split_first_word(rest);
+        match word {
+            "--catch-up" => catch_up = true,
+            "--deliver" => {
+                let (target, after) = split_first_word(after);
+                deliver = Some(Delivery::parse(target).ok_or_else(|| {
+                    format!("Unknown delivery target '{}': use discord, slack or ntfy", target)
+                })?);
+                rest = after;
+                continue;
+            }
+            _ => break,
+        }
+        rest = after;
+    }
+    let text = rest.trim();
+    if spec.trim().is_empty() || text.is_empty() {
+        return Err(SCHEDULE_USAGE.to_string());
+    }
+    // Slash commands reply with system messages rather than an agent reply to forward.
+    if deliver.is_some() && text.starts_with('/') {
+        return Err("--deliver forwards an agent reply, so it needs a prompt rather than a slash command".to_string());
+    }
+    Ok((spec, deliver, catch_up, text))
+}
+
+const SCHEDULE_USAGE: &str =
+    "Usage: /schedule add \"<cron>\"|in:+30m [--deliver discord|slack|ntfy] [--catch-up] <prompt> | /schedule list | /schedule rm <id>";
+
+/// `/schedule add ...`, `/schedule list`, `/schedule rm <id>`.
+async fn handle_schedule_command(
+    command: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let reply_channel = channel.or_else(|| Some("bridge".into()));
+    let (_, args) = split_first_word(command);
+    let (sub, rest) = split_first_word(args);
+    let mut s = state.lock().await;
+    let msg = match sub {
+        "add" => match parse_schedule_add(rest) {
+            Ok((spec, deliver, catch_up, text)) => {
+                match s.schedules.add(spec, text, deliver, catch_up, chrono::Local::now().timestamp()) {
+                    Ok(schedule) => format!(
+                        "Scheduled {} (runs on {}).",
+                        schedule.describe(),
+                        schedule_channel(schedule.id)
+                    ),
+                    Err(e) => format!("Invalid schedule time '{}': {}", spec, e),
+                }
+            }
+            Err(e) => e,
+        },
+        "list" => {
+            let lines: Vec<String> = s.schedules.list().iter().map(Schedule::describe).collect();
+            if lines.is_empty() {
+                "No schedules. Use /schedule add.".to_string()
+            } else {
+                format!("Schedules:\n{}", lines.join("\n"))
+            }
+        }
+        "rm" | "remove" | "delete" => match rest.trim_start_matches('#').parse::<u64>() {
+            Ok(id) if s.schedules.remove(id) => format!("Schedule #{} removed.", id),
+            Ok(id) => format!("No schedule #{}. Use /schedule list to see them.", id),
+            Err(_) => SCHEDULE_USAGE.to_string(),
+        },
+        _ => SCHEDULE_USAGE.to_string(),
+    };
+    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: reply_channel }));
+}
+
+/// How often the scheduler checks for due schedules.
+const SCHEDULER_TICK: Duration = Duration::from_secs(5);
+
+/// How long a scheduled run with a delivery target may take before its reply is given up on.
+const SCHEDULE_REPLY_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Fire due schedules for as long as the bridge runs. The first check happens right
+/// away, which is when runs missed while the bridge was down are skipped or caught up.
+async fn run_scheduler(tx: Arc<broadcast::Sender<SharedEvent>>, state: Arc<Mutex<BridgeState>>) {
+    let mut tick = tokio::time::interval(SCHEDULER_TICK);
+    loop {
+        tick.tick().await;
+        let due = state.lock().await.schedules.take_due(chrono::Local::now().timestamp());
+        for schedule in due {
+            tokio::spawn(fire_schedule(schedule, Arc::clone(&tx), Arc::clone(&state)));
+        }
+    }
+}
+
+/// Run one schedule on its `schedule:<id>` channel and forward the reply if it has a
+/// delivery target.
+async fn fire_schedule(schedule: Schedule, tx: Arc<broadcast::Sender<SharedEvent>>, state: Arc<Mutex<BridgeState>>) {
+    let channel = schedule_channel(schedule.id);
+    info!(channel = %channel, text = %truncate_for_log(&schedule.text, LOG_PROMPT_CHARS), "schedule fired");
+    // Subscribe before the prompt starts so no part of the reply is missed.
+    let replies = schedule.deliver.map(|_| tx.subscribe());
+    if schedule.text.starts_with('/') {
+        if let Err(e) = handle_command(&schedule.text, Some(channel.clone()), &tx, &state).await {
+            warn!("Schedule #{} command failed: {}", schedule.id, e);
+        }
+    } else {
+        run_prompt(schedule.text.clone(), None, Some(channel.clone()), None, false, &tx, &state).await;
+    }
+    let (Some(deliver), Some(mut replies)) = (schedule.deliver, replies) else { return };
+    let Ok(reply) = tokio::time::timeout(SCHEDULE_REPLY_TIMEOUT, collect_reply(&mut replies, &channel)).await else {
+        warn!("Schedule #{} reply did not finish; nothing delivered to {}", schedule.id, deliver.name());
+        return;
+    };
+    let text = format!("[schedule #{}] {}", schedule.id, reply.trim());
+    let sent = match deliver {
+        Delivery::Discord => crate::discord::notify_discord(&text).await,
+        Delivery::Slack => crate::slack::notify_slack(&text).await,
+        Delivery::Ntfy => crate::ntfy::notify_ntfy(&text).await,
+    }
+    .map_err(|e| e.to_string());
+    if let Err(e) = sent {
+        warn!("Delivering schedule #{} to {} failed: {}", schedule.id, deliver.name(), e);
+    }
+}
+
+/// The agent output on `channel` until its `AgentDone` (or `ChannelClosed`), with any
+/// failure message appended.
+async fn collect_reply(rx: &mut broadcast::Receiver<SharedEvent>, channel: &str) -> String {
+    let mut reply = String::new();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(_) => break,
+        };
+        if event.clone_channel().as_deref() != Some(channel) {
+            continue;
+        }
+        match &event.event {
+            ProtocolEvent::AgentChunk { chunk, .. } => reply.push_str(chunk),
+            ProtocolEvent::SystemMessage { msg, .. } if msg.starts_with(AGENT_FAILURE_PREFIX) => {
+                reply.push('\n');
+                reply.push_str(msg);
+            }
+            ProtocolEvent::AgentDone { .. } | ProtocolEvent::ChannelClosed { .. } => break,
+            _ => {}
+        }
+    }
+    reply
+}
+
+/// `/compare <prompt>`: run the prompt on every compare provider at once. Each answer
+/// streams on its own `<channel>#<provider>` channel (see `compare_channel`) in a fresh
+/// session, so the conversation's sessions are neither resumed nor changed; a
+/// `SystemMessage` with each provider's duration follows once all of them are done.
+async fn handle_compare_command(
+    command: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let base = channel.unwrap_or_else(|| "bridge".into());
+    let (_, prompt) = split_first_word(command);
+    let jobs: Vec<PromptJob> = {
+        let s = state.lock().await;
+        let text = match s.templates.expand(prompt) {
+            Ok(expanded) => expanded.unwrap_or_else(|| prompt.to_string()),
+            Err(msg) => {
+                let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: Some(base) }));
+                return;
+            }
+        };
+        if text.trim().is_empty() || s.compare_providers.is_empty() {
+            let msg = if text.trim().is_empty() {
+                "Usage: /compare <prompt>".to_string()
+            } else {
+                "No compare providers. Set ACOMM_COMPARE_PROVIDERS (e.g. gemini,claude).".to_string()
+            };
+            let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: Some(base) }));
+            return;
+        }
+        let agent_text = apply_persona(s.personas.for_channel(Some(base.as_str())), &text);
+        s.compare_providers
+            .iter()
+            .map(|provider| PromptJob {
+                text: text.clone(),
+                channel: Some(compare_channel(&base, provider)),
+                reply_to: None,
+                priority: false,
+                provider: provider.clone(),
+                model: if *provider == s.active_provider {
+                    s.active_model.clone()
+                } else {
+                    default_model_for_provider(provider).map(str::to_string)
+                },
+                agent_text: agent_text.clone(),
+                manager: SessionManager::new(),
+                chunk_limits: s.chunk_limits,
+                timeout: s.agent_timeout,
+                // Comparing providers is the point, so none of them falls back to another.
+                fallback: FallbackPolicy { providers: Vec::new(), ..s.fallback.clone() },
+                slots: s.agent_slots.clone(),
+                running: Arc::clone(&s.running),
+                // Side-by-side answers are not part of the conversation history.
+                memory_log: None,
+            })
+            .collect()
+    };
+    let runs: Vec<_> = jobs
+        .into_iter()
+        .map(|job| {
+            let provider = job.provider.clone();
+            announce_prompt(&job, tx);
+            let started = Instant::now();
+            let handle = spawn_prompt_job(job, tx);
+            async move {
+                let _ = handle.await;
+                format!("{} {:.1}s", provider.command_name(), started.elapsed().as_secs_f64())
+            }
+        })
+        .collect();
+    let tx = Arc::clone(tx);
+    tokio::spawn(async move {
+        let durations = futures_util::future::join_all(runs).await;
+        let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage {
+            msg: format!("Compare finished: {}", durations.join(", ")),
+            channel: Some(base),
+        }));
+    });
+}
+
+/// `/fallback set <provider>...`, `/fallback clear`, `/fallback show`. The chain only
+/// lasts until the bridge restarts; `ACOMM_FALLBACK_PROVIDERS` sets it at startup.
+async fn handle_fallback_command(
+    command: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let reply_channel = channel.or_else(|| Some("bridge".into()));
+    let (_, args) = split_first_word(command);
+    let (sub, rest) = split_first_word(args);
+    let mut s = state.lock().await;
+    let msg = match sub {
+        "set" if !rest.is_empty() => match parse_provider_list(rest) {
+            Ok(providers) => {
+                s.fallback.providers = providers;
+                s.fallback.describe()
+            }
+            Err(e) => format!("{}. Available: {}", e, PROVIDER_NAMES.join(", ")),
+        },
+        "clear" => {
+            s.fallback.providers.clear();
+            "Fallback disabled.".to_string()
+        }
+        "show" | "" => s.fallback.describe(),
+        _ => "Usage: /fallback set <provider>... | /fallback clear | /fallback show".to_string(),
+    };
+    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: reply_channel }));
+}
+
+/// `/quota` shows the per-user counters; `/quota reset <user>` refills one user's quota.
+/// Users are named as in `/status` (`slack:<user>`, `discord:user:<author>`, other
+/// adapters by channel scope such as `matrix:<room>`).
+async fn handle_quota_command(
+    command: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let reply_channel = channel.or_else(|| Some("bridge".into()));
+    let (_, args) = split_first_word(command);
+    let (sub, rest) = split_first_word(args);
+    let mut s = state.lock().await;
+    let msg = match (s.quota.as_mut(), sub) {
+        (None, _) => "No quota configured (set ACOMM_QUOTA_PER_HOUR).".to_string(),
+        (Some(quota), "reset") if !rest.is_empty() => {
+            if quota.reset(rest) {
+                format!("Quota reset for {}.", rest)
+            } else {
+                format!("{} has not used any quota.", rest)
+            }
+        }
+        (Some(quota), "show" | "") => quota.describe(Instant::now()),
+        _ => "Usage: /quota [show] | /quota reset <user> (e.g. slack:U123, discord:user:456)".to_string(),
+    };
+    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: reply_channel }));
+}
+
+/// `/persona set <text>`, `/persona clear`, `/persona default <text>`, `/persona show`.
+///
+/// `set`/`clear` apply to the requesting channel's scope; `default` sets (or, with no
+/// text, clears) the persona used by channels without their own.
+/// `/provider channel <name>` runs the requesting channel's prompts on `name` (with its
+/// default model) without switching the other channels; `/provider channel clear` undoes it.
+async fn handle_channel_provider_command(
+    name: Option<&str>,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let scope = session_scope(channel.as_deref());
+    let mut s = state.lock().await;
+    let msg = match name {
+        Some("clear") => match s.channel_providers.remove(&scope) {
+            Some(_) => format!("Provider cleared for {}; using {}.", scope, s.active_provider.command_name()),
+            None => format!("{} has no provider of its own.", scope),
+        },
+        Some(name) => match provider_from_command_name(name) {
+            Some(provider) => {
+                let model = default_model_for_provider(&provider).map(str::to_string);
+                let msg = match &model {
+                    Some(model) => format!("Switched {} to {}:{}.", scope, provider.command_name(), model),
+                    None => format!("Switched {} to {}.", scope, provider.command_name()),
+                };
+                s.channel_providers.insert(scope, (provider, model));
+                msg
+            }
+            None => format!("Unknown provider '{}'.", name),
+        },
+        None => "Usage: /provider channel <name> | /provider channel clear".to_string(),
+    };
+    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: channel.or_else(|| Some("bridge".into())) }));
+}
+
+async fn handle_persona_command(
+    command: &str,
+    channel: Option<String>,
+    tx: &Arc<broadcast::Sender<SharedEvent>>,
+    state: &Mutex<BridgeState>,
+) {
+    let reply_channel = channel.clone().or_else(|| Some("bridge".into()));
+    let scope = channel_scope(channel.as_deref().unwrap_or("bridge"));
+    let (_, args) = split_first_word(command);
+    let (sub, rest) = split_first_word(args);
+    let mut s = state.lock().await;
+    let msg = match sub {
+        "set" if !rest.is_empty() => {
+            s.personas.channels.insert(scope.clone(), rest.to_string());
+            format!("Persona set for {}.", scope)
+        }
+        "clear" => {
+            s.personas.channels.remove(&scope);
+            format!("Persona cleared for {}; using the default.", scope)
+        }
+        "default" => {
+            s.personas.default = (!rest.is_empty()).then(|| rest.to_string());
+            if rest.is_empty() {
+                "Default persona cleared.".to_string()
+            } else {
+                "Default persona set.".to_string()
+            }
+        }
+        "show" => match s.personas.for_channel(channel.as_deref()) {
+            Some(persona) => format!("Persona for {}:\n{}", scope, persona),
+            None => format!("No persona for {}.", scope),
+        },
+        _ => "Usage: /persona set <text> | /persona clear | /persona default [text] | /persona show".to_string(),
+    };
+    if matches!(sub, "set" | "clear" | "default") {
+        save_personas(s.state_dir.as_deref(), &s.personas);
+    }
+    let _ = tx.send(BusEvent::new(ProtocolEvent::SystemMessage { msg, channel: reply_channel }));
+}
+
+fn load_personas(state_dir: Option<&Path>) -> PersonaStore {
+    state_dir
+        .and_then(|dir| std::fs::read_to_string(dir.join(PERSISTED_PERSONAS_FILE)).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_personas(state_dir: Option<&Path>, personas: &PersonaStore) {
+    let Some(dir) = state_dir else { return };
+    let _ = std::fs::create_dir_all(dir);
+    if let Ok(content) = serde_json::to_string_pretty(personas) {
+        let _ = std::fs::write(dir.join(PERSISTED_PERSONAS_FILE), content);
+    }
+}
+
+fn load_macros(state_dir: Option<&Path>) -> HashMap<String, String> {
+    state_dir
+        .and_then(|dir| std::fs::read_to_string(dir.join(PERSISTED_MACROS_FILE)).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_macros(state_dir: Option<&Path>, macros: &HashMap<String, String>) {
+    let Some(dir) = state_dir else { return };
+    let _ = std::fs::create_dir_all(dir);
+    if let Ok(content) = serde_json::to_string_pretty(macros) {
+        let _ = std::fs::write(dir.join(PERSISTED_MACROS_FILE), content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ProtocolEvent;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::io::{DuplexStream, Lines, ReadHalf, WriteHalf};
+    use std::time::Duration;
+
+    // 実ソケット (/tmp/acomm.sock) を使わず、tokio::io::duplex でクライアント接続を模擬する。
+    // BridgeState と broadcast チャンネルはテストごとに独立しているので並列実行できる。
+    struct TestBridge {
+        tx: Arc<broadcast::Sender<SharedEvent>>,
+        state: Arc<Mutex<BridgeState>>,
+    }
+
+    struct TestClient {
+        lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+        writer: WriteHalf<DuplexStream>,
+    }
+
+    impl TestBridge {
+        fn new() -> Self {
+            let (tx, _) = broadcast::channel(DEFAULT_BROADCAST_CAP);
+            let tx = Arc::new(tx);
+            let (provider, model, _) = resolve_default_provider(None, None);
+            let state = Arc::new(Mutex::new(BridgeState {
+                active_provider: provider,
+                active_model: model,
+                ..command_test_state().into_inner()
+            }));
+            tokio::spawn(manage_state(tx.subscribe(), Arc::clone(&state), None, EventSinks::default()));
+            TestBridge { tx, state }
+        }
+
+        fn connect(&self) -> TestClient {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let (tx, state) = (Arc::clone(&self.tx), Arc::clone(&self.state));
+            tokio::spawn(async move {
+                let _ = handle_bridge_connection(server, tx, state, None).await;
+            });
+            let (reader, writer) = tokio::io::split(client);
+            TestClient { lines: BufReader::new(reader).lines(), writer }
+        }
+    }
+
+    impl TestClient {
+        async fn send(&mut self, event: &ProtocolEvent) {
+            let line = format!("{}\n", serde_json::to_string(event).unwrap());
+            self.writer.write_all(line.as_bytes()).await.unwrap();
+        }
+
+        /// 次のイベント。接続が閉じたら None、5 秒届かなければ panic。
+        async fn next(&mut self) -> Option<ProtocolEvent> {
+            let line = tokio::time::timeout(Duration::from_secs(5), self.lines.next_line())
+                .await
+                .expect("bridge went quiet")
+                .ok()
+                .flatten()?;
+            Some(serde_json::from_str(&line).unwrap())
+        }
+
+        /// BridgeSyncDone までの初期同期（マーカー自体は含まない）。
+        async fn initial_sync(&mut self) -> Vec<ProtocolEvent> {
+            let mut events = Vec::new();
+            loop {
+                match self.next().await.expect("closed during initial sync") {
+                    ProtocolEvent::BridgeSyncDone {} => return events,
+                    event => events.push(event),
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bridge_mock_flow() {
+        let bridge = TestBridge::new();
+        let mut client = bridge.connect();
+        client.initial_sync().await;
+
+        let prompt = ProtocolEvent::Prompt {
+            text: "hello mock".into(),
+            provider: Some(AgentProvider::Mock),
+            channel: Some("test_channel".into()),
+            reply_to: None,
+            priority: false,
+            author: None,
+        };
+        client.send(&prompt).await;
+
+        let mut received = Vec::new();
+        while let Some(ev) = client.next().await {
+            let done = matches!(&ev, ProtocolEvent::AgentDone { channel: Some(c), .. } if c == "test_channel");
+            received.push(ev);
+            if done {
+                break;
+            }
+        }
+
+        assert!(received.iter().any(|e| matches!(e, ProtocolEvent::StatusUpdate { channel: Some(c), .. } if c == "test_channel")));
+        assert!(received.iter().any(|e| matches!(e, ProtocolEvent::AgentChunk { channel: Some(c), .. } if c == "test_channel")));
+    }
+
+    #[tokio::test]
+    async fn test_bridge_initial_sync_emits_completion_marker() {
+        let bridge = TestBridge::new();
+        let mut client = bridge.connect();
+        let events = client.initial_sync().await;
+        assert!(matches!(events.first(), Some(ProtocolEvent::Hello { version }) if *version == PROTOCOL_VERSION));
+    }
+
+    #[tokio::test]
+    async fn test_bridge_acknowledges_published_prompt() {
+        let bridge = TestBridge::new();
+        let mut client = bridge.connect();
+        client.initial_sync().await;
+        for text in ["/status", "/status"] {
+            let prompt = ProtocolEvent::Prompt { text: text.into(), provider: None, channel: Some("ack_channel".into()), reply_to: None, priority: false, author: None };
+            client.send(&prompt).await;
+        }
+
+        let mut acks = Vec::new();
+        while acks.len() < 2 {
+            if let Some(ProtocolEvent::PromptAck { channel, seq }) = client.next().await {
+                assert_eq!(channel.as_deref(), Some("ack_channel"));
+                acks.push(seq);
+            }
+        }
+        assert_eq!(acks, vec![1, 2], "each prompt on the connection should be acknowledged in order");
+    }
+
+    #[tokio::test]
+    async fn test_bridge_does_not_charge_quota_for_duplicate_prompts() {
+        let bridge = TestBridge::new();
+        bridge.state.lock().await.quota = crate::rate_limit::parse_quota_config(Some("2"), None, None).0;
+        let mut client = bridge.connect();
+        client.initial_sync().await;
+        // Enter の二度押しや再配信で同じプロンプトが続けて届いても、消費するのは 1 回分だけ
+        let prompt = ProtocolEvent::Prompt {
+            text: "hello twice".into(),
+            provider: Some(AgentProvider::Mock),
+            channel: Some("discord:42:1001".into()),
+            reply_to: None,
+            priority: false,
+            author: Some("u1".into()),
+        };
+        client.send(&prompt).await;
+        client.send(&prompt).await;
+
+        let mut acks = 0;
+        let mut duplicate_notice = false;
+        while acks < 2 || !duplicate_notice {
+            match client.next().await.expect("bridge closed the connection") {
+                ProtocolEvent::PromptAck { .. } => acks += 1,
+                ProtocolEvent::SystemMessage { msg, .. } if msg.starts_with("Ignored a duplicate prompt") => duplicate_notice = true,
+                _ => {}
+            }
+        }
+        let usage = bridge.state.lock().await.quota.as_ref().unwrap().describe(Instant::now());
+        assert_eq!(usage, "Quota: 2/h, burst 2 — discord:user:u1 1/2 left");
+    }
+
+    #[tokio::test]
+    async fn test_bridge_rejects_client_with_unsupported_protocol_version() {
+        let bridge = TestBridge::new();
+        let mut client = bridge.connect();
+        client.send(&ProtocolEvent::Hello { version: PROTOCOL_VERSION + 1 }).await;
+
+        let mut rejection = None;
+        while let Some(ev) = client.next().await {
+            if let ProtocolEvent::SystemMessage { msg, .. } = ev {
+                if msg.contains("Incompatible acomm protocol") {
+                    rejection = Some(msg);
+                }
+            }
+        }
+
+        // ループを抜けた = ブリッジが接続を閉じた。
+        let msg = rejection.expect("bridge should explain the version mismatch");
+        assert!(msg.contains(&(PROTOCOL_VERSION + 1).to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_bridge_initial_sync_emits_gemini_default_provider_and_model() {
+        let bridge = TestBridge::new();
+        let mut client = bridge.connect();
+        let events = client.initial_sync().await;
+
+        assert!(
+            events.iter().any(|e| matches!(e, ProtocolEvent::ProviderSwitched { provider } if *provider == AgentProvider::Gemini)),
+            "initial sync should include Gemini default provider"
+        );
+        assert!(
+            events.iter().any(|e| matches!(e, ProtocolEvent::ModelSwitched { model } if model == "auto-gemini-3")),
+            "initial sync should include auto-gemini-3 default model"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bridge_replays_backlog_in_order_before_sync_marker() {
+        let bridge = TestBridge::new();
+        {
+            let mut s = bridge.state.lock().await;
+            for text in ["first", "second", "third"] {
+                s.backlog.push_back(BusEvent::new(ProtocolEvent::Prompt { text: text.into(), provider: None, channel: Some("tui".into()), reply_to: None, priority: false, author: None }));
+            }
+        }
+        let mut client = bridge.connect();
+        let replayed: Vec<String> = client
+            .initial_sync()
+            .await
+            .into_iter()
+            .filter_map(|e| match e {
+                ProtocolEvent::Prompt { text, .. } => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(replayed, ["first", "second", "third"]);
+    }
+
+    #[tokio::test]
+    async fn test_bridge_backlog_carries_earlier_traffic_to_late_clients() {
+        let bridge = TestBridge::new();
+        let mut early = bridge.connect();
+        early.initial_sync().await;
+        early.send(&ProtocolEvent::SystemMessage { msg: "before you joined".into(), channel: Some("tui".into()) }).await;
+        while !matches!(early.next().await, Some(ProtocolEvent::SystemMessage { .. })) {}
+        // manage_state がバックログに積むのを待つ。
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while bridge.state.lock().await.backlog.is_empty() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("backlog never recorded the message");
+
+        let mut late = bridge.connect();
+        let events = late.initial_sync().await;
+        assert!(events.iter().any(|e| matches!(e, ProtocolEvent::SystemMessage { msg, .. } if msg == "before you joined")));
+    }
+
+    #[tokio::test]
+    async fn test_bridge_broadcasts_to_every_client_in_the_same_order() {
+        let bridge = TestBridge::new();
+        let mut a = bridge.connect();
+        let mut b = bridge.connect();
+        a.initial_sync().await;
+        b.initial_sync().await;
+
+        for i in 0..10 {
+            a.send(&ProtocolEvent::SystemMessage { msg: format!("m{i}"), channel: None }).await;
+        }
+        let expected: Vec<String> = (0..10).map(|i| format!("m{i}")).collect();
+        for client in [&mut a, &mut b] {
+            let mut seen = Vec::new();
+            while seen.len() < expected.len() {
+                if let Some(ProtocolEvent::SystemMessage { msg, .. }) = client.next().await {
+                    seen.push(msg);
+                }
+            }
+            assert_eq!(seen, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_command_provider_dummy_switches_provider() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = Mutex::new(BridgeState {
+            active_provider: AgentProvider::Gemini,
+            active_model: None,
+            ..command_test_state().into_inner()
+        });
+
+        handle_command("/provider dummy", None, &tx, &state).await.unwrap();
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(ev, ProtocolEvent::ProviderSwitched { provider: AgentProvider::Dummy }));
+    }
+
+    #[tokio::test]
+    async fn test_offline_dummy_provider_streams_chunks_then_done() {
+        let (tx, mut rx) = broadcast::channel(64);
+        let tx = Arc::new(tx);
+        // ACOMM_OFFLINE=1 で起動したブリッジと同じ既定プロバイダー。
+        let state = Mutex::new(BridgeState {
+            active_provider: AgentProvider::Dummy,
+            active_model: Some("echo".into()),
+            ..command_test_state().into_inner()
+        });
+
+        run_prompt("hello offline".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+
+        let mut chunks = Vec::new();
+        loop {
+            let ev = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await.unwrap().unwrap().event.clone();
+            match ev {
+                ProtocolEvent::AgentChunk { chunk, channel } => {
+                    assert_eq!(channel.as_deref(), Some("tui"));
+                    chunks.push(chunk);
+                }
+                ProtocolEvent::AgentDone { channel, .. } => {
+                    assert_eq!(channel.as_deref(), Some("tui"));
+                    break;
+                }
+                ProtocolEvent::SystemMessage { msg, .. } => panic!("offline reply failed: {msg}"),
+                _ => {}
+            }
+        }
+        assert!(chunks.len() > 1, "offline reply should stream in several chunks");
+        assert!(chunks.concat().contains("hello offline"));
+    }
+
+    #[tokio::test]
+    async fn test_priority_prompt_runs_before_earlier_queued_prompt() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.active_provider = AgentProvider::Dummy;
+
+        // 1 件目の実行中に通常・緊急の順で積むと、緊急が先に実行される。
+        run_prompt("first".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        run_prompt("second".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        run_prompt("urgent".into(), None, Some("tui".into()), None, true, &tx, &state).await;
+
+        let mut started = Vec::new();
+        let mut queued = Vec::new();
+        let mut done = 0;
+        while done < 3 {
+            match tokio::time::timeout(Duration::from_secs(10), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::Prompt { text, priority, .. } => started.push((text, priority)),
+                ProtocolEvent::SystemMessage { msg, .. } => queued.push(msg),
+                ProtocolEvent::AgentDone { .. } => done += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(
+            started,
+            vec![("first".to_string(), false), ("urgent".to_string(), true), ("second".to_string(), false)]
+        );
+        assert_eq!(queued, vec!["Queued behind the running prompt (position 1)"; 2]);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_prompts_are_dropped_while_running_queued_or_just_done() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.active_provider = AgentProvider::Dummy;
+
+        // 実行中・待機中と同じプロンプトは捨てられ、別のチャンネルなら通る。
+        run_prompt("first".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        run_prompt("second".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        run_prompt("first".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        run_prompt("second".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        run_prompt("first".into(), None, Some("slack:U1:C1".into()), None, false, &tx, &state).await;
+
+        let mut started = Vec::new();
+        let mut notices = Vec::new();
+        let mut done = 0;
+        while done < 3 {
+            match tokio::time::timeout(Duration::from_secs(10), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::Prompt { text, channel, .. } => started.push((text, channel.unwrap())),
+                ProtocolEvent::SystemMessage { msg, .. } => notices.push(msg),
+                ProtocolEvent::AgentDone { .. } => done += 1,
+                _ => {}
+            }
+        }
+        assert_eq!(started.len(), 3, "{started:?}");
+        assert_eq!(
+            notices,
+            vec![
+                "Queued behind the running prompt (position 1)".to_string(),
+                "Ignored a duplicate prompt: the same prompt is already queued or running.".to_string(),
+                "Ignored a duplicate prompt: the same prompt is already queued or running.".to_string(),
+            ]
+        );
+
+        // 終わった直後の同じプロンプトも捨てられるが、窓を 0 にすれば再実行できる。
+        // AgentDone の直後は、まだ実行中として数えられることもある。
+        let mut rx = tx.subscribe();
+        run_prompt("second".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        assert!(next_system_message(&mut rx).await.starts_with("Ignored a duplicate prompt"));
+        *state.lock().await.dedup.lock().unwrap() = PromptDedup::new(Duration::ZERO);
+        run_prompt("second".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        assert!(matches!(recv(&mut rx).await, ProtocolEvent::Prompt { text, .. } if text == "second"));
+    }
+
+    #[test]
+    fn test_resolve_dedup_window() {
+        assert_eq!(resolve_dedup_window(None), (DEFAULT_DEDUP_WINDOW, None));
+        assert_eq!(resolve_dedup_window(Some(" 30 ")), (Duration::from_secs(30), None));
+        assert_eq!(resolve_dedup_window(Some("0")), (Duration::ZERO, None));
+        let (window, warning) = resolve_dedup_window(Some("soon"));
+        assert_eq!(window, DEFAULT_DEDUP_WINDOW);
+        assert!(warning.unwrap().contains("ACOMM_DEDUP_WINDOW_SECS"));
+    }
+
+    #[tokio::test]
+    async fn test_finished_conversation_is_logged_without_holding_up_the_reply() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        let path = std::env::temp_dir().join(format!("acomm-bridge-memory-log-{}.json", std::process::id()));
+        {
+            let mut s = state.lock().await;
+            s.active_provider = AgentProvider::Dummy;
+            s.memory_log = true;
+            s.memory_cmd = vec!["sh".into(), "-c".into(), r#"cat > "$0""#.into(), path.to_str().unwrap().into()];
+        }
+
+        // 返答を全部ためてから、プロンプトと一緒にメモリへ書き込む。
+        run_prompt("hello".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        let mut reply = String::new();
+        loop {
+            match tokio::time::timeout(Duration::from_secs(10), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::AgentChunk { chunk, .. } => reply.push_str(&chunk),
+                ProtocolEvent::AgentDone { .. } => break,
+                _ => {}
+            }
+        }
+        let mut logged = None;
+        for _ in 0..100 {
+            let raw = std::fs::read_to_string(&path).unwrap_or_default();
+            if raw.ends_with('\n') {
+                logged = Some(serde_json::from_str::<serde_json::Value>(&raw).unwrap());
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        let _ = std::fs::remove_file(&path);
+        let logged = logged.expect("conversation was not logged");
+        assert_eq!(logged["channel"], "tui");
+        assert_eq!(logged["provider"], "dummy");
+        assert_eq!(logged["prompt"], "hello");
+        assert_eq!(logged["reply"], reply.as_str());
+        assert!(!reply.is_empty());
+
+        // メモリ側が失敗しても、返答はいつも通り最後まで届く。
+        state.lock().await.memory_cmd = vec!["acomm-no-such-memory-backend".into()];
+        run_prompt("again".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        loop {
+            match tokio::time::timeout(Duration::from_secs(10), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::SystemMessage { msg, .. } => assert!(!msg.contains("memory"), "{msg}"),
+                ProtocolEvent::AgentDone { .. } => break,
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_agent_chunks_are_capped_at_char_boundaries() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = Mutex::new(BridgeState {
+            active_provider: AgentProvider::Dummy,
+            active_model: Some("echo".into()),
+            // 3 バイト文字が境界をまたぐように、わざと 3 の倍数でない上限にする。
+            chunk_limits: ChunkLimits { max_bytes: 16, coalesce_bytes: 16, ..ChunkLimits::default() },
+            ..command_test_state().into_inner()
+        });
+
+        run_prompt("日本語のプロンプト".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+
+        let mut chunks = Vec::new();
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::AgentChunk { chunk, .. } => chunks.push(chunk),
+                ProtocolEvent::AgentDone { .. } => break,
+                _ => {}
+            }
+        }
+        assert!(chunks.iter().all(|c| !c.is_empty() && c.len() <= 16), "{chunks:?}");
+        assert!(chunks.concat().contains("日本語のプロンプト"));
+    }
+
+    #[tokio::test]
+    async fn test_stalled_agent_times_out_with_terminal_events() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        {
+            let mut s = state.lock().await;
+            // Dummy はチャンクごとに OFFLINE_CHUNK_DELAY 眠るので、それより短いタイムアウトで必ず止まる。
+            s.active_provider = AgentProvider::Dummy;
+            s.active_model = Some("echo".into());
+            s.agent_timeout = Some(OFFLINE_CHUNK_DELAY / 4);
+        }
+
+        run_prompt("止まるプロンプト".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+
+        let mut timed_out = false;
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::SystemMessage { msg, channel } if msg.starts_with(AGENT_FAILURE_PREFIX) => {
+                    assert!(msg.contains("timed out"), "{msg}");
+                    assert_eq!(channel.as_deref(), Some("tui"));
+                    timed_out = true;
+                }
+                ProtocolEvent::AgentDone { channel, .. } => {
+                    assert_eq!(channel.as_deref(), Some("tui"));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert!(timed_out, "the timeout is reported before AgentDone");
+        assert!(matches!(
+            tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap(),
+            ProtocolEvent::StatusUpdate { is_processing: false, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_partially_answered_prompt_is_not_retried_on_fallback() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        {
+            let mut s = state.lock().await;
+            // 最初のチャンクを出した後にタイムアウトするので、フォールバック対象のエラーでも再実行しない。
+            s.active_provider = AgentProvider::Dummy;
+            s.active_model = Some("echo".into());
+            s.agent_timeout = Some(OFFLINE_CHUNK_DELAY / 4);
+            s.fallback = FallbackPolicy { providers: vec![AgentProvider::Mock], patterns: vec!["timed out".into()] };
+        }
+
+        run_prompt("途中まで答えるプロンプト".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::SystemMessage { msg, .. } => assert!(!msg.contains("retrying"), "{msg}"),
+                ProtocolEvent::AgentDone { provider, model, .. } => {
+                    assert_eq!(provider, Some(AgentProvider::Dummy));
+                    assert_eq!(model.as_deref(), Some("echo"));
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compare_streams_each_provider_on_its_own_channel() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.compare_providers = vec![AgentProvider::Dummy];
+
+        handle_command("/compare 比べてほしい質問", Some("tui".into()), &tx, &state).await.unwrap();
+
+        let mut chunks = 0;
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::Prompt { text, provider, channel, .. } => {
+                    assert_eq!(text, "比べてほしい質問");
+                    assert_eq!(provider, Some(AgentProvider::Dummy));
+                    assert_eq!(channel.as_deref(), Some("tui#dummy"));
+                }
+                ProtocolEvent::AgentChunk { channel, .. } => {
+                    assert_eq!(channel.as_deref(), Some("tui#dummy"));
+                    chunks += 1;
+                }
+                ProtocolEvent::AgentDone { channel, provider, .. } => {
+                    assert_eq!(channel.as_deref(), Some("tui#dummy"));
+                    assert_eq!(provider, Some(AgentProvider::Dummy));
+                }
+                ProtocolEvent::SystemMessage { msg, channel } => {
+                    assert!(msg.starts_with("Compare finished: dummy "), "{msg}");
+                    assert_eq!(channel.as_deref(), Some("tui"));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert!(chunks > 0);
+        // 比較用の実行は会話のセッションに残らない。
+        assert!(state.lock().await.sessions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_compare_without_prompt_shows_usage() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.compare_providers = vec![AgentProvider::Dummy];
+
+        handle_command("/compare", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Usage: /compare <prompt>");
+    }
+
+    #[tokio::test]
+    async fn test_agent_slots_limit_concurrent_runs() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        {
+            let mut s = state.lock().await;
+            s.active_provider = AgentProvider::Dummy;
+            s.active_model = Some("echo".into());
+            s.agent_slots = Some(Arc::new(Semaphore::new(1)));
+        }
+
+        // 別チャンネルなのでキューは別々だが、枠が 1 つなので 2 つ目は 1 つ目の完了を待つ。
+        run_prompt("一つ目".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+        run_prompt("二つ目".into(), None, Some("slack:C1".into()), None, false, &tx, &state).await;
+
+        let mut order = Vec::new();
+        while order.len() < 4 {
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::AgentChunk { channel: Some(c), .. } if order.last() != Some(&format!("chunk {c}")) => {
+                    order.push(format!("chunk {c}"));
+                }
+                ProtocolEvent::AgentDone { channel: Some(c), .. } => order.push(format!("done {c}")),
+                _ => {}
+            }
+        }
+        assert_eq!(order, vec!["chunk tui", "done tui", "chunk slack:C1", "done slack:C1"]);
+    }
+
+    #[test]
+    fn test_compare_providers_and_agent_limit_from_env_values() {
+        assert_eq!(resolve_compare_providers(None), (vec![AgentProvider::Gemini, AgentProvider::Claude], None));
+        assert_eq!(resolve_compare_providers(Some("codex claude")).0, vec![AgentProvider::Codex, AgentProvider::Claude]);
+        let (providers, warning) = resolve_compare_providers(Some("gemini,gpt"));
+        assert_eq!(providers, vec![AgentProvider::Gemini, AgentProvider::Claude]);
+        assert!(warning.unwrap().contains("Unknown provider 'gpt'"));
+
+        assert_eq!(resolve_max_concurrent_agents(None), (None, None));
+        assert_eq!(resolve_max_concurrent_agents(Some("0")), (None, None));
+        assert_eq!(resolve_max_concurrent_agents(Some(" 2 ")), (Some(2), None));
+        assert!(resolve_max_concurrent_agents(Some("two")).1.is_some());
+    }
+
+    #[test]
+    fn test_parse_schedule_add_reads_trigger_options_and_prompt() {
+        assert_eq!(
+            parse_schedule_add("\"30 7 * * *\" --deliver discord --catch-up summarize my calendar"),
+            Ok(("30 7 * * *", Some(Delivery::Discord), true, "summarize my calendar"))
+        );
+        assert_eq!(parse_schedule_add("in:+30m /today"), Ok(("in:+30m", None, false, "/today")));
+        assert!(parse_schedule_add("\"30 7 * * * no closing quote").unwrap_err().contains("closing quote"));
+        assert!(parse_schedule_add("in:+30m --deliver teams hi").unwrap_err().contains("'teams'"));
+        assert!(parse_schedule_add("in:+30m --deliver ntfy /today").unwrap_err().contains("slash command"));
+        assert_eq!(parse_schedule_add("in:+30m"), Err(SCHEDULE_USAGE.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_schedule_add_list_and_remove() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/schedule add \"30 7 * * 1-5\" --deliver ntfy 今日の予定をまとめて", Some("tui".into()), &tx, &state)
+            .await
+            .unwrap();
+        let msg = next_system_message(&mut rx).await;
+        assert!(msg.starts_with("Scheduled #1 [30 7 * * 1-5] next "), "{msg}");
+        assert!(msg.contains("→ ntfy") && msg.ends_with("(runs on schedule:1)."), "{msg}");
+        handle_command("/schedule add \"61 7 * * *\" bad", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.starts_with("Invalid schedule time '61 7 * * *'"));
+        handle_command("/schedule list", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.ends_with(": 今日の予定をまとめて"));
+        handle_command("/schedule rm 1", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Schedule #1 removed.");
+        handle_command("/schedule rm 1", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.starts_with("No schedule #1."));
+    }
+
+    #[tokio::test]
+    async fn test_fired_schedule_runs_on_its_own_channel() {
+        let (tx, mut rx) = broadcast::channel(1024);
+        let tx = Arc::new(tx);
+        let state = Arc::new(command_test_state());
+        let schedule = {
+            let mut s = state.lock().await;
+            s.active_provider = AgentProvider::Dummy;
+            s.active_model = Some("echo".into());
+            s.schedules.add("in:+1s", "朝のまとめ", None, false, 0).unwrap();
+            s.schedules.take_due(1).remove(0)
+        };
+
+        fire_schedule(schedule, Arc::clone(&tx), Arc::clone(&state)).await;
+
+        loop {
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::Prompt { text, channel, .. } => {
+                    assert_eq!(text, "朝のまとめ");
+                    assert_eq!(channel.as_deref(), Some("schedule:1"));
+                }
+                ProtocolEvent::AgentDone { channel, .. } => {
+                    assert_eq!(channel.as_deref(), Some("schedule:1"));
+                    break;
+                }
+                _ => {}
+            }
+        }
+        assert!(state.lock().await.schedules.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_set_show_and_clear() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/fallback set claude, codex", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.starts_with("Fallback providers: claude → codex"));
+        assert_eq!(state.lock().await.fallback.providers, vec![AgentProvider::Claude, AgentProvider::Codex]);
+        handle_command("/fallback set claude gpt", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.starts_with("Unknown provider 'gpt'"));
+        handle_command("/fallback clear", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Fallback disabled.");
+        handle_command("/fallback", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.starts_with("No fallback providers"));
+    }
+
+    #[test]
+    fn test_quota_user_uses_the_slack_user_or_the_channel_scope() {
+        assert_eq!(quota_user(Some("slack:U1:C1"), None), "slack:U1");
+        assert_eq!(quota_user(Some("slack:dm:U2:D9"), None), "slack:U2");
+        // Discord はチャンネルに送信者が入らないので、アダプターが付けた author を使う
+        assert_eq!(quota_user(Some("discord:42:1001"), Some("7")), "discord:user:7");
+        assert_eq!(quota_user(Some("discord:42:1001"), None), "discord:42");
+        assert_eq!(quota_user(None, None), "bridge");
+        assert!(charges_quota("hello") && charges_quota("/compare hi"));
+        assert!(!charges_quota("/status"));
+    }
+
+    #[tokio::test]
+    async fn test_quota_rejects_over_limit_prompts_and_can_be_reset() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.quota = crate::rate_limit::parse_quota_config(Some("1"), None, Some("tui")).0;
+
+        assert!(admit_prompt(Some("slack:U1:C1"), None, &tx, &state).await);
+        assert!(!admit_prompt(Some("slack:U1:C2"), None, &tx, &state).await);
+        assert_eq!(next_system_message(&mut rx).await, "rate limit: try again in 60 min");
+        // 除外チャンネルは制限されない
+        assert!(admit_prompt(Some("tui"), None, &tx, &state).await);
+        assert!(admit_prompt(Some("tui"), None, &tx, &state).await);
+
+        handle_command("/status", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.ends_with("Quota: 1/h, burst 1 — slack:U1 0/1 left (exempt: tui)"));
+        handle_command("/quota reset slack:U1", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Quota reset for slack:U1.");
+        assert!(admit_prompt(Some("slack:U1:C1"), None, &tx, &state).await);
+    }
+
+    #[tokio::test]
+    async fn test_quota_is_per_discord_author_not_per_channel() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.quota = crate::rate_limit::parse_quota_config(Some("1"), None, None).0;
+
+        // 同じチャンネルでも送信者ごとに別のバケツになる
+        assert!(admit_prompt(Some("discord:42:1001"), Some("u1"), &tx, &state).await);
+        assert!(admit_prompt(Some("discord:42:1002"), Some("u2"), &tx, &state).await);
+        assert!(!admit_prompt(Some("discord:42:1003"), Some("u1"), &tx, &state).await);
+        assert_eq!(next_system_message(&mut rx).await, "rate limit: try again in 60 min");
+        assert!(!admit_prompt(Some("discord:42:1004"), Some("u2"), &tx, &state).await);
+    }
+
+    #[tokio::test]
+    async fn test_handle_command_provider_codex_emits_default_model() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = Mutex::new(BridgeState {
+            active_provider: AgentProvider::Gemini,
+            active_model: Some("auto-gemini-3".into()),
+            ..command_test_state().into_inner()
+        });
+
+        handle_command("/provider codex", None, &tx, &state).await.unwrap();
+
+        let ev1 = recv(&mut rx).await;
+        let ev2 = recv(&mut rx).await;
+        assert!(matches!(ev1, ProtocolEvent::ProviderSwitched { provider: AgentProvider::Codex }));
+        assert!(matches!(ev2, ProtocolEvent::ModelSwitched { model } if model == "gpt-5.3-codex"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_command_tool_is_an_alias_for_provider() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/tool claude", None, &tx, &state).await.unwrap();
+
+        assert!(matches!(recv(&mut rx).await, ProtocolEvent::ProviderSwitched { provider: AgentProvider::Claude }));
+        assert!(matches!(recv(&mut rx).await, ProtocolEvent::ModelSwitched { model } if model == "claude-sonnet-4-6"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_command_provider_ollama_explains_missing_executor() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/provider ollama", Some("discord:1:2".into()), &tx, &state).await.unwrap();
+
+        match recv(&mut rx).await {
+            ProtocolEvent::SystemMessage { msg, channel } => {
+                assert!(msg.contains("ollama") && msg.contains("llama3"));
+                assert_eq!(channel.as_deref(), Some("discord:1:2"));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(rx.try_recv().is_err(), "no provider switch without an executor");
+        assert_eq!(state.lock().await.active_provider, AgentProvider::Mock);
+    }
+
+    #[tokio::test]
+    async fn test_provider_channel_switches_only_the_requesting_channel() {
+        let (tx, mut rx) = broadcast::channel(64);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/provider channel dummy", Some("discord:1:2".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Switched discord:1 to dummy:echo.");
+        assert_eq!(state.lock().await.active_provider, AgentProvider::Mock, "the active provider stays");
+
+        // 同じチャンネルの別メッセージは dummy、他のチャンネルはアクティブなプロバイダーで動く
+        for (channel, expected) in [("discord:1:3", AgentProvider::Dummy), ("tui", AgentProvider::Mock)] {
+            let mut rx = tx.subscribe();
+            run_prompt("hi".into(), None, Some(channel.into()), None, false, &tx, &state).await;
+            let provider = loop {
+                if let ProtocolEvent::Prompt { provider, .. } = recv(&mut rx).await {
+                    break provider;
+                }
+            };
+            assert_eq!(provider, Some(expected), "{channel}");
+        }
+
+        let mut rx = tx.subscribe();
+        handle_command("/provider channel clear", Some("discord:1:4".into()), &tx, &state).await.unwrap();
+        // 走っているプロンプトのチャンクに混ざって届く
+        let msg = loop {
+            if let ProtocolEvent::SystemMessage { msg, .. } = recv(&mut rx).await {
+                break msg;
+            }
+        };
+        assert_eq!(msg, "Provider cleared for discord:1; using mock.");
+        assert!(state.lock().await.channel_providers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_provider_and_model_to_requesting_channel() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/status", Some("slack:U1:C1".into()), &tx, &state).await.unwrap();
+
+        match recv(&mut rx).await {
+            ProtocolEvent::SystemMessage { msg, channel } => {
+                assert!(msg.contains(AgentProvider::Mock.command_name()));
+                assert_eq!(channel.as_deref(), Some("slack:U1:C1"));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_socket_is_only_claimed_from_a_dead_bridge() {
+        let dir = std::env::temp_dir().join(format!("acomm-claim-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (socket, pid_file) = (dir.join("acomm.sock"), dir.join("acomm.pid"));
+
+        // 誰かが listen していれば、PID ファイルがなくても消さない。
+        let live = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        assert!(claim_socket(&socket, &pid_file).unwrap_err().contains("already listening"));
+        drop(live);
+        assert!(socket.exists());
+
+        // listen していなくても、記録された bridge プロセスが生きていれば消さない。
+        std::fs::write(&pid_file, "1").unwrap();
+        assert!(claim_socket(&socket, &pid_file).unwrap_err().contains("bridge process 1"));
+        assert!(socket.exists());
+
+        // 持ち主のいないソケットだけ消して、終了時には自分の PID ファイルも片付ける。
+        std::fs::write(&pid_file, u32::MAX.to_string()).unwrap();
+        claim_socket(&socket, &pid_file).unwrap();
+        assert!(!socket.exists());
+        std::fs::write(&pid_file, std::process::id().to_string()).unwrap();
+        release_socket(&socket, &pid_file);
+        assert!(!pid_file.exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_process_alive_reads_proc() {
+        assert!(process_alive(std::process::id()));
+        assert!(!process_alive(u32::MAX));
+    }
 
     #[tokio::test]
-    async fn test_handle_command_provider_dummy_switches_provider() {
+    async fn test_panicking_agent_task_still_emits_terminal_event() {
         let (tx, mut rx) = broadcast::channel(8);
         let tx = Arc::new(tx);
-        let state = Mutex::new(BridgeState {
-            active_provider: AgentProvider::Gemini,
-            active_model: None,
-            backlog: VecDeque::new(),
-            session_manager: SessionManager::new(),
-        });
 
-        handle_command("/provider dummy", &tx, &state).await.unwrap();
+        spawn_prompt_task(Some("test_channel".into()), &tx, None, async { panic!("mock provider exploded") })
+            .await
+            .unwrap();
 
-        let ev = rx.recv().await.unwrap();
-        assert!(matches!(ev, ProtocolEvent::ProviderSwitched { provider: AgentProvider::Dummy }));
+        match recv(&mut rx).await {
+            ProtocolEvent::ChannelClosed { channel, reason } => {
+                assert_eq!(channel.as_deref(), Some("test_channel"));
+                assert!(reason.contains("panicked"));
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(matches!(
+            recv(&mut rx).await,
+            ProtocolEvent::StatusUpdate { is_processing: false, channel: Some(c) } if c == "test_channel"
+        ));
     }
 
     #[tokio::test]
-    async fn test_handle_command_provider_codex_emits_default_model() {
+    async fn test_cancel_aborts_only_the_runs_of_the_callers_conversation() {
+        let (tx, mut rx) = broadcast::channel(32);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        let running = Arc::clone(&state.lock().await.running);
+        let mut tasks = Vec::new();
+        for channel in ["tui", "tui#claude", "slack:U1:C1"] {
+            tasks.push(spawn_prompt_task(Some(channel.into()), &tx, Some(&running), std::future::pending()));
+        }
+
+        // 自分の会話（/compare の分も含む）だけが止まり、ほかのチャンネルは走り続ける。
+        handle_command("/cancel", Some("tui".into()), &tx, &state).await.unwrap();
+        for task in tasks.drain(..2) {
+            task.await.unwrap();
+        }
+        let mut closed = Vec::new();
+        let mut notice = None;
+        while let Ok(event) = rx.try_recv() {
+            match event.event.clone() {
+                ProtocolEvent::ChannelClosed { channel, reason } => {
+                    assert!(reason.contains("cancelled"), "{reason}");
+                    closed.push(channel.unwrap());
+                }
+                ProtocolEvent::SystemMessage { msg, .. } => notice = Some(msg),
+                _ => {}
+            }
+        }
+        closed.sort();
+        assert_eq!(closed, vec!["tui".to_string(), "tui#claude".to_string()]);
+        assert_eq!(notice.as_deref(), Some("Cancelled 2 running prompt(s) in tui."));
+        assert_eq!(running.lock().unwrap().len(), 1);
+
+        handle_command("/cancel", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Nothing is running in tui.");
+        handle_command("/cancel", Some("slack:U1:C1".into()), &tx, &state).await.unwrap();
+        tasks.pop().unwrap().await.unwrap();
+        assert!(running.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_completed_agent_task_only_clears_status() {
         let (tx, mut rx) = broadcast::channel(8);
         let tx = Arc::new(tx);
-        let state = Mutex::new(BridgeState {
-            active_provider: AgentProvider::Gemini,
-            active_model: Some("auto-gemini-3".into()),
+
+        spawn_prompt_task(Some("test_channel".into()), &tx, None, async {}).await.unwrap();
+
+        assert!(matches!(recv(&mut rx).await, ProtocolEvent::StatusUpdate { is_processing: false, .. }));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_resolve_default_provider_from_env_values() {
+        let (provider, model, warning) = resolve_default_provider(None, None);
+        assert_eq!(provider, AgentProvider::Gemini);
+        assert_eq!(model.as_deref(), Some("auto-gemini-3"));
+        assert!(warning.is_none());
+
+        let (provider, model, _) = resolve_default_provider(Some(" Claude "), None);
+        assert_eq!(provider, AgentProvider::Claude);
+        assert_eq!(model.as_deref(), Some("claude-sonnet-4-6"));
+
+        let (provider, model, _) = resolve_default_provider(Some("codex"), Some("o1-mini"));
+        assert_eq!(provider, AgentProvider::Codex);
+        assert_eq!(model.as_deref(), Some("o1-mini"));
+
+        let (provider, model, _) = resolve_default_provider(Some("opencode"), Some("  "));
+        assert_eq!(provider, AgentProvider::OpenCode);
+        assert_eq!(model, None);
+    }
+
+    #[test]
+    fn test_resolve_default_provider_falls_back_on_unknown_name() {
+        let (provider, model, warning) = resolve_default_provider(Some("gpt"), Some("o1-mini"));
+        assert_eq!(provider, AgentProvider::Gemini);
+        assert_eq!(model.as_deref(), Some("auto-gemini-3"));
+        assert!(warning.unwrap().contains("gpt"));
+    }
+
+    #[test]
+    fn test_broadcast_capacity_from_env_value() {
+        assert_eq!(broadcast_capacity(None), (100, None));
+        assert_eq!(broadcast_capacity(Some(" 512 ")), (512, None));
+        let (cap, warning) = broadcast_capacity(Some("0"));
+        assert_eq!(cap, 100);
+        assert!(warning.unwrap().contains("ACOMM_BROADCAST_CAP"));
+        assert_eq!(broadcast_capacity(Some("lots")).0, 100);
+    }
+
+    #[test]
+    fn test_agent_timeout_from_env_value() {
+        assert_eq!(resolve_agent_timeout(None), (Some(Duration::from_secs(300)), None));
+        assert_eq!(resolve_agent_timeout(Some(" 30 ")), (Some(Duration::from_secs(30)), None));
+        assert_eq!(resolve_agent_timeout(Some("0")), (None, None));
+        let (timeout, warning) = resolve_agent_timeout(Some("5m"));
+        assert_eq!(timeout, Some(Duration::from_secs(300)));
+        assert!(warning.unwrap().contains("ACOMM_AGENT_TIMEOUT_SECS"));
+    }
+
+    #[tokio::test]
+    async fn test_initial_sync_payload_matches_per_event_serialization() {
+        let state = command_test_state();
+        let mut s = state.lock().await;
+        for i in 0..3 {
+            s.backlog.push_back(BusEvent::new(ProtocolEvent::AgentChunk { chunk: format!("c{i}"), channel: Some("tui".into()) }));
+        }
+        s.backlog.push_back(BusEvent::new(ProtocolEvent::AgentDone { channel: Some("tui".into()), provider: None, model: None }));
+        let snapshot = s.sync_snapshot();
+        let payload = initial_sync_payload(&snapshot, "today".into()).unwrap();
+
+        // 事前シリアライズ前と同じく、イベントを1つずつ serde に通した行の連結になる。
+        let mut expected_events = vec![
+            ProtocolEvent::Hello { version: PROTOCOL_VERSION },
+            ProtocolEvent::SyncContext { context: "today".into() },
+            ProtocolEvent::ProviderSwitched { provider: AgentProvider::Mock },
+            ProtocolEvent::ModelSwitched { model: "mock-model".into() },
+        ];
+        expected_events.extend(s.backlog.iter().map(|e| e.event.clone()));
+        expected_events.push(ProtocolEvent::BridgeSyncDone {});
+        let expected: String =
+            expected_events.iter().map(|e| format!("{}\n", serde_json::to_string(e).unwrap())).collect();
+        assert_eq!(payload, expected);
+
+        // スナップショットはロックを離した後の /clear に影響されない。
+        s.backlog.clear();
+        drop(s);
+        assert_eq!(initial_sync_payload(&snapshot, "today".into()).unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_large_chunk_is_shared_and_serialized_once_across_subscribers() {
+        let (tx, mut first) = broadcast::channel(4);
+        let mut second = tx.subscribe();
+        let chunk = "x".repeat(1024 * 1024);
+        tx.send(BusEvent::new(ProtocolEvent::AgentChunk { chunk, channel: Some("tui".into()) })).unwrap();
+
+        let (a, b) = (first.recv().await.unwrap(), second.recv().await.unwrap());
+        assert!(Arc::ptr_eq(&a, &b), "subscribers must share one event, not clones of a 1 MB chunk");
+        assert!(std::ptr::eq(a.line(), b.line()), "the JSON line is serialized once");
+        assert!(a.line().ends_with("\"}}\n"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscriber_reports_nonzero_lag() {
+        let (tx, mut slow_rx) = broadcast::channel(2);
+        for i in 0..5 {
+            tx.send(BusEvent::new(ProtocolEvent::AgentChunk { chunk: i.to_string(), channel: None })).unwrap();
+        }
+        let skipped = match slow_rx.recv().await {
+            Err(broadcast::error::RecvError::Lagged(skipped)) => skipped,
+            other => panic!("expected lag, got {:?}", other),
+        };
+        assert_eq!(skipped, 3);
+
+        let metrics = BridgeMetrics::default();
+        metrics.record_lag(skipped);
+        assert!(metrics.render().contains("acomm_lagged_events_total 3\n"));
+
+        let state = command_test_state();
+        let payload = lag_resync_payload(skipped, &*state.lock().await).unwrap();
+        let events: Vec<ProtocolEvent> =
+            payload.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        match &events[0] {
+            ProtocolEvent::SystemMessage { msg, channel: None } => assert!(msg.contains("missed 3 events")),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert!(matches!(events[1], ProtocolEvent::ProviderSwitched { provider: AgentProvider::Mock }));
+        assert!(matches!(&events[2], ProtocolEvent::ModelSwitched { model } if model == "mock-model"));
+    }
+
+    #[tokio::test]
+    async fn test_search_with_missing_memory_backend_reports_it() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.memory_cmd = memory_command(Some("acomm-no-such-memory-backend --db x"));
+
+        handle_command("/search rust", Some("tui".into()), &tx, &state).await.unwrap();
+        handle_command("/today", Some("tui".into()), &tx, &state).await.unwrap();
+
+        for _ in 0..2 {
+            match recv(&mut rx).await {
+                ProtocolEvent::SystemMessage { msg, .. } => {
+                    assert_eq!(msg, "memory backend 'acomm-no-such-memory-backend' not found")
+                }
+                other => panic!("unexpected event: {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_output_goes_back_to_the_requesting_channel() {
+        let (tx, mut rx) = broadcast::channel(16);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.memory_cmd = fake_memory_backend("echo \"$0\"");
+        let discord = Some("discord:123:456".to_string());
+
+        for command in ["/search rust", "/today", "/clear", "/clear slack:", "/clear --all"] {
+            handle_command(command, discord.clone(), &tx, &state).await.unwrap();
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::SystemMessage { channel, .. } => assert_eq!(channel, discord, "{command}"),
+                other => panic!("unexpected event for {command}: {:?}", other),
+            }
+            while let Ok(event) = rx.try_recv() {
+                assert!(matches!(event.event, ProtocolEvent::ModelSwitched { .. }), "{command}: {:?}", event.event);
+            }
+        }
+
+        // チャンネルのないプロンプトからのコマンドは従来どおり "bridge" に返す。
+        for command in ["/today", "/clear slack:"] {
+            handle_command(command, None, &tx, &state).await.unwrap();
+            match tokio::time::timeout(Duration::from_secs(5), recv(&mut rx)).await.unwrap() {
+                ProtocolEvent::SystemMessage { channel, .. } => assert_eq!(channel.as_deref(), Some("bridge"), "{command}"),
+                other => panic!("unexpected event for {command}: {:?}", other),
+            }
+        }
+    }
+
+    fn fake_memory_backend(script: &str) -> Vec<String> {
+        // The bridge appends e.g. `search <query>`; sh sees them as $0 and $1.
+        vec!["sh".into(), "-c".into(), script.into()]
+    }
+
+    /// 次のブロードキャストイベントの中身。
+    async fn recv(rx: &mut broadcast::Receiver<SharedEvent>) -> ProtocolEvent {
+        rx.recv().await.unwrap().event.clone()
+    }
+
+    async fn next_system_message(rx: &mut broadcast::Receiver<SharedEvent>) -> String {
+        match tokio::time::timeout(Duration::from_secs(5), rx.recv()).await.unwrap().unwrap().event.clone() {
+            ProtocolEvent::SystemMessage { msg, .. } => msg,
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    #[cfg(not(feature = "sqlite"))]
+    async fn test_history_explains_missing_sqlite_feature() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        handle_command("/history slack 5", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.contains("without the `sqlite` feature"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_search_does_not_block_a_concurrent_ping() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        state.lock().await.memory_cmd = fake_memory_backend("sleep 0.5; echo \"$0 for $1\"");
+
+        let started = Instant::now();
+        handle_command("/search rust", Some("tui".into()), &tx, &state).await.unwrap();
+        handle_command("/ping", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "pong");
+        assert!(started.elapsed() < Duration::from_millis(400), "ping waited for the search");
+        assert_eq!(next_system_message(&mut rx).await, "Search results:\nsearch for rust\n");
+    }
+
+    #[tokio::test]
+    async fn test_memory_output_is_streamed_in_parts() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let args = ["today".to_string()];
+        let backend = fake_memory_backend("echo one; sleep 0.4; echo two");
+        stream_memory_command(&backend, &args, "Today", Duration::from_millis(100), Some("tui".into()), &tx).await;
+        assert_eq!(next_system_message(&mut rx).await, "Today:\none\n");
+        assert_eq!(next_system_message(&mut rx).await, "Today (continued):\ntwo\n");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_memory_backend_failure_is_reported_as_a_message() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let args = ["today".to_string()];
+        let backend = fake_memory_backend("echo 'index is locked' >&2; exit 3");
+        stream_memory_command(&backend, &args, "Today", Duration::from_secs(2), Some("tui".into()), &tx).await;
+        assert_eq!(next_system_message(&mut rx).await, "memory backend 'sh' exited with status 3: index is locked");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn memory_command_defaults_to_amem() {
+        assert_eq!(memory_command(None), vec!["amem"]);
+        assert_eq!(memory_command(Some("  ")), vec!["amem"]);
+        assert_eq!(memory_command(Some("mem --db /tmp/x")), vec!["mem", "--db", "/tmp/x"]);
+    }
+
+    /// テスト用の BridgeState。Mock プロバイダーと standup マクロのほかは既定値。
+    /// 別の状態が要るテストは必要なフィールドだけ書き、残りを `..command_test_state().into_inner()` で埋める。
+    fn command_test_state() -> Mutex<BridgeState> {
+        Mutex::new(BridgeState {
+            active_provider: AgentProvider::Mock,
+            active_model: Some("mock-model".into()),
             backlog: VecDeque::new(),
-            session_manager: SessionManager::new(),
-        });
+            sessions: HashMap::new(),
+            macros: HashMap::from([("standup".to_string(), "Summarize today's notes".to_string())]),
+            personas: PersonaStore::default(),
+            channel_providers: HashMap::new(),
+            templates: TemplateStore::default(),
+            schedules: ScheduleStore::default(),
+            state_dir: None,
+            memory_cmd: memory_command(None),
+            memory_log: false,
+            chunk_limits: ChunkLimits::default(),
+            agent_timeout: None,
+            fallback: FallbackPolicy::default(),
+            compare_providers: Vec::new(),
+            agent_slots: None,
+            dedup: Default::default(),
+            running: Default::default(),
+            quota: None,
+            queues: HashMap::new(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_scoped_clear_keeps_other_channels_and_session_state() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        {
+            let mut s = state.lock().await;
+            s.active_model = Some("gemini-2.5-pro".into());
+            for channel in ["discord:123:1", "discord:123:2", "discord:456:1", "slack:U1:C1"] {
+                s.backlog.push_back(BusEvent::new(ProtocolEvent::AgentDone { channel: Some(channel.into()), provider: None, model: None }));
+            }
+            s.backlog.push_back(BusEvent::new(ProtocolEvent::ModelSwitched { model: "gemini-2.5-pro".into() }));
+        }
 
-        handle_command("/provider codex", &tx, &state).await.unwrap();
+        handle_command("/clear discord:123", Some("tui".into()), &tx, &state).await.unwrap();
 
-        let ev1 = rx.recv().await.unwrap();
-        let ev2 = rx.recv().await.unwrap();
-        assert!(matches!(ev1, ProtocolEvent::ProviderSwitched { provider: AgentProvider::Codex }));
-        assert!(matches!(ev2, ProtocolEvent::ModelSwitched { model } if model == "gpt-5.3-codex"));
+        let ev = recv(&mut rx).await;
+        assert!(matches!(
+            ev,
+            ProtocolEvent::SystemMessage { msg, channel: Some(c) }
+                if msg.starts_with("Cleared 2 backlog event(s) for discord:123") && c == "tui"
+        ));
+        assert!(rx.try_recv().is_err(), "no ModelSwitched: the model selection is kept");
+        let s = state.lock().await;
+        let remaining: Vec<Option<String>> = s.backlog.iter().map(|e| e.clone_channel()).collect();
+        assert_eq!(remaining, vec![Some("discord:456:1".into()), Some("slack:U1:C1".into()), None]);
+        assert_eq!(s.active_model.as_deref(), Some("gemini-2.5-pro"));
+    }
+
+    #[tokio::test]
+    async fn test_plain_clear_only_resets_the_requesting_conversation() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        {
+            let mut s = state.lock().await;
+            s.active_model = Some("gemini-2.5-pro".into());
+            for channel in ["discord:1:1", "tui", "discord:1:2", "tui", "discord:10:1"] {
+                s.backlog.push_back(BusEvent::new(ProtocolEvent::AgentDone { channel: Some(channel.into()), provider: None, model: None }));
+            }
+            for scope in ["discord:1", "tui", "discord:10"] {
+                s.sessions.insert(scope.into(), SessionManager::new());
+            }
+        }
+
+        handle_command("/clear", Some("discord:1:2".into()), &tx, &state).await.unwrap();
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(
+            ev,
+            ProtocolEvent::SystemMessage { msg, channel: Some(c) }
+                if msg.starts_with("Cleared 2 backlog event(s) and the agent session for discord:1.") && c == "discord:1:2"
+        ));
+        assert!(rx.try_recv().is_err(), "no ModelSwitched: the model selection is kept");
+        let s = state.lock().await;
+        let remaining: Vec<Option<String>> = s.backlog.iter().map(|e| e.clone_channel()).collect();
+        assert_eq!(remaining, vec![Some("tui".into()), Some("tui".into()), Some("discord:10:1".into())]);
+        let mut scopes: Vec<&String> = s.sessions.keys().collect();
+        scopes.sort();
+        assert_eq!(scopes, vec!["discord:10", "tui"]);
+        assert_eq!(s.active_model.as_deref(), Some("gemini-2.5-pro"));
+    }
+
+    #[tokio::test]
+    async fn test_clear_all_resets_every_channel_and_the_model() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        {
+            let mut s = state.lock().await;
+            s.active_model = Some("gemini-2.5-pro".into());
+            s.backlog.push_back(BusEvent::new(ProtocolEvent::AgentDone { channel: Some("tui".into()), provider: None, model: None }));
+            s.sessions.insert("tui".into(), SessionManager::new());
+        }
+
+        handle_command("/clear --all", Some("tui".into()), &tx, &state).await.unwrap();
+
+        assert!(matches!(recv(&mut rx).await, ProtocolEvent::SystemMessage { msg, .. } if msg == "Cleared every channel."));
+        assert!(matches!(recv(&mut rx).await, ProtocolEvent::ModelSwitched { .. }));
+        let s = state.lock().await;
+        assert!(s.backlog.is_empty());
+        assert!(s.sessions.is_empty());
+        assert_ne!(s.active_model.as_deref(), Some("gemini-2.5-pro"));
+    }
+
+    #[tokio::test]
+    async fn test_macro_set_stores_text_verbatim() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/macro set review Review this diff:\n  be strict", Some("tui".into()), &tx, &state)
+            .await
+            .unwrap();
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(ev, ProtocolEvent::SystemMessage { msg, .. } if msg.contains("'review' saved")));
+        let s = state.lock().await;
+        assert_eq!(s.macros.get("review").map(String::as_str), Some("Review this diff:\n  be strict"));
+    }
+
+    #[tokio::test]
+    async fn test_macro_list_reports_defined_names() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/macro list", Some("tui".into()), &tx, &state).await.unwrap();
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(ev, ProtocolEvent::SystemMessage { msg, channel: Some(c) } if msg.contains("standup") && c == "tui"));
+    }
+
+    #[tokio::test]
+    async fn test_macro_expands_to_prompt_on_requesting_channel() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/macro standup", Some("discord:1:2".into()), &tx, &state).await.unwrap();
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(
+            ev,
+            ProtocolEvent::Prompt { text, channel: Some(c), .. }
+                if text == "Summarize today's notes" && c == "discord:1:2"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_macro_undefined_emits_helpful_system_message() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/macro missing", Some("tui".into()), &tx, &state).await.unwrap();
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(ev, ProtocolEvent::SystemMessage { msg, .. } if msg.contains("Unknown macro 'missing'") && msg.contains("/macro list")));
+    }
+
+    #[tokio::test]
+    async fn test_template_save_list_and_delete() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/template save ja 日本語で簡潔に答えて", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.contains("'ja' saved"));
+        handle_command("/template save review You are reviewing a PR; output a bullet list", Some("tui".into()), &tx, &state)
+            .await
+            .unwrap();
+        let _ = recv(&mut rx).await;
+        handle_command("/template list", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Templates: ja, review");
+        handle_command("/template delete review", Some("tui".into()), &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Template 'review' deleted.");
+        handle_command("/template save bad.name x", Some("tui".into()), &tx, &state).await.unwrap();
+        assert!(next_system_message(&mut rx).await.starts_with("Invalid template name 'bad.name'"));
+        assert_eq!(state.lock().await.templates.names(), vec!["ja"]);
+    }
+
+    #[tokio::test]
+    async fn test_template_reference_is_expanded_in_the_echoed_prompt() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        handle_command("/template save ja Answer in Japanese, concisely.", None, &tx, &state).await.unwrap();
+        let _ = recv(&mut rx).await;
+
+        run_prompt("@ja what is a monad?".into(), None, Some("discord:1:2".into()), None, false, &tx, &state).await;
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(
+            ev,
+            ProtocolEvent::Prompt { text, channel: Some(c), .. }
+                if text == "Answer in Japanese, concisely.\n\nwhat is a monad?" && c == "discord:1:2"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_template_reports_the_available_ones_without_running() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+        handle_command("/template save ja x", None, &tx, &state).await.unwrap();
+        let _ = recv(&mut rx).await;
+
+        run_prompt("@jp hello".into(), None, Some("tui".into()), None, false, &tx, &state).await;
+
+        let ev = recv(&mut rx).await;
+        assert!(matches!(
+            ev,
+            ProtocolEvent::SystemMessage { msg, channel: Some(c) }
+                if msg == "Unknown template 'jp'. Available templates: ja" && c == "tui"
+        ));
+        assert!(rx.try_recv().is_err(), "nothing was run");
+    }
+
+    #[tokio::test]
+    async fn test_persona_set_and_clear_apply_to_requesting_channel_scope() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        handle_command("/persona set You are a terse ops bot.", Some("slack:U1:C1".into()), &tx, &state)
+            .await
+            .unwrap();
+        let _ = recv(&mut rx).await;
+        {
+            let s = state.lock().await;
+            assert_eq!(s.personas.for_channel(Some("slack:U2:C1")), Some("You are a terse ops bot."));
+            assert_eq!(s.personas.for_channel(Some("discord:1:2")), None);
+        }
+
+        handle_command("/persona clear", Some("slack:U1:C1".into()), &tx, &state).await.unwrap();
+        let _ = recv(&mut rx).await;
+        let s = state.lock().await;
+        assert_eq!(s.personas.for_channel(Some("slack:U1:C1")), None);
+    }
+
+    #[tokio::test]
+    async fn test_persona_set_without_a_channel_applies_to_channel_less_prompts() {
+        let (tx, mut rx) = broadcast::channel(8);
+        let tx = Arc::new(tx);
+        let state = command_test_state();
+
+        // チャンネルなしの設定は bridge スコープに入り、チャンネルなしのプロンプトに使われる。
+        handle_command("/persona set Answer in English.", None, &tx, &state).await.unwrap();
+        assert_eq!(next_system_message(&mut rx).await, "Persona set for bridge.");
+        let s = state.lock().await;
+        assert_eq!(s.personas.for_channel(None), Some("Answer in English."));
+        assert_eq!(s.personas.for_channel(Some("tui")), None);
+    }
+
+    #[test]
+    fn test_persona_is_prepended_only_for_its_channel() {
+        let mut personas = PersonaStore::default();
+        personas.channels.insert("discord:10".into(), "執事として答えてください。".into());
+
+        let discord = apply_persona(personas.for_channel(Some("discord:10:99")), "今日の予定は？");
+        let tui = apply_persona(personas.for_channel(Some("tui")), "今日の予定は？");
+
+        assert_eq!(discord, "執事として答えてください。\n\n今日の予定は？");
+        assert_eq!(tui, "今日の予定は？");
+    }
+
+    #[test]
+    fn test_persona_falls_back_to_global_default() {
+        let personas = PersonaStore {
+            default: Some("Be concise.".into()),
+            channels: HashMap::from([("tui".to_string(), "Be verbose.".to_string())]),
+        };
+        assert_eq!(personas.for_channel(Some("ntfy:abc")), Some("Be concise."));
+        assert_eq!(personas.for_channel(Some("tui")), Some("Be verbose."));
+        assert_eq!(personas.for_channel(None), Some("Be concise."));
+    }
+
+    #[test]
+    fn test_channel_scope_strips_per_message_ids() {
+        assert_eq!(channel_scope("discord:123:456"), "discord:123");
+        assert_eq!(channel_scope("slack:U1:C1"), "slack:C1");
+        assert_eq!(channel_scope("slack:dm:U1:D1"), "slack:D1");
+        assert_eq!(channel_scope("ntfy:msg1"), "ntfy");
+        assert_eq!(channel_scope("tui"), "tui");
+        assert_eq!(channel_scope("matrix:!room:example.org:$event"), "matrix:!room:example.org");
+    }
+
+    #[test]
+    fn test_session_key_narrows_threaded_prompts_to_their_thread() {
+        assert_eq!(session_key(Some("slack:U1:C1"), None), "slack:C1");
+        assert_eq!(session_key(Some("slack:U1:C1"), Some("17.1")), "slack:C1#17.1");
+        assert_eq!(session_key(Some("slack:U2:C1"), Some("17.1")), "slack:C1#17.1");
+        assert_eq!(session_key(Some("discord:1:2"), Some("")), "discord:1");
+        assert_eq!(session_key(None, Some("x")), "bridge#x");
+    }
+
+    #[test]
+    fn test_split_first_word_keeps_remainder_verbatim() {
+        assert_eq!(split_first_word("set standup  Summarize\ntoday"), ("set", "standup  Summarize\ntoday"));
+        assert_eq!(split_first_word("  list"), ("list", ""));
+        assert_eq!(split_first_word(""), ("", ""));
+    }
+
+    #[test]
+    fn test_remove_persisted_files_skips_missing_and_reports_removed() {
+        let dir = std::env::temp_dir().join(format!("acomm-purge-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let existing = dir.join("backlog.jsonl");
+        let missing = dir.join("history.txt");
+        std::fs::write(&existing, "{}\n").unwrap();
+
+        let removed = remove_persisted_files(&[existing.clone(), missing]).unwrap();
+
+        assert_eq!(removed, vec![existing.clone()]);
+        assert!(!existing.exists());
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
@@ -478,7 +3604,7 @@ mod tests {
         let preset = discord_magic_provider_preset("p-gemini", Some("discord:1:2"))
             .expect("p-gemini should map to a preset");
         assert_eq!(preset.provider, AgentProvider::Gemini);
-        assert_eq!(preset.model, "auto-gemini-3");
+        assert_eq!(preset.model, Some("auto-gemini-3"));
     }
 
     #[test]
@@ -486,12 +3612,30 @@ mod tests {
         let codex = discord_magic_provider_preset("p-codex", Some("discord:1:2"))
             .expect("p-codex should map to codex preset");
         assert_eq!(codex.provider, AgentProvider::Codex);
-        assert_eq!(codex.model, "gpt-5.3-codex");
+        assert_eq!(codex.model, Some("gpt-5.3-codex"));
 
         let claude = discord_magic_provider_preset("p-claude", Some("discord:1:2"))
             .expect("p-claude should map to claude preset");
         assert_eq!(claude.provider, AgentProvider::Claude);
-        assert_eq!(claude.model, "claude-sonnet-4-6");
+        assert_eq!(claude.model, Some("claude-sonnet-4-6"));
+    }
+
+    #[test]
+    fn test_discord_magic_provider_preset_covers_remaining_providers() {
+        let opencode = discord_magic_provider_preset("p-opencode", Some("discord:1:2"))
+            .expect("p-opencode should map to opencode preset");
+        assert_eq!(opencode.provider, AgentProvider::OpenCode);
+        assert_eq!(opencode.model, None);
+
+        let dummy = discord_magic_provider_preset("p-dummy", Some("discord:1:2"))
+            .expect("p-dummy should map to dummy preset");
+        assert_eq!(dummy.provider, AgentProvider::Dummy);
+        assert_eq!(dummy.model, Some("echo"));
+
+        let mock = discord_magic_provider_preset(" p-mock ", Some("discord:1:2"))
+            .expect("p-mock should map to mock preset");
+        assert_eq!(mock.provider, AgentProvider::Mock);
+        assert_eq!(mock.model, Some("mock-model"));
     }
 
     #[test]
@@ -0,0 +1,228 @@
+/**
+ * Reshaping of the agent's output stream before it is broadcast as `AgentChunk`s.
+ *
+ * Agent CLIs are inconsistent about chunk sizes: some print the whole answer as one
+ * chunk of hundreds of KB, others emit it a byte at a time. Both are bad for the bus:
+ * a huge event bloats the backlog and makes clients append it in one go, and a storm
+ * of tiny events floods every subscriber. `forward_chunks` sits between the agent and
+ * the broadcast channel, buffering small chunks for a short time (or until enough
+ * text has piled up) and splitting anything larger than `ChunkLimits::max_bytes` at
+ * UTF-8 character boundaries. The concatenated text is never changed.
+ */
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+pub const DEFAULT_MAX_CHUNK_BYTES: usize = 8 * 1024;
+/// Buffered text is sent as soon as it reaches this many bytes...
+const DEFAULT_COALESCE_BYTES: usize = 256;
+/// ...or this long after the first buffered byte arrived.
+const DEFAULT_COALESCE_INTERVAL: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkLimits {
+    /// Largest `AgentChunk` the bridge sends, in bytes.
+    pub max_bytes: usize,
+    pub coalesce_bytes: usize,
+    pub coalesce_interval: Duration,
+}
+
+impl Default for ChunkLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_CHUNK_BYTES,
+            coalesce_bytes: DEFAULT_COALESCE_BYTES,
+            coalesce_interval: DEFAULT_COALESCE_INTERVAL,
+        }
+    }
+}
+
+impl ChunkLimits {
+    /// Limits with `max_bytes` from `ACOMM_MAX_CHUNK_BYTES`. Unset, zero, or unparsable
+    /// values use `DEFAULT_MAX_CHUNK_BYTES`; the coalescing threshold never exceeds the cap.
+    pub fn from_env_value(raw: Option<&str>) -> (Self, Option<String>) {
+        let (max_bytes, warning) = match raw.map(str::trim).filter(|v| !v.is_empty()) {
+            None => (DEFAULT_MAX_CHUNK_BYTES, None),
+            Some(raw) => match raw.parse::<usize>() {
+                Ok(max) if max > 0 => (max, None),
+                _ => (
+                    DEFAULT_MAX_CHUNK_BYTES,
+                    Some(format!("Invalid ACOMM_MAX_CHUNK_BYTES '{}'; using {}", raw, DEFAULT_MAX_CHUNK_BYTES)),
+                ),
+            },
+        };
+        let limits = Self { max_bytes, coalesce_bytes: DEFAULT_COALESCE_BYTES.min(max_bytes), ..Self::default() };
+        (limits, warning)
+    }
+}
+
+/// Split `text` into pieces of at most `max_bytes`, cutting only at character boundaries.
+/// A single character wider than `max_bytes` becomes a piece of its own.
+pub fn split_chunk(text: &str, max_bytes: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > max_bytes {
+        let mut end = max_bytes;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (piece, tail) = rest.split_at(end);
+        pieces.push(piece.to_string());
+        rest = tail;
+    }
+    if !rest.is_empty() {
+        pieces.push(rest.to_string());
+    }
+    pieces
+}
+
+/// Buffers agent output and hands back the chunks that are ready to send.
+#[derive(Debug)]
+pub struct ChunkCoalescer {
+    limits: ChunkLimits,
+    pending: String,
+}
+
+impl ChunkCoalescer {
+    pub fn new(limits: ChunkLimits) -> Self {
+        Self { limits, pending: String::new() }
+    }
+
+    /// Add `chunk`; once enough text is buffered, return all of it split to the cap.
+    pub fn push(&mut self, chunk: &str) -> Vec<String> {
+        self.pending.push_str(chunk);
+        if self.pending.len() >= self.limits.coalesce_bytes { self.flush() } else { Vec::new() }
+    }
+
+    /// Everything buffered so far, split to the cap.
+    pub fn flush(&mut self) -> Vec<String> {
+        let pieces = split_chunk(&self.pending, self.limits.max_bytes);
+        self.pending.clear();
+        pieces
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+/// Pass the chunks received on `rx` to `emit`, coalesced and capped per `limits`.
+/// Buffered text is flushed once `coalesce_interval` has passed since it started
+/// piling up, and when `rx` closes; returns after the final flush.
+pub async fn forward_chunks(mut rx: mpsc::UnboundedReceiver<String>, limits: ChunkLimits, mut emit: impl FnMut(String)) {
+    let mut coalescer = ChunkCoalescer::new(limits);
+    let mut deadline: Option<Instant> = None;
+    loop {
+        let next = match deadline {
+            Some(at) => match tokio::time::timeout_at(at, rx.recv()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    coalescer.flush().into_iter().for_each(&mut emit);
+                    deadline = None;
+                    continue;
+                }
+            },
+            None => rx.recv().await,
+        };
+        let Some(chunk) = next else { break };
+        coalescer.push(&chunk).into_iter().for_each(&mut emit);
+        if !coalescer.has_pending() {
+            deadline = None;
+        } else if deadline.is_none() {
+            deadline = Some(Instant::now() + limits.coalesce_interval);
+        }
+    }
+    coalescer.flush().into_iter().for_each(&mut emit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_never_cuts_a_multibyte_character() {
+        // "あ" is 3 bytes, "🎉" 4: no cap below lines up with every boundary.
+        let text = "aあい🎉うbえ🎉".repeat(50);
+        for max in 1..=12 {
+            let pieces = split_chunk(&text, max);
+            assert_eq!(pieces.concat(), text);
+            for piece in &pieces {
+                assert!(piece.len() <= max || piece.chars().count() == 1, "max {max}: {piece:?}");
+            }
+        }
+        assert_eq!(split_chunk("あいう", 4), vec!["あ", "い", "う"]);
+        assert_eq!(split_chunk("🎉", 2), vec!["🎉"]);
+        assert!(split_chunk("", 8).is_empty());
+    }
+
+    #[test]
+    fn large_chunk_is_split_to_the_cap() {
+        let limits = ChunkLimits::default();
+        let text = "日本語".repeat(30_000);
+        let mut coalescer = ChunkCoalescer::new(limits);
+        let pieces = coalescer.push(&text);
+        assert!(pieces.len() > 1);
+        assert!(pieces.iter().all(|p| p.len() <= DEFAULT_MAX_CHUNK_BYTES));
+        assert_eq!(pieces.concat(), text);
+        assert!(!coalescer.has_pending());
+    }
+
+    #[test]
+    fn small_chunks_are_held_until_the_threshold() {
+        let limits = ChunkLimits { max_bytes: 8, coalesce_bytes: 6, ..ChunkLimits::default() };
+        let mut coalescer = ChunkCoalescer::new(limits);
+        assert!(coalescer.push("あ").is_empty());
+        assert_eq!(coalescer.push("い"), vec!["あい"]);
+        assert!(coalescer.push("x").is_empty());
+        assert_eq!(coalescer.push("うえお"), vec!["xうえ", "お"]);
+        assert!(coalescer.flush().is_empty());
+    }
+
+    #[test]
+    fn max_chunk_bytes_env_is_validated() {
+        assert_eq!(ChunkLimits::from_env_value(None), (ChunkLimits::default(), None));
+        let (limits, warning) = ChunkLimits::from_env_value(Some(" 100 "));
+        assert_eq!((limits.max_bytes, limits.coalesce_bytes, warning), (100, 100, None));
+        for bad in ["0", "-1", "8k"] {
+            let (limits, warning) = ChunkLimits::from_env_value(Some(bad));
+            assert_eq!(limits, ChunkLimits::default());
+            assert!(warning.unwrap().contains(bad));
+        }
+    }
+
+    #[tokio::test]
+    async fn byte_at_a_time_storm_is_coalesced() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let text = "ストリーム🎉".repeat(40);
+        // One event per character, like CLIs that flush after every byte they write.
+        for c in text.chars() {
+            tx.send(c.to_string()).unwrap();
+        }
+        drop(tx);
+        let mut emitted = Vec::new();
+        forward_chunks(rx, ChunkLimits::default(), |chunk| emitted.push(chunk)).await;
+        assert_eq!(emitted.concat(), text);
+        assert!(emitted.len() <= text.len() / DEFAULT_COALESCE_BYTES + 1, "{} events", emitted.len());
+    }
+
+    #[tokio::test]
+    async fn pending_text_is_flushed_after_the_interval() {
+        let limits = ChunkLimits { coalesce_interval: Duration::from_millis(10), ..ChunkLimits::default() };
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let forwarder = tokio::spawn(forward_chunks(rx, limits, move |chunk| {
+            let _ = out_tx.send(chunk);
+        }));
+        tx.send("こん".to_string()).unwrap();
+        tx.send("にちは".to_string()).unwrap();
+        // The sender stays open, so only the interval can release the buffered text.
+        let flushed = tokio::time::timeout(Duration::from_secs(5), out_rx.recv()).await.unwrap();
+        assert_eq!(flushed.as_deref(), Some("こんにちは"));
+        drop(tx);
+        forwarder.await.unwrap();
+        assert_eq!(out_rx.recv().await, None);
+    }
+}
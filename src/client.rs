@@ -0,0 +1,519 @@
+/**
+ * Typed client for the acomm bridge socket.
+ *
+ * `AcommClient` wraps one bridge connection: it announces the protocol version on
+ * connect, frames `ProtocolEvent`s as JSON lines, and tells the initial backlog
+ * replay (everything before `BridgeSyncDone`) apart from live traffic.
+ *
+ *   let mut client = AcommClient::connect(DEFAULT_SOCKET_PATH).await?;
+ *   let id = client.publish(ProtocolEvent::Prompt { text, provider: None, channel: Some("mytool".into()), reply_to: None, priority: false, author: None }).await?;
+ *   let reply = client.wait_for_reply(&id, Duration::from_secs(120)).await?;
+ *
+ * `publish` waits for the bridge's `PromptAck`; events that arrive meanwhile are
+ * kept and handed out by the next read, so nothing is lost. A reply is collected
+ * from live events only, so an older answer on the same channel in the backlog is
+ * never mistaken for it. `into_split` gives separate reader and writer halves for
+ * callers that read and write from different tasks.
+ */
+use crate::protocol::{AGENT_FAILURE_PREFIX, PROTOCOL_VERSION, ProtocolEvent};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines, ReadHalf, WriteHalf};
+use tokio::net::UnixStream;
+
+/// Where `acomm --bridge` listens.
+pub const DEFAULT_SOCKET_PATH: &str = "/tmp/acomm.sock";
+
+/// Identifies an accepted prompt: its channel and the per-connection sequence
+/// number from the bridge's `PromptAck`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestId {
+    pub channel: Option<String>,
+    pub seq: u64,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Io(io::Error),
+    /// The bridge closed the connection.
+    Disconnected,
+    Timeout(Duration),
+    /// The agent failed or its task ended without `AgentDone`; `partial` is what arrived before.
+    Agent { reason: String, partial: String },
+    /// `publish` was given an event other than `Prompt`.
+    NotAPrompt,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "{}", e),
+            ClientError::Disconnected => write!(f, "bridge closed the connection"),
+            ClientError::Timeout(after) => write!(f, "no answer from the bridge after {} seconds", after.as_secs()),
+            ClientError::Agent { reason, .. } => write!(f, "{}", reason),
+            ClientError::NotAPrompt => write!(f, "only Prompt events can be published"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> Self {
+        ClientError::Io(e)
+    }
+}
+
+/// How a reply being waited for ended.
+#[derive(Debug, PartialEq)]
+pub enum WaitOutcome {
+    Done,
+    Failed(String),
+}
+
+/// Apply one event to a reply being collected for `channel`: `AgentChunk`s are appended
+/// to `reply`, and `AgentDone`, an agent-failure `SystemMessage` or `ChannelClosed` end it.
+/// Events for other channels are ignored.
+pub fn apply_wait_event(event: &ProtocolEvent, channel: Option<&str>, reply: &mut String) -> Option<WaitOutcome> {
+    if event.clone_channel().as_deref() != channel {
+        return None;
+    }
+    match event {
+        ProtocolEvent::AgentChunk { chunk, .. } => {
+            reply.push_str(chunk);
+            None
+        }
+        ProtocolEvent::AgentDone { .. } => Some(WaitOutcome::Done),
+        ProtocolEvent::SystemMessage { msg, .. } if msg.starts_with(AGENT_FAILURE_PREFIX) => {
+            Some(WaitOutcome::Failed(msg.clone()))
+        }
+        ProtocolEvent::ChannelClosed { reason, .. } => Some(WaitOutcome::Failed(reason.clone())),
+        _ => None,
+    }
+}
+
+/// Reading half of a bridge connection.
+pub struct EventReader<S> {
+    lines: Lines<BufReader<ReadHalf<S>>>,
+    /// Lines read ahead while waiting for an ack, with whether they were live.
+    pending: VecDeque<(String, bool)>,
+    sync_done: bool,
+}
+
+/// Writing half of a bridge connection.
+pub struct EventWriter<S> {
+    writer: WriteHalf<S>,
+}
+
+pub struct AcommClient<S = UnixStream> {
+    reader: EventReader<S>,
+    writer: EventWriter<S>,
+}
+
+impl AcommClient<UnixStream> {
+    /// Connect to the bridge socket at `path` and announce the protocol version.
+    pub async fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_stream(UnixStream::connect(path).await?).await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AcommClient<S> {
+    /// Use an already connected stream (e.g. an in-memory duplex in tests).
+    pub async fn from_stream(stream: S) -> io::Result<Self> {
+        let (reader, writer) = tokio::io::split(stream);
+        let mut client = Self {
+            reader: EventReader { lines: BufReader::new(reader).lines(), pending: VecDeque::new(), sync_done: false },
+            writer: EventWriter { writer },
+        };
+        client.send(&ProtocolEvent::Hello { version: PROTOCOL_VERSION }).await?;
+        Ok(client)
+    }
+
+    pub async fn send(&mut self, event: &ProtocolEvent) -> io::Result<()> {
+        self.writer.send(event).await
+    }
+
+    /// Send a `Prompt` and wait for the bridge to acknowledge it.
+    pub async fn publish(&mut self, prompt: ProtocolEvent) -> Result<RequestId, ClientError> {
+        if !matches!(prompt, ProtocolEvent::Prompt { .. }) {
+            return Err(ClientError::NotAPrompt);
+        }
+        self.writer.send(&prompt).await?;
+        self.reader.wait_for_ack().await
+    }
+
+    /// Send a slash command (a leading `/` is added if missing) and wait for the ack.
+    /// Its answer arrives as a `SystemMessage` on the `bridge` channel.
+    pub async fn command(&mut self, command: &str) -> Result<RequestId, ClientError> {
+        let text = if command.starts_with('/') { command.to_string() } else { format!("/{}", command) };
+        self.publish(ProtocolEvent::Prompt { text, provider: None, channel: Some("bridge".into()), reply_to: None, priority: false, author: None }).await
+    }
+
+    /// Collect the reply to an accepted prompt until `AgentDone`.
+    pub async fn wait_for_reply(&mut self, id: &RequestId, timeout: Duration) -> Result<String, ClientError> {
+        self.wait_for_reply_with(id, timeout, |_| {}).await
+    }
+
+    /// Like `wait_for_reply`, calling `on_event` for every live event while waiting
+    /// (e.g. to print chunks as they stream in).
+    pub async fn wait_for_reply_with(
+        &mut self,
+        id: &RequestId,
+        timeout: Duration,
+        mut on_event: impl FnMut(&ProtocolEvent),
+    ) -> Result<String, ClientError> {
+        let mut reply = String::new();
+        let collected = tokio::time::timeout(timeout, async {
+            loop {
+                let Some(event) = self.reader.next_live_event().await? else {
+                    return Err(ClientError::Disconnected);
+                };
+                on_event(&event);
+                match apply_wait_event(&event, id.channel.as_deref(), &mut reply) {
+                    Some(WaitOutcome::Done) => return Ok(()),
+                    Some(WaitOutcome::Failed(reason)) => {
+                        return Err(ClientError::Agent { reason, partial: std::mem::take(&mut reply) });
+                    }
+                    None => {}
+                }
+            }
+        })
+        .await;
+        match collected {
+            Ok(Ok(())) => Ok(reply),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(ClientError::Timeout(timeout)),
+        }
+    }
+
+    pub async fn next_event(&mut self) -> io::Result<Option<ProtocolEvent>> {
+        self.reader.next_event().await
+    }
+
+    /// Every event from now on, backlog replay included, until the connection closes.
+    pub fn subscribe(self) -> impl Stream<Item = ProtocolEvent> {
+        futures_util::stream::unfold(self.reader, |mut reader| async move {
+            match reader.next_event().await {
+                Ok(Some(event)) => Some((event, reader)),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn into_split(self) -> (EventReader<S>, EventWriter<S>) {
+        (self.reader, self.writer)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> EventReader<S> {
+    async fn next_raw(&mut self) -> io::Result<Option<(String, bool)>> {
+        if let Some(entry) = self.pending.pop_front() {
+            return Ok(Some(entry));
+        }
+        let Some(line) = self.lines.next_line().await? else { return Ok(None) };
+        let live = self.sync_done;
+        if !live && matches!(serde_json::from_str::<ProtocolEvent>(&line), Ok(ProtocolEvent::BridgeSyncDone {})) {
+            self.sync_done = true;
+        }
+        Ok(Some((line, live)))
+    }
+
+    /// The next raw line, including lines that are not valid events.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.next_raw().await?.map(|(line, _)| line))
+    }
+
+    pub async fn next_event(&mut self) -> io::Result<Option<ProtocolEvent>> {
+        while let Some(line) = self.next_line().await? {
+            if let Ok(event) = serde_json::from_str(&line) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The next event after the backlog replay; replayed events are skipped.
+    pub async fn next_live_event(&mut self) -> io::Result<Option<ProtocolEvent>> {
+        while let Some((line, live)) = self.next_raw().await? {
+            if !live {
+                continue;
+            }
+            if let Ok(event) = serde_json::from_str(&line) {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The backlog replay up to `BridgeSyncDone`, leaving the live events after it unread.
+    /// If no marker arrives (older bridges) the replay is taken as complete after
+    /// `idle_timeout` of silence, or when the connection closes.
+    pub async fn read_initial_sync(&mut self, idle_timeout: Duration) -> io::Result<Vec<(String, ProtocolEvent)>> {
+        let mut events = Vec::new();
+        loop {
+            let (line, live) = match tokio::time::timeout(idle_timeout, self.next_raw()).await {
+                Ok(Ok(Some(entry))) => entry,
+                Ok(Ok(None)) => break,
+                Ok(Err(e)) => return Err(e),
+                Err(_) => {
+                    tracing::debug!("no BridgeSyncDone within {:?}; treating the backlog as complete", idle_timeout);
+                    break;
+                }
+            };
+            if live {
+                self.pending.push_front((line, live));
+                break;
+            }
+            let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) else { continue };
+            if matches!(event, ProtocolEvent::BridgeSyncDone {}) {
+                break;
+            }
+            events.push((line, event));
+        }
+        Ok(events)
+    }
+
+    async fn wait_for_ack(&mut self) -> Result<RequestId, ClientError> {
+        loop {
+            let Some(line) = self.lines.next_line().await? else {
+                return Err(ClientError::Disconnected);
+            };
+            if let Ok(ProtocolEvent::PromptAck { channel, seq }) = serde_json::from_str::<ProtocolEvent>(&line) {
+                return Ok(RequestId { channel, seq });
+            }
+            let live = self.sync_done;
+            if !live && matches!(serde_json::from_str::<ProtocolEvent>(&line), Ok(ProtocolEvent::BridgeSyncDone {})) {
+                self.sync_done = true;
+            }
+            self.pending.push_back((line, live));
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> EventWriter<S> {
+    pub async fn send(&mut self, event: &ProtocolEvent) -> io::Result<()> {
+        let line = format!("{}\n", serde_json::to_string(event)?);
+        self.writer.write_all(line.as_bytes()).await
+    }
+
+    pub async fn shutdown(&mut self) -> io::Result<()> {
+        self.writer.shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::StreamExt;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, DuplexStream};
+
+    fn jsonl(events: &[ProtocolEvent]) -> String {
+        events.iter().map(|e| format!("{}\n", serde_json::to_string(e).unwrap())).collect()
+    }
+
+    fn chunk(text: &str, channel: &str) -> ProtocolEvent {
+        ProtocolEvent::AgentChunk { chunk: text.into(), channel: Some(channel.into()) }
+    }
+
+    fn done(channel: &str) -> ProtocolEvent {
+        ProtocolEvent::AgentDone { channel: Some(channel.into()), provider: None, model: None }
+    }
+
+    /// A client connected to an in-memory "bridge" whose side of the pipe is returned,
+    /// with the client's Hello already read off it.
+    async fn connected() -> (AcommClient<DuplexStream>, BufReader<DuplexStream>) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let client = AcommClient::from_stream(client).await.unwrap();
+        let mut server = BufReader::new(server);
+        let mut hello = String::new();
+        server.read_line(&mut hello).await.unwrap();
+        assert!(hello.contains("\"Hello\""));
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn publish_returns_the_ack_and_keeps_events_read_before_it() {
+        let (mut client, mut server) = connected().await;
+        let events = jsonl(&[
+            chunk("old", "mytool"),
+            ProtocolEvent::BridgeSyncDone {},
+            chunk("live", "tui"),
+            ProtocolEvent::PromptAck { channel: Some("mytool".into()), seq: 1 },
+        ]);
+        server.get_mut().write_all(events.as_bytes()).await.unwrap();
+
+        let id = client
+            .publish(ProtocolEvent::Prompt { text: "hi".into(), provider: None, channel: Some("mytool".into()), reply_to: None, priority: false, author: None })
+            .await
+            .unwrap();
+        assert_eq!(id, RequestId { channel: Some("mytool".into()), seq: 1 });
+        let mut sent = String::new();
+        server.read_line(&mut sent).await.unwrap();
+        assert!(sent.contains("\"text\":\"hi\""));
+        // The events before the ack are still handed out, in order.
+        assert!(matches!(client.next_event().await.unwrap(), Some(ProtocolEvent::AgentChunk { chunk, .. }) if chunk == "old"));
+        assert!(matches!(client.next_event().await.unwrap(), Some(ProtocolEvent::BridgeSyncDone {})));
+        assert!(matches!(client.next_event().await.unwrap(), Some(ProtocolEvent::AgentChunk { chunk, .. }) if chunk == "live"));
+    }
+
+    #[tokio::test]
+    async fn wait_for_reply_ignores_backlog_and_other_channels() {
+        let (mut client, mut server) = connected().await;
+        let events = jsonl(&[
+            chunk("stale answer", "mytool"),
+            done("mytool"),
+            ProtocolEvent::BridgeSyncDone {},
+            ProtocolEvent::PromptAck { channel: Some("mytool".into()), seq: 1 },
+            chunk("Hello, ", "mytool"),
+            chunk("other", "tui"),
+            done("tui"),
+            chunk("world", "mytool"),
+            done("mytool"),
+        ]);
+        server.get_mut().write_all(events.as_bytes()).await.unwrap();
+
+        let id = client
+            .publish(ProtocolEvent::Prompt { text: "hi".into(), provider: None, channel: Some("mytool".into()), reply_to: None, priority: false, author: None })
+            .await
+            .unwrap();
+        let reply = client.wait_for_reply(&id, Duration::from_secs(2)).await.unwrap();
+        assert_eq!(reply, "Hello, world");
+    }
+
+    #[tokio::test]
+    async fn wait_for_reply_reports_failures_timeouts_and_disconnects() {
+        let id = RequestId { channel: Some("cli".into()), seq: 1 };
+
+        let (mut client, mut server) = connected().await;
+        let failure = ProtocolEvent::SystemMessage {
+            msg: format!("{}: quota exceeded", AGENT_FAILURE_PREFIX),
+            channel: Some("cli".into()),
+        };
+        let events = jsonl(&[ProtocolEvent::BridgeSyncDone {}, chunk("partial ", "cli"), failure]);
+        server.get_mut().write_all(events.as_bytes()).await.unwrap();
+        let err = client.wait_for_reply(&id, Duration::from_secs(2)).await.unwrap_err();
+        assert!(matches!(err, ClientError::Agent { ref reason, ref partial } if reason.contains("quota") && partial == "partial "));
+
+        let (mut client, _server) = connected().await;
+        let err = client.wait_for_reply(&id, Duration::from_millis(50)).await.unwrap_err();
+        assert!(matches!(err, ClientError::Timeout(_)));
+
+        let (mut client, server) = connected().await;
+        drop(server);
+        let err = client.wait_for_reply(&id, Duration::from_secs(2)).await.unwrap_err();
+        assert!(matches!(err, ClientError::Disconnected));
+    }
+
+    #[tokio::test]
+    async fn command_adds_the_slash_and_uses_the_bridge_channel() {
+        let (mut client, mut server) = connected().await;
+        let ack = jsonl(&[ProtocolEvent::PromptAck { channel: Some("bridge".into()), seq: 1 }]);
+        server.get_mut().write_all(ack.as_bytes()).await.unwrap();
+        client.command("status").await.unwrap();
+        let mut sent = String::new();
+        server.read_line(&mut sent).await.unwrap();
+        let event: ProtocolEvent = serde_json::from_str(&sent).unwrap();
+        assert!(matches!(event, ProtocolEvent::Prompt { text, channel: Some(c), .. } if text == "/status" && c == "bridge"));
+        assert!(matches!(
+            client.publish(done("x")).await,
+            Err(ClientError::NotAPrompt)
+        ));
+    }
+
+    #[tokio::test]
+    async fn subscribe_streams_events_until_the_bridge_closes() {
+        let (client, mut server) = connected().await;
+        let events = jsonl(&[chunk("a", "tui"), done("tui")]);
+        server.get_mut().write_all(events.as_bytes()).await.unwrap();
+        server.get_mut().write_all(b"not json\n").await.unwrap();
+        drop(server);
+        let received: Vec<ProtocolEvent> = client.subscribe().collect().await;
+        assert_eq!(received.len(), 2);
+        assert!(matches!(&received[1], ProtocolEvent::AgentDone { .. }));
+    }
+
+    #[test]
+    fn wait_collects_only_its_own_channel_until_done() {
+        let mut reply = String::new();
+        let ch = Some("cli:wait:1");
+        assert_eq!(apply_wait_event(&chunk("Hello, ", "cli:wait:1"), ch, &mut reply), None);
+        assert_eq!(apply_wait_event(&chunk("other", "tui"), ch, &mut reply), None);
+        assert_eq!(apply_wait_event(&done("tui"), ch, &mut reply), None);
+        assert_eq!(apply_wait_event(&chunk("world", "cli:wait:1"), ch, &mut reply), None);
+        assert_eq!(apply_wait_event(&done("cli:wait:1"), ch, &mut reply), Some(WaitOutcome::Done));
+        assert_eq!(reply, "Hello, world");
+    }
+
+    #[test]
+    fn wait_reports_agent_failures() {
+        let mut reply = String::new();
+        let failure = ProtocolEvent::SystemMessage {
+            msg: format!("{}: quota exceeded", AGENT_FAILURE_PREFIX),
+            channel: Some("cli".into()),
+        };
+        assert!(matches!(apply_wait_event(&failure, Some("cli"), &mut reply), Some(WaitOutcome::Failed(m)) if m.contains("quota")));
+        let info = ProtocolEvent::SystemMessage { msg: "Switched to codex.".into(), channel: Some("cli".into()) };
+        assert_eq!(apply_wait_event(&info, Some("cli"), &mut reply), None);
+        let closed = ProtocolEvent::ChannelClosed { channel: Some("cli".into()), reason: "Agent task panicked".into() };
+        assert_eq!(apply_wait_event(&closed, Some("cli"), &mut reply), Some(WaitOutcome::Failed("Agent task panicked".into())));
+    }
+
+    #[tokio::test]
+    async fn initial_sync_stops_at_marker_while_a_channel_keeps_streaming() {
+        let (client, server) = connected().await;
+        let mut server = server.into_inner();
+        let backlog = jsonl(&[
+            ProtocolEvent::Prompt { text: "a".into(), provider: None, channel: Some("tui".into()), reply_to: None, priority: false, author: None },
+            ProtocolEvent::BridgeSyncDone {},
+        ]);
+        // Live chunks keep flowing after the marker.
+        let streaming = tokio::spawn(async move {
+            server.write_all(backlog.as_bytes()).await.unwrap();
+            let chunk = jsonl(&[chunk("x", "tui")]);
+            while server.write_all(chunk.as_bytes()).await.is_ok() {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        });
+        let (mut reader, _writer) = client.into_split();
+        let events = tokio::time::timeout(Duration::from_secs(2), reader.read_initial_sync(Duration::from_secs(30)))
+            .await
+            .expect("dump must not wait for live events")
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0].1, ProtocolEvent::Prompt { text, .. } if text == "a"));
+        streaming.abort();
+    }
+
+    #[tokio::test]
+    async fn initial_sync_without_marker_falls_back_to_idle_timeout() {
+        let (client, mut server) = connected().await;
+        server.get_mut().write_all(jsonl(&[done("tui")]).as_bytes()).await.unwrap();
+        // The server stays open (older bridges send no marker and go quiet).
+        let (mut reader, _writer) = client.into_split();
+        let events = reader.read_initial_sync(Duration::from_millis(50)).await.unwrap();
+        assert_eq!(events.len(), 1);
+        drop(server);
+    }
+
+    #[tokio::test]
+    async fn client_initial_sync_leaves_live_events_for_later_reads() {
+        let (client, mut server) = connected().await;
+        let events = jsonl(&[done("tui"), ProtocolEvent::BridgeSyncDone {}, chunk("live", "tui")]);
+        server.get_mut().write_all(events.as_bytes()).await.unwrap();
+        let (mut reader, _writer) = client.into_split();
+        let backlog = reader.read_initial_sync(Duration::from_secs(2)).await.unwrap();
+        assert_eq!(backlog.len(), 1);
+        assert!(matches!(reader.next_live_event().await.unwrap(), Some(ProtocolEvent::AgentChunk { chunk, .. }) if chunk == "live"));
+    }
+}
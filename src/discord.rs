@@ -11,6 +11,14 @@
  * Optional environment variables:
  *   DISCORD_ALLOWED_USER_IDS — comma-separated Discord user IDs to allow.
  *   If set, messages from other users are ignored.
+ *   DISCORD_TYPING_INTERVAL_SECS — typing indicator refresh interval in seconds
+ *   (default 8, clamped to 5–9 so the ~10s indicator never lapses).
+ *   DISCORD_STATUS_FORMAT — template for the status line appended to replies,
+ *   using `{provider}` / `{model}` (default `__{provider}:{model}__`, e.g.
+ *   `-# {provider}/{model}` for small text); `none` disables it, unless
+ *   ACOMM_REPLY_FOOTER=1 asks for the footer on every surface.
+ *   ACOMM_REPLY_BUFFER_MAX_BYTES / ACOMM_REPLY_BUFFER_TTL_SECS — cap and lifetime
+ *   of the per-channel reply buffers (see `reply_buffer`).
  *
  * Required bot intents (Gateway subscribe):
  *   GUILD_MESSAGES (1 << 9) = 512
@@ -19,7 +27,13 @@
  * Optional (for reading guild message content reliably):
  *   MESSAGE_CONTENT (1 << 15) = 32768
  */
-use crate::protocol::ProtocolEvent;
+use crate::format_profile::{FormatProfile, OutboundMessage, mark_partial_reply, parse_max_chars, reply_footer_from_env};
+use crate::protocol::{ActiveAgent, ProtocolEvent, final_answer, split_compare_channel};
+use crate::quiet_hours;
+use crate::rate_limit::{RateDecision, RateLimiter, rate_limited_message};
+use crate::redact::redact_output;
+use crate::reply_buffer::{REPLY_BUFFER_SWEEP_INTERVAL, ReplyBuffer, ReplyBufferLimits, expired_keys};
+use acomm::client::DEFAULT_SOCKET_PATH;
 use futures_util::{Sink, SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
@@ -31,13 +45,20 @@ use tokio::net::UnixStream;
 use tokio::time::Instant;
 use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, warn};
 
-const SOCKET_PATH: &str = "/tmp/acomm.sock";
 const DISCORD_GATEWAY_URL: &str = "wss://gateway.discord.gg/?v=10&encoding=json";
 const DISCORD_API_BASE: &str = "https://discord.com/api/v10";
+/// Default message length: a safety margin below Discord's 2000-char limit.
 const DISCORD_SAFE_MESSAGE_LIMIT: usize = 1900;
+/// Bounds for `DISCORD_MAX_CHARS`; bots cannot exceed the 2000-char limit.
+const DISCORD_MAX_CHARS_MIN: usize = 200;
+const DISCORD_MAX_CHARS_MAX: usize = 2000;
+/// Message flag that posts without a push/desktop notification.
+const DISCORD_SUPPRESS_NOTIFICATIONS_FLAG: u64 = 1 << 12;
 const DEFAULT_DISCORD_PROVIDER_NAME: &str = "gemini";
 const DEFAULT_DISCORD_MODEL_NAME: &str = "auto-gemini-3";
+const DEFAULT_DISCORD_STATUS_FORMAT: &str = "__{provider}:{model}__";
 
 /// Gateway opcodes
 const OP_DISPATCH: u64 = 0;
@@ -51,6 +72,8 @@ const DISCORD_PRESENCE_ONLINE: &str = "online";
 const DISCORD_PRESENCE_DND: &str = "dnd";
 const DISCORD_PRESENCE_INVISIBLE: &str = "invisible";
 const DISCORD_TYPING_REFRESH_SECS: u64 = 8;
+const DISCORD_TYPING_REFRESH_MIN_SECS: u64 = 5;
+const DISCORD_TYPING_REFRESH_MAX_SECS: u64 = 9;
 const DISCORD_TYPING_MAX_DURATION_SECS: u64 = 120;
 
 /// Gateway intents: GUILD_MESSAGES | DIRECT_MESSAGES
@@ -59,6 +82,15 @@ const DISCORD_TYPING_MAX_DURATION_SECS: u64 = 120;
 /// enabling the privileged intent. DM text content is still available.
 const GATEWAY_INTENTS: u64 = (1 << 9) | (1 << 12);
 
+/// Interaction type of a slash-command invocation.
+const INTERACTION_APPLICATION_COMMAND: u64 = 2;
+/// Interaction callback type: reply with a message.
+const INTERACTION_CHANNEL_MESSAGE_WITH_SOURCE: u64 = 4;
+/// Message flag: only the invoking user sees the reply.
+const DISCORD_EPHEMERAL_FLAG: u64 = 1 << 6;
+/// Providers offered as choices by the `/provider` application command.
+const DISCORD_PROVIDER_CHOICES: &[&str] = &["gemini", "claude", "codex", "opencode"];
+
 #[derive(Debug, Serialize, Deserialize)]
 struct GatewayPayload {
     op: u64,
@@ -77,6 +109,15 @@ pub struct DiscordMessage {
     pub channel_id: String,
     pub content: String,
     pub author: DiscordUser,
+    /// The message this one replies to (null/absent unless it is a reply).
+    #[serde(default)]
+    pub referenced_message: Option<DiscordMessageRef>,
+}
+
+/// Just the id of a `referenced_message`; the rest of the embedded message is ignored.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiscordMessageRef {
+    pub id: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -111,9 +152,11 @@ struct DiscordLogMessage {
 
 #[derive(Debug, Clone)]
 struct DiscordReplyBuffer {
-    content: String,
+    content: ReplyBuffer,
     provider: String,
     model: String,
+    /// Message to answer as a Discord reply, set when the prompt was itself a reply.
+    reply_to: Option<String>,
 }
 
 fn build_identify_payload(token: &str) -> GatewayPayload {
@@ -170,9 +213,23 @@ fn discord_typing_max_duration() -> Duration {
     Duration::from_secs(DISCORD_TYPING_MAX_DURATION_SECS)
 }
 
+/// Parse a typing refresh interval, clamped to the window where the indicator stays continuous.
+fn parse_discord_typing_interval(raw: Option<&str>) -> Duration {
+    let secs = raw
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(DISCORD_TYPING_REFRESH_SECS)
+        .clamp(DISCORD_TYPING_REFRESH_MIN_SECS, DISCORD_TYPING_REFRESH_MAX_SECS);
+    Duration::from_secs(secs)
+}
+
+fn load_discord_typing_interval_from_env() -> Duration {
+    parse_discord_typing_interval(std::env::var("DISCORD_TYPING_INTERVAL_SECS").ok().as_deref())
+}
+
 fn discord_event_requests_typing_stop(event: &ProtocolEvent, channel: &str) -> bool {
     match event {
-        ProtocolEvent::AgentDone { channel: Some(ch) } => ch == channel,
+        ProtocolEvent::AgentDone { channel: Some(ch), .. }
+        | ProtocolEvent::ChannelClosed { channel: Some(ch), .. } => ch == channel,
         ProtocolEvent::StatusUpdate {
             is_processing: false,
             channel: Some(ch),
@@ -287,6 +344,7 @@ fn default_model_for_provider_name(provider_name: &str) -> Option<&'static str>
         "codex" => Some("gpt-5.3-codex"),
         "dummy" => Some("echo"),
         "mock" => Some("mock-model"),
+        "ollama" => Some("llama3"),
         _ => None,
     }
 }
@@ -299,24 +357,104 @@ fn discord_channel_id_from_bridge_channel(channel: &str) -> Option<&str> {
     }
 }
 
-fn truncate_for_discord(content: &str) -> String {
+/// The user's message id in a `discord:<channel_id>:<message_id>` bridge channel.
+fn discord_message_id_from_bridge_channel(channel: &str) -> Option<&str> {
+    match channel.splitn(3, ':').collect::<Vec<_>>().as_slice() {
+        ["discord", _, message_id] if !message_id.is_empty() => Some(message_id),
+        _ => None,
+    }
+}
+
+/// Heading for the reply of one `/compare` stream (`discord:<channel>:<message>#<provider>`),
+/// so the per-provider replies can be told apart; `None` for ordinary channels.
+fn compare_reply_label(channel: &str) -> Option<String> {
+    split_compare_channel(channel).map(|(_, provider)| format!("**[{}]**\n", provider.command_name()))
+}
+
+fn truncate_for_discord(content: &str, max_chars: usize) -> String {
     let trimmed = content.trim_end();
-    if trimmed.chars().count() <= DISCORD_SAFE_MESSAGE_LIMIT {
+    if trimmed.chars().count() <= max_chars {
         return trimmed.to_string();
     }
 
-    let mut out = String::new();
-    for (idx, ch) in trimmed.chars().enumerate() {
-        if idx >= DISCORD_SAFE_MESSAGE_LIMIT.saturating_sub(1) {
-            break;
-        }
-        out.push(ch);
+    discord_head(trimmed, max_chars)
+}
+
+/// Message length from `DISCORD_MAX_CHARS`, defaulting to `DISCORD_SAFE_MESSAGE_LIMIT`.
+fn load_discord_max_chars_from_env() -> usize {
+    let (max_chars, warning) = parse_max_chars(
+        "DISCORD_MAX_CHARS",
+        std::env::var("DISCORD_MAX_CHARS").ok().as_deref(),
+        DISCORD_SAFE_MESSAGE_LIMIT,
+        DISCORD_MAX_CHARS_MIN,
+        DISCORD_MAX_CHARS_MAX,
+    );
+    if let Some(warning) = warning {
+        warn!("{}", warning);
+    }
+    max_chars
+}
+
+/// `None` when `DISCORD_STATUS_FORMAT=none`; unset or blank uses the default template.
+fn parse_discord_status_format(raw: Option<&str>) -> Option<String> {
+    match raw.map(str::trim) {
+        None | Some("") => Some(DEFAULT_DISCORD_STATUS_FORMAT.to_string()),
+        Some(v) if v.eq_ignore_ascii_case("none") => None,
+        Some(v) => Some(v.to_string()),
+    }
+}
+
+/// The status line is Discord's rendering of `reply_footer`, so `ACOMM_REPLY_FOOTER=1`
+/// brings back the default one when `DISCORD_STATUS_FORMAT=none`.
+fn load_discord_status_format_from_env() -> Option<String> {
+    parse_discord_status_format(std::env::var("DISCORD_STATUS_FORMAT").ok().as_deref())
+        .or_else(|| reply_footer_from_env().then(|| DEFAULT_DISCORD_STATUS_FORMAT.to_string()))
+}
+
+/// Discord replies: the final answer extracted from the agent's output, followed by
+/// the status line, kept within `max_chars`.
+struct DiscordProfile {
+    status_format: Option<String>,
+    max_chars: usize,
+}
+
+impl DiscordProfile {
+    fn from_env() -> Self {
+        Self { status_format: load_discord_status_format_from_env(), max_chars: load_discord_max_chars_from_env() }
+    }
+
+    /// Bridge notices are sent whole (no answer extraction) with the same status line.
+    fn format_system_message(&self, msg: &str, provider: &str, model: &str) -> OutboundMessage {
+        let body = format_discord_agent_reply_with_status(msg, provider, model, self.status_format.as_deref(), self.max_chars);
+        OutboundMessage { body, ..Default::default() }
+    }
+
+    /// Like `format_reply`, but marks the answer as cut short. The note is added after
+    /// answer extraction so it is never mistaken for the final paragraph.
+    fn format_partial_reply(&self, content: &str, provider: &str, model: &str) -> Vec<OutboundMessage> {
+        let answer = mark_partial_reply(&extract_discord_answer(content, self.max_chars));
+        let body = format_discord_agent_reply_with_status(&answer, provider, model, self.status_format.as_deref(), self.max_chars);
+        vec![OutboundMessage { body, ..Default::default() }]
     }
-    out.push('…');
-    out
 }
 
-fn format_discord_agent_reply_with_status(content: &str, provider: &str, model: &str) -> String {
+impl FormatProfile for DiscordProfile {
+    fn format_reply(&self, content: &str, provider: &str, model: &str) -> Vec<OutboundMessage> {
+        let answer = extract_discord_answer(content, self.max_chars);
+        let body = format_discord_agent_reply_with_status(&answer, provider, model, self.status_format.as_deref(), self.max_chars);
+        vec![OutboundMessage { body, ..Default::default() }]
+    }
+}
+
+/// Append the rendered status line (`status_format`, `None` = no status line) to a
+/// reply, truncating the body so the whole message stays within `max_chars`.
+fn format_discord_agent_reply_with_status(
+    content: &str,
+    provider: &str,
+    model: &str,
+    status_format: Option<&str>,
+    max_chars: usize,
+) -> String {
     let provider = provider.trim();
     let provider = if provider.is_empty() {
         DEFAULT_DISCORD_PROVIDER_NAME
@@ -330,34 +468,31 @@ fn format_discord_agent_reply_with_status(content: &str, provider: &str, model:
         model
     };
 
-    let suffix = format!("__{}:{}__", provider, model);
     let body = content.trim_end();
+    let Some(status_format) = status_format else {
+        return if body.is_empty() { "…".to_string() } else { truncate_for_discord(body, max_chars) };
+    };
+    let suffix = status_format
+        .replace("{provider}", provider)
+        .replace("{model}", model);
     if body.is_empty() {
-        return truncate_for_discord(&suffix);
+        return truncate_for_discord(&suffix, max_chars);
     }
 
     let separator = "\n\n";
     let reserved = suffix.chars().count() + separator.chars().count();
-    if reserved >= DISCORD_SAFE_MESSAGE_LIMIT {
-        return truncate_for_discord(&suffix);
+    if reserved >= max_chars {
+        return truncate_for_discord(&suffix, max_chars);
     }
 
-    let body_budget = DISCORD_SAFE_MESSAGE_LIMIT - reserved;
+    let body_budget = max_chars - reserved;
     let body_chars = body.chars().count();
     let body_part = if body_chars <= body_budget {
         body.to_string()
     } else if body_budget <= 1 {
         "…".to_string()
     } else {
-        let mut truncated = String::new();
-        for (idx, ch) in body.chars().enumerate() {
-            if idx >= body_budget - 1 {
-                break;
-            }
-            truncated.push(ch);
-        }
-        truncated.push('…');
-        truncated
+        discord_head(body, body_budget)
     };
 
     format!("{body_part}{separator}{suffix}")
@@ -368,12 +503,15 @@ fn format_discord_agent_reply_with_status(content: &str, provider: &str, model:
 /// Required environment variables:
 ///   DISCORD_BOT_TOKEN         — bot token
 ///   DISCORD_NOTIFY_CHANNEL_ID — target channel ID for agent-initiated messages
+///
+/// During `ACOMM_QUIET_HOURS` the message is posted silently (no push/desktop ping).
 pub async fn notify_discord(text: &str) -> Result<(), Box<dyn Error>> {
     let token = std::env::var("DISCORD_BOT_TOKEN")
         .map_err(|_| "DISCORD_BOT_TOKEN environment variable not set")?;
     let channel_id = std::env::var("DISCORD_NOTIFY_CHANNEL_ID")
         .map_err(|_| "DISCORD_NOTIFY_CHANNEL_ID environment variable not set")?;
-    send_discord_message(&token, &channel_id, text).await
+    let text = final_answer(text).unwrap_or(text);
+    send_discord_message(&token, &channel_id, text, quiet_hours::quiet_now(), None, load_discord_max_chars_from_env()).await
 }
 
 pub async fn fetch_recent_discord_messages(
@@ -409,33 +547,36 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
     let token = std::env::var("DISCORD_BOT_TOKEN")
         .map_err(|_| "DISCORD_BOT_TOKEN environment variable not set")?;
     let allowed_user_ids = load_allowed_discord_user_ids_from_env();
+    let typing_interval = load_discord_typing_interval_from_env();
+    let profile = DiscordProfile::from_env();
+    let mut rate_limiter = RateLimiter::from_env();
+    let buffer_limits = ReplyBufferLimits::from_env();
 
-    println!("Discord adapter starting...");
+    info!("Discord adapter starting...");
     if let Some(ids) = &allowed_user_ids {
-        println!("Discord author allowlist enabled: {} user id(s)", ids.len());
+        info!("Discord author allowlist enabled: {} user id(s)", ids.len());
     }
 
-    let bridge_stream = UnixStream::connect(SOCKET_PATH).await.map_err(|e| {
+    let bridge_stream = UnixStream::connect(DEFAULT_SOCKET_PATH).await.map_err(|e| {
         format!(
             "Bridge is not running. Please start it with 'acomm --bridge'. Error: {}",
             e
         )
     })?;
-    println!("Connected to acomm bridge.");
+    info!("Connected to acomm bridge.");
     let (bridge_reader, mut bridge_writer) = tokio::io::split(bridge_stream);
     let mut bridge_lines = BufReader::new(bridge_reader).lines();
 
-    println!("Connecting to Discord Gateway: {}...", DISCORD_GATEWAY_URL);
+    info!("Connecting to Discord Gateway: {}...", DISCORD_GATEWAY_URL);
     let (ws_stream, _) = connect_async(DISCORD_GATEWAY_URL).await?;
     let (mut ws_sink, mut ws_stream) = ws_stream.split();
 
-    println!("Connected to Discord Gateway.");
+    info!("Connected to Discord Gateway.");
 
     let mut heartbeat_interval_ms: u64 = 41250; // default fallback
     let mut sequence: Option<u64> = None;
     let mut bot_user_id: Option<String> = None;
-    let mut active_provider_name = DEFAULT_DISCORD_PROVIDER_NAME.to_string();
-    let mut active_model_name = DEFAULT_DISCORD_MODEL_NAME.to_string();
+    let mut active = ActiveAgent::new(DEFAULT_DISCORD_PROVIDER_NAME, DEFAULT_DISCORD_MODEL_NAME);
     let mut reply_buffers: HashMap<String, DiscordReplyBuffer> = HashMap::new();
     let mut typing_tasks: HashMap<String, tokio::task::JoinHandle<()>> = HashMap::new();
     let mut bridge_sync_done = false;
@@ -446,6 +587,7 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
     let mut heartbeat_ticker: Option<tokio::time::Interval> = None;
     let mut heartbeat_ack_pending = false;
     let mut last_heartbeat_sent_at: Option<Instant> = None;
+    let mut buffer_sweep = tokio::time::interval(REPLY_BUFFER_SWEEP_INTERVAL);
 
     loop {
         tokio::select! {
@@ -492,7 +634,7 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                         // Send IDENTIFY
                         let identify = build_identify_payload(&token);
                         send_discord_gateway_payload(&mut ws_sink, &identify).await?;
-                        println!("Sent IDENTIFY to Discord Gateway.");
+                        info!("Sent IDENTIFY to Discord Gateway.");
                     }
                     OP_HEARTBEAT_ACK => {
                         // Heartbeat acknowledged — connection is healthy.
@@ -516,14 +658,50 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                                 if let Some(d) = &payload.d {
                                     if let Some(uid) = d["user"]["id"].as_str() {
                                         bot_user_id = Some(uid.to_string());
-                                        println!("Discord READY. Bot user id: {}", uid);
+                                        info!("Discord READY. Bot user id: {}", uid);
+                                    }
+                                    if let Some(app_id) = d["application"]["id"].as_str() {
+                                        match register_discord_commands(&token, app_id).await {
+                                            Ok(()) => info!("Discord /provider command registered."),
+                                            Err(e) => warn!("Discord command registration failed: {}", e),
+                                        }
                                     }
                                 }
                                 let presence = build_presence_update_payload(DISCORD_PRESENCE_ONLINE);
                                 send_discord_gateway_payload(&mut ws_sink, &presence).await?;
                                 discord_gateway_ready = true;
                                 discord_presence_status = DISCORD_PRESENCE_ONLINE.to_string();
-                                println!("Discord presence set to {}.", DISCORD_PRESENCE_ONLINE);
+                                info!("Discord presence set to {}.", DISCORD_PRESENCE_ONLINE);
+                            }
+                            Some("INTERACTION_CREATE") => {
+                                let Some(interaction) = payload.d.as_ref().and_then(parse_provider_interaction) else {
+                                    continue;
+                                };
+                                let allowed = allowed_user_ids
+                                    .as_ref()
+                                    .is_none_or(|ids| ids.contains(&interaction.user_id));
+                                let reply = if allowed {
+                                    format!("Switching this channel to {}…", interaction.provider)
+                                } else {
+                                    info!("Ignoring Discord /provider from non-allowed user: {}", interaction.user_id);
+                                    "You are not allowed to use this bot.".to_string()
+                                };
+                                if let Err(e) = respond_to_discord_interaction(&interaction, &reply).await {
+                                    warn!("Discord interaction response failed: {}", e);
+                                }
+                                if allowed {
+                                    // Unlike the `p-<provider>` magic words this only switches the
+                                    // invoking channel; the bridge confirms there.
+                                    let event = transform_discord_message(
+                                        &interaction.bridge_command(),
+                                        &interaction.channel_id,
+                                        &interaction.id,
+                                        None,
+                                        Some(&interaction.user_id),
+                                    );
+                                    let j = serde_json::to_string(&event)?;
+                                    bridge_writer.write_all(format!("{}\n", j).as_bytes()).await?;
+                                }
                             }
                             Some("MESSAGE_CREATE") => {
                                 if let Some(d) = &payload.d {
@@ -538,18 +716,34 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                                             allowed_user_ids.as_ref(),
                                         ) {
                                             if !is_allowed_sender && !msg.author.bot.unwrap_or(false) {
-                                                println!(
+                                                info!(
                                                     "Ignoring Discord message from non-allowed user: {} ({})",
                                                     msg.author.username, msg.author.id
                                                 );
                                             }
                                             continue;
                                         }
+                                        if let Some(limiter) = rate_limiter.as_mut() {
+                                            if let RateDecision::Throttled { retry_after, notify } =
+                                                limiter.check(&msg.author.id, std::time::Instant::now())
+                                            {
+                                                info!("Rate limited Discord user {} ({})", msg.author.username, msg.author.id);
+                                                if notify {
+                                                    let notice = rate_limited_message(retry_after);
+                                                    if let Err(e) = send_discord_message(&token, &msg.channel_id, &notice, false, None, profile.max_chars).await {
+                                                        warn!("Discord rate limit notice failed: {}", e);
+                                                    }
+                                                }
+                                                continue;
+                                            }
+                                        }
 
                                         let event = transform_discord_message(
                                             &msg.content,
                                             &msg.channel_id,
                                             &msg.id,
+                                            msg.referenced_message.as_ref().map(|m| m.id.as_str()),
+                                            Some(&msg.author.id),
                                         );
                                         let j = serde_json::to_string(&event)?;
                                         bridge_writer.write_all(format!("{}\n", j).as_bytes()).await?;
@@ -581,15 +775,49 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                 ).await?;
             }
 
+            // Drop reply buffers whose AgentDone never arrived.
+            _ = buffer_sweep.tick() => {
+                let expired = expired_keys(
+                    reply_buffers.iter().map(|(ch, buf)| (ch, &buf.content)),
+                    buffer_limits.ttl,
+                    std::time::Instant::now(),
+                );
+                for ch in expired {
+                    warn!(
+                        "Dropping the Discord reply buffer for {} after {}s without AgentDone",
+                        ch,
+                        buffer_limits.ttl.as_secs()
+                    );
+                    reply_buffers.remove(&ch);
+                    if let Some(handle) = typing_tasks.remove(&ch) {
+                        handle.abort();
+                    }
+                }
+                if discord_gateway_ready
+                    && reply_buffers.is_empty()
+                    && discord_presence_status != DISCORD_PRESENCE_ONLINE
+                {
+                    let presence = build_presence_update_payload(DISCORD_PRESENCE_ONLINE);
+                    send_discord_gateway_payload(&mut ws_sink, &presence).await?;
+                    discord_presence_status = DISCORD_PRESENCE_ONLINE.to_string();
+                    info!("Discord presence set to {}.", DISCORD_PRESENCE_ONLINE);
+                }
+            }
+
             // Bridge protocol events
             line_res = bridge_lines.next_line() => {
                 let line = match line_res? {
                     Some(l) => l,
                     None => {
+                        for (discord_channel_id, body) in partial_reply_flush_messages(&reply_buffers, &profile) {
+                            if let Err(e) = send_discord_message(&token, &discord_channel_id, &body, false, None, profile.max_chars).await {
+                                warn!("Discord partial reply delivery failed: {}", e);
+                            }
+                        }
                         if discord_gateway_ready {
                             let presence = build_presence_update_payload(DISCORD_PRESENCE_INVISIBLE);
                             let _ = send_discord_gateway_payload(&mut ws_sink, &presence).await;
-                            println!(
+                            info!(
                                 "Discord presence set to {} before adapter shutdown.",
                                 DISCORD_PRESENCE_INVISIBLE
                             );
@@ -598,47 +826,34 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                     }
                 };
                 if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
-                    if let ProtocolEvent::ProviderSwitched { ref provider } = event {
-                        active_provider_name = provider.command_name().to_string();
-                        if let Some(model) = default_model_for_provider_name(&active_provider_name) {
-                            active_model_name = model.to_string();
-                        }
-                    }
-                    if let ProtocolEvent::ModelSwitched { ref model } = event {
-                        active_model_name = model.clone();
-                    }
+                    active.observe(&event);
                     if !bridge_sync_done {
                         if matches!(event, ProtocolEvent::BridgeSyncDone { .. }) {
                             bridge_sync_done = true;
-                            println!("Bridge initial sync complete (backlog ignored for Discord outbound replay safety).");
+                            info!("Bridge initial sync complete (backlog ignored for Discord outbound replay safety).");
                         }
                         continue;
                     }
                     match event {
-                        ProtocolEvent::Prompt { provider, channel: Some(ref ch), .. }
+                        ProtocolEvent::Prompt { provider, channel: Some(ref ch), ref reply_to, .. }
                             if ch.starts_with("discord:") =>
                         {
                             let should_switch_presence_to_dnd = reply_buffers.is_empty()
                                 && discord_gateway_ready
                                 && discord_presence_status != DISCORD_PRESENCE_DND;
                             let key = ch.to_string();
-                            let provider_name = provider
-                                .as_ref()
-                                .map(|p| p.command_name().to_string())
-                                .unwrap_or_else(|| active_provider_name.clone());
-                            let model_name = if active_model_name.trim().is_empty() {
-                                default_model_for_provider_name(&provider_name)
-                                    .unwrap_or("unknown")
-                                    .to_string()
-                            } else {
-                                active_model_name.clone()
-                            };
+                            let agent = active.for_prompt(provider.as_ref());
                             reply_buffers.insert(
                                 key.clone(),
                                 DiscordReplyBuffer {
-                                    content: String::new(),
-                                    provider: provider_name,
-                                    model: model_name,
+                                    content: ReplyBuffer::new(&buffer_limits, std::time::Instant::now()),
+                                    provider: agent.provider,
+                                    model: agent.model,
+                                    // Answer threaded prompts as a reply so the conversation stays in the chain.
+                                    reply_to: reply_to
+                                        .as_ref()
+                                        .and_then(|_| discord_message_id_from_bridge_channel(ch))
+                                        .map(str::to_string),
                                 },
                             );
                             // Start typing indicator while agent processes.
@@ -651,7 +866,7 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                                             break;
                                         }
                                         let _ = trigger_discord_typing(&token_clone, &discord_channel_id).await;
-                                        tokio::time::sleep(Duration::from_secs(DISCORD_TYPING_REFRESH_SECS)).await;
+                                        tokio::time::sleep(typing_interval).await;
                                     }
                                 });
                                 if let Some(old) = typing_tasks.insert(key, handle) {
@@ -662,14 +877,14 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                                 let presence = build_presence_update_payload(DISCORD_PRESENCE_DND);
                                 send_discord_gateway_payload(&mut ws_sink, &presence).await?;
                                 discord_presence_status = DISCORD_PRESENCE_DND.to_string();
-                                println!("Discord presence set to {}.", DISCORD_PRESENCE_DND);
+                                info!("Discord presence set to {}.", DISCORD_PRESENCE_DND);
                             }
                         }
                         ProtocolEvent::AgentChunk { ref chunk, channel: Some(ref ch) }
                             if ch.starts_with("discord:") =>
                         {
                             if let Some(buf) = reply_buffers.get_mut(ch) {
-                                buf.content.push_str(chunk);
+                                buf.content.push(chunk);
                             }
                         }
                         ref ev if ev
@@ -688,20 +903,21 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                             if let Some(handle) = typing_tasks.remove(ch.as_str()) {
                                 handle.abort();
                             }
-                            if matches!(ev, ProtocolEvent::AgentDone { .. }) {
+                            if matches!(ev, ProtocolEvent::AgentDone { .. } | ProtocolEvent::ChannelClosed { .. }) {
                                 let key = ch.to_string();
                                 if let Some(buf) = reply_buffers.remove(&key) {
                                     if !buf.content.is_empty() {
-                                        let answer = extract_discord_answer(&buf.content);
-                                        let formatted = format_discord_agent_reply_with_status(
-                                            &answer,
-                                            &buf.provider,
-                                            &buf.model,
-                                        );
                                         if let Some(discord_channel_id) =
                                             discord_channel_id_from_bridge_channel(&ch)
                                         {
-                                            send_discord_message(&token, discord_channel_id, &formatted).await?;
+                                            let mut reply_to = buf.reply_to.as_deref();
+                                            // A fallback provider may have answered instead of the one the prompt started on.
+                                            let agent = ActiveAgent::new(&buf.provider, &buf.model).answered(ev);
+                                            let mut label = compare_reply_label(&ch);
+                                            for message in profile.format_reply(&buf.content.text(), &agent.provider, &agent.model) {
+                                                let body = format!("{}{}", label.take().unwrap_or_default(), message.body);
+                                                send_discord_message(&token, discord_channel_id, &body, false, reply_to.take(), profile.max_chars).await?;
+                                            }
                                         }
                                     }
                                 }
@@ -713,19 +929,15 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
                                 let presence = build_presence_update_payload(DISCORD_PRESENCE_ONLINE);
                                 send_discord_gateway_payload(&mut ws_sink, &presence).await?;
                                 discord_presence_status = DISCORD_PRESENCE_ONLINE.to_string();
-                                println!("Discord presence set to {}.", DISCORD_PRESENCE_ONLINE);
+                                info!("Discord presence set to {}.", DISCORD_PRESENCE_ONLINE);
                             }
                         }
                         ProtocolEvent::SystemMessage { msg, channel: Some(ref ch) }
                             if ch.starts_with("discord:") =>
                         {
                             if let Some(discord_channel_id) = discord_channel_id_from_bridge_channel(ch) {
-                                let formatted = format_discord_agent_reply_with_status(
-                                    &msg,
-                                    &active_provider_name,
-                                    &active_model_name,
-                                );
-                                send_discord_message(&token, discord_channel_id, &formatted).await?;
+                                let message = profile.format_system_message(&msg, &active.provider, &active.model);
+                                send_discord_message(&token, discord_channel_id, &message.body, false, None, profile.max_chars).await?;
                             }
                         }
                         _ => {}
@@ -738,22 +950,59 @@ pub async fn start_discord_adapter() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Replies still being collected when the bridge disconnected, marked as partial,
+/// as `(Discord channel id, message body)` in bridge-channel order.
+fn partial_reply_flush_messages(
+    reply_buffers: &HashMap<String, DiscordReplyBuffer>,
+    profile: &DiscordProfile,
+) -> Vec<(String, String)> {
+    let mut channels: Vec<&String> = reply_buffers
+        .iter()
+        .filter(|(_, buf)| !buf.content.text().trim().is_empty())
+        .map(|(ch, _)| ch)
+        .collect();
+    channels.sort();
+    let mut messages = Vec::new();
+    for ch in channels {
+        let Some(discord_channel_id) = discord_channel_id_from_bridge_channel(ch) else { continue };
+        let buf = &reply_buffers[ch];
+        for message in profile.format_partial_reply(&buf.content.text(), &buf.provider, &buf.model) {
+            messages.push((discord_channel_id.to_string(), message.body));
+        }
+    }
+    messages
+}
+
+/// Message body for the create-message endpoint; `silent` sets SUPPRESS_NOTIFICATIONS and
+/// `reply_to` posts it as a reply to that message. The content is cut to `max_chars`.
+fn discord_message_payload(content: &str, silent: bool, reply_to: Option<&str>, max_chars: usize) -> Value {
+    let mut payload = json!({ "content": truncate_for_discord(&redact_output(content), max_chars) });
+    if silent {
+        payload["flags"] = json!(DISCORD_SUPPRESS_NOTIFICATIONS_FLAG);
+    }
+    if let Some(message_id) = reply_to {
+        // Still post the answer if the original message was deleted meanwhile.
+        payload["message_reference"] = json!({ "message_id": message_id, "fail_if_not_exists": false });
+    }
+    payload
+}
+
 /// Send a message to a Discord channel via REST API.
 async fn send_discord_message(
     token: &str,
     channel_id: &str,
     content: &str,
+    silent: bool,
+    reply_to: Option<&str>,
+    max_chars: usize,
 ) -> Result<(), Box<dyn Error>> {
-    // Keep a safety margin below Discord's 2000-char limit and truncate by chars.
-    let truncated = truncate_for_discord(content);
-
     let client = reqwest::Client::new();
     let url = format!("{}/channels/{}/messages", DISCORD_API_BASE, channel_id);
     let response = client
         .post(&url)
         .header("Authorization", format!("Bot {}", token))
         .header("Content-Type", "application/json")
-        .json(&json!({ "content": truncated }))
+        .json(&discord_message_payload(content, silent, reply_to, max_chars))
         .send()
         .await?;
     let status = response.status();
@@ -838,59 +1087,268 @@ async fn trigger_discord_typing(token: &str, channel_id: &str) -> Result<(), Box
 /// Extract the final answer from an agent's full output for Discord delivery.
 ///
 /// Agent outputs include intermediate tool-call narration followed by the final
-/// answer. This function walks backwards through double-newline separators to find
-/// the last substantive paragraph (≥ 30 Unicode chars) that fits within Discord's
-/// 1900-char limit. Uses character counts (not byte lengths) so multi-byte Unicode
-/// is handled correctly. If no usable separator is found, the last 1899 chars are
-/// returned with a leading ellipsis.
-pub fn extract_discord_answer(content: &str) -> String {
-    const DISCORD_LIMIT: usize = 1900;
+/// answer. This function walks backwards through blank-line separators to find
+/// the last substantive paragraph (≥ 30 Unicode chars) that fits within `limit`
+/// (the adapter's `DISCORD_MAX_CHARS`). Uses character counts (not byte lengths) so multi-byte Unicode
+/// is handled correctly. If no usable separator is found, the last chars are
+/// returned with a leading ellipsis. Secrets are masked before the length checks.
+///
+/// When the executor delimited the answer with `FINAL_ANSWER_MARKER`, the text after
+/// it is used as is (cut to `limit` like any other answer) instead of guessing.
+///
+/// Blank lines inside fenced code blocks are not separators, so a code block stays
+/// with its paragraph, and the result never has an unbalanced ```` ``` ````: a cut
+/// inside a code block reopens it, and a block left open is closed.
+fn extract_discord_answer(content: &str, limit: usize) -> String {
+    let content = redact_output(content);
+    if let Some(answer) = final_answer(&content) {
+        return fit_fenced_for_discord(answer, limit);
+    }
     let trimmed = content.trim_end();
 
-    if trimmed.chars().count() <= DISCORD_LIMIT {
-        return trimmed.to_string();
+    if trimmed.chars().count() <= limit {
+        return fit_fenced_for_discord(trimmed, limit);
     }
 
-    // Walk backwards through double-newline separators to find the last
+    // Walk backwards through the separators to find the last
     // substantive block (≥ 30 chars) that fits within the Discord limit.
-    let mut search = trimmed;
-    while let Some(pos) = search.rfind("\n\n") {
-        let candidate = search[pos + 2..].trim();
-        let char_count = candidate.chars().count();
-        if char_count >= 30 {
-            if char_count <= DISCORD_LIMIT {
-                return candidate.to_string();
-            }
-            // Candidate itself too long — take the last (DISCORD_LIMIT - 1) chars.
-            let chars: Vec<char> = candidate.chars().collect();
-            let start = chars.len().saturating_sub(DISCORD_LIMIT - 1);
-            let truncated: String = chars[start..].iter().collect();
-            return format!("…{}", truncated);
+    let mut end = trimmed.len();
+    for pos in discord_paragraph_breaks(trimmed).into_iter().rev() {
+        let candidate = trimmed[(pos + 2).min(end)..end].trim();
+        if candidate.chars().count() >= 30 {
+            return fit_fenced_for_discord(candidate, limit);
         }
         // Candidate too short — look for an earlier separator.
-        search = &search[..pos];
+        end = pos;
     }
 
-    // No usable separator found — take the last (DISCORD_LIMIT - 1) chars.
-    let chars: Vec<char> = trimmed.chars().collect();
-    let start = chars.len().saturating_sub(DISCORD_LIMIT - 1);
-    let truncated: String = chars[start..].iter().collect();
-    format!("…{}", truncated)
+    // No usable separator found — take the end of the output.
+    discord_tail(trimmed, limit)
+}
+
+const DISCORD_FENCE: &str = "```";
+/// Inserted after the leading ellipsis when the cut falls inside a code block.
+const DISCORD_FENCE_REOPEN: &str = "\n```\n";
+/// Appended when the text ends inside a code block.
+const DISCORD_FENCE_CLOSE: &str = "\n```";
+
+/// Byte offsets of the lines that open or close a fenced code block.
+fn discord_fence_offsets(text: &str) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with(DISCORD_FENCE) {
+            offsets.push(offset);
+        }
+        offset += line.len();
+    }
+    offsets
+}
+
+/// Byte offsets of the `\n\n` separators that are outside fenced code blocks.
+fn discord_paragraph_breaks(text: &str) -> Vec<usize> {
+    let mut breaks = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with(DISCORD_FENCE) {
+            in_fence = !in_fence;
+        } else if line == "\n" && offset > 0 && !in_fence {
+            breaks.push(offset - 1);
+        }
+        offset += line.len();
+    }
+    breaks
+}
+
+/// `text` as is when it fits, closing a code block left open if there is room;
+/// otherwise its end via `discord_tail`.
+fn fit_fenced_for_discord(text: &str, limit: usize) -> String {
+    let chars = text.chars().count();
+    let open = discord_fence_offsets(text).len() % 2 == 1;
+    if !open && chars <= limit {
+        text.to_string()
+    } else if open && chars + DISCORD_FENCE_CLOSE.chars().count() <= limit {
+        format!("{text}{DISCORD_FENCE_CLOSE}")
+    } else {
+        discord_tail(text, limit)
+    }
+}
+
+/// The last chars of `text` behind an ellipsis, within `limit` chars and with
+/// balanced code fences.
+fn discord_tail(text: &str, limit: usize) -> String {
+    let fences = discord_fence_offsets(text);
+    // Where to cut so `budget` chars remain, and whether the kept text then
+    // starts inside a code block and/or ends inside one.
+    let cut_for = |budget: usize| {
+        let skip = text.chars().count().saturating_sub(budget);
+        let cut = text.char_indices().nth(skip).map_or(text.len(), |(i, _)| i);
+        let opened_before = fences.iter().filter(|&&start| start < cut).count();
+        let kept = fences.len() - opened_before;
+        (cut, opened_before % 2 == 1, (opened_before + kept) % 2 == 1)
+    };
+    let budget = limit.saturating_sub(1);
+    let (mut cut, mut reopen, mut close) = cut_for(budget);
+    if reopen || close {
+        let reserved = DISCORD_FENCE_REOPEN.chars().count() + DISCORD_FENCE_CLOSE.chars().count();
+        (cut, reopen, close) = cut_for(budget.saturating_sub(reserved));
+    }
+    let mut out = String::from("…");
+    if reopen {
+        out.push_str(DISCORD_FENCE_REOPEN);
+    }
+    out.push_str(&text[cut..]);
+    if close {
+        out.push_str(DISCORD_FENCE_CLOSE);
+    }
+    out
+}
+
+/// The first chars of `text` followed by an ellipsis, within `limit` chars. A cut
+/// inside a code block closes it after the ellipsis.
+fn discord_head(text: &str, limit: usize) -> String {
+    let fences = discord_fence_offsets(text);
+    let cut_for = |budget: usize| {
+        let cut = text.char_indices().nth(budget).map_or(text.len(), |(i, _)| i);
+        (cut, fences.iter().filter(|&&start| start < cut).count() % 2 == 1)
+    };
+    let budget = limit.saturating_sub(1);
+    let (mut cut, mut close) = cut_for(budget);
+    if close {
+        (cut, close) = cut_for(budget.saturating_sub(DISCORD_FENCE_CLOSE.chars().count()));
+    }
+    let mut out = format!("{}…", &text[..cut]);
+    if close {
+        out.push_str(DISCORD_FENCE_CLOSE);
+    }
+    out
+}
+
+/// Body for `PUT /applications/{app_id}/commands`: a global `/provider` command with
+/// one required string option limited to the known providers.
+fn provider_application_commands() -> Value {
+    let choices: Vec<Value> = DISCORD_PROVIDER_CHOICES
+        .iter()
+        .map(|name| json!({ "name": name, "value": name }))
+        .collect();
+    json!([{
+        "name": "provider",
+        "description": "Switch the agent that answers in this channel",
+        "type": 1,
+        "options": [{
+            "type": 3,
+            "name": "name",
+            "description": "Provider to use in this channel",
+            "required": true,
+            "choices": choices,
+        }],
+    }])
+}
+
+/// Register (overwrite) the bot's global application commands.
+async fn register_discord_commands(token: &str, app_id: &str) -> Result<(), Box<dyn Error>> {
+    let url = format!("{}/applications/{}/commands", DISCORD_API_BASE, app_id);
+    let response = reqwest::Client::new()
+        .put(&url)
+        .header("Authorization", format!("Bot {}", token))
+        .json(&provider_application_commands())
+        .send()
+        .await?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    validate_discord_api_response(status, &body, "Discord command registration")
+}
+
+/// A `/provider <name>` invocation from an `INTERACTION_CREATE` dispatch.
+#[derive(Debug, Clone, PartialEq)]
+struct ProviderInteraction {
+    id: String,
+    token: String,
+    channel_id: String,
+    user_id: String,
+    provider: String,
+}
+
+impl ProviderInteraction {
+    /// The bridge command applying the choice to the invoking channel only.
+    fn bridge_command(&self) -> String {
+        format!("/provider channel {}", self.provider)
+    }
+}
+
+/// Extract a `/provider` invocation. Guild interactions carry the user under
+/// `member.user`, DMs under `user`. Other commands and interaction types are ignored.
+fn parse_provider_interaction(d: &Value) -> Option<ProviderInteraction> {
+    if d["type"].as_u64() != Some(INTERACTION_APPLICATION_COMMAND) || d["data"]["name"] != "provider" {
+        return None;
+    }
+    let provider = d["data"]["options"]
+        .as_array()?
+        .iter()
+        .find(|option| option["name"] == "name")?["value"]
+        .as_str()?
+        .to_ascii_lowercase();
+    if !DISCORD_PROVIDER_CHOICES.contains(&provider.as_str()) {
+        return None;
+    }
+    let user_id = d["member"]["user"]["id"].as_str().or_else(|| d["user"]["id"].as_str())?;
+    Some(ProviderInteraction {
+        id: d["id"].as_str()?.to_string(),
+        token: d["token"].as_str()?.to_string(),
+        channel_id: d["channel_id"].as_str()?.to_string(),
+        user_id: user_id.to_string(),
+        provider,
+    })
+}
+
+/// Ephemeral reply payload for an interaction callback.
+fn ephemeral_interaction_response(content: &str) -> Value {
+    json!({
+        "type": INTERACTION_CHANNEL_MESSAGE_WITH_SOURCE,
+        "data": { "content": content, "flags": DISCORD_EPHEMERAL_FLAG },
+    })
+}
+
+/// Acknowledge an interaction; Discord requires this within 3 seconds.
+async fn respond_to_discord_interaction(
+    interaction: &ProviderInteraction,
+    content: &str,
+) -> Result<(), Box<dyn Error>> {
+    let url = format!(
+        "{}/interactions/{}/{}/callback",
+        DISCORD_API_BASE, interaction.id, interaction.token
+    );
+    let response = reqwest::Client::new()
+        .post(&url)
+        .json(&ephemeral_interaction_response(content))
+        .send()
+        .await?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    validate_discord_api_response(status, &body, "Discord interaction response")
 }
 
 /// Transform a Discord message event into a ProtocolEvent::Prompt for the bridge.
 ///
 /// Channel format: `discord:<channel_id>:<message_id>`
 /// This encodes both the channel (needed for replies) and the message id (for deduplication).
+/// `reply_to` is the id of the message this one replies to, if any.
+/// `author` is the sender's user id, which the bridge charges quotas to.
 pub fn transform_discord_message(
     content: &str,
     channel_id: &str,
     message_id: &str,
+    reply_to: Option<&str>,
+    author: Option<&str>,
 ) -> ProtocolEvent {
     ProtocolEvent::Prompt {
         text: content.to_string(),
         provider: None,
         channel: Some(format!("discord:{}:{}", channel_id, message_id)),
+        reply_to: reply_to.map(str::to_string),
+        priority: false,
+        author: author.map(str::to_string),
     }
 }
 
@@ -904,18 +1362,81 @@ pub fn format_discord_reply(content: &str) -> String {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_provider_application_command_lists_provider_choices() {
+        let commands = provider_application_commands();
+        assert_eq!(commands[0]["name"], "provider");
+        let option = &commands[0]["options"][0];
+        assert_eq!(option["required"], true);
+        let choices: Vec<&str> = option["choices"].as_array().unwrap().iter().map(|c| c["value"].as_str().unwrap()).collect();
+        assert_eq!(choices, DISCORD_PROVIDER_CHOICES);
+    }
+
+    #[test]
+    fn test_parse_provider_interaction_from_guild_and_dm() {
+        let guild = json!({
+            "id": "i1", "token": "tok", "type": 2, "channel_id": "c1",
+            "member": { "user": { "id": "u1" } },
+            "data": { "name": "provider", "options": [{ "name": "name", "type": 3, "value": "Claude" }] },
+        });
+        assert_eq!(
+            parse_provider_interaction(&guild),
+            Some(ProviderInteraction {
+                id: "i1".into(),
+                token: "tok".into(),
+                channel_id: "c1".into(),
+                user_id: "u1".into(),
+                provider: "claude".into(),
+            })
+        );
+
+        assert_eq!(parse_provider_interaction(&guild).unwrap().bridge_command(), "/provider channel claude");
+
+        let mut dm = guild.clone();
+        dm.as_object_mut().unwrap().remove("member");
+        dm["user"] = json!({ "id": "u2" });
+        assert_eq!(parse_provider_interaction(&dm).unwrap().user_id, "u2");
+    }
+
+    #[test]
+    fn test_parse_provider_interaction_ignores_other_interactions() {
+        let ping = json!({ "id": "i1", "token": "t", "type": 1, "channel_id": "c1" });
+        assert!(parse_provider_interaction(&ping).is_none());
+        let other = json!({
+            "id": "i1", "token": "t", "type": 2, "channel_id": "c1", "user": { "id": "u" },
+            "data": { "name": "model", "options": [{ "name": "name", "value": "x" }] },
+        });
+        assert!(parse_provider_interaction(&other).is_none());
+        let unknown = json!({
+            "id": "i1", "token": "t", "type": 2, "channel_id": "c1", "user": { "id": "u" },
+            "data": { "name": "provider", "options": [{ "name": "name", "value": "mock" }] },
+        });
+        assert!(parse_provider_interaction(&unknown).is_none());
+    }
+
+    #[test]
+    fn test_ephemeral_interaction_response_sets_flag() {
+        let payload = ephemeral_interaction_response("ok");
+        assert_eq!(payload["type"], 4);
+        assert_eq!(payload["data"]["flags"], 64);
+        assert_eq!(payload["data"]["content"], "ok");
+    }
+
     #[test]
     fn test_transform_discord_message() {
-        let event = transform_discord_message("Hello 執事！", "987654321", "111222333");
+        let event = transform_discord_message("Hello 執事！", "987654321", "111222333", None, None);
         if let ProtocolEvent::Prompt {
             text,
             channel,
             provider,
+            reply_to,
+            ..
         } = event
         {
             assert_eq!(text, "Hello 執事！");
             assert_eq!(channel, Some("discord:987654321:111222333".to_string()));
             assert!(provider.is_none());
+            assert!(reply_to.is_none());
         } else {
             panic!("Transform failed to produce a Prompt event");
         }
@@ -923,7 +1444,7 @@ mod tests {
 
     #[test]
     fn test_transform_discord_message_channel_prefix() {
-        let event = transform_discord_message("test", "ch123", "msg456");
+        let event = transform_discord_message("test", "ch123", "msg456", None, None);
         if let ProtocolEvent::Prompt { channel, .. } = event {
             let ch = channel.unwrap();
             assert!(
@@ -945,7 +1466,7 @@ mod tests {
 
     #[test]
     fn test_transform_discord_message_empty_content() {
-        let event = transform_discord_message("", "ch1", "msg1");
+        let event = transform_discord_message("", "ch1", "msg1", None, None);
         if let ProtocolEvent::Prompt { text, .. } = event {
             assert_eq!(text, "");
         } else {
@@ -953,6 +1474,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_transform_discord_reply_carries_referenced_message_id() {
+        let msg: DiscordMessage = serde_json::from_value(json!({
+            "id": "m2", "channel_id": "c1", "content": "and tomorrow?",
+            "author": { "id": "u1", "username": "user", "bot": false },
+            "referenced_message": { "id": "m1", "channel_id": "c1", "content": "It is sunny today." },
+        }))
+        .unwrap();
+        let referenced = msg.referenced_message.as_ref().map(|m| m.id.as_str());
+        match transform_discord_message(&msg.content, &msg.channel_id, &msg.id, referenced, Some(&msg.author.id)) {
+            ProtocolEvent::Prompt { channel, reply_to, author, .. } => {
+                assert_eq!(channel.as_deref(), Some("discord:c1:m2"));
+                assert_eq!(reply_to.as_deref(), Some("m1"));
+                assert_eq!(author.as_deref(), Some("u1"));
+            }
+            _ => panic!("Not a Prompt event"),
+        }
+
+        let plain: DiscordMessage = serde_json::from_value(json!({
+            "id": "m3", "channel_id": "c1", "content": "hi",
+            "author": { "id": "u1", "username": "user" }, "referenced_message": null,
+        }))
+        .unwrap();
+        assert!(plain.referenced_message.is_none());
+        assert_eq!(discord_message_id_from_bridge_channel("discord:c1:m2"), Some("m2"));
+        assert_eq!(discord_message_id_from_bridge_channel("discord:c1"), None);
+    }
+
+    #[test]
+    fn test_compare_replies_are_labelled_with_their_provider() {
+        assert_eq!(compare_reply_label("discord:c1:m2#claude").as_deref(), Some("**[claude]**\n"));
+        assert_eq!(discord_channel_id_from_bridge_channel("discord:c1:m2#claude"), Some("c1"));
+        assert_eq!(compare_reply_label("discord:c1:m2"), None);
+    }
+
+    #[test]
+    fn test_discord_message_payload_references_the_replied_message() {
+        let payload = discord_message_payload("answer", false, Some("m2"), DISCORD_SAFE_MESSAGE_LIMIT);
+        assert_eq!(payload["message_reference"], json!({ "message_id": "m2", "fail_if_not_exists": false }));
+        assert!(discord_message_payload("answer", false, None, DISCORD_SAFE_MESSAGE_LIMIT).get("message_reference").is_none());
+    }
+
+    #[test]
+    fn test_discord_message_payload_sets_suppress_flag_only_when_silent() {
+        let loud = discord_message_payload("hi", false, None, DISCORD_SAFE_MESSAGE_LIMIT);
+        assert_eq!(loud, json!({ "content": "hi" }));
+        let silent = discord_message_payload("hi", true, None, DISCORD_SAFE_MESSAGE_LIMIT);
+        assert_eq!(silent["flags"], json!(4096));
+        assert_eq!(silent["content"], json!("hi"));
+    }
+
     #[test]
     fn test_format_discord_reply() {
         let reply = format_discord_reply("こんにちは！");
@@ -967,9 +1539,70 @@ mod tests {
         assert!(reply.contains("test message"));
     }
 
+    #[test]
+    fn test_shutdown_flushes_pending_reply_buffers_as_partial() {
+        let profile = DiscordProfile { status_format: Some(DEFAULT_DISCORD_STATUS_FORMAT.to_string()), max_chars: DISCORD_SAFE_MESSAGE_LIMIT };
+        let buffer = |content: &str| DiscordReplyBuffer {
+            content: {
+                let mut buf = ReplyBuffer::new(&ReplyBufferLimits::default(), std::time::Instant::now());
+                buf.push(content);
+                buf
+            },
+            provider: "claude".into(),
+            model: "claude-sonnet-4-6".into(),
+            reply_to: None,
+        };
+        let mut reply_buffers = HashMap::new();
+        reply_buffers.insert("discord:200:m2".to_string(), buffer("Second half-written answer"));
+        reply_buffers.insert("discord:100:m1".to_string(), buffer("First answer so far"));
+        reply_buffers.insert("discord:300:m3".to_string(), buffer(""));
+        reply_buffers.insert("discord:400:m4".to_string(), buffer(&"A long answer paragraph. ".repeat(200)));
+
+        let messages = partial_reply_flush_messages(&reply_buffers, &profile);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].0, "100");
+        assert_eq!(
+            messages[0].1,
+            "(partial — bridge disconnected)\n\nFirst answer so far\n\n__claude:claude-sonnet-4-6__"
+        );
+        assert_eq!(messages[1].0, "200");
+        assert_eq!(messages[2].0, "400");
+        assert!(messages[2].1.starts_with("(partial — bridge disconnected)"));
+        assert!(messages[2].1.chars().count() <= DISCORD_SAFE_MESSAGE_LIMIT);
+    }
+
+    #[test]
+    fn test_discord_profile_extracts_answer_and_keeps_status_within_limit() {
+        let profile = DiscordProfile { status_format: Some(DEFAULT_DISCORD_STATUS_FORMAT.to_string()), max_chars: DISCORD_SAFE_MESSAGE_LIMIT };
+        let narration = "Reading files and running tools. ".repeat(80);
+        let answer = "The build fails because the lockfile is out of date. ".repeat(30);
+        let messages = profile.format_reply(&format!("{narration}\n\n{answer}"), "claude", "claude-sonnet-4-6");
+        assert_eq!(messages.len(), 1);
+        let body = &messages[0].body;
+        assert!(body.chars().count() <= DISCORD_SAFE_MESSAGE_LIMIT);
+        assert!(body.starts_with("The build fails"));
+        assert!(body.ends_with("\n\n__claude:claude-sonnet-4-6__"));
+        assert!(!body.contains("Reading files"));
+    }
+
+    #[test]
+    fn test_discord_profile_system_message_is_not_extracted() {
+        let profile = DiscordProfile { status_format: None, max_chars: DISCORD_SAFE_MESSAGE_LIMIT };
+        let msg = format!("{}\n\nshort tail", "x".repeat(2000));
+        let message = profile.format_system_message(&msg, "gemini", "");
+        assert!(message.body.starts_with("xxx"));
+        assert_eq!(message.body.chars().count(), DISCORD_SAFE_MESSAGE_LIMIT);
+    }
+
     #[test]
     fn test_format_discord_agent_reply_with_status_appends_suffix() {
-        let reply = format_discord_agent_reply_with_status("pong", "gemini", "auto-gemini-3");
+        let reply = format_discord_agent_reply_with_status(
+            "pong",
+            "gemini",
+            "auto-gemini-3",
+            Some(DEFAULT_DISCORD_STATUS_FORMAT),
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
         assert!(reply.starts_with("pong"));
         assert!(reply.ends_with("__gemini:auto-gemini-3__"));
         assert!(reply.contains("\n\n__gemini:auto-gemini-3__"));
@@ -979,11 +1612,114 @@ mod tests {
     #[test]
     fn test_format_discord_agent_reply_with_status_preserves_suffix_when_truncated() {
         let body = "あ".repeat(2500);
-        let reply = format_discord_agent_reply_with_status(&body, "claude", "claude-sonnet-4-6");
+        let reply = format_discord_agent_reply_with_status(
+            &body,
+            "claude",
+            "claude-sonnet-4-6",
+            Some(DEFAULT_DISCORD_STATUS_FORMAT),
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
         assert!(reply.ends_with("__claude:claude-sonnet-4-6__"));
         assert!(reply.chars().count() <= 1900);
     }
 
+    #[test]
+    fn test_discord_replies_respect_a_custom_lower_limit() {
+        let profile = DiscordProfile { status_format: Some(DEFAULT_DISCORD_STATUS_FORMAT.to_string()), max_chars: 300 };
+        let messages = profile.format_reply(&"The answer is long. ".repeat(100), "claude", "claude-sonnet-4-6");
+        assert_eq!(messages[0].body.chars().count(), 300);
+        assert!(messages[0].body.ends_with("\n\n__claude:claude-sonnet-4-6__"));
+        let payload = discord_message_payload(&"x".repeat(1000), false, None, 300);
+        assert_eq!(payload["content"].as_str().unwrap().chars().count(), 300);
+    }
+
+    #[test]
+    fn test_extract_discord_answer_uses_the_configured_limit() {
+        let answer = "The final answer, short enough for a tight limit.";
+        let full = format!("{}\n\n{}", "Reading files. ".repeat(60), answer);
+        assert_eq!(extract_discord_answer(&full, DISCORD_SAFE_MESSAGE_LIMIT), full);
+        assert_eq!(extract_discord_answer(&full, 300), answer);
+    }
+
+    #[test]
+    fn test_discord_max_chars_from_env_value() {
+        let parse = |raw| parse_max_chars("DISCORD_MAX_CHARS", raw, DISCORD_SAFE_MESSAGE_LIMIT, DISCORD_MAX_CHARS_MIN, DISCORD_MAX_CHARS_MAX);
+        assert_eq!(parse(None), (1900, None));
+        assert_eq!(parse(Some(" 1500 ")), (1500, None));
+        let (max_chars, warning) = parse(Some("10"));
+        assert_eq!(max_chars, 1900);
+        assert_eq!(warning.as_deref(), Some("Invalid DISCORD_MAX_CHARS '10': expected 200-2000; using 1900"));
+        assert!(parse(Some("lots")).1.is_some());
+    }
+
+    #[test]
+    fn test_parse_discord_status_format() {
+        assert_eq!(parse_discord_status_format(None).as_deref(), Some(DEFAULT_DISCORD_STATUS_FORMAT));
+        assert_eq!(parse_discord_status_format(Some("  ")).as_deref(), Some(DEFAULT_DISCORD_STATUS_FORMAT));
+        assert_eq!(parse_discord_status_format(Some("NONE")), None);
+        assert_eq!(
+            parse_discord_status_format(Some("-# {provider}/{model}")).as_deref(),
+            Some("-# {provider}/{model}")
+        );
+    }
+
+    #[test]
+    fn test_format_discord_agent_reply_with_custom_status_format() {
+        let reply = format_discord_agent_reply_with_status(
+            "pong",
+            "codex",
+            "gpt-5.3-codex",
+            Some("-# {provider}/{model}"),
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
+        assert_eq!(reply, "pong\n\n-# codex/gpt-5.3-codex");
+
+        // A longer suffix shrinks the body budget so the total still fits.
+        let long_format = format!("{}{{provider}}", "x".repeat(500));
+        let reply = format_discord_agent_reply_with_status(
+            &"a".repeat(2500),
+            "gemini",
+            "m",
+            Some(&long_format),
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
+        assert!(reply.ends_with("gemini"));
+        assert_eq!(reply.chars().count(), 1900);
+    }
+
+    #[test]
+    fn test_format_discord_agent_reply_uses_ollama_default_model_when_blank() {
+        let reply = format_discord_agent_reply_with_status(
+            "pong",
+            "ollama",
+            "",
+            Some(DEFAULT_DISCORD_STATUS_FORMAT),
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
+        assert!(reply.ends_with("__ollama:llama3__"));
+    }
+
+    #[test]
+    fn test_format_discord_agent_reply_without_status() {
+        let reply = format_discord_agent_reply_with_status(
+            "pong\n",
+            "gemini",
+            "auto-gemini-3",
+            None,
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
+        assert_eq!(reply, "pong");
+        let reply = format_discord_agent_reply_with_status(
+            &"a".repeat(2500),
+            "gemini",
+            "auto-gemini-3",
+            None,
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
+        assert!(!reply.contains("gemini"));
+        assert!(reply.chars().count() <= 1900);
+    }
+
     #[test]
     fn test_gateway_intents_include_direct_messages_for_dm_support() {
         const DIRECT_MESSAGES_INTENT: u64 = 1 << 12;
@@ -1043,10 +1779,21 @@ mod tests {
         assert_eq!(discord_typing_max_duration(), Duration::from_secs(120));
     }
 
+    #[test]
+    fn test_parse_discord_typing_interval_defaults_and_clamps() {
+        assert_eq!(parse_discord_typing_interval(None), Duration::from_secs(8));
+        assert_eq!(parse_discord_typing_interval(Some("not-a-number")), Duration::from_secs(8));
+        assert_eq!(parse_discord_typing_interval(Some(" 6 ")), Duration::from_secs(6));
+        assert_eq!(parse_discord_typing_interval(Some("1")), Duration::from_secs(5));
+        assert_eq!(parse_discord_typing_interval(Some("30")), Duration::from_secs(9));
+    }
+
     #[test]
     fn test_discord_event_requests_typing_stop_on_agent_done_same_channel() {
         let event = ProtocolEvent::AgentDone {
             channel: Some("discord:1:2".to_string()),
+            provider: None,
+            model: None,
         };
 
         assert!(discord_event_requests_typing_stop(&event, "discord:1:2"));
@@ -1100,6 +1847,7 @@ mod tests {
                 global_name: None,
                 bot: Some(false),
             },
+            referenced_message: None,
         }
     }
 
@@ -1228,13 +1976,13 @@ mod tests {
     #[test]
     fn test_extract_discord_answer_short_content_unchanged() {
         let short = "Hello, 天気は晴れです。";
-        assert_eq!(extract_discord_answer(short), short);
+        assert_eq!(extract_discord_answer(short, DISCORD_SAFE_MESSAGE_LIMIT), short);
     }
 
     #[test]
     fn test_extract_discord_answer_exactly_at_limit_unchanged() {
         let content = "a".repeat(1900);
-        assert_eq!(extract_discord_answer(&content), content);
+        assert_eq!(extract_discord_answer(&content, DISCORD_SAFE_MESSAGE_LIMIT), content);
     }
 
     #[test]
@@ -1249,7 +1997,7 @@ mod tests {
             full.chars().count() > 1900,
             "Precondition: full content must exceed 1900 chars"
         );
-        let result = extract_discord_answer(&full);
+        let result = extract_discord_answer(&full, DISCORD_SAFE_MESSAGE_LIMIT);
         assert_eq!(
             result, answer,
             "Should extract the last paragraph as the final answer"
@@ -1267,7 +2015,7 @@ mod tests {
             full.chars().count() > 1900,
             "Precondition: full content must exceed 1900 chars"
         );
-        let result = extract_discord_answer(&full);
+        let result = extract_discord_answer(&full, DISCORD_SAFE_MESSAGE_LIMIT);
         assert_eq!(
             result, early_answer,
             "Should skip short trailing block and use earlier paragraph"
@@ -1279,7 +2027,7 @@ mod tests {
         // No double-newline — falls back to last 1899 chars with ellipsis prefix.
         // Discord limits are character-based, so we check chars().count().
         let content = "a".repeat(2000);
-        let result = extract_discord_answer(&content);
+        let result = extract_discord_answer(&content, DISCORD_SAFE_MESSAGE_LIMIT);
         assert!(
             result.starts_with('…'),
             "Should start with ellipsis when truncated"
@@ -1293,7 +2041,67 @@ mod tests {
     #[test]
     fn test_extract_discord_answer_trims_trailing_whitespace() {
         let content = format!("short answer\n\n\n   ");
-        assert_eq!(extract_discord_answer(&content), "short answer");
+        assert_eq!(extract_discord_answer(&content, DISCORD_SAFE_MESSAGE_LIMIT), "short answer");
+    }
+
+    fn fence_lines(text: &str) -> usize {
+        text.lines().filter(|line| line.trim_start().starts_with("```")).count()
+    }
+
+    #[test]
+    fn test_extract_discord_answer_keeps_code_block_with_blank_lines_whole() {
+        // The blank line inside the fence must not be taken as a paragraph break.
+        let answer = "修正版は次のとおりです。\n```rust\nfn a() {}\n\nfn b() {}\n```";
+        let full = format!("{}\n\n{}", "x".repeat(2000), answer);
+        assert_eq!(extract_discord_answer(&full, DISCORD_SAFE_MESSAGE_LIMIT), answer);
+    }
+
+    #[test]
+    fn test_extract_discord_answer_reopens_code_block_cut_at_the_limit() {
+        // A trailing code block longer than the limit would be cut mid-fence.
+        let code: String = (0..400).map(|i| format!("let 変数{i} = {i};\n")).collect();
+        let full = format!("{}\n\nFinal answer:\n```rust\n{}```", "x".repeat(2000), code);
+        let result = extract_discord_answer(&full, DISCORD_SAFE_MESSAGE_LIMIT);
+        assert!(result.chars().count() <= 1900, "{} chars", result.chars().count());
+        assert!(result.starts_with("…\n```\n"), "{}", &result[..40]);
+        assert!(result.ends_with("let 変数399 = 399;\n```"));
+        assert_eq!(fence_lines(&result) % 2, 0);
+    }
+
+    #[test]
+    fn test_extract_discord_answer_closes_unterminated_code_block() {
+        let content = "Run this:\n```sh\nls -la";
+        assert_eq!(extract_discord_answer(content, DISCORD_SAFE_MESSAGE_LIMIT), "Run this:\n```sh\nls -la\n```");
+    }
+
+    #[test]
+    fn test_extract_discord_answer_returns_the_text_after_the_final_answer_marker() {
+        // The heuristic alone would return only the last paragraph ("Sources: ...").
+        let answer = "Tokyo will be sunny tomorrow, with a high of 24°C.\n\nSources: the JMA forecast for Tokyo.";
+        let full = format!(
+            "{}\n\nLet me check the forecast first.\n{}\n{}\n",
+            "Searching for weather data. ".repeat(80),
+            crate::protocol::FINAL_ANSWER_MARKER,
+            answer
+        );
+        assert_eq!(extract_discord_answer(&full, DISCORD_SAFE_MESSAGE_LIMIT), answer);
+        let profile = DiscordProfile { status_format: None, max_chars: DISCORD_SAFE_MESSAGE_LIMIT };
+        assert_eq!(profile.format_reply(&full, "gemini", "")[0].body, answer);
+    }
+
+    #[test]
+    fn test_discord_reply_truncation_closes_code_block() {
+        let body = format!("```\n{}```", "コード\n".repeat(1000));
+        let reply = format_discord_agent_reply_with_status(
+            &body,
+            "codex",
+            "gpt-5.3-codex",
+            Some("({provider})"),
+            DISCORD_SAFE_MESSAGE_LIMIT,
+        );
+        assert!(reply.chars().count() <= 1900);
+        assert!(reply.ends_with("…\n```\n\n(codex)"));
+        assert_eq!(fence_lines(&reply) % 2, 0);
     }
 
     // ─── parse_allowed_discord_user_ids tests ──────────────────────────────────
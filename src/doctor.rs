@@ -0,0 +1,314 @@
+/**
+ * `acomm doctor`: environment diagnostics for setting up a new machine.
+ *
+ * Checks the bridge socket (including the protocol version it announces and its
+ * file permissions), the agent CLIs and `amem` on PATH, and which adapter environment variables are set.
+ * Values of environment variables are never printed. Only failures that break
+ * acomm outright (stale socket, incompatible bridge, no agent CLI at all) are
+ * critical; missing optional pieces are reported as warnings.
+ */
+use crate::protocol::{self, ProtocolEvent};
+use acomm::client::DEFAULT_SOCKET_PATH;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+
+const HELLO_TIMEOUT: Duration = Duration::from_secs(2);
+const PROVIDER_COMMANDS: &[&str] = &["gemini", "claude", "codex", "opencode"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Check {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: CheckStatus, detail: impl Into<String>) -> Self {
+        Self { name: name.into(), status, detail: detail.into() }
+    }
+
+    pub fn render(&self) -> String {
+        let tag = match self.status {
+            CheckStatus::Pass => "[ ok ]",
+            CheckStatus::Warn => "[warn]",
+            CheckStatus::Fail => "[FAIL]",
+        };
+        format!("{} {}: {}", tag, self.name, self.detail)
+    }
+
+    /// `render` with the tag in green, yellow or red, for a terminal.
+    pub fn render_colored(&self) -> String {
+        let color = match self.status {
+            CheckStatus::Pass => "32",
+            CheckStatus::Warn => "33",
+            CheckStatus::Fail => "31",
+        };
+        let plain = self.render();
+        let (tag, rest) = plain.split_at(6);
+        format!("\x1b[{}m{}\x1b[0m{}", color, tag, rest)
+    }
+}
+
+/// An adapter and the environment variables it needs (`a|b` means either one).
+struct AdapterEnv {
+    name: &'static str,
+    required: &'static [&'static str],
+}
+
+const ADAPTERS: &[AdapterEnv] = &[
+    AdapterEnv { name: "Discord", required: &["DISCORD_BOT_TOKEN", "DISCORD_NOTIFY_CHANNEL_ID"] },
+    AdapterEnv { name: "Slack", required: &["SLACK_APP_TOKEN", "SLACK_BOT_TOKEN", "SLACK_NOTIFY_CHANNEL_ID"] },
+    AdapterEnv { name: "ntfy", required: &["NTFY_TOPIC|NTFY_TOPIC_IN"] },
+    AdapterEnv { name: "Matrix", required: &["MATRIX_HOMESERVER", "MATRIX_ACCESS_TOKEN", "MATRIX_ROOM_IDS"] },
+    AdapterEnv { name: "webhook", required: &["ACOMM_WEBHOOKS|ACOMM_WEBHOOKS_FILE"] },
+];
+
+/// Run every check; the caller prints them and fails when any is `Fail`.
+pub async fn run_checks() -> Vec<Check> {
+    let mut checks = vec![check_bridge().await];
+    if let Some(check) = check_socket_permissions(Path::new(DEFAULT_SOCKET_PATH)) {
+        checks.push(check);
+    }
+    let path = std::env::var_os("PATH");
+    checks.extend(check_provider_commands(path.as_deref()));
+    let memory_cmd = crate::bridge::memory_command(std::env::var("ACOMM_MEMORY_CMD").ok().as_deref());
+    let memory = &memory_cmd[0];
+    checks.push(match find_on_path(memory, path.as_deref()) {
+        Some(p) => Check::new(memory, CheckStatus::Pass, p.display().to_string()),
+        None => Check::new(memory, CheckStatus::Warn, "not found on PATH; /search and /today will fail"),
+    });
+    let env = |key: &str| std::env::var(key).ok();
+    checks.extend(ADAPTERS.iter().map(|adapter| check_adapter_env(adapter, &env)));
+    checks
+}
+
+async fn check_bridge() -> Check {
+    if !Path::new(DEFAULT_SOCKET_PATH).exists() {
+        return Check::new(
+            "bridge",
+            CheckStatus::Warn,
+            format!("not running ({} missing); clients start it on demand or run 'acomm --bridge'", DEFAULT_SOCKET_PATH),
+        );
+    }
+    let stream = match UnixStream::connect(DEFAULT_SOCKET_PATH).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            return Check::new(
+                "bridge",
+                CheckStatus::Fail,
+                format!("stale socket {} ({}); remove it or restart 'acomm --bridge'", DEFAULT_SOCKET_PATH, e),
+            );
+        }
+    };
+    match tokio::time::timeout(HELLO_TIMEOUT, read_bridge_hello(stream)).await {
+        Ok(Ok(Some(version))) => bridge_version_check(version),
+        Ok(Ok(None)) => Check::new("bridge", CheckStatus::Fail, "connected but the bridge sent no Hello (older acomm?)"),
+        Ok(Err(e)) => Check::new("bridge", CheckStatus::Fail, format!("connected but reading failed: {}", e)),
+        Err(_) => Check::new(
+            "bridge",
+            CheckStatus::Fail,
+            format!("connected but no Hello within {}s", HELLO_TIMEOUT.as_secs()),
+        ),
+    }
+}
+
+/// The socket must be a socket, and anyone who can write to it can send prompts to the
+/// agents, so group/world write access is flagged. `None` when there is no socket yet.
+fn check_socket_permissions(path: &Path) -> Option<Check> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    let meta = std::fs::symlink_metadata(path).ok()?;
+    let mode = meta.permissions().mode() & 0o777;
+    let check = if !meta.file_type().is_socket() {
+        Check::new(
+            "socket permissions",
+            CheckStatus::Fail,
+            format!("{} is not a socket; remove it so the bridge can bind", path.display()),
+        )
+    } else if mode & 0o022 != 0 {
+        Check::new(
+            "socket permissions",
+            CheckStatus::Warn,
+            format!("{} is {:o}; other local users can send prompts (chmod 700 or tighten the umask)", path.display(), mode),
+        )
+    } else {
+        Check::new("socket permissions", CheckStatus::Pass, format!("{:o}", mode))
+    };
+    Some(check)
+}
+
+/// Introduce ourselves and return the version from the bridge's Hello, if it sends one
+/// before its initial sync ends.
+async fn read_bridge_hello(mut stream: UnixStream) -> Result<Option<u32>, Box<dyn Error>> {
+    let hello = ProtocolEvent::Hello { version: protocol::PROTOCOL_VERSION };
+    stream.write_all(format!("{}\n", serde_json::to_string(&hello)?).as_bytes()).await?;
+    let mut lines = BufReader::new(stream).lines();
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str::<ProtocolEvent>(&line) {
+            Ok(ProtocolEvent::Hello { version }) => return Ok(Some(version)),
+            Ok(ProtocolEvent::BridgeSyncDone {}) => return Ok(None),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+fn bridge_version_check(version: u32) -> Check {
+    match protocol::version_mismatch_message("bridge", version) {
+        None => Check::new("bridge", CheckStatus::Pass, format!("running at {} (protocol v{})", DEFAULT_SOCKET_PATH, version)),
+        Some(msg) => Check::new("bridge", CheckStatus::Fail, msg),
+    }
+}
+
+/// One check per agent CLI, plus a critical failure when none is installed.
+fn check_provider_commands(path: Option<&OsStr>) -> Vec<Check> {
+    let mut checks: Vec<Check> = PROVIDER_COMMANDS
+        .iter()
+        .map(|cmd| match find_on_path(cmd, path) {
+            Some(p) => Check::new(*cmd, CheckStatus::Pass, p.display().to_string()),
+            None => Check::new(*cmd, CheckStatus::Warn, "not found on PATH"),
+        })
+        .collect();
+    if checks.iter().all(|c| c.status != CheckStatus::Pass) {
+        checks.push(Check::new(
+            "agent CLIs",
+            CheckStatus::Fail,
+            format!("none of {} is installed", PROVIDER_COMMANDS.join(", ")),
+        ));
+    }
+    checks
+}
+
+/// First executable file named `cmd` in the `PATH`-style list `path`.
+fn find_on_path(cmd: &str, path: Option<&OsStr>) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+    std::env::split_paths(path?)
+        .map(|dir| dir.join(cmd))
+        .find(|candidate| {
+            std::fs::metadata(candidate).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+/// Which of the adapter's variables are set, naming only the missing ones.
+fn check_adapter_env(adapter: &AdapterEnv, env: &dyn Fn(&str) -> Option<String>) -> Check {
+    let is_set = |key: &str| env(key).is_some_and(|v| !v.trim().is_empty());
+    let missing: Vec<&str> = adapter
+        .required
+        .iter()
+        .filter(|alternatives| !alternatives.split('|').any(is_set))
+        .map(|alternatives| alternatives.split('|').next().unwrap_or_default())
+        .collect();
+    let name = format!("{} adapter", adapter.name);
+    if missing.is_empty() {
+        Check::new(name, CheckStatus::Pass, "configured")
+    } else if missing.len() == adapter.required.len() {
+        Check::new(name, CheckStatus::Warn, "not configured")
+    } else {
+        Check::new(name, CheckStatus::Warn, format!("missing {}", missing.join(", ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env_from(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let map: HashMap<String, String> = pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |key| map.get(key).cloned()
+    }
+
+    #[test]
+    fn adapter_env_reports_missing_names_but_never_values() {
+        let slack = &ADAPTERS[1];
+        let check = check_adapter_env(slack, &env_from(&[("SLACK_BOT_TOKEN", "xoxb-secret"), ("SLACK_APP_TOKEN", " ")]));
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert_eq!(check.detail, "missing SLACK_APP_TOKEN, SLACK_NOTIFY_CHANNEL_ID");
+        assert!(!check.render().contains("xoxb-secret"));
+
+        assert_eq!(check_adapter_env(slack, &env_from(&[])).detail, "not configured");
+    }
+
+    #[test]
+    fn ntfy_accepts_either_topic_variable() {
+        let ntfy = &ADAPTERS[2];
+        assert_eq!(check_adapter_env(ntfy, &env_from(&[("NTFY_TOPIC_IN", "in")])).status, CheckStatus::Pass);
+        assert_eq!(check_adapter_env(ntfy, &env_from(&[])).detail, "not configured");
+    }
+
+    #[test]
+    fn find_on_path_requires_an_executable_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("acomm-doctor-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("gemini");
+        std::fs::write(&exe, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&exe, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let plain = dir.join("claude");
+        std::fs::write(&plain, "").unwrap();
+        std::fs::set_permissions(&plain, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let path = std::env::join_paths([Path::new("/nonexistent"), dir.as_path()]).unwrap();
+        assert_eq!(find_on_path("gemini", Some(path.as_os_str())), Some(exe));
+        assert_eq!(find_on_path("claude", Some(path.as_os_str())), None);
+        assert_eq!(find_on_path("gemini", None), None);
+
+        let checks = check_provider_commands(Some(path.as_os_str()));
+        assert!(checks.iter().all(|c| c.status != CheckStatus::Fail));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn no_agent_cli_at_all_is_critical() {
+        let checks = check_provider_commands(Some(OsStr::new("/nonexistent")));
+        assert_eq!(checks.len(), PROVIDER_COMMANDS.len() + 1);
+        assert_eq!(checks.last().unwrap().status, CheckStatus::Fail);
+    }
+
+    #[test]
+    fn socket_permissions_flag_shared_write_access_and_non_sockets() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("acomm-doctor-sock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sock = dir.join("acomm.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&sock).unwrap();
+
+        std::fs::set_permissions(&sock, std::fs::Permissions::from_mode(0o700)).unwrap();
+        let check = check_socket_permissions(&sock).unwrap();
+        assert_eq!((check.status, check.detail.as_str()), (CheckStatus::Pass, "700"));
+
+        std::fs::set_permissions(&sock, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert_eq!(check_socket_permissions(&sock).unwrap().status, CheckStatus::Warn);
+
+        let plain = dir.join("plain");
+        std::fs::write(&plain, "").unwrap();
+        assert_eq!(check_socket_permissions(&plain).unwrap().status, CheckStatus::Fail);
+        assert_eq!(check_socket_permissions(&dir.join("missing")), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn colored_render_only_wraps_the_tag() {
+        let check = Check::new("bridge", CheckStatus::Fail, "down");
+        assert_eq!(check.render_colored(), "\x1b[31m[FAIL]\x1b[0m bridge: down");
+        assert!(Check::new("x", CheckStatus::Pass, "").render_colored().starts_with("\x1b[32m[ ok ]"));
+    }
+
+    #[test]
+    fn incompatible_bridge_version_fails() {
+        assert_eq!(bridge_version_check(protocol::PROTOCOL_VERSION).status, CheckStatus::Pass);
+        let check = bridge_version_check(protocol::PROTOCOL_VERSION + 1);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.render().starts_with("[FAIL] bridge: Incompatible"));
+    }
+}
@@ -0,0 +1,172 @@
+/**
+ * Append-only audit log of everything broadcast on the bridge.
+ *
+ * With `ACOMM_EVENT_LOG=<path>` the bridge's manager task appends every broadcast
+ * ProtocolEvent as one JSON line, `{"ts": "<rfc3339>", "seq": <n>, "event": {...}}`,
+ * where `seq` counts events since the bridge started. Unlike the persisted backlog
+ * (capped, and rewritten as state changes) this file is only ever appended to, so
+ * it shows exactly what flowed over the bus. When a write would push the file past
+ * `ACOMM_EVENT_LOG_MAX_BYTES` (default 10 MiB) it is rotated to `<path>.1`, shifting
+ * older files up to `<path>.3`.
+ */
+use crate::protocol::ProtocolEvent;
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const ROTATED_FILES: usize = 3;
+
+pub struct EventLog {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    size: u64,
+    seq: u64,
+}
+
+/// Parse `ACOMM_EVENT_LOG_MAX_BYTES`; invalid or zero values fall back to the default.
+pub fn parse_event_log_max_bytes(raw: Option<&str>) -> u64 {
+    match raw.map(str::trim).filter(|v| !v.is_empty()) {
+        None => DEFAULT_MAX_BYTES,
+        Some(value) => match value.parse::<u64>() {
+            Ok(bytes) if bytes > 0 => bytes,
+            _ => {
+                warn!("Invalid ACOMM_EVENT_LOG_MAX_BYTES '{}'; using {}", value, DEFAULT_MAX_BYTES);
+                DEFAULT_MAX_BYTES
+            }
+        },
+    }
+}
+
+impl EventLog {
+    /// The log configured by `ACOMM_EVENT_LOG`, or `None` when unset or unusable.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("ACOMM_EVENT_LOG").ok().filter(|p| !p.trim().is_empty())?;
+        let max_bytes = parse_event_log_max_bytes(std::env::var("ACOMM_EVENT_LOG_MAX_BYTES").ok().as_deref());
+        match Self::open(Path::new(path.trim()), max_bytes) {
+            Ok(log) => Some(log),
+            Err(e) => {
+                warn!("Cannot open event log {}: {}; event logging disabled", path.trim(), e);
+                None
+            }
+        }
+    }
+
+    pub fn open(path: &Path, max_bytes: u64) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), max_bytes, file, size, seq: 0 })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one event, rotating first if the line would not fit.
+    pub fn append(&mut self, event: &ProtocolEvent) -> io::Result<()> {
+        self.seq += 1;
+        let entry = json!({
+            "ts": chrono::Utc::now().to_rfc3339(),
+            "seq": self.seq,
+            "event": event,
+        });
+        let line = format!("{}\n", entry);
+        if self.size > 0 && self.size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..ROTATED_FILES).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("acomm-event-log-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn chunk(n: usize) -> ProtocolEvent {
+        ProtocolEvent::AgentChunk { chunk: format!("chunk {}", n), channel: Some("tui".into()) }
+    }
+
+    #[test]
+    fn n_events_produce_n_lines_with_increasing_seq() {
+        let dir = test_dir("lines");
+        let path = dir.join("events.jsonl");
+        let mut log = EventLog::open(&path, DEFAULT_MAX_BYTES).unwrap();
+        for n in 0..5 {
+            log.append(&chunk(n)).unwrap();
+        }
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0]["seq"], 1);
+        assert_eq!(lines[4]["seq"], 5);
+        assert_eq!(lines[2]["event"]["AgentChunk"]["chunk"], "chunk 2");
+        assert!(lines[0]["ts"].as_str().is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotates_at_the_size_limit_and_keeps_three_files() {
+        let dir = test_dir("rotate");
+        let path = dir.join("events.jsonl");
+        let line_len = {
+            let mut probe = EventLog::open(&dir.join("probe.jsonl"), DEFAULT_MAX_BYTES).unwrap();
+            probe.append(&chunk(0)).unwrap();
+            probe.size
+        };
+        // Two lines fit per file.
+        let mut log = EventLog::open(&path, line_len * 2 + 10).unwrap();
+        for n in 0..10 {
+            log.append(&chunk(n)).unwrap();
+        }
+        let count = |p: &Path| std::fs::read_to_string(p).map(|s| s.lines().count()).unwrap_or(0);
+        assert_eq!(count(&path), 2);
+        assert_eq!(count(&rotated_path(&path, 1)), 2);
+        assert_eq!(count(&rotated_path(&path, 3)), 2);
+        assert!(!rotated_path(&path, 4).exists());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn max_bytes_defaults_on_invalid_values() {
+        assert_eq!(parse_event_log_max_bytes(None), DEFAULT_MAX_BYTES);
+        assert_eq!(parse_event_log_max_bytes(Some("0")), DEFAULT_MAX_BYTES);
+        assert_eq!(parse_event_log_max_bytes(Some("lots")), DEFAULT_MAX_BYTES);
+        assert_eq!(parse_event_log_max_bytes(Some(" 2048 ")), 2048);
+    }
+}
@@ -0,0 +1,247 @@
+/**
+ * Fallback providers for failed agent runs.
+ *
+ * When a run fails with an error that looks transient on the provider's side (quota,
+ * HTTP 429, overload), the bridge can re-run the same prompt on the next provider of
+ * an opt-in chain, with that provider's default model. The chain comes from
+ * `ACOMM_FALLBACK_PROVIDERS` (e.g. `claude,codex`) or `/fallback set claude codex`;
+ * which errors count as retryable comes from `ACOMM_FALLBACK_PATTERNS`, a
+ * comma-separated list of case-insensitive substrings.
+ */
+use crate::protocol::{default_model_for_provider, provider_from_command_name};
+use acore::AgentProvider;
+use std::future::Future;
+
+const DEFAULT_PATTERNS: &[&str] = &["quota", "429", "overloaded", "rate limit", "resource_exhausted"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FallbackPolicy {
+    /// Providers to try, in order, after the one the prompt ran on failed.
+    pub providers: Vec<AgentProvider>,
+    /// Lowercase substrings of an error message that make it retryable.
+    pub patterns: Vec<String>,
+}
+
+impl Default for FallbackPolicy {
+    fn default() -> Self {
+        Self { providers: Vec::new(), patterns: DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect() }
+    }
+}
+
+/// Parse provider names separated by commas and/or whitespace.
+pub fn parse_provider_list(raw: &str) -> Result<Vec<AgentProvider>, String> {
+    raw.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .map(|name| provider_from_command_name(name).ok_or_else(|| format!("Unknown provider '{}'", name)))
+        .collect()
+}
+
+impl FallbackPolicy {
+    /// Policy from `ACOMM_FALLBACK_PROVIDERS` / `ACOMM_FALLBACK_PATTERNS`, plus one warning
+    /// per invalid value. An invalid chain disables fallback; unset patterns use the defaults.
+    pub fn from_env_values(providers: Option<&str>, patterns: Option<&str>) -> (Self, Vec<String>) {
+        let mut policy = Self::default();
+        let mut warnings = Vec::new();
+        if let Some(raw) = providers {
+            match parse_provider_list(raw) {
+                Ok(chain) => policy.providers = chain,
+                Err(e) => warnings.push(format!("Invalid ACOMM_FALLBACK_PROVIDERS '{}': {}; fallback disabled", raw, e)),
+            }
+        }
+        if let Some(raw) = patterns {
+            let parsed: Vec<String> =
+                raw.split(',').map(|p| p.trim().to_lowercase()).filter(|p| !p.is_empty()).collect();
+            if !parsed.is_empty() {
+                policy.patterns = parsed;
+            }
+        }
+        (policy, warnings)
+    }
+
+    pub fn from_env() -> (Self, Vec<String>) {
+        Self::from_env_values(
+            std::env::var("ACOMM_FALLBACK_PROVIDERS").ok().as_deref(),
+            std::env::var("ACOMM_FALLBACK_PATTERNS").ok().as_deref(),
+        )
+    }
+
+    pub fn is_retryable(&self, error: &str) -> bool {
+        let error = error.to_lowercase();
+        self.patterns.iter().any(|pattern| error.contains(pattern.as_str()))
+    }
+
+    /// The providers to fall back to from `first`: the chain without `first` and without repeats.
+    pub fn chain_after(&self, first: &AgentProvider) -> Vec<AgentProvider> {
+        let mut chain: Vec<AgentProvider> = Vec::new();
+        for provider in &self.providers {
+            if provider != first && !chain.contains(provider) {
+                chain.push(provider.clone());
+            }
+        }
+        chain
+    }
+
+    /// `/fallback show` text.
+    pub fn describe(&self) -> String {
+        if self.providers.is_empty() {
+            return "No fallback providers. Use /fallback set <provider>...".to_string();
+        }
+        let names: Vec<&str> = self.providers.iter().map(AgentProvider::command_name).collect();
+        format!("Fallback providers: {} (on errors containing: {})", names.join(" → "), self.patterns.join(", "))
+    }
+}
+
+/// The last attempt of `run_with_fallback` and the provider/model that made it.
+#[derive(Debug, PartialEq)]
+pub struct FallbackOutcome {
+    pub result: Result<(), String>,
+    pub provider: AgentProvider,
+    pub model: Option<String>,
+}
+
+/// Run `attempt` on `provider`, then on each provider of the chain while the failure is
+/// retryable. `attempt` reports whether it produced any output: a run that already
+/// streamed part of an answer is not retried, since the retry's answer would be appended
+/// to it. `on_retry(failed, error, next)` is called before each retry.
+pub async fn run_with_fallback<F, Fut>(
+    policy: &FallbackPolicy,
+    provider: AgentProvider,
+    model: Option<String>,
+    mut attempt: F,
+    mut on_retry: impl FnMut(&AgentProvider, &str, &AgentProvider),
+) -> FallbackOutcome
+where
+    F: FnMut(AgentProvider, Option<String>) -> Fut,
+    Fut: Future<Output = (Result<(), String>, bool)>,
+{
+    let mut fallbacks = policy.chain_after(&provider).into_iter();
+    let (mut provider, mut model) = (provider, model);
+    loop {
+        let (result, produced_output) = attempt(provider.clone(), model.clone()).await;
+        let retry = match &result {
+            Err(e) if !produced_output && policy.is_retryable(e) => fallbacks.next().map(|next| (e.clone(), next)),
+            _ => None,
+        };
+        let Some((error, next)) = retry else {
+            return FallbackOutcome { result, provider, model };
+        };
+        on_retry(&provider, &error, &next);
+        model = default_model_for_provider(&next).map(str::to_string);
+        provider = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_values_set_the_chain_and_patterns() {
+        assert_eq!(FallbackPolicy::from_env_values(None, None), (FallbackPolicy::default(), vec![]));
+        let (policy, warnings) = FallbackPolicy::from_env_values(Some("claude, codex"), Some(" Capacity , 503 "));
+        assert_eq!(policy.providers, vec![AgentProvider::Claude, AgentProvider::Codex]);
+        assert_eq!(policy.patterns, vec!["capacity", "503"]);
+        assert!(warnings.is_empty());
+        let (policy, warnings) = FallbackPolicy::from_env_values(Some("claude,gpt"), None);
+        assert!(policy.providers.is_empty());
+        assert!(warnings[0].contains("Unknown provider 'gpt'"));
+    }
+
+    #[test]
+    fn retryable_errors_match_patterns_case_insensitively() {
+        let policy = FallbackPolicy::default();
+        assert!(policy.is_retryable("Gemini: Quota exceeded for model"));
+        assert!(policy.is_retryable("HTTP 429 Too Many Requests"));
+        assert!(policy.is_retryable("API is Overloaded"));
+        assert!(!policy.is_retryable("syntax error in prompt"));
+        assert!(!policy.is_retryable("timed out after 300s"));
+    }
+
+    #[test]
+    fn chain_skips_the_failed_provider_and_repeats() {
+        let policy = FallbackPolicy {
+            providers: parse_provider_list("claude gemini claude codex").unwrap(),
+            ..FallbackPolicy::default()
+        };
+        assert_eq!(policy.chain_after(&AgentProvider::Gemini), vec![AgentProvider::Claude, AgentProvider::Codex]);
+        assert_eq!(policy.chain_after(&AgentProvider::Claude), vec![AgentProvider::Gemini, AgentProvider::Codex]);
+        assert!(FallbackPolicy::default().chain_after(&AgentProvider::Gemini).is_empty());
+    }
+
+    fn chain(names: &str) -> FallbackPolicy {
+        FallbackPolicy { providers: parse_provider_list(names).unwrap(), ..FallbackPolicy::default() }
+    }
+
+    #[tokio::test]
+    async fn quota_errors_move_down_the_chain_until_one_answers() {
+        let mut tried = Vec::new();
+        let mut retries = Vec::new();
+        let outcome = run_with_fallback(
+            &chain("claude,codex"),
+            AgentProvider::Gemini,
+            Some("gemini-2.5-pro".into()),
+            |provider, model| {
+                tried.push((provider.command_name().to_string(), model));
+                let result = match provider {
+                    AgentProvider::Codex => Ok(()),
+                    _ => Err(format!("{}: 429 quota exceeded", provider.command_name())),
+                };
+                async move { (result, false) }
+            },
+            |failed, error, next| retries.push(format!("{} -> {} ({})", failed.command_name(), next.command_name(), error)),
+        )
+        .await;
+        assert_eq!(
+            outcome,
+            FallbackOutcome { result: Ok(()), provider: AgentProvider::Codex, model: Some("gpt-5.3-codex".into()) }
+        );
+        assert_eq!(
+            tried,
+            vec![
+                ("gemini".to_string(), Some("gemini-2.5-pro".to_string())),
+                ("claude".to_string(), Some("claude-sonnet-4-6".to_string())),
+                ("codex".to_string(), Some("gpt-5.3-codex".to_string())),
+            ]
+        );
+        assert_eq!(retries[0], "gemini -> claude (gemini: 429 quota exceeded)");
+    }
+
+    #[tokio::test]
+    async fn partial_output_or_other_errors_are_not_retried() {
+        let policy = chain("claude");
+        for (error, produced_output) in [("quota exceeded", true), ("invalid flag", false)] {
+            let mut attempts = 0;
+            let outcome = run_with_fallback(
+                &policy,
+                AgentProvider::Gemini,
+                None,
+                |_, _| {
+                    attempts += 1;
+                    async move { (Err(error.to_string()), produced_output) }
+                },
+                |_, _, _| {},
+            )
+            .await;
+            assert_eq!(attempts, 1, "{error}");
+            assert_eq!(outcome.provider, AgentProvider::Gemini);
+            assert_eq!(outcome.result, Err(error.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn last_failure_is_returned_when_the_chain_runs_out() {
+        let outcome = run_with_fallback(
+            &chain("claude"),
+            AgentProvider::Gemini,
+            None,
+            |provider, _| {
+                let error = format!("{} overloaded", provider.command_name());
+                async move { (Err(error), false) }
+            },
+            |_, _, _| {},
+        )
+        .await;
+        assert_eq!(outcome.result, Err("claude overloaded".to_string()));
+        assert_eq!(outcome.provider, AgentProvider::Claude);
+    }
+}
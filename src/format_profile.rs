@@ -0,0 +1,98 @@
+/**
+ * Per-surface formatting of finished agent replies.
+ *
+ * Each adapter owns a `FormatProfile` that turns a reply plus the provider and
+ * model that produced it into the messages it actually sends: Discord extracts the
+ * final answer and appends its status line within its message limit, Slack
+ * renders Block Kit or falls back to a snippet, and ntfy titles, tags and splits
+ * notifications at its body limit. The adapters only deliver what the profile
+ * returns, so the limit, suffix and splitting rules can be tested in isolation.
+ *
+ * Every surface attributes a reply to the agent that wrote it with the same
+ * `reply_footer` text, rendered its own way (Discord `__claude:opus__`, a Slack
+ * context block, an italic Matrix line). `ACOMM_REPLY_FOOTER=1` turns it on where
+ * it is otherwise off: Discord with `DISCORD_STATUS_FORMAT=none`, the stdio adapter
+ * and the TUI.
+ */
+use serde_json::Value;
+
+/// One message to deliver on a surface. Fields a surface has no use for stay empty.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct OutboundMessage {
+    /// Message text (Slack: the mrkdwn fallback or the snippet summary).
+    pub body: String,
+    pub title: Option<String>,
+    pub priority: Option<&'static str>,
+    pub tags: Vec<String>,
+    /// Slack Block Kit blocks to post instead of plain text.
+    pub blocks: Option<Vec<Value>>,
+    /// Full reply to upload as a file next to `body` (Slack snippets).
+    pub attachment: Option<String>,
+}
+
+/// Appended to replies that an adapter sends unfinished because the bridge went away.
+pub const PARTIAL_REPLY_NOTE: &str = "(partial — bridge disconnected)";
+
+/// `content` led by the partial-reply note, so truncating the end never drops it.
+pub fn mark_partial_reply(content: &str) -> String {
+    format!("{}\n\n{}", PARTIAL_REPLY_NOTE, content.trim())
+}
+
+/// Resolve a surface's per-message length from its env value (`DISCORD_MAX_CHARS`,
+/// `SLACK_MAX_CHARS`). Unset uses `default`; values outside `min..=max` or unparsable
+/// ones also use `default`, with a warning.
+pub fn parse_max_chars(var: &str, raw: Option<&str>, default: usize, min: usize, max: usize) -> (usize, Option<String>) {
+    match raw.map(str::trim).filter(|v| !v.is_empty()) {
+        None => (default, None),
+        Some(raw) => match raw.parse::<usize>() {
+            Ok(limit) if (min..=max).contains(&limit) => (limit, None),
+            _ => (default, Some(format!("Invalid {} '{}': expected {}-{}; using {}", var, raw, min, max, default))),
+        },
+    }
+}
+
+/// The attribution appended to a reply: `provider:model`, just `provider` when the model
+/// is unknown, or empty when the provider is.
+pub fn reply_footer(provider: &str, model: &str) -> String {
+    match (provider.trim(), model.trim()) {
+        ("", _) => String::new(),
+        (provider, "") => provider.to_string(),
+        (provider, model) => format!("{}:{}", provider, model),
+    }
+}
+
+/// Whether `ACOMM_REPLY_FOOTER` asks for the footer on every surface (`1` / `true`).
+pub fn parse_reply_footer_flag(raw: Option<&str>) -> bool {
+    raw.is_some_and(|v| matches!(v.trim(), "1" | "true"))
+}
+
+pub fn reply_footer_from_env() -> bool {
+    parse_reply_footer_flag(std::env::var("ACOMM_REPLY_FOOTER").ok().as_deref())
+}
+
+/// How a surface renders a finished reply.
+pub trait FormatProfile {
+    /// Messages to send for `content`, in order. Empty when there is nothing to send.
+    fn format_reply(&self, content: &str, provider: &str, model: &str) -> Vec<OutboundMessage>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_footer_names_provider_and_model() {
+        assert_eq!(reply_footer("claude", "claude-sonnet-4-6"), "claude:claude-sonnet-4-6");
+        assert_eq!(reply_footer(" opencode ", " "), "opencode");
+        assert_eq!(reply_footer("", "gpt-5.3-codex"), "");
+    }
+
+    #[test]
+    fn reply_footer_flag_is_opt_in() {
+        assert!(!parse_reply_footer_flag(None));
+        assert!(!parse_reply_footer_flag(Some("0")));
+        assert!(!parse_reply_footer_flag(Some("")));
+        assert!(parse_reply_footer_flag(Some("1")));
+        assert!(parse_reply_footer_flag(Some(" true ")));
+    }
+}
@@ -0,0 +1,427 @@
+/**
+ * Queryable SQLite history of bridge traffic (cargo feature `sqlite`).
+ *
+ * The bridge's manager task writes every broadcast ProtocolEvent into
+ * `~/.cache/acomm/history.db` (override with `ACOMM_HISTORY_DB`), one row per event:
+ *
+ *   events(seq INTEGER PRIMARY KEY, ts TEXT, kind TEXT, channel TEXT, provider TEXT, payload TEXT)
+ *
+ * `ts` is RFC 3339 UTC with millisecond precision so that string comparison orders
+ * rows by time, `provider` is the provider that was active when the event was
+ * broadcast, and `payload` is the event's JSON. The bridge answers `/history` from
+ * this table and `acomm history` reads it directly, so it works without a bridge.
+ *
+ * Retention is applied once when the bridge opens the store:
+ *   ACOMM_HISTORY_RETENTION_DAYS — delete rows older than this many days
+ *   ACOMM_HISTORY_MAX_ROWS       — keep at most this many of the newest rows
+ */
+use crate::protocol::ProtocolEvent;
+use acore::AgentProvider;
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, TimeZone, Utc};
+use rusqlite::{Connection, params};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Rows scanned by `/history` before merging chunks; bounds the work per request.
+const HISTORY_SCAN_ROWS: usize = 2000;
+
+/// Event kinds that make up a conversation transcript.
+const TRANSCRIPT_KINDS: [&str; 3] = ["Prompt", "AgentChunk", "SystemMessage"];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Retention {
+    pub max_days: Option<u64>,
+    pub max_rows: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRow {
+    pub seq: i64,
+    pub ts: String,
+    pub kind: String,
+    pub channel: Option<String>,
+    pub provider: Option<String>,
+    pub payload: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    /// Channel prefix, e.g. `slack` or `slack:C123`.
+    pub channel: Option<String>,
+    /// Only rows at or after this instant.
+    pub since: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the event payload.
+    pub grep: Option<String>,
+    pub limit: usize,
+}
+
+pub struct HistoryStore {
+    conn: Connection,
+}
+
+/// Parse a positive integer retention setting; unset, empty or invalid disables that limit.
+pub fn parse_retention_limit(name: &str, raw: Option<&str>) -> Option<u64> {
+    let value = raw.map(str::trim).filter(|v| !v.is_empty())?;
+    match value.parse::<u64>() {
+        Ok(n) if n > 0 => Some(n),
+        _ => {
+            warn!("Invalid {} '{}'; ignoring", name, value);
+            None
+        }
+    }
+}
+
+pub fn retention_from_env() -> Retention {
+    Retention {
+        max_days: parse_retention_limit(
+            "ACOMM_HISTORY_RETENTION_DAYS",
+            std::env::var("ACOMM_HISTORY_RETENTION_DAYS").ok().as_deref(),
+        ),
+        max_rows: parse_retention_limit("ACOMM_HISTORY_MAX_ROWS", std::env::var("ACOMM_HISTORY_MAX_ROWS").ok().as_deref()),
+    }
+}
+
+/// `ACOMM_HISTORY_DB`, falling back to `history.db` in the persisted state dir.
+pub fn history_db_path(raw: Option<&str>) -> Option<PathBuf> {
+    match raw.map(str::trim).filter(|p| !p.is_empty()) {
+        Some(path) => Some(PathBuf::from(path)),
+        None => crate::bridge::persisted_state_dir().map(|dir| dir.join("history.db")),
+    }
+}
+
+/// Parse `--since`: either an RFC 3339 timestamp or a `YYYY-MM-DD` date taken as local midnight.
+pub fn parse_since(raw: &str) -> Result<DateTime<Utc>, String> {
+    let raw = raw.trim();
+    if let Ok(ts) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(ts.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid --since '{}': expected YYYY-MM-DD or an RFC 3339 timestamp", raw))?;
+    let midnight = date.and_hms_opt(0, 0, 0).expect("midnight is a valid time");
+    Local
+        .from_local_datetime(&midnight)
+        .earliest()
+        .map(|ts| ts.with_timezone(&Utc))
+        .ok_or_else(|| format!("Invalid --since '{}': no such local time", raw))
+}
+
+fn format_ts(ts: DateTime<Utc>) -> String {
+    ts.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// The variant name serde uses as the event's JSON tag, e.g. `AgentChunk`.
+fn event_kind(event: &ProtocolEvent) -> &'static str {
+    match event {
+        ProtocolEvent::Prompt { .. } => "Prompt",
+        ProtocolEvent::AgentChunk { .. } => "AgentChunk",
+        ProtocolEvent::AgentDone { .. } => "AgentDone",
+        ProtocolEvent::SystemMessage { .. } => "SystemMessage",
+        ProtocolEvent::StatusUpdate { .. } => "StatusUpdate",
+        ProtocolEvent::PromptAck { .. } => "PromptAck",
+        ProtocolEvent::ChannelClosed { .. } => "ChannelClosed",
+        ProtocolEvent::Hello { .. } => "Hello",
+        ProtocolEvent::BridgeSyncDone { .. } => "BridgeSyncDone",
+        ProtocolEvent::SyncContext { .. } => "SyncContext",
+        ProtocolEvent::ProviderSwitched { .. } => "ProviderSwitched",
+        ProtocolEvent::ModelSwitched { .. } => "ModelSwitched",
+    }
+}
+
+/// Escape `%`, `_` and `\` so `term` matches literally inside a `LIKE ... ESCAPE '\'` pattern.
+fn like_pattern(term: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in term.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn Error>> {
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    fn open_in_memory() -> Result<Self, Box<dyn Error>> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Box<dyn Error>> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS events (
+                 seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                 ts TEXT NOT NULL,
+                 kind TEXT NOT NULL,
+                 channel TEXT,
+                 provider TEXT,
+                 payload TEXT NOT NULL
+             );
+             CREATE INDEX IF NOT EXISTS events_channel_ts ON events (channel, ts);
+             CREATE INDEX IF NOT EXISTS events_ts ON events (ts);",
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Open the store the bridge writes to, or `None` (with a warning) when it can't be opened.
+    pub fn from_env() -> Option<Self> {
+        let path = history_db_path(std::env::var("ACOMM_HISTORY_DB").ok().as_deref())?;
+        match Self::open(&path) {
+            Ok(store) => Some(store),
+            Err(e) => {
+                warn!("Cannot open history store {}: {}; history disabled", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn record(&self, event: &ProtocolEvent, provider: &AgentProvider) -> Result<(), Box<dyn Error>> {
+        self.record_at(event, provider, Utc::now())
+    }
+
+    fn record_at(&self, event: &ProtocolEvent, provider: &AgentProvider, ts: DateTime<Utc>) -> Result<(), Box<dyn Error>> {
+        let provider = match event {
+            ProtocolEvent::Prompt { provider: Some(p), .. } => p.command_name(),
+            _ => provider.command_name(),
+        };
+        self.conn.execute(
+            "INSERT INTO events (ts, kind, channel, provider, payload) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![format_ts(ts), event_kind(event), event.clone_channel(), provider, serde_json::to_string(event)?],
+        )?;
+        Ok(())
+    }
+
+    /// Apply the retention limits; returns the number of deleted rows.
+    pub fn prune(&self, retention: Retention, now: DateTime<Utc>) -> Result<usize, Box<dyn Error>> {
+        let mut deleted = 0;
+        if let Some(days) = retention.max_days {
+            let cutoff = now - chrono::Duration::days(days.min(1_000_000) as i64);
+            deleted += self.conn.execute("DELETE FROM events WHERE ts < ?1", params![format_ts(cutoff)])?;
+        }
+        if let Some(rows) = retention.max_rows {
+            deleted += self.conn.execute(
+                "DELETE FROM events WHERE seq <= (SELECT seq FROM events ORDER BY seq DESC LIMIT 1 OFFSET ?1)",
+                params![rows.min(i64::MAX as u64) as i64],
+            )?;
+        }
+        Ok(deleted)
+    }
+
+    /// Transcript rows (prompts, chunks, system messages) matching `query`, oldest first.
+    /// `limit` keeps the newest rows.
+    pub fn query(&self, query: &HistoryQuery) -> Result<Vec<HistoryRow>, Box<dyn Error>> {
+        let mut sql = format!(
+            "SELECT seq, ts, kind, channel, provider, payload FROM events WHERE kind IN ('{}')",
+            TRANSCRIPT_KINDS.join("', '")
+        );
+        let mut args: Vec<String> = Vec::new();
+        if let Some(ref channel) = query.channel {
+            args.push(channel.clone());
+            args.push(format!("{}:%", channel.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")));
+            sql.push_str(&format!(" AND (channel = ?{} OR channel LIKE ?{} ESCAPE '\\')", args.len() - 1, args.len()));
+        }
+        if let Some(since) = query.since {
+            args.push(format_ts(since));
+            sql.push_str(&format!(" AND ts >= ?{}", args.len()));
+        }
+        if let Some(ref grep) = query.grep {
+            args.push(like_pattern(grep));
+            sql.push_str(&format!(" AND payload LIKE ?{} ESCAPE '\\'", args.len()));
+        }
+        sql.push_str(&format!(" ORDER BY seq DESC LIMIT {}", query.limit));
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(args.iter()), |row| {
+            Ok(HistoryRow {
+                seq: row.get(0)?,
+                ts: row.get(1)?,
+                kind: row.get(2)?,
+                channel: row.get(3)?,
+                provider: row.get(4)?,
+                payload: row.get(5)?,
+            })
+        })?;
+        let mut rows = rows.collect::<Result<Vec<_>, _>>()?;
+        rows.reverse();
+        Ok(rows)
+    }
+
+    /// The last `n` transcript entries of `channel`, with each reply's chunks merged.
+    pub fn recent_entries(&self, channel: &str, n: usize) -> Result<Vec<HistoryEntry>, Box<dyn Error>> {
+        let rows = self.query(&HistoryQuery {
+            channel: Some(channel.to_string()),
+            limit: HISTORY_SCAN_ROWS,
+            ..HistoryQuery::default()
+        })?;
+        let mut entries = merge_entries(&rows);
+        let skip = entries.len().saturating_sub(n);
+        Ok(entries.split_off(skip))
+    }
+}
+
+/// One line of a transcript: a prompt, a whole reply, or a system message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub ts: String,
+    pub channel: Option<String>,
+    pub provider: Option<String>,
+    pub role: &'static str,
+    pub text: String,
+}
+
+impl HistoryEntry {
+    pub fn render(&self) -> String {
+        let who = match (self.role, self.provider.as_deref()) {
+            ("agent", Some(provider)) => provider.to_string(),
+            (role, _) => role.to_string(),
+        };
+        format!(
+            "[{}] [{}] {}: {}",
+            self.ts.get(..19).unwrap_or(&self.ts).replace('T', " "),
+            self.channel.as_deref().unwrap_or("-"),
+            who,
+            self.text.trim_end()
+        )
+    }
+}
+
+/// Turn transcript rows into entries, joining consecutive chunks of the same channel.
+pub fn merge_entries(rows: &[HistoryRow]) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+    for row in rows {
+        let Ok(event) = serde_json::from_str::<ProtocolEvent>(&row.payload) else { continue };
+        let (role, text) = match event {
+            ProtocolEvent::Prompt { text, .. } => ("user", text),
+            ProtocolEvent::AgentChunk { chunk, .. } => ("agent", chunk),
+            ProtocolEvent::SystemMessage { msg, .. } => ("system", msg),
+            _ => continue,
+        };
+        if let Some(last) = entries.last_mut() {
+            if role == "agent" && last.role == "agent" && last.channel == row.channel {
+                last.text.push_str(&text);
+                continue;
+            }
+        }
+        entries.push(HistoryEntry {
+            ts: row.ts.clone(),
+            channel: row.channel.clone(),
+            provider: row.provider.clone(),
+            role,
+            text,
+        });
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(text: &str, channel: &str) -> ProtocolEvent {
+        ProtocolEvent::Prompt { text: text.into(), provider: None, channel: Some(channel.into()), reply_to: None, priority: false, author: None }
+    }
+
+    fn chunk(text: &str, channel: &str) -> ProtocolEvent {
+        ProtocolEvent::AgentChunk { chunk: text.into(), channel: Some(channel.into()) }
+    }
+
+    fn at(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, day, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn records_rows_with_kind_channel_and_provider() {
+        let store = HistoryStore::open_in_memory().unwrap();
+        store.record_at(&prompt("hi", "slack:C1"), &AgentProvider::Claude, at(1)).unwrap();
+        store.record_at(&ProtocolEvent::AgentDone { channel: None, provider: None, model: None }, &AgentProvider::Gemini, at(1)).unwrap();
+        let rows = store.query(&HistoryQuery { limit: 10, ..HistoryQuery::default() }).unwrap();
+        // AgentDone is stored but is not part of the transcript.
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].kind, "Prompt");
+        assert_eq!(rows[0].channel.as_deref(), Some("slack:C1"));
+        assert_eq!(rows[0].provider.as_deref(), Some("claude"));
+        assert_eq!(rows[0].ts, "2024-06-01T12:00:00.000Z");
+        let count: i64 = store.conn.query_row("SELECT COUNT(*) FROM events", [], |r| r.get(0)).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn query_filters_by_channel_prefix_since_and_grep() {
+        let store = HistoryStore::open_in_memory().unwrap();
+        store.record_at(&prompt("old docker question", "slack:C1"), &AgentProvider::Claude, at(1)).unwrap();
+        store.record_at(&prompt("new Docker question", "slack:C1:123"), &AgentProvider::Claude, at(5)).unwrap();
+        store.record_at(&prompt("docker elsewhere", "discord:9"), &AgentProvider::Claude, at(5)).unwrap();
+        store.record_at(&prompt("unrelated", "slack:C1"), &AgentProvider::Claude, at(5)).unwrap();
+        store.record_at(&prompt("prefix lookalike docker", "slackish"), &AgentProvider::Claude, at(5)).unwrap();
+
+        let rows = store
+            .query(&HistoryQuery {
+                channel: Some("slack".into()),
+                since: Some(at(3)),
+                grep: Some("docker".into()),
+                limit: 10,
+            })
+            .unwrap();
+        assert_eq!(rows.len(), 1);
+        assert!(rows[0].payload.contains("new Docker question"));
+    }
+
+    #[test]
+    fn grep_treats_like_wildcards_literally() {
+        let store = HistoryStore::open_in_memory().unwrap();
+        store.record_at(&prompt("100% sure", "tui"), &AgentProvider::Claude, at(1)).unwrap();
+        store.record_at(&prompt("100 sure", "tui"), &AgentProvider::Claude, at(1)).unwrap();
+        let rows = store.query(&HistoryQuery { grep: Some("0%".into()), limit: 10, ..HistoryQuery::default() }).unwrap();
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[test]
+    fn recent_entries_merge_reply_chunks_and_keep_the_last_n() {
+        let store = HistoryStore::open_in_memory().unwrap();
+        store.record_at(&prompt("first", "tui"), &AgentProvider::Claude, at(1)).unwrap();
+        store.record_at(&chunk("one ", "tui"), &AgentProvider::Claude, at(1)).unwrap();
+        store.record_at(&prompt("second", "tui"), &AgentProvider::Claude, at(2)).unwrap();
+        store.record_at(&chunk("two ", "tui"), &AgentProvider::Claude, at(2)).unwrap();
+        store.record_at(&chunk("parts", "tui"), &AgentProvider::Claude, at(2)).unwrap();
+        let entries = store.recent_entries("tui", 2).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].text, "second");
+        assert_eq!(entries[1].text, "two parts");
+        assert_eq!(entries[1].render(), "[2024-06-02 12:00:00] [tui] claude: two parts");
+    }
+
+    #[test]
+    fn prune_applies_age_and_row_limits() {
+        let store = HistoryStore::open_in_memory().unwrap();
+        for day in 1..=10 {
+            store.record_at(&prompt(&format!("day {}", day), "tui"), &AgentProvider::Claude, at(day)).unwrap();
+        }
+        let deleted = store.prune(Retention { max_days: Some(5), max_rows: None }, at(10)).unwrap();
+        assert_eq!(deleted, 4);
+        let deleted = store.prune(Retention { max_days: None, max_rows: Some(2) }, at(10)).unwrap();
+        assert_eq!(deleted, 4);
+        let rows = store.query(&HistoryQuery { limit: 10, ..HistoryQuery::default() }).unwrap();
+        assert!(rows[0].payload.contains("day 9"));
+        assert!(rows[1].payload.contains("day 10"));
+    }
+
+    #[test]
+    fn parses_since_and_retention_settings() {
+        assert_eq!(parse_since("2024-06-01T09:00:00+09:00").unwrap(), Utc.with_ymd_and_hms(2024, 6, 1, 0, 0, 0).unwrap());
+        let local = parse_since("2024-06-01").unwrap().with_timezone(&Local);
+        assert_eq!(local.format("%Y-%m-%d %H:%M").to_string(), "2024-06-01 00:00");
+        assert!(parse_since("last tuesday").is_err());
+
+        assert_eq!(parse_retention_limit("X", Some(" 30 ")), Some(30));
+        assert_eq!(parse_retention_limit("X", Some("0")), None);
+        assert_eq!(parse_retention_limit("X", Some("forever")), None);
+        assert_eq!(parse_retention_limit("X", None), None);
+    }
+}
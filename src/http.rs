@@ -0,0 +1,572 @@
+/**
+ * HTTP adapter: the bridge as REST + Server-Sent Events.
+ *
+ * `acomm adapter http` listens on `--listen` / `ACOMM_HTTP_ADDR` (default
+ * `127.0.0.1:8765`) so phone shortcuts and small web pages can use acomm without
+ * the Unix-socket protocol:
+ *
+ *   POST /prompt   {"text", "channel"?, "provider"?} → 202 {"request_id", "channel"}
+ *   POST /command  {"command": "/provider claude", "channel"?} → 202 {"request_id", "channel"}
+ *   GET  /events   SSE stream of ProtocolEvents after the initial sync (`?channel=<prefix>`)
+ *   GET  /backlog  JSON array of the bridge backlog (`?channel=<prefix>`)
+ *
+ * Each request opens its own bridge connection, like the other adapters; an SSE
+ * client that disconnects drops its bridge connection with it. When
+ * `ACOMM_HTTP_TOKEN` is set every request needs `Authorization: Bearer <token>`;
+ * `/events` also takes `?token=<token>`, since a browser `EventSource` cannot set
+ * headers. The server runs on hyper (HTTP/1.1 keep-alive, chunked request bodies)
+ * and answers CORS preflights, so web pages on other origins can call it.
+ */
+use crate::bridge::is_backlog_event;
+use crate::protocol::{ProtocolEvent, provider_from_command_name};
+use acomm::client::DEFAULT_SOCKET_PATH;
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Empty, Full, LengthLimitError, Limited, StreamBody};
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::{
+    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+    CACHE_CONTROL, CONTENT_TYPE,
+};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{debug, info, warn};
+
+const DEFAULT_HTTP_ADDR: &str = "127.0.0.1:8765";
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
+const SSE_KEEPALIVE: Duration = Duration::from_secs(15);
+/// Frames buffered for a slow SSE client before the bridge reader waits on it.
+const SSE_BUFFER: usize = 64;
+/// Channel prefix for prompts posted without an explicit channel.
+const HTTP_CHANNEL_PREFIX: &str = "http";
+
+static NEXT_REQUEST: AtomicU64 = AtomicU64::new(1);
+
+type HttpBody = BoxBody<Bytes, Infallible>;
+
+/// One HTTP request, read in full from hyper.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    /// Header names lowercased.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PromptRequest {
+    text: String,
+    #[serde(default)]
+    channel: Option<String>,
+    #[serde(default)]
+    provider: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    command: String,
+    #[serde(default)]
+    channel: Option<String>,
+}
+
+/// What a route resolved to: a plain response, or work that needs the bridge.
+#[derive(Debug)]
+enum Route {
+    Respond { status: StatusCode, body: serde_json::Value },
+    /// A CORS preflight; browsers send it without credentials.
+    Preflight,
+    Publish { event: ProtocolEvent, request_id: String },
+    Events { filter: Option<String> },
+    Backlog { filter: Option<String> },
+}
+
+/// The listen address: `--listen`, else `ACOMM_HTTP_ADDR`, else the loopback default.
+pub fn http_listen_addr(flag: Option<&str>, env: Option<&str>) -> String {
+    [flag, env]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .find(|v| !v.is_empty())
+        .unwrap_or(DEFAULT_HTTP_ADDR)
+        .to_string()
+}
+
+pub async fn start_http_adapter(listen: &str) -> Result<(), Box<dyn Error>> {
+    let token: Option<Arc<str>> = std::env::var("ACOMM_HTTP_TOKEN")
+        .ok()
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .map(Arc::from);
+    let listener = TcpListener::bind(listen).await?;
+    info!("acomm HTTP adapter listening on http://{}", listener.local_addr()?);
+    if token.is_none() {
+        warn!("ACOMM_HTTP_TOKEN is not set; the HTTP adapter accepts unauthenticated requests");
+    }
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let token = token.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| handle_request(request, token.clone()));
+            if let Err(e) = http1::Builder::new().keep_alive(true).serve_connection(TokioIo::new(stream), service).await {
+                debug!("HTTP connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_request<B>(request: Request<B>, token: Option<Arc<str>>) -> Result<Response<HttpBody>, Infallible>
+where
+    B: Body,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    let request = match read_request(request).await {
+        Ok(request) => request,
+        Err((status, message)) => return Ok(json_response(status, &json!({ "error": message }))),
+    };
+    let response = match route(&request, token.as_deref()) {
+        Route::Respond { status, body } => json_response(status, &body),
+        Route::Preflight => preflight_response(),
+        Route::Publish { event, request_id } => {
+            let channel = event.clone_channel();
+            match publish(&event).await {
+                Ok(()) => json_response(StatusCode::ACCEPTED, &json!({ "request_id": request_id, "channel": channel })),
+                Err(e) => json_response(StatusCode::BAD_GATEWAY, &json!({ "error": e.to_string() })),
+            }
+        }
+        Route::Backlog { filter } => match fetch_backlog(filter.as_deref()).await {
+            Ok(events) => json_response(StatusCode::OK, &json!(events)),
+            Err(e) => json_response(StatusCode::BAD_GATEWAY, &json!({ "error": e.to_string() })),
+        },
+        Route::Events { filter } => stream_events(filter).await,
+    };
+    Ok(response)
+}
+
+/// Resolve a request to a response or an action, checking the bearer token first.
+fn route(request: &HttpRequest, token: Option<&str>) -> Route {
+    if request.method == "OPTIONS" {
+        return Route::Preflight;
+    }
+    if !authorized(request, token) {
+        return error_route(StatusCode::UNAUTHORIZED, "missing or invalid bearer token");
+    }
+    let filter = request.query.get("channel").filter(|c| !c.is_empty()).cloned();
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/prompt") => match serde_json::from_slice::<PromptRequest>(&request.body) {
+            Ok(prompt) => prompt_route(prompt),
+            Err(e) => error_route(StatusCode::BAD_REQUEST, &format!("invalid prompt body: {}", e)),
+        },
+        ("POST", "/command") => match serde_json::from_slice::<CommandRequest>(&request.body) {
+            Ok(command) => command_route(command),
+            Err(e) => error_route(StatusCode::BAD_REQUEST, &format!("invalid command body: {}", e)),
+        },
+        ("GET", "/events") => Route::Events { filter },
+        ("GET", "/backlog") => Route::Backlog { filter },
+        (_, "/prompt" | "/command" | "/events" | "/backlog") => {
+            error_route(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        }
+        _ => error_route(StatusCode::NOT_FOUND, "not found"),
+    }
+}
+
+fn prompt_route(prompt: PromptRequest) -> Route {
+    if prompt.text.trim().is_empty() {
+        return error_route(StatusCode::BAD_REQUEST, "text must not be empty");
+    }
+    let provider = match prompt.provider.as_deref().map(str::trim).filter(|p| !p.is_empty()) {
+        None => None,
+        Some(name) => match provider_from_command_name(name) {
+            Some(provider) => Some(provider),
+            None => return error_route(StatusCode::BAD_REQUEST, &format!("unknown provider '{}'", name)),
+        },
+    };
+    let request_id = next_request_id();
+    let channel = request_channel(prompt.channel, &request_id);
+    let event = ProtocolEvent::Prompt { text: prompt.text, provider, channel: Some(channel), reply_to: None, priority: false, author: None };
+    Route::Publish { event, request_id }
+}
+
+fn command_route(command: CommandRequest) -> Route {
+    let text = command.command.trim();
+    if !text.starts_with('/') {
+        return error_route(StatusCode::BAD_REQUEST, "command must start with '/'");
+    }
+    let request_id = next_request_id();
+    let channel = request_channel(command.channel, &request_id);
+    let event = ProtocolEvent::Prompt { text: text.to_string(), provider: None, channel: Some(channel), reply_to: None, priority: false, author: None };
+    Route::Publish { event, request_id }
+}
+
+fn error_route(status: StatusCode, message: &str) -> Route {
+    Route::Respond { status, body: json!({ "error": message }) }
+}
+
+/// The caller's channel, or `http:<request_id>` so its reply can be followed on /events.
+fn request_channel(channel: Option<String>, request_id: &str) -> String {
+    channel
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .unwrap_or_else(|| format!("{}:{}", HTTP_CHANNEL_PREFIX, request_id))
+}
+
+fn next_request_id() -> String {
+    let n = NEXT_REQUEST.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{}", chrono::Utc::now().timestamp_millis(), n)
+}
+
+/// The bearer token from `Authorization`, or from `?token=` on `/events`, where a
+/// browser `EventSource` has no way to send headers.
+fn authorized(request: &HttpRequest, token: Option<&str>) -> bool {
+    let Some(token) = token else { return true };
+    let bearer = request.headers.get("authorization").and_then(|value| value.strip_prefix("Bearer "));
+    let query = request.query.get("token").filter(|_| request.path == "/events");
+    bearer.or(query.map(String::as_str)).is_some_and(|given| token_matches(given.trim(), token))
+}
+
+/// Compare tokens in constant time so response timing does not leak a prefix.
+fn token_matches(given: &str, token: &str) -> bool {
+    given.as_bytes().ct_eq(token.as_bytes()).into()
+}
+
+fn channel_matches(event: &ProtocolEvent, filter: Option<&str>) -> bool {
+    match filter {
+        None => true,
+        Some(prefix) => event.clone_channel().is_some_and(|ch| ch.starts_with(prefix)),
+    }
+}
+
+/// Send one event to the bridge and wait until it acknowledges the prompt.
+async fn publish(event: &ProtocolEvent) -> Result<(), Box<dyn Error>> {
+    let stream = connect_bridge().await?;
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(format!("{}\n", serde_json::to_string(event)?).as_bytes()).await?;
+    let mut lines = BufReader::new(reader).lines();
+    let acked = tokio::time::timeout(ACK_TIMEOUT, async {
+        while let Some(line) = lines.next_line().await? {
+            if let Ok(ProtocolEvent::PromptAck { .. }) = serde_json::from_str::<ProtocolEvent>(&line) {
+                return Ok(true);
+            }
+        }
+        Ok::<bool, std::io::Error>(false)
+    })
+    .await;
+    let _ = writer.shutdown().await;
+    match acked {
+        Ok(Ok(true)) => Ok(()),
+        Ok(Ok(false)) => Err("bridge closed the connection".into()),
+        Ok(Err(e)) => Err(e.into()),
+        Err(_) => Err("bridge did not acknowledge the prompt".into()),
+    }
+}
+
+/// The backlog the bridge replays on connect, up to its `BridgeSyncDone` marker.
+async fn fetch_backlog(filter: Option<&str>) -> Result<Vec<ProtocolEvent>, Box<dyn Error>> {
+    let stream = connect_bridge().await?;
+    let mut lines = BufReader::new(stream).lines();
+    let mut events = Vec::new();
+    tokio::time::timeout(SYNC_TIMEOUT, async {
+        while let Some(line) = lines.next_line().await? {
+            match serde_json::from_str::<ProtocolEvent>(&line) {
+                Ok(ProtocolEvent::BridgeSyncDone {}) => break,
+                Ok(event) if is_backlog_event(&event) && channel_matches(&event, filter) => events.push(event),
+                _ => {}
+            }
+        }
+        Ok::<(), std::io::Error>(())
+    })
+    .await
+    .map_err(|_| "bridge did not finish its initial sync")??;
+    Ok(events)
+}
+
+/// Answer with an SSE stream fed from a fresh bridge connection.
+async fn stream_events(filter: Option<String>) -> Response<HttpBody> {
+    let bridge = match connect_bridge().await {
+        Ok(bridge) => bridge,
+        Err(e) => return json_response(StatusCode::BAD_GATEWAY, &json!({ "error": e.to_string() })),
+    };
+    let (tx, rx) = mpsc::channel(SSE_BUFFER);
+    tokio::spawn(forward_events(bridge, filter, tx));
+    let body = StreamBody::new(ReceiverStream::new(rx).map(|frame| Ok::<_, Infallible>(Frame::data(frame))));
+    with_cors(Response::builder())
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        .body(body.boxed())
+        .expect("static SSE response headers are valid")
+}
+
+/// Forward live bridge events as SSE frames until either side goes away. hyper drops
+/// the receiver when the client disconnects; leaving this function then drops the
+/// bridge connection, which ends the subscription on the bridge side.
+async fn forward_events(bridge: UnixStream, filter: Option<String>, tx: mpsc::Sender<Bytes>) {
+    let mut lines = BufReader::new(bridge).lines();
+    let mut synced = false;
+    let mut keepalive = tokio::time::interval(SSE_KEEPALIVE);
+    keepalive.tick().await;
+    loop {
+        let frame = tokio::select! {
+            line = lines.next_line() => {
+                let Ok(Some(line)) = line else {
+                    let _ = tx.send(Bytes::from_static(b"event: closed\ndata: bridge closed the connection\n\n")).await;
+                    break;
+                };
+                let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) else { continue };
+                if matches!(event, ProtocolEvent::BridgeSyncDone {}) {
+                    synced = true;
+                    continue;
+                }
+                if !synced || !channel_matches(&event, filter.as_deref()) {
+                    continue;
+                }
+                Bytes::from(sse_frame(&line))
+            }
+            _ = keepalive.tick() => Bytes::from_static(b": keepalive\n\n"),
+            _ = tx.closed() => break,
+        };
+        if tx.send(frame).await.is_err() {
+            break;
+        }
+    }
+    debug!("SSE client gone; dropping its bridge connection");
+}
+
+/// One SSE `data:` frame; protocol events are single-line JSON.
+fn sse_frame(json_line: &str) -> String {
+    format!("data: {}\n\n", json_line)
+}
+
+async fn connect_bridge() -> Result<UnixStream, Box<dyn Error>> {
+    UnixStream::connect(DEFAULT_SOCKET_PATH).await.map_err(|e| {
+        format!("Bridge is not running. Please start it with 'acomm --bridge'. Error: {}", e).into()
+    })
+}
+
+/// Collect a hyper request, decoding chunked bodies and capping them at `MAX_BODY_BYTES`.
+async fn read_request<B>(request: Request<B>) -> Result<HttpRequest, (StatusCode, String)>
+where
+    B: Body,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    let (parts, body) = request.into_parts();
+    let body = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+        Ok(collected) => collected.to_bytes().to_vec(),
+        Err(e) if e.is::<LengthLimitError>() => {
+            return Err((StatusCode::PAYLOAD_TOO_LARGE, "request body too large".into()));
+        }
+        Err(e) => return Err((StatusCode::BAD_REQUEST, format!("could not read the request body: {}", e))),
+    };
+    let headers = parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str().to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    Ok(HttpRequest {
+        method: parts.method.to_string(),
+        path: parts.uri.path().to_string(),
+        query: parse_query(parts.uri.query().unwrap_or("")),
+        headers,
+        body,
+    })
+}
+
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(k), percent_decode(v))
+        })
+        .collect()
+}
+
+/// Decode `%XX` escapes and `+` in a query component.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Every response allows any origin; access is gated by the token, not by cookies.
+fn with_cors(builder: hyper::http::response::Builder) -> hyper::http::response::Builder {
+    builder.header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+}
+
+fn preflight_response() -> Response<HttpBody> {
+    with_cors(Response::builder())
+        .status(StatusCode::NO_CONTENT)
+        .header(ACCESS_CONTROL_ALLOW_METHODS, "GET, POST, OPTIONS")
+        .header(ACCESS_CONTROL_ALLOW_HEADERS, "Authorization, Content-Type")
+        .header(ACCESS_CONTROL_MAX_AGE, "86400")
+        .body(Empty::new().boxed())
+        .expect("static preflight headers are valid")
+}
+
+fn json_response(status: StatusCode, body: &serde_json::Value) -> Response<HttpBody> {
+    with_cors(Response::builder())
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())).boxed())
+        .expect("static JSON response headers are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use acore::AgentProvider;
+
+    fn request(method: &str, target: &str, body: &str) -> HttpRequest {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: parse_query(query),
+            body: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_query_headers_and_chunked_bodies() {
+        let request = Request::builder()
+            .method("GET")
+            .uri("/events?channel=discord%3A123&x")
+            .header("Authorization", "Bearer abc")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let request = read_request(request).await.unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/events");
+        assert_eq!(request.query.get("channel").map(String::as_str), Some("discord:123"));
+        assert_eq!(request.headers.get("authorization").map(String::as_str), Some("Bearer abc"));
+        assert_eq!(percent_decode("a+b%2"), "a b%2");
+
+        let chunks = ["{\"text\":", "\"hi\"}"].map(|c| Ok::<_, Infallible>(Frame::data(Bytes::from_static(c.as_bytes()))));
+        let request = Request::builder()
+            .method("POST")
+            .uri("/prompt")
+            .body(StreamBody::new(tokio_stream::iter(chunks)))
+            .unwrap();
+        assert_eq!(read_request(request).await.unwrap().body, br#"{"text":"hi"}"#);
+
+        let request = Request::builder().method("POST").uri("/prompt").body(Full::new(Bytes::from(vec![b'x'; MAX_BODY_BYTES + 1]))).unwrap();
+        assert_eq!(read_request(request).await.unwrap_err().0, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn listen_addr_prefers_flag_then_env() {
+        assert_eq!(http_listen_addr(Some("0.0.0.0:9000"), Some("127.0.0.1:1")), "0.0.0.0:9000");
+        assert_eq!(http_listen_addr(None, Some(" 127.0.0.1:1 ")), "127.0.0.1:1");
+        assert_eq!(http_listen_addr(Some(""), None), DEFAULT_HTTP_ADDR);
+    }
+
+    #[test]
+    fn bearer_token_is_required_only_when_configured() {
+        let unauthorized = |r: Route| matches!(r, Route::Respond { status: StatusCode::UNAUTHORIZED, .. });
+        let mut req = request("GET", "/backlog", "");
+        assert!(matches!(route(&req, None), Route::Backlog { filter: None }));
+        assert!(unauthorized(route(&req, Some("s3cret"))));
+        req.headers.insert("authorization".into(), "Bearer wrong".into());
+        assert!(unauthorized(route(&req, Some("s3cret"))));
+        req.headers.insert("authorization".into(), "Bearer s3cret".into());
+        assert!(matches!(route(&req, Some("s3cret")), Route::Backlog { filter: None }));
+
+        // EventSource cannot set headers, so /events (and only /events) takes ?token=.
+        assert!(matches!(route(&request("GET", "/events?token=s3cret", ""), Some("s3cret")), Route::Events { filter: None }));
+        assert!(unauthorized(route(&request("GET", "/events?token=s3cre", ""), Some("s3cret"))));
+        assert!(unauthorized(route(&request("GET", "/backlog?token=s3cret", ""), Some("s3cret"))));
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cret!", "s3cret"));
+
+        // Preflights carry no credentials and are answered with the CORS headers.
+        assert!(matches!(route(&request("OPTIONS", "/prompt", ""), Some("s3cret")), Route::Preflight));
+        let preflight = preflight_response();
+        assert_eq!(preflight.status(), StatusCode::NO_CONTENT);
+        assert_eq!(preflight.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(preflight.headers()[ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap().contains("Authorization"));
+        assert_eq!(json_response(StatusCode::OK, &json!([])).headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[test]
+    fn prompt_route_builds_a_prompt_on_a_per_request_channel() {
+        let routed = route(&request("POST", "/prompt", r#"{"text":"hi","provider":"Claude"}"#), None);
+        let Route::Publish { event: ProtocolEvent::Prompt { text, provider, channel, .. }, request_id } = routed else {
+            panic!("expected a prompt, got {routed:?}");
+        };
+        assert_eq!(text, "hi");
+        assert_eq!(provider, Some(AgentProvider::Claude));
+        assert_eq!(channel, Some(format!("http:{}", request_id)));
+
+        let routed = route(&request("POST", "/prompt", r#"{"text":"hi","channel":"phone"}"#), None);
+        assert!(matches!(routed, Route::Publish { event: ProtocolEvent::Prompt { channel: Some(ref c), .. }, .. } if c == "phone"));
+
+        let routed = route(&request("POST", "/command", r#"{"command":" /provider codex "}"#), None);
+        assert!(matches!(routed, Route::Publish { event: ProtocolEvent::Prompt { ref text, provider: None, .. }, .. } if text == "/provider codex"));
+    }
+
+    #[test]
+    fn invalid_bodies_and_routes_are_rejected() {
+        let status = |r: Route| match r {
+            Route::Respond { status, .. } => status,
+            other => panic!("expected an error response, got {other:?}"),
+        };
+        assert_eq!(status(route(&request("POST", "/prompt", "{}"), None)), StatusCode::BAD_REQUEST);
+        assert_eq!(status(route(&request("POST", "/prompt", r#"{"text":"  "}"#), None)), StatusCode::BAD_REQUEST);
+        assert_eq!(status(route(&request("POST", "/prompt", r#"{"text":"x","provider":"nope"}"#), None)), StatusCode::BAD_REQUEST);
+        assert_eq!(status(route(&request("POST", "/command", r#"{"command":"provider x"}"#), None)), StatusCode::BAD_REQUEST);
+        assert_eq!(status(route(&request("GET", "/prompt", ""), None)), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(status(route(&request("GET", "/", ""), None)), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn events_filter_by_channel_prefix() {
+        let routed = route(&request("GET", "/events?channel=http%3A", ""), None);
+        assert!(matches!(routed, Route::Events { filter: Some(ref f) } if f == "http:"));
+        let chunk = ProtocolEvent::AgentChunk { chunk: "x".into(), channel: Some("http:1".into()) };
+        let other = ProtocolEvent::AgentChunk { chunk: "x".into(), channel: Some("tui".into()) };
+        let global = ProtocolEvent::ProviderSwitched { provider: AgentProvider::Gemini };
+        assert!(channel_matches(&chunk, Some("http:")));
+        assert!(!channel_matches(&other, Some("http:")));
+        assert!(!channel_matches(&global, Some("http:")));
+        assert!(channel_matches(&global, None));
+        assert_eq!(sse_frame(r#"{"AgentDone":{"channel":null}}"#), "data: {\"AgentDone\":{\"channel\":null}}\n\n");
+    }
+}
@@ -0,0 +1,10 @@
+/**
+ * Library side of acomm: the bridge wire protocol and a typed client for it.
+ *
+ * Tools that want to talk to a running bridge can depend on this crate instead of
+ * shelling out to `acomm --publish` or re-implementing the JSON-lines framing:
+ * `protocol` has the `ProtocolEvent` types and `client::AcommClient` connects,
+ * publishes prompts and waits for replies. The `acomm` binary uses the same client.
+ */
+pub mod client;
+pub mod protocol;
@@ -0,0 +1,98 @@
+/**
+ * Logging setup shared by the bridge and the adapters.
+ *
+ * Long-running processes log through `tracing`. The filter comes from `--log-level`
+ * (applied to acomm's own targets), else `RUST_LOG`, else `acomm=info`; output goes
+ * to stderr or, with `--log-file`, is appended to that file. A bridge auto-started by
+ * a client has no terminal, so it is spawned with `--log-file` pointing at
+ * `~/.cache/acomm/bridge.log`.
+ */
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tracing_subscriber::EnvFilter;
+
+const DEFAULT_LOG_DIRECTIVE: &str = "acomm=info";
+const BRIDGE_LOG_FILE: &str = "bridge.log";
+
+/// Where an auto-started (detached) bridge writes its log.
+pub fn default_bridge_log_path() -> Option<PathBuf> {
+    crate::bridge::persisted_state_dir().map(|dir| dir.join(BRIDGE_LOG_FILE))
+}
+
+/// The `EnvFilter` directive to use: a bare level from `--log-level` applies to acomm only,
+/// anything else (from the flag or `RUST_LOG`) is used as a full directive string.
+fn log_filter_directive(level_flag: Option<&str>, rust_log: Option<&str>) -> String {
+    let level_flag = level_flag.map(str::trim).filter(|v| !v.is_empty());
+    let rust_log = rust_log.map(str::trim).filter(|v| !v.is_empty());
+    match (level_flag, rust_log) {
+        (Some(level), _) if is_bare_level(level) => format!("acomm={}", level.to_ascii_lowercase()),
+        (Some(directive), _) => directive.to_string(),
+        (None, Some(directive)) => directive.to_string(),
+        (None, None) => DEFAULT_LOG_DIRECTIVE.to_string(),
+    }
+}
+
+fn is_bare_level(value: &str) -> bool {
+    ["trace", "debug", "info", "warn", "error", "off"].iter().any(|l| value.eq_ignore_ascii_case(l))
+}
+
+/// Install the global subscriber. Call once, before the bridge or an adapter starts.
+pub fn init_logging(log_file: Option<&Path>, level_flag: Option<&str>) -> Result<(), Box<dyn Error>> {
+    let directive = log_filter_directive(level_flag, std::env::var("RUST_LOG").ok().as_deref());
+    let filter = EnvFilter::try_new(&directive).map_err(|e| format!("Invalid log filter '{}': {}", directive, e))?;
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match log_file {
+        Some(path) => {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Cannot open log file {}: {}", path.display(), e))?;
+            builder
+                .with_ansi(false)
+                .with_writer(std::sync::Mutex::new(file))
+                .try_init()
+                .map_err(|e| format!("Cannot install the logger: {}", e))?;
+        }
+        None => builder
+            .with_writer(std::io::stderr)
+            .try_init()
+            .map_err(|e| format!("Cannot install the logger: {}", e))?,
+    }
+    Ok(())
+}
+
+/// Shorten prompt text for log lines.
+pub fn truncate_for_log(text: &str, max_chars: usize) -> String {
+    let single_line = text.replace('\n', " ");
+    match single_line.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}…", &single_line[..idx]),
+        None => single_line,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_level_flag_scopes_bare_levels_to_acomm() {
+        assert_eq!(log_filter_directive(Some("DEBUG"), Some("warn")), "acomm=debug");
+        assert_eq!(log_filter_directive(Some("acomm::bridge=trace,info"), None), "acomm::bridge=trace,info");
+    }
+
+    #[test]
+    fn rust_log_is_used_when_no_flag_and_default_otherwise() {
+        assert_eq!(log_filter_directive(None, Some("acomm=debug")), "acomm=debug");
+        assert_eq!(log_filter_directive(Some(" "), Some("")), DEFAULT_LOG_DIRECTIVE);
+    }
+
+    #[test]
+    fn truncate_for_log_keeps_one_line() {
+        assert_eq!(truncate_for_log("short\ntext", 20), "short text");
+        assert_eq!(truncate_for_log("こんにちは世界", 5), "こんにちは…");
+    }
+}
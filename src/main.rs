@@ -1,12 +1,36 @@
 mod bridge;
+mod chunking;
 mod discord;
+mod doctor;
+mod event_log;
+mod fallback;
+mod format_profile;
+#[cfg(feature = "sqlite")]
+mod history_store;
+mod http;
+mod logging;
+mod memory_log;
+#[cfg(feature = "matrix")]
+mod matrix;
+mod metrics;
 mod ntfy;
-mod protocol;
+mod offline;
+mod prompt_queue;
+mod quiet_hours;
+mod rate_limit;
+mod redact;
+mod reply_buffer;
+mod schedule;
 mod slack;
+mod stdio;
+mod templates;
 mod tui;
+mod webhook;
 
+use acomm::client::{self, AcommClient, ClientError};
+use acomm::protocol::{self, ProtocolEvent};
 use acore::AgentProvider;
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
 use crossterm::{
     event::{
         self, DisableMouseCapture, EnableMouseCapture, Event, KeyboardEnhancementFlags,
@@ -18,29 +42,76 @@ use crossterm::{
         supports_keyboard_enhancement,
     },
 };
-use protocol::ProtocolEvent;
 use ratatui::{Terminal, backend::CrosstermBackend};
 use std::{error::Error, io, path::Path};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
 use tokio::net::UnixStream;
 use tokio::sync::mpsc;
 use tui::{App, AppEvent, InputMode, InputState};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(group(ArgGroup::new("prompt_source").args(["publish", "file"])))]
 struct CliArgs {
     #[arg(short, long)]
     bridge: bool,
+    /// --bridge と併用し、指定アドレス (例: 127.0.0.1:9464) で Prometheus 形式の /metrics を公開する
+    #[arg(long, value_name = "ADDR:PORT", requires = "bridge")]
+    metrics: Option<String>,
+    /// ブリッジ / アダプターのログをこのファイルに追記する（自動起動されたブリッジは ~/.cache/acomm/bridge.log）
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<std::path::PathBuf>,
+    /// ブリッジ / アダプターのログレベル (error / warn / info / debug / trace)。未指定なら RUST_LOG
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<String>,
     #[arg(short, long)]
     publish: Option<String>,
+    /// 標準入力を1行ずつ（--delimiter 指定時はその区切りごとに）別々のプロンプトとして送信する。空のレコードは飛ばす
+    #[arg(long, conflicts_with_all = ["publish", "file", "ask", "wait"])]
+    publish_lines: bool,
+    /// --publish-lines のレコード区切り文字列（例: "---"）。複数行のレコードを送るときに使う
+    #[arg(long, value_name = "TEXT", requires = "publish_lines", allow_hyphen_values = true)]
+    delimiter: Option<String>,
+    /// --publish-lines でプロンプトを送る間隔（ミリ秒）
+    #[arg(long, default_value_t = 500, requires = "publish_lines")]
+    publish_delay_ms: u64,
+    /// ファイルの内容をプロンプトとして送信する（--publish とは排他。--wait と併用可）
+    #[arg(long, value_name = "PATH")]
+    file: Option<std::path::PathBuf>,
+    /// --file の本文の前に置く指示文（空行を挟んで連結する）
+    #[arg(long, value_name = "TEXT", requires = "file", conflicts_with = "publish")]
+    prepend: Option<String>,
+    /// 送受信するチャンネル。TUI で未指定なら `tui:<user>@<host>`（`default_tui_channel`）
     #[arg(short, long)]
     channel: Option<String>,
     #[arg(short, long, alias = "s")]
     subscribe: bool,
+    /// --subscribe で --channel 指定時も、チャンネルを持たないイベント (ProviderSwitched 等) を表示する
+    #[arg(long, requires = "subscribe")]
+    all_system: bool,
+    /// --subscribe で StatusUpdate と Thinking スピナーを表示しない（ログ出力向け）
+    #[arg(long, requires = "subscribe")]
+    quiet_status: bool,
+    /// --subscribe でブリッジとの接続が切れても終了せず、再接続して続きから出力する
+    #[arg(long, requires = "subscribe")]
+    follow: bool,
     #[arg(short, long)]
     dump: bool,
+    /// --dump / --subscribe の出力形式。json は受信した ProtocolEvent を1行1件でそのまま出す
+    #[arg(long, value_enum, default_value_t = OutputFormat::Pretty)]
+    format: OutputFormat,
+    /// --dump でバックログの末尾 N 件だけを出力する（--channel で前方一致フィルタも可能）
+    #[arg(long, value_name = "N", requires = "dump")]
+    last: Option<usize>,
+    /// ブリッジのバックログとセッションを消去する。--channel 指定時はそのチャンネル（前方一致）のバックログだけを消す
     #[arg(short, long)]
     reset: bool,
+    /// --reset と併用し、永続化されたバックログと TUI の入力履歴も削除する
+    #[arg(long, requires = "reset", conflicts_with = "channel")]
+    hard: bool,
+    /// --reset の確認プロンプトを省略する（スクリプト向け）
+    #[arg(long, requires = "reset")]
+    yes: bool,
     #[arg(long)]
     slack: bool,
     #[arg(long)]
@@ -54,17 +125,75 @@ struct CliArgs {
     /// (--discord / --slack / --ntfy でチャンネルをフィルタ可能)
     #[arg(long)]
     receive: bool,
-    /// --receive のタイムアウト秒数。指定秒数内に入力がなければ exit 1 で終了する
+    /// --publish / --file と併用し、エージェントの返答を待って標準出力に書き出す
+    /// (エージェントのエラーで exit 3、タイムアウトで exit 4)
+    #[arg(long, requires = "prompt_source")]
+    wait: bool,
+    /// TUI を使わずに1回だけ質問し、返答を逐次標準出力に流して終了する（"-" で標準入力から読む）。
+    /// チャンネルは --channel 未指定なら "cli"
+    #[arg(long, value_name = "PROMPT", conflicts_with = "publish")]
+    ask: Option<String>,
+    /// --receive / --publish --wait / --ask のタイムアウト秒数。
+    /// --receive は指定秒数内に入力がなければ exit 4、--wait / --ask は既定 120 秒
     #[arg(long)]
     timeout: Option<u64>,
+    /// 記録したセッション (ProtocolEvent の JSON 配列、または `--dump --format json` の 1 行 1 イベント) の Prompt を順に bridge へ再送する
+    #[arg(long, value_name = "FILE")]
+    replay: Option<std::path::PathBuf>,
+    /// --replay で Prompt を送る間隔（ミリ秒）
+    #[arg(long, default_value_t = 1000, requires = "replay")]
+    replay_delay_ms: u64,
+    /// TUI 起動時にブリッジが動いていなくても自動起動せず、すぐにエラー終了する
+    /// （systemd などでブリッジを管理している環境向け）
+    #[arg(long)]
+    no_autostart: bool,
+    /// `acomm doctor` と同じ自己診断を行う（ブリッジの起動もプロンプトの送信もしない）
+    #[arg(long)]
+    doctor: bool,
     #[command(subcommand)]
     command: Option<CliCommand>,
 }
 
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Pretty,
+    Json,
+}
+
 #[derive(Subcommand, Debug, Clone)]
 enum CliCommand {
     /// 外部チャネルの直近ログを取得する
     Logs(LogArgs),
+    /// ブリッジ・エージェント CLI・アダプターの設定を診断する（致命的な問題があれば exit 1）
+    Doctor,
+    /// SQLite の履歴を検索する（`--features sqlite` でビルドしたときのみ、ブリッジ不要）
+    #[cfg(feature = "sqlite")]
+    History(HistoryArgs),
+    /// ブリッジに接続する常駐アダプターを起動する
+    Adapter {
+        #[command(subcommand)]
+        kind: AdapterCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum AdapterCommand {
+    /// ブリッジを REST + Server-Sent Events として公開する（ACOMM_HTTP_TOKEN 設定時は Bearer 認証必須）
+    Http(HttpArgs),
+    /// 標準入出力の JSON-RPC でブリッジを操作する（エディタ拡張向け、stdin が閉じたら終了）
+    Stdio,
+    /// ブリッジのイベントを ACOMM_WEBHOOKS の URL へ JSON で POST する
+    Webhook,
+    /// Matrix のルームをブリッジにつなぐ（`--features matrix` でビルドしたときのみ）
+    #[cfg(feature = "matrix")]
+    Matrix,
+}
+
+#[derive(Args, Debug, Clone)]
+struct HttpArgs {
+    /// 待ち受けアドレス（未指定なら ACOMM_HTTP_ADDR、それもなければ 127.0.0.1:8765）
+    #[arg(long, value_name = "ADDR:PORT")]
+    listen: Option<String>,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -80,16 +209,135 @@ struct LogArgs {
     json: bool,
 }
 
-const SOCKET_PATH: &str = "/tmp/acomm.sock";
+#[cfg(feature = "sqlite")]
+#[derive(Args, Debug, Clone)]
+struct HistoryArgs {
+    /// チャンネル名の接頭辞で絞り込む（例: slack, discord:123）
+    #[arg(long)]
+    channel: Option<String>,
+    /// この日（YYYY-MM-DD、ローカル時刻）または RFC 3339 時刻以降に絞り込む
+    #[arg(long)]
+    since: Option<String>,
+    /// 本文に含まれる文字列で絞り込む（大文字小文字は区別しない）
+    #[arg(long)]
+    grep: Option<String>,
+    /// 表示する最大件数（新しいものから）
+    #[arg(long, default_value_t = 50)]
+    limit: usize,
+    /// 整形せずに行ごとの JSON を出力する
+    #[arg(long)]
+    json: bool,
+}
+
+const SOCKET_PATH: &str = client::DEFAULT_SOCKET_PATH;
+
+// 終了コード。スクリプトから失敗の理由を判別できるようにする（その他の失敗は 1）。
+const EXIT_FAILURE: i32 = 1;
+const EXIT_BRIDGE_UNREACHABLE: i32 = 2;
+const EXIT_AGENT_ERROR: i32 = 3;
+const EXIT_TIMEOUT: i32 = 4;
+const EXIT_INVALID_ARGS: i32 = 5;
+
+/// 終了コードを指定したいエラー。それ以外の `Box<dyn Error>` は EXIT_FAILURE で終了する。
+#[derive(Debug)]
+struct CliError {
+    code: i32,
+    message: String,
+}
+
+impl CliError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for CliError {}
+
+fn exit_code(err: &(dyn Error + 'static)) -> i32 {
+    err.downcast_ref::<CliError>().map_or(EXIT_FAILURE, |e| e.code)
+}
+
+/// stderr に出すエラー行。`grep 'acomm: error:'` で拾えるよう必ず1行にまとめる。
+fn error_line(message: &str) -> String {
+    let message = message.trim_start().strip_prefix("error: ").unwrap_or(message);
+    format!("acomm: error: {}", message.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// --file のプロンプトを読み込む。読めない・空のファイルは引数エラー (exit 5)。
+fn read_prompt_file(path: &Path, prepend: Option<&str>) -> Result<String, CliError> {
+    let body = std::fs::read_to_string(path)
+        .map_err(|e| CliError::new(EXIT_INVALID_ARGS, format!("cannot read prompt file {}: {}", path.display(), e)))?;
+    compose_file_prompt(&body, prepend)
+        .ok_or_else(|| CliError::new(EXIT_INVALID_ARGS, format!("prompt file {} is empty", path.display())))
+}
+
+/// --prepend の指示文とファイル本文を空行で連結する。本文が空白だけなら `None`。
+fn compose_file_prompt(body: &str, prepend: Option<&str>) -> Option<String> {
+    if body.trim().is_empty() {
+        return None;
+    }
+    match prepend.map(str::trim_end).filter(|p| !p.trim().is_empty()) {
+        Some(prepend) => Some(format!("{}\n\n{}", prepend, body)),
+        None => Some(body.to_string()),
+    }
+}
+
+/// チャンネル名は空白や制御文字を含まない空でない文字列に限る（プレフィックス一致のキーとして使うため）。
+fn validate_channel(channel: &str) -> Result<(), CliError> {
+    if channel.is_empty() || channel.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(CliError::new(
+            EXIT_INVALID_ARGS,
+            format!("invalid channel {:?}: must be non-empty without whitespace", channel),
+        ));
+    }
+    Ok(())
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let args = CliArgs::parse();
+async fn main() {
+    let args = match CliArgs::try_parse() {
+        Ok(args) => args,
+        // --help / --version は clap にそのまま出力させて exit 0
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let message = e.to_string();
+            eprintln!("{} (see 'acomm --help')", error_line(message.lines().next().unwrap_or_default()));
+            std::process::exit(EXIT_INVALID_ARGS);
+        }
+    };
+    if let Err(e) = run(args).await {
+        let _ = io::Write::flush(&mut io::stdout());
+        eprintln!("{}", error_line(&e.to_string()));
+        std::process::exit(exit_code(e.as_ref()));
+    }
+}
+
+async fn run(args: CliArgs) -> Result<(), Box<dyn Error>> {
+    if let Some(ref channel) = args.channel {
+        validate_channel(channel)?;
+    }
+    // 常駐するブリッジとアダプターは tracing でログを出す。単発の CLI は --log-file / --log-level
+    // 指定時だけ（bridge への接続判断などを追える）。
+    let long_running = args.bridge
+        || matches!(args.command, Some(CliCommand::Adapter { .. }))
+        || (args.command.is_none() && args.agent.is_none() && !args.receive && (args.slack || args.ntfy || args.discord));
+    if long_running || args.log_file.is_some() || args.log_level.is_some() {
+        logging::init_logging(args.log_file.as_deref(), args.log_level.as_deref())?;
+    }
+    if args.doctor {
+        return run_command(CliCommand::Doctor).await;
+    }
     if let Some(command) = args.command.clone() {
         return run_command(command).await;
     }
     if args.bridge {
-        return bridge::start_bridge().await;
+        return bridge::start_bridge(args.metrics.clone()).await;
     }
 
     // --agent: send a proactive message as the bot without going through the AI pipeline.
@@ -126,7 +374,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
 
     if args.reset {
-        return publish_to_bridge("/clear", Some("bridge")).await;
+        if args.hard {
+            return hard_reset(args.yes).await;
+        }
+        if let Some(ref prefix) = args.channel {
+            return publish_to_bridge(&format!("/clear {}", prefix), Some("bridge")).await;
+        }
+        let question = "This clears the backlog, the agent session and the model selection for every channel. Continue? [y/N] ";
+        if !args.yes && !confirm_on_stderr(question).await? {
+            eprintln!("Aborted. Pass --yes to skip this prompt, or --channel <prefix> to clear one channel.");
+            return Ok(());
+        }
+        return publish_to_bridge("/clear --all", Some("bridge")).await;
     }
     if args.slack {
         loop {
@@ -135,10 +394,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 Err(e) => {
                     let message = e.to_string();
                     if should_retry_slack_adapter_error(&message) {
-                        eprintln!(
-                            "Slack adapter transient error; retrying in 2s ({})",
-                            message
-                        );
+                        tracing::warn!("Slack adapter transient error; retrying in 2s ({})", message);
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
                         continue;
                     }
@@ -157,10 +413,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 Err(e) => {
                     let message = e.to_string();
                     if should_retry_discord_adapter_error(&message) {
-                        eprintln!(
-                            "Discord adapter reconnect requested by gateway; retrying in 2s ({})",
-                            message
-                        );
+                        tracing::warn!("Discord adapter reconnect requested by gateway; retrying in 2s ({})", message);
                         tokio::time::sleep(std::time::Duration::from_secs(2)).await;
                         continue;
                     }
@@ -169,28 +422,59 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         }
     }
-    if let Some(mut msg) = args.publish {
-        if msg == "-" {
+    if args.publish_lines {
+        let delay = std::time::Duration::from_millis(args.publish_delay_ms);
+        return publish_lines(args.channel.as_deref(), args.delimiter.as_deref(), delay).await;
+    }
+    let file_prompt = match args.file {
+        Some(ref path) => Some(read_prompt_file(path, args.prepend.as_deref())?),
+        None => None,
+    };
+    if let Some(mut msg) = args.publish.or(file_prompt) {
+        if msg == "-" && args.file.is_none() {
             let mut buffer = String::new();
             tokio::io::stdin().read_to_string(&mut buffer).await?;
             msg = buffer;
         }
+        if args.wait {
+            let channel = args.channel.clone().unwrap_or_else(|| format!("cli:wait:{}", std::process::id()));
+            let timeout = std::time::Duration::from_secs(args.timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT_SECS));
+            return publish_and_wait(&msg, &channel, timeout, false).await;
+        }
         return publish_to_bridge(&msg, args.channel.as_deref()).await;
     }
+    if let Some(mut prompt) = args.ask.clone() {
+        if prompt == "-" {
+            let mut buffer = String::new();
+            tokio::io::stdin().read_to_string(&mut buffer).await?;
+            prompt = buffer;
+        }
+        let channel = args.channel.as_deref().unwrap_or(ASK_CHANNEL);
+        let timeout = std::time::Duration::from_secs(args.timeout.unwrap_or(DEFAULT_WAIT_TIMEOUT_SECS));
+        return publish_and_wait(&prompt, channel, timeout, true).await;
+    }
+    if let Some(ref path) = args.replay {
+        return replay_session(path, std::time::Duration::from_millis(args.replay_delay_ms)).await;
+    }
     if args.dump {
-        return start_dump().await;
+        return start_dump(args.format, args.channel.as_deref(), args.last).await;
     }
     if args.subscribe {
-        return start_subscribe().await;
+        let filter = SubscribeFilter {
+            channel_prefix: args.channel.as_deref(),
+            all_system: args.all_system,
+            quiet_status: args.quiet_status,
+        };
+        return start_subscribe(args.format, filter, args.follow).await;
     }
-    start_tui(args.channel.as_deref()).await
+    start_tui(args.channel.as_deref(), !args.no_autostart).await
 }
 
 async fn run_command(command: CliCommand) -> Result<(), Box<dyn Error>> {
     match command {
         CliCommand::Logs(args) => {
             if !args.discord {
-                return Err("logs currently requires --discord".into());
+                return Err(CliError::new(EXIT_INVALID_ARGS, "logs currently requires --discord").into());
             }
 
             let entries = discord::fetch_recent_discord_messages(args.limit.into()).await?;
@@ -203,61 +487,510 @@ async fn run_command(command: CliCommand) -> Result<(), Box<dyn Error>> {
             }
             Ok(())
         }
+        CliCommand::Doctor => {
+            let checks = doctor::run_checks().await;
+            // 端末に出すときだけ結果を色分けする（パイプ先には ANSI エスケープを混ぜない）。
+            let color = io::IsTerminal::is_terminal(&io::stdout());
+            for check in &checks {
+                println!("{}", if color { check.render_colored() } else { check.render() });
+            }
+            let failed = checks.iter().filter(|c| c.status == doctor::CheckStatus::Fail).count();
+            if failed > 0 {
+                return Err(format!("doctor: {} critical check(s) failed", failed).into());
+            }
+            Ok(())
+        }
+        #[cfg(feature = "sqlite")]
+        CliCommand::History(args) => {
+            let since = match args.since.as_deref() {
+                Some(raw) => Some(history_store::parse_since(raw).map_err(|e| CliError::new(EXIT_INVALID_ARGS, e))?),
+                None => None,
+            };
+            let path = history_store::history_db_path(std::env::var("ACOMM_HISTORY_DB").ok().as_deref())
+                .ok_or("no cache directory for the history store")?;
+            if !path.exists() {
+                return Err(format!("no history store at {} (start the bridge from a build with --features sqlite)", path.display()).into());
+            }
+            let store = history_store::HistoryStore::open(&path)?;
+            let rows = store.query(&history_store::HistoryQuery {
+                channel: args.channel,
+                since,
+                grep: args.grep,
+                limit: args.limit,
+            })?;
+            if args.json {
+                for row in &rows {
+                    let payload: serde_json::Value = serde_json::from_str(&row.payload)?;
+                    println!("{}", serde_json::json!({
+                        "seq": row.seq, "ts": row.ts, "kind": row.kind,
+                        "channel": row.channel, "provider": row.provider, "event": payload,
+                    }));
+                }
+            } else {
+                for entry in history_store::merge_entries(&rows) {
+                    println!("{}", entry.render());
+                }
+            }
+            Ok(())
+        }
+        CliCommand::Adapter { kind: AdapterCommand::Http(args) } => {
+            let env_addr = std::env::var("ACOMM_HTTP_ADDR").ok();
+            http::start_http_adapter(&http::http_listen_addr(args.listen.as_deref(), env_addr.as_deref())).await
+        }
+        CliCommand::Adapter { kind: AdapterCommand::Stdio } => stdio::start_stdio_adapter().await,
+        CliCommand::Adapter { kind: AdapterCommand::Webhook } => webhook::start_webhook_adapter().await,
+        #[cfg(feature = "matrix")]
+        CliCommand::Adapter { kind: AdapterCommand::Matrix } => loop {
+            match matrix::start_matrix_adapter().await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    let message = e.to_string();
+                    if should_retry_matrix_adapter_error(&message) {
+                        tracing::warn!("Matrix adapter transient error; retrying in 2s ({})", message);
+                        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+                        continue;
+                    }
+                    return Err(e);
+                }
+            }
+        },
     }
 }
 
-async fn ensure_bridge_connection(auto_start: bool) -> Result<UnixStream, Box<dyn Error>> {
+/// ブリッジに接続してプロトコルバージョンを名乗った AcommClient を返す。
+/// 非互換ならブリッジが理由を送って切断する。
+async fn ensure_bridge_connection(auto_start: bool) -> Result<AcommClient, Box<dyn Error>> {
     if !auto_start {
-        return UnixStream::connect(SOCKET_PATH)
-            .await
-            .map_err(|e| format!("Bridge not running: {e}").into());
+        let client = AcommClient::connect(SOCKET_PATH).await.map_err(|e| {
+            CliError::new(EXIT_BRIDGE_UNREACHABLE, format!("bridge unreachable at {SOCKET_PATH} ({e})"))
+        })?;
+        return Ok(client);
     }
-    for _ in 0..3 {
-        match UnixStream::connect(SOCKET_PATH).await {
-            Ok(s) => return Ok(s),
-            Err(_) => {
-                if Path::new(SOCKET_PATH).exists() {
-                    let _ = std::fs::remove_file(SOCKET_PATH);
+    let schedule = bridge_connect_backoff_schedule(
+        BRIDGE_CONNECT_ATTEMPTS,
+        BRIDGE_CONNECT_BASE_DELAY,
+        BRIDGE_CONNECT_MAX_DELAY,
+    );
+    let stream = connect_or_start_bridge(
+        Path::new(SOCKET_PATH),
+        Path::new(bridge::BRIDGE_PID_PATH),
+        &schedule,
+        &mut spawn_detached_bridge,
+    )
+    .await?;
+    Ok(AcommClient::from_stream(stream).await?)
+}
+
+/// 接続に失敗したときの対応。
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConnectFailureAction {
+    /// ソケットがないので bridge を起動する。
+    StartBridge,
+    /// 誰も listen しておらず bridge プロセスも生きていないので、古いソケットを消して起動し直す。
+    ReplaceStaleSocket,
+    /// 一時的な失敗か、bridge が生きている（起動中・busy）ので待つだけにする。
+    Wait,
+}
+
+/// ECONNREFUSED でも、STALE_SOCKET_CONFIRM_ATTEMPTS 回続けて拒否され、かつ PID ファイルの
+/// プロセスが生きていない場合だけ古いソケットとみなす。健全な bridge のソケットを消さないため。
+fn connect_failure_action(kind: io::ErrorKind, failures: u32, bridge_process_alive: bool) -> ConnectFailureAction {
+    match kind {
+        io::ErrorKind::NotFound => ConnectFailureAction::StartBridge,
+        io::ErrorKind::ConnectionRefused
+            if failures >= STALE_SOCKET_CONFIRM_ATTEMPTS && !bridge_process_alive =>
+        {
+            ConnectFailureAction::ReplaceStaleSocket
+        }
+        _ => ConnectFailureAction::Wait,
+    }
+}
+
+/// `schedule` の間隔で接続を試み、必要なら一度だけ `start_bridge` で bridge を起動する。
+async fn connect_or_start_bridge(
+    socket_path: &Path,
+    pid_path: &Path,
+    schedule: &[std::time::Duration],
+    start_bridge: &mut dyn FnMut() -> io::Result<()>,
+) -> Result<UnixStream, Box<dyn Error>> {
+    let mut spawned = false;
+    let mut failures = 0;
+    for delay in schedule {
+        let e = match UnixStream::connect(socket_path).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => e,
+        };
+        failures += 1;
+        if !spawned {
+            let pid = bridge::read_bridge_pid(pid_path);
+            let alive = pid.is_some_and(bridge::process_alive);
+            match connect_failure_action(e.kind(), failures, alive) {
+                ConnectFailureAction::StartBridge => {
+                    tracing::info!("no bridge socket at {}; starting a bridge", socket_path.display());
+                    start_bridge()?;
+                    spawned = true;
                 }
-                let exe = std::env::current_exe()?;
-                let _ = std::process::Command::new(exe).arg("--bridge").spawn();
-                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+                ConnectFailureAction::ReplaceStaleSocket => {
+                    tracing::warn!(
+                        "stale bridge socket at {} ({}; no live bridge process); removing it and starting a bridge",
+                        socket_path.display(),
+                        e
+                    );
+                    let _ = std::fs::remove_file(socket_path);
+                    start_bridge()?;
+                    spawned = true;
+                }
+                ConnectFailureAction::Wait => match pid.filter(|_| alive) {
+                    Some(pid) => tracing::info!("bridge process {} is alive but not accepting ({}); waiting", pid, e),
+                    None => tracing::info!("bridge connect failed ({}); retrying", e),
+                },
             }
+        } else {
+            tracing::debug!("waiting for the started bridge ({})", e);
         }
+        tokio::time::sleep(with_jitter(*delay, jitter_seed())).await;
+    }
+    Err(CliError::new(
+        EXIT_BRIDGE_UNREACHABLE,
+        format!("bridge unreachable at {} (failed to start or connect)", socket_path.display()),
+    )
+    .into())
+}
+
+/// 自分自身を `--bridge` で切り離して起動する。端末を持たないので、ログはファイルに残す。
+fn spawn_detached_bridge() -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let mut command = std::process::Command::new(exe);
+    command
+        .arg("--bridge")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    if let Some(log_path) = logging::default_bridge_log_path() {
+        command.arg("--log-file").arg(log_path);
     }
-    Err("Failed to start or connect to bridge.".into())
+    command.spawn().map(|_| ())
 }
 
+const BRIDGE_CONNECT_ATTEMPTS: u32 = 6;
+const BRIDGE_CONNECT_BASE_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+const BRIDGE_CONNECT_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(2);
+/// ECONNREFUSED がこの回数続くまでは古いソケットとみなさない。
+const STALE_SOCKET_CONFIRM_ATTEMPTS: u32 = 2;
+
+/// 接続リトライの待機時間（ジッター適用前）を返す。base から倍々に増やし max で頭打ちにする。
+fn bridge_connect_backoff_schedule(
+    attempts: u32,
+    base: std::time::Duration,
+    max: std::time::Duration,
+) -> Vec<std::time::Duration> {
+    (0..attempts)
+        .map(|i| base.saturating_mul(1u32 << i.min(16)).min(max))
+        .collect()
+}
+
+/// 待機時間を [delay/2, delay) の範囲にばらして、同時起動した TUI 同士の衝突を避ける。
+fn with_jitter(delay: std::time::Duration, seed: u64) -> std::time::Duration {
+    let half = delay / 2;
+    let half_nanos = half.as_nanos().max(1) as u64;
+    half + std::time::Duration::from_nanos(seed % half_nanos)
+}
+
+fn jitter_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    hasher.finish()
+}
+
+/// stderr に質問を出し、標準入力の y / yes で true を返す（EOF は false）。
+async fn confirm_on_stderr(question: &str) -> io::Result<bool> {
+    eprint!("{}", question);
+    io::Write::flush(&mut io::stderr())?;
+    let mut answer = String::new();
+    BufReader::new(tokio::io::stdin()).read_line(&mut answer).await?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// `--reset --hard`: stderr で確認を取ったうえで（`--yes` なら省略）、ブリッジのメモリ上の状態に加えて
+/// ディスク上の永続化データも削除する。ブリッジが停止していてもファイル削除は行う。
+async fn hard_reset(yes: bool) -> Result<(), Box<dyn Error>> {
+    let question = "This will delete the persisted backlog and TUI input history. Continue? [y/N] ";
+    if !yes && !confirm_on_stderr(question).await? {
+        eprintln!("Aborted.");
+        return Ok(());
+    }
+
+    if let Err(e) = publish_to_bridge("/clear --all", Some("bridge")).await {
+        eprintln!("Bridge not cleared ({}); removing persisted files only.", e);
+    }
+    let removed = bridge::purge_persisted_state(true)?;
+    if removed.is_empty() {
+        eprintln!("No persisted state found.");
+    }
+    for path in removed {
+        eprintln!("Removed {}", path.display());
+    }
+    Ok(())
+}
+
+const PUBLISH_ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Prompt を送り、ブリッジが受け付けた印の PromptAck が返るまで待つ。
 async fn publish_to_bridge(msg: &str, channel: Option<&str>) -> Result<(), Box<dyn Error>> {
-    let mut stream = ensure_bridge_connection(false).await?;
+    let mut client = ensure_bridge_connection(false).await?;
     let event = ProtocolEvent::Prompt {
         text: msg.to_string(),
         provider: None,
         channel: channel.map(|s| s.to_string()),
+        reply_to: None,
+        priority: false,
+        author: None,
     };
-    let j = serde_json::to_string(&event)?;
-    stream.write_all(format!("{}\n", j).as_bytes()).await?;
-    let _ = stream.shutdown().await;
+    let acked = tokio::time::timeout(PUBLISH_ACK_TIMEOUT, client.publish(event)).await;
+    let (_, mut writer) = client.into_split();
+    let _ = writer.shutdown().await;
+    match acked {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(e)) => Err(client_error(e)),
+        Err(_) => Err(CliError::new(
+            EXIT_TIMEOUT,
+            format!("bridge did not acknowledge the prompt within {} seconds", PUBLISH_ACK_TIMEOUT.as_secs()),
+        )
+        .into()),
+    }
+}
+
+/// --publish-lines の入力をレコードに分ける。区切りがなければ1行1件。
+/// 各レコードの前後の空白は落とし、空になったレコードは送らない。
+fn split_publish_records(input: &str, delimiter: Option<&str>) -> Vec<String> {
+    let records: Vec<&str> = match delimiter.filter(|d| !d.is_empty()) {
+        Some(delimiter) => input.split(delimiter).collect(),
+        None => input.lines().collect(),
+    };
+    records.into_iter().map(str::trim).filter(|r| !r.is_empty()).map(str::to_string).collect()
+}
+
+/// 標準入力の各レコードを別々の Prompt として送る。ブリッジのキューを溢れさせないよう、
+/// 1件ごとに PromptAck を待ち、次を送る前に `delay` 空ける。
+async fn publish_lines(channel: Option<&str>, delimiter: Option<&str>, delay: std::time::Duration) -> Result<(), Box<dyn Error>> {
+    let mut input = String::new();
+    tokio::io::stdin().read_to_string(&mut input).await?;
+    let records = split_publish_records(&input, delimiter);
+    if records.is_empty() {
+        eprintln!("No prompts on stdin");
+        return Ok(());
+    }
+    let mut client = ensure_bridge_connection(false).await?;
+    let total = records.len();
+    for (i, text) in records.into_iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(delay).await;
+        }
+        let event = ProtocolEvent::Prompt {
+            text,
+            provider: None,
+            channel: channel.map(|s| s.to_string()),
+            reply_to: None,
+            priority: false,
+            author: None,
+        };
+        match tokio::time::timeout(PUBLISH_ACK_TIMEOUT, client.publish(event)).await {
+            Ok(Ok(_)) => eprintln!("Published {}/{}", i + 1, total),
+            Ok(Err(e)) => return Err(client_error(e)),
+            Err(_) => {
+                return Err(CliError::new(
+                    EXIT_TIMEOUT,
+                    format!(
+                        "bridge did not acknowledge prompt {}/{} within {} seconds",
+                        i + 1,
+                        total,
+                        PUBLISH_ACK_TIMEOUT.as_secs()
+                    ),
+                )
+                .into());
+            }
+        }
+    }
+    let (_, mut writer) = client.into_split();
+    let _ = writer.shutdown().await;
     Ok(())
 }
 
-async fn start_dump() -> Result<(), Box<dyn Error>> {
-    let stream = ensure_bridge_connection(false).await?;
-    let mut lines = BufReader::new(stream).lines();
-    let mut provider = "bot".to_string();
-    loop {
-        match tokio::time::timeout(std::time::Duration::from_millis(100), lines.next_line()).await {
-            Ok(Ok(Some(line))) => {
-                if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
-                    display_event(&event, &mut provider, &mut true)?;
+/// 途中でブリッジとの接続が切れた。ブリッジが落ちたとみなして EXIT_BRIDGE_UNREACHABLE で終了する。
+fn bridge_disconnected() -> CliError {
+    CliError::new(EXIT_BRIDGE_UNREACHABLE, format!("bridge at {SOCKET_PATH} closed the connection"))
+}
+
+/// AcommClient のエラーを、理由に応じた終了コードの CliError にする。
+fn client_error(e: ClientError) -> Box<dyn Error> {
+    match e {
+        ClientError::Disconnected => bridge_disconnected().into(),
+        ClientError::Timeout(after) => {
+            CliError::new(EXIT_TIMEOUT, format!("no reply after {} seconds", after.as_secs())).into()
+        }
+        ClientError::Agent { reason, .. } => CliError::new(EXIT_AGENT_ERROR, reason).into(),
+        other => other.into(),
+    }
+}
+
+const DEFAULT_WAIT_TIMEOUT_SECS: u64 = 120;
+const ASK_CHANNEL: &str = "cli";
+
+/// Prompt を送り、同じチャンネルの返答を最後まで集めて標準出力に書き出す。
+/// 返答はライブのイベントからだけ集めるので、バックログにある過去の返答を今回の返答と取り違えない。
+/// `stream` (--ask) では AgentChunk を届いた順に書き出し、provider/model の切り替えを stderr に出す。
+async fn publish_and_wait(
+    msg: &str,
+    channel: &str,
+    timeout: std::time::Duration,
+    stream: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut client = ensure_bridge_connection(false).await?;
+    let deadline = tokio::time::Instant::now() + timeout;
+    let timed_out = || CliError::new(EXIT_TIMEOUT, format!("no reply after {} seconds", timeout.as_secs()));
+    let prompt = ProtocolEvent::Prompt {
+        text: msg.to_string(),
+        provider: None,
+        channel: Some(channel.to_string()),
+        reply_to: None,
+        priority: false,
+        author: None,
+    };
+    let id = match tokio::time::timeout(timeout, client.publish(prompt)).await {
+        Ok(id) => id.map_err(client_error)?,
+        Err(_) => return Err(timed_out().into()),
+    };
+    let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+    let result = client
+        .wait_for_reply_with(&id, remaining, |event| {
+            if !stream {
+                return;
+            }
+            match event {
+                ProtocolEvent::AgentChunk { chunk, channel: Some(ch) } if ch == channel => {
+                    print!("{}", chunk);
+                    let _ = io::Write::flush(&mut io::stdout());
+                }
+                ProtocolEvent::ProviderSwitched { provider } => {
+                    eprintln!("[acomm] provider: {}", provider.command_name());
                 }
+                ProtocolEvent::ModelSwitched { model } => eprintln!("[acomm] model: {}", model),
+                _ => {}
+            }
+        })
+        .await;
+    match result {
+        Ok(reply) => {
+            if !stream {
+                print!("{}", reply);
             }
-            _ => break,
+            if !reply.ends_with('\n') {
+                println!();
+            }
+            Ok(())
+        }
+        Err(ClientError::Agent { reason, partial }) => {
+            if !stream {
+                print!("{}", partial);
+            }
+            Err(CliError::new(EXIT_AGENT_ERROR, reason).into())
+        }
+        Err(ClientError::Timeout(_)) => Err(timed_out().into()),
+        Err(e) => Err(client_error(e)),
+    }
+}
+
+/// 記録ファイルから Prompt だけを取り出す。記録は ProtocolEvent の JSON 配列か、
+/// `--dump --format json` の出力と同じ 1 行 1 イベントのどちらでもよい。
+/// 再生時はその時点の bridge の provider を使うため、記録された provider は外す。
+/// ProtocolEvent として解釈できない要素（未知のイベント等）も読み飛ばす。
+fn parse_replay_prompts(content: &str) -> Result<Vec<ProtocolEvent>, serde_json::Error> {
+    let values: Vec<serde_json::Value> = if content.trim_start().starts_with('[') {
+        serde_json::from_str(content)?
+    } else {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?
+    };
+    Ok(values
+        .into_iter()
+        .filter_map(|v| serde_json::from_value::<ProtocolEvent>(v).ok())
+        .filter_map(|event| match event {
+            ProtocolEvent::Prompt { text, channel, reply_to, priority, .. } => Some(ProtocolEvent::Prompt {
+                text,
+                provider: None,
+                channel,
+                reply_to,
+                priority,
+                author: None,
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+async fn replay_session(path: &Path, delay: std::time::Duration) -> Result<(), Box<dyn Error>> {
+    let content = tokio::fs::read_to_string(path).await?;
+    let prompts = parse_replay_prompts(&content)
+        .map_err(|e| format!("{}: expected a JSON array of events or one event per line ({})", path.display(), e))?;
+    let mut client = ensure_bridge_connection(false).await?;
+    let total = prompts.len();
+    for (i, event) in prompts.iter().enumerate() {
+        if i > 0 {
+            tokio::time::sleep(delay).await;
+        }
+        client.send(event).await?;
+        eprintln!("Replayed {}/{}", i + 1, total);
+    }
+    let (_, mut writer) = client.into_split();
+    let _ = writer.shutdown().await;
+    Ok(())
+}
+
+/// 初期同期（BridgeSyncDone まで）を読み切ってから出力するので、遅いマシンでも途中で切れない。
+async fn start_dump(
+    format: OutputFormat,
+    channel_prefix: Option<&str>,
+    last: Option<usize>,
+) -> Result<(), Box<dyn Error>> {
+    let (mut reader, _writer) = ensure_bridge_connection(false).await?.into_split();
+    let events = reader.read_initial_sync(DUMP_IDLE_TIMEOUT).await?;
+    let mut provider = "bot".to_string();
+    for (line, event) in select_dump_events(events, channel_prefix, last) {
+        match format {
+            OutputFormat::Json => println!("{}", line),
+            OutputFormat::Pretty => display_event(&event, &mut provider, &mut true)?,
         }
     }
     Ok(())
 }
 
+/// BridgeSyncDone を送らない古いブリッジ向けのフォールバック: この間なにも届かなければ同期完了とみなす。
+const DUMP_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// `--channel` の前方一致で絞り込み（チャンネルを持たないイベントは除外）、`--last` で末尾 N 件に制限する。
+fn select_dump_events(
+    events: Vec<(String, ProtocolEvent)>,
+    channel_prefix: Option<&str>,
+    last: Option<usize>,
+) -> Vec<(String, ProtocolEvent)> {
+    let mut selected: Vec<_> = events
+        .into_iter()
+        .filter(|(_, event)| match channel_prefix {
+            Some(prefix) => event.clone_channel().is_some_and(|ch| ch.starts_with(prefix)),
+            None => true,
+        })
+        .collect();
+    if let Some(n) = last {
+        let skip = selected.len().saturating_sub(n);
+        selected.drain(..skip);
+    }
+    selected
+}
+
 fn display_event(
     event: &ProtocolEvent,
     active_provider_name: &mut String,
@@ -291,6 +1024,14 @@ fn display_event(
             }
             *is_start_of_line = true;
         }
+        ProtocolEvent::ChannelClosed { channel, reason } => {
+            println!(
+                "\n[System ({})]: {}",
+                channel.as_deref().unwrap_or("bridge"),
+                reason
+            );
+            *is_start_of_line = true;
+        }
         ProtocolEvent::ProviderSwitched { provider } => {
             *active_provider_name = provider.command_name().to_string();
             println!(
@@ -307,6 +1048,11 @@ fn display_event(
             );
             *is_start_of_line = true;
         }
+        ProtocolEvent::Hello { version } => {
+            if let Some(msg) = protocol::version_mismatch_message("bridge", *version) {
+                eprintln!("{}", msg);
+            }
+        }
         _ => {}
     }
     io::Write::flush(&mut io::stdout())?;
@@ -315,16 +1061,14 @@ fn display_event(
 
 /// bridge に接続し、バックログをスキップしてから最初の Prompt イベントを待つ。
 /// チャンネルフィルタに合致した Prompt の text を stdout に出力して exit 0。
-/// timeout_secs 以内に合致する入力がなければ stderr にメッセージを出して exit 1。
+/// timeout_secs 以内に合致する入力がなければ EXIT_TIMEOUT で終了する。
 async fn receive_from_bridge(
     discord: bool,
     slack: bool,
     ntfy: bool,
     timeout_secs: Option<u64>,
 ) -> Result<(), Box<dyn Error>> {
-    let stream = ensure_bridge_connection(false).await?;
-    let mut lines = BufReader::new(stream).lines();
-    let mut sync_done = false;
+    let (mut reader, _writer) = ensure_bridge_connection(false).await?.into_split();
 
     // デッドラインを一度だけ作成して pin する。
     // タイムアウト未指定時は事実上無限大（≈584年）にする。
@@ -337,30 +1081,21 @@ async fn receive_from_bridge(
     loop {
         tokio::select! {
             _ = &mut sleep => {
-                eprintln!(
-                    "acomm --receive: no input after {} seconds.",
-                    timeout_secs.unwrap_or(0)
-                );
-                std::process::exit(1);
-            }
-            line_res = lines.next_line() => {
-                let line = match line_res? {
-                    Some(l) => l,
-                    None => return Err("Bridge disconnected.".into()),
+                return Err(CliError::new(
+                    EXIT_TIMEOUT,
+                    format!("no input after {} seconds", timeout_secs.unwrap_or(0)),
+                )
+                .into());
+            }
+            // バックログの再生は読み飛ばし、BridgeSyncDone 以降のみ処理する。
+            event_res = reader.next_live_event() => {
+                let Some(event) = event_res? else {
+                    return Err(bridge_disconnected().into());
                 };
-                if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
-                    // バックログの再生を読み飛ばし、BridgeSyncDone 以降のみ処理する。
-                    if !sync_done {
-                        if matches!(event, ProtocolEvent::BridgeSyncDone {}) {
-                            sync_done = true;
-                        }
-                        continue;
-                    }
-                    if let ProtocolEvent::Prompt { text, channel, .. } = event {
-                        if channel_passes_filter(channel.as_deref(), discord, slack, ntfy) {
-                            println!("{}", text);
-                            return Ok(());
-                        }
+                if let ProtocolEvent::Prompt { text, channel, .. } = event {
+                    if channel_passes_filter(channel.as_deref(), discord, slack, ntfy) {
+                        println!("{}", text);
+                        return Ok(());
                     }
                 }
             }
@@ -406,6 +1141,12 @@ fn should_retry_discord_adapter_error(message: &str) -> bool {
     matches!(code, Some(1000) | Some(1001) | Some(1012) | Some(1013))
 }
 
+/// Matrix の同期失敗（ネットワーク・5xx など）とホームサーバーへの接続失敗は再試行し、トークン拒否は諦める。
+#[cfg(feature = "matrix")]
+fn should_retry_matrix_adapter_error(message: &str) -> bool {
+    message.starts_with("Matrix sync failed") || message.starts_with("Could not connect to Matrix homeserver")
+}
+
 /// Slack Socket Mode の一時切断/タイムアウトだけを再試行対象にする。
 fn should_retry_slack_adapter_error(message: &str) -> bool {
     let m = message.to_ascii_lowercase();
@@ -601,6 +1342,355 @@ mod tests {
         assert!(!should_retry_slack_adapter_error(
             "apps.connections.open failed: {\"ok\":false,\"error\":\"invalid_auth\"}"
         ));
+        assert!(!should_retry_slack_adapter_error(
+            "Slack app access revoked (tokens_revoked); reinstall the app and update SLACK_BOT_TOKEN / SLACK_APP_TOKEN"
+        ));
+    }
+
+    #[cfg(feature = "matrix")]
+    #[test]
+    fn matrix_retry_error_retries_sync_failures_but_not_rejected_tokens() {
+        assert!(should_retry_matrix_adapter_error("Matrix sync failed: error sending request"));
+        assert!(should_retry_matrix_adapter_error("Matrix sync failed with HTTP 502 Bad Gateway: "));
+        assert!(should_retry_matrix_adapter_error("Could not connect to Matrix homeserver https://m.org: dns error"));
+        assert!(!should_retry_matrix_adapter_error("Matrix access token rejected (HTTP 401 Unauthorized): {}"));
+        assert!(!should_retry_matrix_adapter_error("MATRIX_ROOM_IDS is not set"));
+    }
+
+    #[test]
+    fn bridge_connect_backoff_schedule_doubles_and_caps() {
+        let ms = std::time::Duration::from_millis;
+        let schedule = bridge_connect_backoff_schedule(6, ms(100), ms(2000));
+        assert_eq!(
+            schedule,
+            vec![ms(100), ms(200), ms(400), ms(800), ms(1600), ms(2000)]
+        );
+    }
+
+    #[test]
+    fn refused_socket_is_only_replaced_when_confirmed_and_no_bridge_process() {
+        use io::ErrorKind::{ConnectionRefused, NotFound, WouldBlock};
+        assert_eq!(connect_failure_action(NotFound, 1, false), ConnectFailureAction::StartBridge);
+        assert_eq!(connect_failure_action(ConnectionRefused, 1, false), ConnectFailureAction::Wait);
+        assert_eq!(connect_failure_action(ConnectionRefused, 2, false), ConnectFailureAction::ReplaceStaleSocket);
+        assert_eq!(connect_failure_action(ConnectionRefused, 5, true), ConnectFailureAction::Wait);
+        assert_eq!(connect_failure_action(WouldBlock, 5, false), ConnectFailureAction::Wait);
+    }
+
+    fn connect_test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("acomm-connect-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// 誰も listen していないソケットファイルを残す。
+    fn leave_dead_socket(path: &Path) {
+        drop(std::os::unix::net::UnixListener::bind(path).unwrap());
+    }
+
+    #[tokio::test]
+    async fn slow_live_bridge_keeps_its_socket() {
+        let dir = connect_test_dir("slow");
+        let socket = dir.join("acomm.sock");
+        let pid_file = dir.join("acomm.pid");
+        leave_dead_socket(&socket);
+        std::fs::write(&pid_file, std::process::id().to_string()).unwrap();
+
+        // 生きている bridge が少し遅れて listen を始める。ソケットは rename で差し替え、途中で消えないようにする。
+        let staging = dir.join("staging.sock");
+        let late = {
+            let (socket, staging) = (socket.clone(), staging.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+                let listener = tokio::net::UnixListener::bind(&staging).unwrap();
+                std::fs::rename(&staging, &socket).unwrap();
+                listener.accept().await.unwrap()
+            })
+        };
+
+        let mut starts = 0;
+        let schedule = vec![std::time::Duration::from_millis(100); 8];
+        let stream = connect_or_start_bridge(&socket, &pid_file, &schedule, &mut || {
+            starts += 1;
+            Ok(())
+        })
+        .await;
+        assert!(stream.is_ok(), "should connect once the bridge listens");
+        assert_eq!(starts, 0, "a live bridge must not be replaced");
+        late.await.unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn stale_socket_without_bridge_process_is_replaced_once() {
+        let dir = connect_test_dir("stale");
+        let socket = dir.join("acomm.sock");
+        let pid_file = dir.join("acomm.pid");
+        leave_dead_socket(&socket);
+
+        let mut started: Vec<std::os::unix::net::UnixListener> = Vec::new();
+        let schedule = vec![std::time::Duration::from_millis(20); 6];
+        let stream = connect_or_start_bridge(&socket, &pid_file, &schedule, &mut || {
+            started.push(std::os::unix::net::UnixListener::bind(&socket)?);
+            Ok(())
+        })
+        .await;
+        assert!(stream.is_ok());
+        assert_eq!(started.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn with_jitter_stays_within_half_to_full_delay() {
+        let delay = std::time::Duration::from_millis(800);
+        for seed in [0, 1, 399_999_999, u64::MAX] {
+            let jittered = with_jitter(delay, seed);
+            assert!(jittered >= delay / 2);
+            assert!(jittered < delay);
+        }
+    }
+
+    #[test]
+    fn hard_flag_requires_reset() {
+        assert!(CliArgs::try_parse_from(["acomm", "--hard"]).is_err());
+        let args = CliArgs::try_parse_from(["acomm", "--reset", "--hard"])
+            .expect("--reset --hard should parse");
+        assert!(args.reset);
+        assert!(args.hard);
+    }
+
+    #[test]
+    fn scoped_reset_takes_a_channel_but_not_hard() {
+        let args = CliArgs::try_parse_from(["acomm", "--reset", "--channel", "discord:123", "--yes"])
+            .expect("--reset --channel should parse");
+        assert_eq!(args.channel.as_deref(), Some("discord:123"));
+        assert!(args.yes);
+        assert!(CliArgs::try_parse_from(["acomm", "--reset", "--hard", "--channel", "discord:123"]).is_err());
+        assert!(CliArgs::try_parse_from(["acomm", "--yes"]).is_err());
+    }
+
+    #[test]
+    fn default_tui_channel_is_per_user_and_host() {
+        assert_eq!(default_tui_channel(Some("yui"), Some("laptop.local\n")), "tui:yui@laptop");
+        assert_eq!(default_tui_channel(Some("yui"), None), "tui:yui");
+        assert_eq!(default_tui_channel(Some("first last"), Some(" ")), "tui:first-last");
+        assert_eq!(default_tui_channel(None, Some("laptop")), "tui");
+        assert_eq!(default_tui_channel(Some(""), Some("laptop")), "tui");
+        assert!(validate_channel(&default_tui_channel(Some("a b"), Some("c\td"))).is_ok());
+    }
+
+    #[test]
+    fn no_autostart_flag_parses_and_explains_the_failure() {
+        assert!(!CliArgs::try_parse_from(["acomm"]).unwrap().no_autostart);
+        let args = CliArgs::try_parse_from(["acomm", "--no-autostart", "--channel", "tui:ops"]).unwrap();
+        assert!(args.no_autostart);
+        let msg = no_autostart_message("bridge unreachable at /tmp/acomm.sock (No such file or directory)");
+        assert!(msg.starts_with("bridge unreachable at /tmp/acomm.sock"));
+        assert!(msg.contains("--no-autostart") && msg.contains("acomm --bridge"));
+    }
+
+    #[test]
+    fn parse_replay_prompts_keeps_prompts_in_order_without_provider() {
+        let recorded = r#"[
+            {"ProviderSwitched":{"provider":"Claude"}},
+            {"Prompt":{"text":"first","provider":"Claude","channel":"tui"}},
+            {"AgentChunk":{"chunk":"answer","channel":"tui"}},
+            {"FutureEvent":{"x":1}},
+            {"Prompt":{"text":"second","provider":null,"channel":"discord:1:2"}},
+            {"AgentDone":{"channel":"tui"}}
+        ]"#;
+        let prompts = parse_replay_prompts(recorded).unwrap();
+        let summary: Vec<(String, Option<String>)> = prompts
+            .into_iter()
+            .map(|e| match e {
+                ProtocolEvent::Prompt { text, provider, channel, .. } => {
+                    assert!(provider.is_none());
+                    (text, channel)
+                }
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("first".to_string(), Some("tui".to_string())),
+                ("second".to_string(), Some("discord:1:2".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn publish_lines_splits_stdin_into_non_empty_records() {
+        let input = "要約して: a.md\n\n  translate b.md  \r\n\t\nlast line";
+        assert_eq!(split_publish_records(input, None), vec!["要約して: a.md", "translate b.md", "last line"]);
+        let records = "first\nrecord\n---\n\n---\nsecond record\n---\n";
+        assert_eq!(split_publish_records(records, Some("---")), vec!["first\nrecord", "second record"]);
+        assert_eq!(split_publish_records("a\nb", Some("")), vec!["a", "b"]);
+        assert!(split_publish_records(" \n\n", None).is_empty());
+    }
+
+    #[test]
+    fn publish_lines_options_parse_and_conflict() {
+        let args = CliArgs::try_parse_from(["acomm", "--publish-lines", "--delimiter", "---", "--channel", "batch"])
+            .expect("--publish-lines should parse");
+        assert!(args.publish_lines);
+        assert_eq!(args.delimiter.as_deref(), Some("---"));
+        assert_eq!(args.publish_delay_ms, 500);
+        assert!(CliArgs::try_parse_from(["acomm", "--delimiter", "---"]).is_err());
+        assert!(CliArgs::try_parse_from(["acomm", "--publish-lines", "--publish", "x"]).is_err());
+        assert!(CliArgs::try_parse_from(["acomm", "--publish-lines", "--wait"]).is_err());
+    }
+
+    #[test]
+    fn parse_replay_prompts_reads_dump_json_output() {
+        // `--dump --format json` は 1 行 1 イベントで書き出す。その出力をそのまま再生できる。
+        let dumped: String = select_dump_events(dump_fixture(), None, None)
+            .into_iter()
+            .map(|(line, _)| format!("{}\n", line))
+            .collect();
+        let texts: Vec<String> = parse_replay_prompts(&dumped)
+            .unwrap()
+            .into_iter()
+            .map(|e| match e {
+                ProtocolEvent::Prompt { text, provider: None, .. } => text,
+                other => panic!("unexpected event: {:?}", other),
+            })
+            .collect();
+        assert_eq!(texts, vec!["a", "b", "c"]);
+        assert!(parse_replay_prompts("{\"Prompt\":\n").is_err());
+    }
+
+    #[test]
+    fn wait_flag_requires_publish() {
+        assert!(CliArgs::try_parse_from(["acomm", "--wait"]).is_err());
+        let args = CliArgs::try_parse_from(["acomm", "-p", "-", "--wait", "--timeout", "30"])
+            .expect("--publish --wait should parse");
+        assert!(args.wait);
+        assert_eq!(args.timeout, Some(30));
+    }
+
+    fn dump_fixture() -> Vec<(String, ProtocolEvent)> {
+        [
+            ProtocolEvent::ProviderSwitched { provider: AgentProvider::Gemini },
+            ProtocolEvent::Prompt { text: "a".into(), provider: None, channel: Some("discord:1:10".into()), reply_to: None, priority: false, author: None },
+            ProtocolEvent::Prompt { text: "b".into(), provider: None, channel: Some("tui".into()), reply_to: None, priority: false, author: None },
+            ProtocolEvent::AgentDone { channel: Some("discord:1:10".into()), provider: None, model: None },
+            ProtocolEvent::Prompt { text: "c".into(), provider: None, channel: Some("discord:2:11".into()), reply_to: None, priority: false, author: None },
+        ]
+        .into_iter()
+        .map(|event| (serde_json::to_string(&event).unwrap(), event))
+        .collect()
+    }
+
+    #[test]
+    fn dump_selection_filters_by_channel_prefix_and_keeps_last_n() {
+        assert_eq!(select_dump_events(dump_fixture(), None, None).len(), 5);
+
+        let discord = select_dump_events(dump_fixture(), Some("discord:"), None);
+        let lines: Vec<_> = discord.iter().map(|(line, _)| line.as_str()).collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines.iter().all(|l| l.contains("discord:")));
+
+        let tail = select_dump_events(dump_fixture(), Some("discord:"), Some(2));
+        assert!(matches!(&tail[0].1, ProtocolEvent::AgentDone { .. }));
+        assert!(matches!(&tail[1].1, ProtocolEvent::Prompt { text, .. } if text == "c"));
+        assert_eq!(select_dump_events(dump_fixture(), None, Some(10)).len(), 5);
+    }
+
+    #[test]
+    fn format_flag_parses_json_and_last_requires_dump() {
+        let args = CliArgs::try_parse_from(["acomm", "--dump", "--format", "json", "--last", "3"]).unwrap();
+        assert_eq!(args.format, OutputFormat::Json);
+        assert_eq!(args.last, Some(3));
+        assert_eq!(CliArgs::try_parse_from(["acomm", "--subscribe"]).unwrap().format, OutputFormat::Pretty);
+        assert!(CliArgs::try_parse_from(["acomm", "--last", "3"]).is_err());
+    }
+
+    #[test]
+    fn follow_resumes_after_the_last_line_seen_before_disconnect() {
+        let lines = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        let recent = lines(&["a", "b", "c"]);
+        // 切断中に d, e が届いた: 既に見た c の直後から
+        assert_eq!(resume_point(&lines(&["p", "m", "a", "b", "c", "d", "e"]), &recent), 5);
+        // 何も届いていない: 再生分はすべて出力済み
+        assert_eq!(resume_point(&lines(&["a", "b", "c"]), &recent), 3);
+        // バックログの先頭が切り詰められて b, c だけ残っている
+        assert_eq!(resume_point(&lines(&["b", "c", "d"]), &recent), 2);
+        // ブリッジが再起動して一致しない: すべて新しいイベント
+        assert_eq!(resume_point(&lines(&["p", "m"]), &recent), 0);
+        assert_eq!(resume_point(&lines(&["p"]), &[]), 0);
+    }
+
+    #[test]
+    fn follow_requires_subscribe() {
+        assert!(CliArgs::try_parse_from(["acomm", "--follow"]).is_err());
+        assert!(CliArgs::try_parse_from(["acomm", "-s", "--follow"]).unwrap().follow);
+    }
+
+    #[test]
+    fn subscribe_filter_matches_channel_prefix_and_optional_system_events() {
+        let discord = ProtocolEvent::AgentChunk { chunk: "x".into(), channel: Some("discord:1:2".into()) };
+        let tui = ProtocolEvent::AgentChunk { chunk: "x".into(), channel: Some("tui".into()) };
+        let switched = ProtocolEvent::ProviderSwitched { provider: AgentProvider::Claude };
+        let status = ProtocolEvent::StatusUpdate { is_processing: true, channel: Some("discord:1:2".into()) };
+
+        let all = SubscribeFilter::default();
+        assert!(all.shows(&discord) && all.shows(&tui) && all.shows(&switched) && all.shows(&status));
+
+        let only_discord = SubscribeFilter { channel_prefix: Some("discord:"), ..Default::default() };
+        assert!(only_discord.shows(&discord));
+        assert!(!only_discord.shows(&tui));
+        assert!(!only_discord.shows(&switched));
+
+        let with_system = SubscribeFilter { channel_prefix: Some("discord:"), all_system: true, ..Default::default() };
+        assert!(with_system.shows(&switched));
+
+        let quiet = SubscribeFilter { quiet_status: true, ..Default::default() };
+        assert!(!quiet.shows(&status));
+        assert!(quiet.shows(&discord));
+    }
+
+    #[test]
+    fn file_conflicts_with_publish_and_works_with_wait() {
+        let args = CliArgs::try_parse_from(["acomm", "--file", "prompt.md", "--prepend", "Review: ", "--wait"])
+            .expect("--file --wait should parse");
+        assert_eq!(args.file.as_deref(), Some(Path::new("prompt.md")));
+        assert!(args.wait);
+        assert!(CliArgs::try_parse_from(["acomm", "--file", "a.md", "--publish", "x"]).is_err());
+        assert!(CliArgs::try_parse_from(["acomm", "--prepend", "x", "--publish", "y"]).is_err());
+    }
+
+    #[test]
+    fn compose_file_prompt_prepends_instruction_and_rejects_empty_files() {
+        assert_eq!(compose_file_prompt("body\n", None).as_deref(), Some("body\n"));
+        assert_eq!(compose_file_prompt("body", Some("Instruction: ")).as_deref(), Some("Instruction:\n\nbody"));
+        assert_eq!(compose_file_prompt("body", Some("  ")).as_deref(), Some("body"));
+        assert_eq!(compose_file_prompt(" \n\t", Some("x")), None);
+    }
+
+    #[test]
+    fn missing_prompt_file_is_an_argument_error() {
+        let err = read_prompt_file(Path::new("/nonexistent/acomm-prompt.md"), None).unwrap_err();
+        assert_eq!(err.code, EXIT_INVALID_ARGS);
+        assert!(err.message.contains("/nonexistent/acomm-prompt.md"));
+    }
+
+    #[test]
+    fn ask_parses_prompt_and_conflicts_with_publish() {
+        let args = CliArgs::try_parse_from(["acomm", "--ask", "what time is it?", "--timeout", "10"])
+            .expect("--ask should parse");
+        assert_eq!(args.ask.as_deref(), Some("what time is it?"));
+        assert_eq!(args.timeout, Some(10));
+        assert!(CliArgs::try_parse_from(["acomm", "--ask", "-", "--publish", "x"]).is_err());
+    }
+
+    #[test]
+    fn metrics_flag_requires_bridge() {
+        assert!(CliArgs::try_parse_from(["acomm", "--metrics", "127.0.0.1:9464"]).is_err());
+        let args = CliArgs::try_parse_from(["acomm", "--bridge", "--metrics", "127.0.0.1:9464"])
+            .expect("--bridge --metrics should parse");
+        assert_eq!(args.metrics.as_deref(), Some("127.0.0.1:9464"));
     }
 
     #[test]
@@ -618,42 +1708,283 @@ mod tests {
             other => panic!("expected logs subcommand, got: {:?}", other),
         }
     }
+
+    #[test]
+    fn doctor_flag_parses_without_a_subcommand() {
+        let args = CliArgs::try_parse_from(["acomm", "--doctor"]).expect("--doctor should parse");
+        assert!(args.doctor);
+        assert!(args.command.is_none());
+    }
+
+    #[test]
+    fn error_line_is_a_single_prefixed_line() {
+        assert_eq!(
+            error_line("error: unexpected argument '--bogus' found"),
+            "acomm: error: unexpected argument '--bogus' found"
+        );
+        assert_eq!(error_line("ntfy rejected\n  HTTP 403"), "acomm: error: ntfy rejected HTTP 403");
+    }
+
+    #[test]
+    fn exit_code_comes_from_cli_errors_only() {
+        let timeout: Box<dyn Error> = CliError::new(EXIT_TIMEOUT, "no reply").into();
+        assert_eq!(exit_code(timeout.as_ref()), 4);
+        let other: Box<dyn Error> = "boom".into();
+        assert_eq!(exit_code(other.as_ref()), EXIT_FAILURE);
+    }
+
+    #[test]
+    fn channels_with_whitespace_are_invalid_arguments() {
+        assert!(validate_channel("discord:123:456").is_ok());
+        for bad in ["", "my channel", "cli\n"] {
+            assert_eq!(validate_channel(bad).unwrap_err().code, EXIT_INVALID_ARGS, "{bad:?}");
+        }
+    }
+}
+
+/// `--subscribe` で表示するイベントの条件。
+#[derive(Debug, Default)]
+struct SubscribeFilter<'a> {
+    /// チャンネルの前方一致 (`--channel`)。
+    channel_prefix: Option<&'a str>,
+    /// 前方一致指定時も ProviderSwitched などチャンネルを持たないイベントを表示する (`--all-system`)。
+    all_system: bool,
+    /// StatusUpdate を表示しない (`--quiet-status`)。
+    quiet_status: bool,
+}
+
+impl SubscribeFilter<'_> {
+    fn shows(&self, event: &ProtocolEvent) -> bool {
+        if self.quiet_status && matches!(event, ProtocolEvent::StatusUpdate { .. }) {
+            return false;
+        }
+        match (self.channel_prefix, event.clone_channel()) {
+            (None, _) => true,
+            (Some(prefix), Some(channel)) => channel.starts_with(prefix),
+            (Some(_), None) => self.all_system,
+        }
+    }
+}
+
+/// `--follow` で再接続したとき、切断前の位置を探すために覚えておく直近の行数。
+const FOLLOW_RESUME_TAIL: usize = 16;
+const FOLLOW_RECONNECT_MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// 再接続をまたいで引き継ぐ `--subscribe` の表示状態。
+struct SubscribeState {
+    format: OutputFormat,
+    spinner_enabled: bool,
+    active_provider_name: String,
+    is_start_of_line: bool,
+    is_thinking: bool,
+    /// 直近に受け取ったバックログ対象イベントの生の行（再接続時の再開位置の特定に使う）。
+    recent: std::collections::VecDeque<String>,
 }
 
-async fn start_subscribe() -> Result<(), Box<dyn Error>> {
-    let stream = ensure_bridge_connection(false).await?;
-    let mut lines = BufReader::new(stream).lines();
-    let mut active_provider_name = "bot".to_string();
-    let mut is_thinking = false;
-    let mut is_start_of_line = true;
+impl SubscribeState {
+    /// 受信した1行を記録し、フィルタに合えば出力する。
+    fn handle_line(&mut self, line: &str, filter: &SubscribeFilter<'_>) -> io::Result<()> {
+        let parsed = serde_json::from_str::<ProtocolEvent>(line);
+        if let Ok(ref event) = parsed {
+            if bridge::is_backlog_event(event) {
+                self.recent.push_back(line.to_string());
+                if self.recent.len() > FOLLOW_RESUME_TAIL {
+                    self.recent.pop_front();
+                }
+            }
+        }
+        if self.format == OutputFormat::Json {
+            if parsed.as_ref().map_or(true, |event| filter.shows(event)) {
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+        let Ok(event) = parsed else { return Ok(()) };
+        if !filter.shows(&event) {
+            // 表示しなくても返答の接頭辞に使うプロバイダー名は追跡する。
+            if let ProtocolEvent::ProviderSwitched { ref provider } = event {
+                self.active_provider_name = provider.command_name().to_string();
+            }
+            return Ok(());
+        }
+        if matches!(event, ProtocolEvent::StatusUpdate { is_processing: true, .. }) {
+            self.is_thinking = self.spinner_enabled;
+        } else if matches!(event, ProtocolEvent::StatusUpdate { is_processing: false, .. } | ProtocolEvent::AgentChunk { .. } | ProtocolEvent::AgentDone { .. } | ProtocolEvent::ChannelClosed { .. }) {
+            self.clear_spinner();
+        }
+        display_event(&event, &mut self.active_provider_name, &mut self.is_start_of_line)
+    }
+
+    fn clear_spinner(&mut self) {
+        if self.is_thinking {
+            print!("\r\x1B[K");
+            self.is_thinking = false;
+        }
+    }
+
+    /// 再接続の区切り。JSON 出力を汚さないよう、json では stderr に出す。
+    fn print_reconnected(&mut self) {
+        if self.format == OutputFormat::Json {
+            eprintln!("--- reconnected ---");
+            return;
+        }
+        if !self.is_start_of_line {
+            println!();
+        }
+        println!("--- reconnected ---");
+        self.is_start_of_line = true;
+    }
+}
+
+/// 再接続時に再生されたバックログ `replay` のうち、まだ出力していない部分の開始位置を返す。
+/// 切断前に受け取った直近の行 `recent` と一致する箇所の直後から再開する
+/// （`replay` の先頭にかかる場合は重なった分だけで判定する）。
+/// 一致しなければブリッジの再起動などで全て新しいイベントとみなし 0 を返す。
+fn resume_point(replay: &[String], recent: &[String]) -> usize {
+    if recent.is_empty() {
+        return 0;
+    }
+    (1..=replay.len())
+        .rev()
+        .find(|&end| {
+            let overlap = end.min(recent.len());
+            replay[end - overlap..end] == recent[recent.len() - overlap..]
+        })
+        .unwrap_or(0)
+}
+
+async fn start_subscribe(format: OutputFormat, filter: SubscribeFilter<'_>, follow: bool) -> Result<(), Box<dyn Error>> {
+    let mut client = ensure_bridge_connection(false).await?;
+    let mut state = SubscribeState {
+        format,
+        // リダイレクト先のログに \r やエスケープシーケンスを書き込まないよう、端末でなければスピナーを出さない。
+        spinner_enabled: format == OutputFormat::Pretty && !filter.quiet_status && io::IsTerminal::is_terminal(&io::stdout()),
+        active_provider_name: "bot".to_string(),
+        is_start_of_line: true,
+        is_thinking: false,
+        recent: std::collections::VecDeque::new(),
+    };
+    if format == OutputFormat::Pretty {
+        println!("--- Subscribed to acomm bridge ---");
+    }
+    let mut reconnected = false;
+    loop {
+        if let Err(e) = subscribe_connection(client, &filter, &mut state, reconnected).await {
+            if !follow {
+                return Err(e);
+            }
+            eprintln!("Bridge connection error: {}", e);
+        }
+        if !follow {
+            return Ok(());
+        }
+        state.clear_spinner();
+        eprintln!("--- bridge disconnected; reconnecting ---");
+        client = reconnect_with_backoff().await;
+        reconnected = true;
+    }
+}
+
+/// 1本の接続でイベントを出力し続け、切断されたら戻る。
+/// `reconnected` のときはバックログの再生を BridgeSyncDone まで溜め、既に出力した分を飛ばす。
+async fn subscribe_connection(
+    client: AcommClient,
+    filter: &SubscribeFilter<'_>,
+    state: &mut SubscribeState,
+    reconnected: bool,
+) -> Result<(), Box<dyn Error>> {
+    let (mut reader, _writer) = client.into_split();
+    let mut replay: Option<Vec<String>> = reconnected.then(Vec::new);
     let spinner_chars = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
     let mut spinner_idx = 0;
-    println!("--- Subscribed to acomm bridge ---");
     loop {
         tokio::select! {
-            line_res = lines.next_line() => {
-                let line = match line_res? { Some(l) => l, None => break };
-                if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
-                    if matches!(event, ProtocolEvent::StatusUpdate { is_processing: true, .. }) { is_thinking = true; }
-                    else if matches!(event, ProtocolEvent::StatusUpdate { is_processing: false, .. } | ProtocolEvent::AgentChunk { .. } | ProtocolEvent::AgentDone { .. }) {
-                        if is_thinking { print!("\r\x1B[K"); is_thinking = false; }
+            line_res = reader.next_line() => {
+                let Some(line) = line_res? else { return Ok(()) };
+                if let Some(buffer) = replay.as_mut() {
+                    match serde_json::from_str::<ProtocolEvent>(&line) {
+                        Ok(ProtocolEvent::BridgeSyncDone {}) => {
+                            let buffer = replay.take().unwrap_or_default();
+                            let start = resume_point(&buffer, state.recent.make_contiguous());
+                            state.print_reconnected();
+                            for replayed in &buffer[start..] {
+                                state.handle_line(replayed, filter)?;
+                            }
+                        }
+                        Ok(ProtocolEvent::Hello { version }) => {
+                            if let Some(msg) = protocol::version_mismatch_message("bridge", version) {
+                                eprintln!("{}", msg);
+                            }
+                        }
+                        _ => buffer.push(line),
                     }
-                    display_event(&event, &mut active_provider_name, &mut is_start_of_line)?;
+                    continue;
                 }
+                state.handle_line(&line, filter)?;
             }
-            _ = tokio::time::sleep(std::time::Duration::from_millis(100)), if is_thinking => {
+            _ = tokio::time::sleep(std::time::Duration::from_millis(100)), if state.is_thinking => {
                 spinner_idx = (spinner_idx + 1) % spinner_chars.len();
                 print!("\r[Status] Thinking {}", spinner_chars[spinner_idx]);
                 io::Write::flush(&mut io::stdout())?;
             }
         }
     }
-    Ok(())
 }
 
-async fn start_tui(channel: Option<&str>) -> Result<(), Box<dyn Error>> {
-    let stream = ensure_bridge_connection(true).await?;
-    let (reader, mut writer) = tokio::io::split(stream);
+/// --channel 未指定の TUI のチャンネル。複数人で 1 つのブリッジを共有しても混ざらないよう
+/// `tui:<user>@<host>` にする（ホスト名はドメインを除く）。ユーザー名が分からなければ `tui`。
+fn default_tui_channel(user: Option<&str>, host: Option<&str>) -> String {
+    // 空白や制御文字はチャンネル名に使えないので `-` に置き換える
+    let clean = |s: &str| -> String {
+        s.trim().chars().map(|c| if c.is_whitespace() || c.is_control() { '-' } else { c }).collect()
+    };
+    let user = user.map(clean).filter(|u| !u.is_empty());
+    let host = host.map(|h| clean(h.trim().split('.').next().unwrap_or(""))).filter(|h| !h.is_empty());
+    match (user, host) {
+        (Some(user), Some(host)) => format!("tui:{}@{}", user, host),
+        (Some(user), None) => format!("tui:{}", user),
+        (None, _) => "tui".to_string(),
+    }
+}
+
+/// whoami / hostname 相当の値（`$USER`、`/proc/sys/kernel/hostname` か `hostname` コマンド）から
+/// `default_tui_channel` を作る。
+fn local_tui_channel() -> String {
+    let user = std::env::var("USER").or_else(|_| std::env::var("LOGNAME")).ok();
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname").ok().or_else(|| {
+        let output = std::process::Command::new("hostname").output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    });
+    default_tui_channel(user.as_deref(), host.as_deref())
+}
+
+/// `--no-autostart` で TUI がブリッジに接続できなかったときのメッセージ。
+fn no_autostart_message(error: &str) -> String {
+    format!("{error}; --no-autostart is set, so the bridge was not started. Start it first (e.g. your systemd unit or `acomm --bridge`)")
+}
+
+/// `--follow`: ブリッジに接続できるまで待つ。待機時間は倍々で増やし FOLLOW_RECONNECT_MAX_DELAY で頭打ちにする。
+async fn reconnect_with_backoff() -> AcommClient {
+    let mut delay = BRIDGE_CONNECT_BASE_DELAY;
+    loop {
+        tokio::time::sleep(with_jitter(delay, jitter_seed())).await;
+        if let Ok(client) = ensure_bridge_connection(false).await {
+            return client;
+        }
+        delay = delay.saturating_mul(2).min(FOLLOW_RECONNECT_MAX_DELAY);
+    }
+}
+
+/// `auto_start` が false ならブリッジを起動せず、接続できなければその旨を伝えて終了する。
+async fn start_tui(channel: Option<&str>, auto_start: bool) -> Result<(), Box<dyn Error>> {
+    let client = ensure_bridge_connection(auto_start).await.map_err(|e| -> Box<dyn Error> {
+        if auto_start {
+            e
+        } else {
+            CliError::new(EXIT_BRIDGE_UNREACHABLE, no_autostart_message(&e.to_string())).into()
+        }
+    })?;
+    let (mut reader, mut writer) = client.into_split();
     enable_raw_mode()?;
     let mut stdout = io::stdout();
     let _ = execute!(stdout, EnterAlternateScreen, EnableMouseCapture);
@@ -680,16 +2011,24 @@ async fn start_tui(channel: Option<&str>) -> Result<(), Box<dyn Error>> {
         is_processing: false,
         scroll: 0,
         auto_scroll: true,
-        channel: channel.unwrap_or("tui").to_string(),
+        channel: channel.map(str::to_string).unwrap_or_else(local_tui_channel),
         spinner_idx: 0,
+        completions: Vec::new(),
+        chat_width: 0,
+        chat_height: 0,
+        pending_g: false,
+        pending_acks: 0,
+        input_warn_chars: tui::parse_input_warn_chars(std::env::var("ACOMM_TUI_INPUT_WARN_CHARS").ok().as_deref()),
+        picker: None,
+        chunk_lines: std::collections::HashMap::new(),
+        reply_footer: format_profile::reply_footer_from_env(),
+        ctrl_c_at: None,
+        running_channels: std::collections::HashSet::new(),
     };
     let tx_bridge = tx.clone();
     let bridge_handle = tokio::spawn(async move {
-        let mut lines = BufReader::new(reader).lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
-                let _ = tx_bridge.send(AppEvent::BusEvent(event)).await;
-            }
+        while let Ok(Some(event)) = reader.next_event().await {
+            let _ = tx_bridge.send(AppEvent::BusEvent(event)).await;
         }
     });
     let tx_keys = tx.clone();
@@ -0,0 +1,697 @@
+/**
+ * Matrix adapter for acomm bridge (cargo feature `matrix`).
+ *
+ * Long-polls the Client-Server `/sync` API for the configured rooms, forwards
+ * `m.text` messages from allowed users to the bridge as ProtocolEvent::Prompt with
+ * channel `matrix:<room_id>:<event_id>`, and answers with an `m.room.message` reply
+ * (HTML `formatted_body` rendered from the markdown, plain `body` as fallback).
+ *
+ * Required environment variables:
+ *   MATRIX_HOMESERVER    — e.g. https://matrix.example.org
+ *   MATRIX_ACCESS_TOKEN  — access token of the bot account
+ *   MATRIX_ROOM_IDS      — comma-separated room IDs to listen in (the bot must have joined)
+ *
+ * Optional environment variables:
+ *   MATRIX_ALLOWED_USER_IDS — comma-separated user IDs; others are ignored.
+ *
+ * The sync token is persisted to `~/.cache/acomm/matrix_sync_token`, so a restart
+ * resumes where it stopped. Without a stored token the first sync only records the
+ * current position, which keeps room history from being replayed into the bridge.
+ */
+
+use crate::format_profile::{FormatProfile, OutboundMessage, reply_footer, reply_footer_from_env};
+use crate::protocol::{ActiveAgent, ProtocolEvent, final_answer};
+use crate::redact::redact_output;
+use acomm::client::DEFAULT_SOCKET_PATH;
+use regex::Regex;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tracing::{debug, info, warn};
+
+const MATRIX_SYNC_TIMEOUT_MS: u64 = 30_000;
+const MATRIX_SYNC_TOKEN_FILE: &str = "matrix_sync_token";
+/// Matrix events are capped at 64 KiB; leave room for `formatted_body` and the envelope.
+const MATRIX_MAX_BODY_CHARS: usize = 16_000;
+
+static NEXT_TXN: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatrixConfig {
+    pub homeserver: String,
+    pub access_token: String,
+    pub room_ids: Vec<String>,
+    pub allowed_user_ids: Option<HashSet<String>>,
+}
+
+impl MatrixConfig {
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let var = |key: &str| std::env::var(key).ok();
+        Ok(Self::from_vars(
+            var("MATRIX_HOMESERVER").as_deref(),
+            var("MATRIX_ACCESS_TOKEN").as_deref(),
+            var("MATRIX_ROOM_IDS").as_deref(),
+            var("MATRIX_ALLOWED_USER_IDS").as_deref(),
+        )?)
+    }
+
+    fn from_vars(
+        homeserver: Option<&str>,
+        access_token: Option<&str>,
+        room_ids: Option<&str>,
+        allowed_user_ids: Option<&str>,
+    ) -> Result<Self, String> {
+        let required = |value: Option<&str>, key: &str| {
+            value
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(str::to_string)
+                .ok_or_else(|| format!("{} is not set", key))
+        };
+        let homeserver = required(homeserver, "MATRIX_HOMESERVER")?.trim_end_matches('/').to_string();
+        let access_token = required(access_token, "MATRIX_ACCESS_TOKEN")?;
+        let room_ids = split_ids(&required(room_ids, "MATRIX_ROOM_IDS")?);
+        if room_ids.is_empty() {
+            return Err("MATRIX_ROOM_IDS is not set".into());
+        }
+        let allowed_user_ids = allowed_user_ids
+            .map(split_ids)
+            .filter(|ids| !ids.is_empty())
+            .map(|ids| ids.into_iter().collect());
+        Ok(Self { homeserver, access_token, room_ids, allowed_user_ids })
+    }
+
+    /// `<homeserver>/_matrix/client/v3/<segments...>`, escaping each segment.
+    fn api_url(&self, segments: &[&str]) -> Result<reqwest::Url, Box<dyn Error>> {
+        let mut url = reqwest::Url::parse(&self.homeserver)?;
+        url.path_segments_mut()
+            .map_err(|_| format!("MATRIX_HOMESERVER is not a base URL: {}", self.homeserver))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        Ok(url)
+    }
+}
+
+fn split_ids(raw: &str) -> Vec<String> {
+    raw.split(',').map(str::trim).filter(|s| !s.is_empty()).map(str::to_string).collect()
+}
+
+/// A room message to forward to the bridge.
+#[derive(Debug, Clone, PartialEq)]
+struct MatrixMessage {
+    room_id: String,
+    event_id: String,
+    sender: String,
+    body: String,
+}
+
+/// A reply being collected for one forwarded message.
+#[derive(Debug, Clone, Default)]
+struct MatrixReply {
+    room_id: String,
+    event_id: String,
+    content: String,
+    agent: ActiveAgent,
+}
+
+pub async fn start_matrix_adapter() -> Result<(), Box<dyn Error>> {
+    let config = Arc::new(MatrixConfig::from_env()?);
+    let client = reqwest::Client::new();
+    let own_user_id = fetch_own_user_id(&client, &config).await?;
+    info!("Matrix adapter starting as {} for rooms: {}", own_user_id, config.room_ids.join(", "));
+
+    let state_dir = crate::bridge::persisted_state_dir();
+    let mut since = load_sync_token(state_dir.as_deref());
+    if since.is_none() {
+        // First run: start from "now" instead of replaying room history.
+        let sync = matrix_sync(client.clone(), Arc::clone(&config), None, 0).await?;
+        since = sync["next_batch"].as_str().map(str::to_string);
+        if let Some(ref token) = since {
+            save_sync_token(state_dir.as_deref(), token);
+        }
+        info!("Matrix adapter has no sync token yet; skipping existing room history");
+    }
+
+    let stream = UnixStream::connect(DEFAULT_SOCKET_PATH).await.map_err(|e| {
+        format!("Bridge is not running. Please start it with 'acomm --bridge'. Error: {}", e)
+    })?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut bridge_lines = BufReader::new(reader).lines();
+
+    let mut replies: HashMap<String, MatrixReply> = HashMap::new();
+    let mut active = ActiveAgent::default();
+    let profile = MatrixProfile { footer: reply_footer_from_env() };
+    let mut sync = Box::pin(matrix_sync(client.clone(), Arc::clone(&config), since.clone(), MATRIX_SYNC_TIMEOUT_MS));
+
+    loop {
+        tokio::select! {
+            result = &mut sync => {
+                let response = result?;
+                for message in extract_matrix_messages(&response, &config, &own_user_id) {
+                    debug!(room = %message.room_id, sender = %message.sender, "forwarding Matrix message");
+                    let event = transform_matrix_message(&message.body, &message.room_id, &message.event_id);
+                    if let Some(channel) = event.clone_channel() {
+                        replies.insert(channel, MatrixReply {
+                            room_id: message.room_id.clone(),
+                            event_id: message.event_id.clone(),
+                            ..Default::default()
+                        });
+                    }
+                    writer.write_all(format!("{}\n", serde_json::to_string(&event)?).as_bytes()).await?;
+                }
+                if let Some(next) = response["next_batch"].as_str() {
+                    save_sync_token(state_dir.as_deref(), next);
+                    since = Some(next.to_string());
+                }
+                sync = Box::pin(matrix_sync(client.clone(), Arc::clone(&config), since.clone(), MATRIX_SYNC_TIMEOUT_MS));
+            }
+            line_res = bridge_lines.next_line() => {
+                let Some(line) = line_res? else { break };
+                let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) else { continue };
+                active.observe(&event);
+                match event {
+                    ProtocolEvent::Prompt { ref provider, channel: Some(ref ch), .. } => {
+                        if let Some(reply) = replies.get_mut(ch) {
+                            reply.agent = active.for_prompt(provider.as_ref());
+                        }
+                    }
+                    ProtocolEvent::AgentChunk { ref chunk, channel: Some(ref ch) } => {
+                        if let Some(reply) = replies.get_mut(ch) {
+                            reply.content.push_str(chunk);
+                        }
+                    }
+                    ProtocolEvent::SystemMessage { ref msg, channel: Some(ref ch) } => {
+                        if let Some(reply) = replies.get(ch) {
+                            let notice = OutboundMessage { body: msg.clone(), ..Default::default() };
+                            if let Err(e) = send_matrix_message(&client, &config, reply, &notice, "m.notice").await {
+                                warn!("Matrix notice failed: {}", e);
+                            }
+                        }
+                    }
+                    ProtocolEvent::AgentDone { channel: Some(ref ch), .. }
+                    | ProtocolEvent::ChannelClosed { channel: Some(ref ch), .. } => {
+                        let Some(reply) = replies.remove(ch) else { continue };
+                        let agent = reply.agent.answered(&event);
+                        for message in profile.format_reply(&reply.content, &agent.provider, &agent.model) {
+                            send_matrix_message(&client, &config, &reply, &message, "m.text").await?;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn fetch_own_user_id(client: &reqwest::Client, config: &MatrixConfig) -> Result<String, Box<dyn Error>> {
+    let res = client
+        .get(config.api_url(&["account", "whoami"])?)
+        .bearer_auth(&config.access_token)
+        .send()
+        .await
+        .map_err(|e| format!("Could not connect to Matrix homeserver {}: {}", config.homeserver, e))?;
+    let status = res.status();
+    let body: Value = res.json().await.unwrap_or(Value::Null);
+    match body["user_id"].as_str() {
+        Some(user_id) if status.is_success() => Ok(user_id.to_string()),
+        _ => Err(format!("Matrix whoami failed with HTTP {} (check MATRIX_ACCESS_TOKEN): {}", status, body).into()),
+    }
+}
+
+/// One `/sync` request. Owns its inputs so the future can be kept across `select!` turns.
+async fn matrix_sync(
+    client: reqwest::Client,
+    config: Arc<MatrixConfig>,
+    since: Option<String>,
+    timeout_ms: u64,
+) -> Result<Value, String> {
+    let mut url = config.api_url(&["sync"]).map_err(|e| e.to_string())?;
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("timeout", &timeout_ms.to_string())
+            .append_pair("filter", &sync_filter(&config.room_ids).to_string());
+        if let Some(since) = since.as_deref() {
+            query.append_pair("since", since);
+        }
+    }
+    let res = client
+        .get(url)
+        .bearer_auth(&config.access_token)
+        .timeout(std::time::Duration::from_millis(timeout_ms + 30_000))
+        .send()
+        .await
+        .map_err(|e| format!("Matrix sync failed: {}", e))?;
+    let status = res.status();
+    if !status.is_success() {
+        let body = res.text().await.unwrap_or_default();
+        // Auth failures are fatal; anything else is left to the adapter supervisor to retry.
+        return Err(if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            format!("Matrix access token rejected (HTTP {}): {}", status, body)
+        } else {
+            format!("Matrix sync failed with HTTP {}: {}", status, body)
+        });
+    }
+    res.json().await.map_err(|e| format!("Matrix sync failed: invalid response: {}", e))
+}
+
+/// Sync only the timelines of the configured rooms and skip presence / account data.
+fn sync_filter(room_ids: &[String]) -> Value {
+    json!({
+        "presence": { "types": [] },
+        "account_data": { "types": [] },
+        "room": {
+            "rooms": room_ids,
+            "timeline": { "types": ["m.room.message"] },
+            "state": { "types": [] },
+            "ephemeral": { "types": [] },
+            "account_data": { "types": [] },
+        },
+    })
+}
+
+/// Text messages in the configured rooms, skipping our own and those from users not
+/// on the allowlist.
+fn extract_matrix_messages(sync: &Value, config: &MatrixConfig, own_user_id: &str) -> Vec<MatrixMessage> {
+    let Some(joined) = sync["rooms"]["join"].as_object() else { return Vec::new() };
+    let mut messages = Vec::new();
+    for room_id in &config.room_ids {
+        let Some(events) = joined.get(room_id).and_then(|room| room["timeline"]["events"].as_array()) else {
+            continue;
+        };
+        for event in events {
+            let (Some(sender), Some(event_id), Some(body)) =
+                (event["sender"].as_str(), event["event_id"].as_str(), event["content"]["body"].as_str())
+            else {
+                continue;
+            };
+            if event["type"] != "m.room.message" || event["content"]["msgtype"] != "m.text" || sender == own_user_id {
+                continue;
+            }
+            if config.allowed_user_ids.as_ref().is_some_and(|allowed| !allowed.contains(sender)) {
+                continue;
+            }
+            let body = strip_reply_fallback(body);
+            if body.trim().is_empty() {
+                continue;
+            }
+            messages.push(MatrixMessage {
+                room_id: room_id.clone(),
+                event_id: event_id.to_string(),
+                sender: sender.to_string(),
+                body,
+            });
+        }
+    }
+    messages
+}
+
+/// Drop the `> <@user> quoted` lines clients prepend to the body of a reply.
+fn strip_reply_fallback(body: &str) -> String {
+    let mut lines = body.lines().peekable();
+    if body.starts_with("> <") {
+        while lines.next_if(|l| l.starts_with('>')).is_some() {}
+        lines.next_if(|l| l.is_empty());
+    }
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+pub fn transform_matrix_message(text: &str, room_id: &str, event_id: &str) -> ProtocolEvent {
+    ProtocolEvent::Prompt {
+        text: text.to_string(),
+        provider: None,
+        channel: Some(format!("matrix:{}:{}", room_id, event_id)),
+        reply_to: None,
+        priority: false,
+        author: None,
+    }
+}
+
+async fn send_matrix_message(
+    client: &reqwest::Client,
+    config: &MatrixConfig,
+    reply: &MatrixReply,
+    message: &OutboundMessage,
+    msgtype: &str,
+) -> Result<(), Box<dyn Error>> {
+    let txn_id = format!("acomm-{}-{}", chrono::Utc::now().timestamp_millis(), NEXT_TXN.fetch_add(1, Ordering::Relaxed));
+    let url = config.api_url(&["rooms", &reply.room_id, "send", "m.room.message", &txn_id])?;
+    let content = matrix_message_content(&message.body, msgtype, &reply.event_id);
+    let res = client.put(url).bearer_auth(&config.access_token).json(&content).send().await?;
+    if !res.status().is_success() {
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        return Err(format!("Matrix send to {} failed with HTTP {}: {}", reply.room_id, status, body).into());
+    }
+    Ok(())
+}
+
+/// `m.room.message` content replying to `in_reply_to`, with HTML when the markdown renders to any.
+fn matrix_message_content(body: &str, msgtype: &str, in_reply_to: &str) -> Value {
+    let mut content = json!({
+        "msgtype": msgtype,
+        "body": body,
+        "m.relates_to": { "m.in_reply_to": { "event_id": in_reply_to } },
+    });
+    let html = markdown_to_matrix_html(body);
+    if !html.is_empty() {
+        content["format"] = json!("org.matrix.custom.html");
+        content["formatted_body"] = json!(html);
+    }
+    content
+}
+
+/// Matrix replies: the redacted reply, with an italic `provider:model` line when
+/// `ACOMM_REPLY_FOOTER` is set, split into several messages when it would not fit in one event.
+struct MatrixProfile {
+    footer: bool,
+}
+
+impl FormatProfile for MatrixProfile {
+    fn format_reply(&self, content: &str, provider: &str, model: &str) -> Vec<OutboundMessage> {
+        let content = redact_output(final_answer(content).unwrap_or(content));
+        if content.trim().is_empty() {
+            return Vec::new();
+        }
+        let status = match reply_footer(provider, model) {
+            _ if !self.footer => String::new(),
+            footer if footer.is_empty() => String::new(),
+            footer => format!("\n\n_{}_", footer),
+        };
+        split_matrix_body(&format!("{}{}", content.trim_end(), status), MATRIX_MAX_BODY_CHARS)
+            .into_iter()
+            .map(|body| OutboundMessage { body, ..Default::default() })
+            .collect()
+    }
+}
+
+/// Split at line boundaries into parts of at most `limit` chars (a single longer line
+/// is cut). Code fences left open by a split are closed and reopened.
+fn split_matrix_body(text: &str, limit: usize) -> Vec<String> {
+    const FENCE: &str = "```\n";
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut in_fence = false;
+    for line in text.split_inclusive('\n') {
+        let toggles = line.trim_start().starts_with("```");
+        // Room for the closing fence, unless this line is the closing fence.
+        let reserve = if in_fence && !toggles { FENCE.len() } else { 0 };
+        let mut line = line.to_string();
+        loop {
+            let room = limit.saturating_sub(current.chars().count() + reserve);
+            if line.chars().count() <= room {
+                current.push_str(&line);
+                break;
+            }
+            let fresh_room = limit.saturating_sub(reserve + if in_fence { FENCE.len() } else { 0 });
+            if room > 0 && (current.is_empty() || line.chars().count() > fresh_room) {
+                let cut: String = line.chars().take(room).collect();
+                line = line[cut.len()..].to_string();
+                current.push_str(&cut);
+            }
+            if in_fence {
+                if !current.ends_with('\n') {
+                    current.push('\n');
+                }
+                current.push_str(FENCE);
+            }
+            parts.push(std::mem::take(&mut current));
+            if in_fence {
+                current.push_str(FENCE);
+            }
+            if line.is_empty() {
+                break;
+            }
+        }
+        if toggles {
+            in_fence = !in_fence;
+        }
+    }
+    if !current.is_empty() {
+        parts.push(current);
+    }
+    parts
+}
+
+/// Inline markdown patterns: links, bold, italics.
+fn inline_patterns() -> &'static [(Regex, &'static str); 3] {
+    static PATTERNS: OnceLock<[(Regex, &'static str); 3]> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        let compile = |p: &str| Regex::new(p).expect("built-in markdown pattern must compile");
+        [
+            (compile(r"\[([^\]]+)\]\((https?://[^)\s]+)\)"), "<a href=\"$2\">$1</a>"),
+            (compile(r"\*\*(.+?)\*\*"), "<strong>$1</strong>"),
+            (compile(r"(^|[^\w*])[*_]([^*_\s][^*_]*?)[*_]($|[^\w*])"), "$1<em>$2</em>$3"),
+        ]
+    })
+}
+
+/// Render the markdown agents usually produce (fences, headings, lists, bold, italics,
+/// inline code, links) as Matrix's HTML subset. Returns an empty string for plain text.
+fn markdown_to_matrix_html(markdown: &str) -> String {
+    let mut html = String::new();
+    let mut list_open = false;
+    let mut fence: Option<String> = None;
+    let mut paragraph: Vec<String> = Vec::new();
+    let flush_paragraph = |html: &mut String, paragraph: &mut Vec<String>| {
+        if !paragraph.is_empty() {
+            html.push_str(&format!("<p>{}</p>", paragraph.join("<br>")));
+            paragraph.clear();
+        }
+    };
+    for line in markdown.lines() {
+        if let Some(code) = fence.as_mut() {
+            if line.trim_start().starts_with("```") {
+                html.push_str(&format!("{}</code></pre>", code));
+                fence = None;
+            } else {
+                code.push_str(&escape_html(line));
+                code.push('\n');
+            }
+            continue;
+        }
+        let trimmed = line.trim_start();
+        let item = trimmed.strip_prefix("- ").or_else(|| trimmed.strip_prefix("* "));
+        if list_open && item.is_none() {
+            html.push_str("</ul>");
+            list_open = false;
+        }
+        if let Some(lang) = trimmed.strip_prefix("```") {
+            flush_paragraph(&mut html, &mut paragraph);
+            let lang = lang.trim();
+            fence = Some(if lang.is_empty() {
+                "<pre><code>".to_string()
+            } else {
+                format!("<pre><code class=\"language-{}\">", escape_html(lang))
+            });
+        } else if let Some(item) = item {
+            flush_paragraph(&mut html, &mut paragraph);
+            if !list_open {
+                html.push_str("<ul>");
+                list_open = true;
+            }
+            html.push_str(&format!("<li>{}</li>", render_inline(item)));
+        } else if let Some((level, heading)) = heading(trimmed) {
+            flush_paragraph(&mut html, &mut paragraph);
+            html.push_str(&format!("<h{level}>{}</h{level}>", render_inline(heading)));
+        } else if trimmed.is_empty() {
+            flush_paragraph(&mut html, &mut paragraph);
+        } else {
+            paragraph.push(render_inline(line));
+        }
+    }
+    if let Some(code) = fence {
+        html.push_str(&format!("{}</code></pre>", code));
+    }
+    if list_open {
+        html.push_str("</ul>");
+    }
+    // A single plain paragraph needs no HTML at all.
+    if paragraph.len() == 1 && html.is_empty() && paragraph[0] == escape_html(markdown.trim()) {
+        return String::new();
+    }
+    flush_paragraph(&mut html, &mut paragraph);
+    html
+}
+
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.chars().take_while(|&c| c == '#').count();
+    let text = line[level..].strip_prefix(' ')?;
+    (1..=6).contains(&level).then_some((level, text.trim()))
+}
+
+/// Inline markdown on one line; text inside backticks is only escaped.
+fn render_inline(text: &str) -> String {
+    let ticks = text.matches('`').count();
+    text.split('`')
+        .enumerate()
+        .map(|(i, part)| {
+            let escaped = escape_html(part);
+            if i % 2 == 1 && i < ticks {
+                return format!("<code>{}</code>", escaped);
+            }
+            let rendered = inline_patterns()
+                .iter()
+                .fold(escaped, |s, (re, repl)| re.replace_all(&s, *repl).into_owned());
+            // An unmatched backtick stays literal.
+            if i % 2 == 1 { format!("`{}", rendered) } else { rendered }
+        })
+        .collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn load_sync_token(dir: Option<&Path>) -> Option<String> {
+    let path = sync_token_path(dir?);
+    std::fs::read_to_string(path).ok().map(|t| t.trim().to_string()).filter(|t| !t.is_empty())
+}
+
+/// Best effort: a lost token only means the next start skips ahead to "now".
+fn save_sync_token(dir: Option<&Path>, token: &str) {
+    let Some(dir) = dir else { return };
+    let result = std::fs::create_dir_all(dir).and_then(|_| std::fs::write(sync_token_path(dir), token));
+    if let Err(e) = result {
+        warn!("Could not persist the Matrix sync token: {}", e);
+    }
+}
+
+fn sync_token_path(dir: &Path) -> PathBuf {
+    dir.join(MATRIX_SYNC_TOKEN_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> MatrixConfig {
+        MatrixConfig::from_vars(
+            Some("https://matrix.example.org/"),
+            Some("tok"),
+            Some("!room:example.org, !other:example.org"),
+            Some("@alice:example.org"),
+        )
+        .unwrap()
+    }
+
+    fn text_event(sender: &str, event_id: &str, body: &str) -> Value {
+        json!({
+            "type": "m.room.message",
+            "sender": sender,
+            "event_id": event_id,
+            "content": { "msgtype": "m.text", "body": body },
+        })
+    }
+
+    #[test]
+    fn config_requires_homeserver_token_and_rooms() {
+        let config = config();
+        assert_eq!(config.homeserver, "https://matrix.example.org");
+        assert_eq!(config.room_ids, vec!["!room:example.org", "!other:example.org"]);
+        assert!(config.allowed_user_ids.as_ref().unwrap().contains("@alice:example.org"));
+        let err = MatrixConfig::from_vars(Some("https://m.org"), None, Some("!r:m.org"), None).unwrap_err();
+        assert_eq!(err, "MATRIX_ACCESS_TOKEN is not set");
+        assert!(MatrixConfig::from_vars(Some("https://m.org"), Some("t"), Some(" , "), None).is_err());
+    }
+
+    #[test]
+    fn api_url_appends_escaped_segments() {
+        let url = config().api_url(&["rooms", "!room:example.org", "send", "m.room.message", "a/b#c"]).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://matrix.example.org/_matrix/client/v3/rooms/!room:example.org/send/m.room.message/a%2Fb%23c"
+        );
+    }
+
+    #[test]
+    fn extracts_allowed_text_messages_from_configured_rooms() {
+        let sync = json!({
+            "next_batch": "s2",
+            "rooms": { "join": {
+                "!room:example.org": { "timeline": { "events": [
+                    text_event("@alice:example.org", "$1", "hello"),
+                    text_event("@bot:example.org", "$2", "my own reply"),
+                    text_event("@mallory:example.org", "$3", "not allowed"),
+                    { "type": "m.room.message", "sender": "@alice:example.org", "event_id": "$4",
+                      "content": { "msgtype": "m.image", "body": "cat.png" } },
+                    text_event("@alice:example.org", "$5", "> <@bot:example.org> earlier\n\nfollow-up"),
+                ] } },
+                "!unconfigured:example.org": { "timeline": { "events": [
+                    text_event("@alice:example.org", "$6", "elsewhere"),
+                ] } },
+            } },
+        });
+        let messages = extract_matrix_messages(&sync, &config(), "@bot:example.org");
+        let bodies: Vec<_> = messages.iter().map(|m| (m.event_id.as_str(), m.body.as_str())).collect();
+        assert_eq!(bodies, vec![("$1", "hello"), ("$5", "follow-up")]);
+        assert_eq!(messages[0].room_id, "!room:example.org");
+    }
+
+    #[test]
+    fn transform_uses_room_and_event_in_channel() {
+        let event = transform_matrix_message("hi", "!room:example.org", "$ev");
+        assert_eq!(event.clone_channel().as_deref(), Some("matrix:!room:example.org:$ev"));
+    }
+
+    #[test]
+    fn markdown_renders_to_matrix_html() {
+        let html = markdown_to_matrix_html("# Title\n\nSome **bold** and `a<b>` with [docs](https://x.org).\n- one\n- two\n\n```rust\nlet x = 1 < 2;\n```");
+        assert_eq!(
+            html,
+            "<h1>Title</h1><p>Some <strong>bold</strong> and <code>a&lt;b&gt;</code> with <a href=\"https://x.org\">docs</a>.</p>\
+             <ul><li>one</li><li>two</li></ul><pre><code class=\"language-rust\">let x = 1 &lt; 2;\n</code></pre>"
+        );
+        assert_eq!(markdown_to_matrix_html("just text"), "");
+        assert_eq!(markdown_to_matrix_html("snake_case_name stays"), "");
+        assert_eq!(render_inline("a `b"), "a `b");
+    }
+
+    #[test]
+    fn message_content_replies_and_falls_back_to_plain_body() {
+        let content = matrix_message_content("plain", "m.text", "$ev");
+        assert_eq!(content["body"], "plain");
+        assert_eq!(content["m.relates_to"]["m.in_reply_to"]["event_id"], "$ev");
+        assert!(content.get("formatted_body").is_none());
+        let content = matrix_message_content("**hi**", "m.text", "$ev");
+        assert_eq!(content["format"], "org.matrix.custom.html");
+        assert_eq!(content["formatted_body"], "<p><strong>hi</strong></p>");
+    }
+
+    #[test]
+    fn profile_appends_status_and_splits_long_replies() {
+        let profile = MatrixProfile { footer: true };
+        let messages = profile.format_reply("answer\n", "claude", "sonnet");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body, "answer\n\n_claude:sonnet_");
+        assert!(profile.format_reply("  ", "claude", "").is_empty());
+        let quiet = MatrixProfile { footer: false }.format_reply("answer\n", "claude", "sonnet");
+        assert_eq!(quiet[0].body, "answer");
+
+        let parts = split_matrix_body("```\naaaa\nbbbb\n```\ntail\n", 14);
+        assert!(parts.iter().all(|p| p.chars().count() <= 14), "{parts:?}");
+        assert_eq!(parts[0], "```\naaaa\n```\n");
+        assert_eq!(parts[1], "```\nbbbb\n```\n");
+        assert_eq!(parts[2], "tail\n");
+        let cut = split_matrix_body("abcdefghij", 4);
+        assert_eq!(cut, vec!["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn sync_token_round_trips_through_the_state_dir() {
+        let dir = std::env::temp_dir().join(format!("acomm-matrix-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(load_sync_token(Some(&dir)), None);
+        save_sync_token(Some(&dir), "s42_7");
+        assert_eq!(load_sync_token(Some(&dir)).as_deref(), Some("s42_7"));
+        assert_eq!(load_sync_token(None), None);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
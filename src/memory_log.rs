@@ -0,0 +1,115 @@
+/**
+ * Conversation logging into the memory backend.
+ *
+ * With `ACOMM_AMEM_LOG=1` the bridge hands every prompt that finished successfully
+ * back to the memory backend behind `/search` and `/today` (`ACOMM_MEMORY_CMD`,
+ * default `amem`), so later searches can find earlier answers. Each conversation
+ * is run as `<memory cmd> add` with one JSON record on stdin:
+ *
+ *   {"timestamp": "2026-10-18T07:30:00+09:00", "channel": "discord:1", "provider": "claude",
+ *    "model": "claude-sonnet-4-6", "prompt": "...", "reply": "..."}
+ *
+ * Logging runs in the background after `AgentDone`; a failure only logs a warning.
+ */
+use serde::Serialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// How long `<memory cmd> add` may take before it is killed.
+const MEMORY_LOG_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether `ACOMM_AMEM_LOG` turns conversation logging on (`1` / `true`).
+pub fn memory_log_enabled(raw: Option<&str>) -> bool {
+    raw.is_some_and(|v| matches!(v.trim(), "1" | "true"))
+}
+
+/// One finished prompt and the reply the agent streamed for it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConversationRecord {
+    /// RFC 3339 local time at which the reply finished.
+    pub timestamp: String,
+    pub channel: Option<String>,
+    pub provider: String,
+    pub model: Option<String>,
+    pub prompt: String,
+    pub reply: String,
+}
+
+/// Run `<memory_cmd> add` with `record` as JSON on stdin. Errors describe what went
+/// wrong (missing binary, non-zero exit, timeout) for the caller to log.
+pub async fn log_conversation(memory_cmd: &[String], record: &ConversationRecord) -> Result<(), String> {
+    let (program, base_args) = memory_cmd.split_first().ok_or("memory backend is not configured")?;
+    let payload = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    let mut child = tokio::process::Command::new(program)
+        .args(base_args)
+        .arg("add")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("memory backend '{}' failed to start: {}", program, e))?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let run = async move {
+        // A backend that exits without reading stdin shows up in its exit status instead.
+        let _ = stdin.write_all(payload.as_bytes()).await;
+        let _ = stdin.write_all(b"\n").await;
+        drop(stdin);
+        child.wait_with_output().await
+    };
+    let output = tokio::time::timeout(MEMORY_LOG_TIMEOUT, run)
+        .await
+        .map_err(|_| format!("memory backend '{}' timed out after {:?}", program, MEMORY_LOG_TIMEOUT))?
+        .map_err(|e| format!("memory backend '{}' failed: {}", program, e))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(format!("memory backend '{}' add exited with {}: {}", program, output.status, stderr.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> ConversationRecord {
+        ConversationRecord {
+            timestamp: "2026-10-18T07:30:00+09:00".into(),
+            channel: Some("discord:1".into()),
+            provider: "claude".into(),
+            model: Some("claude-sonnet-4-6".into()),
+            prompt: "今日の予定は？".into(),
+            reply: "Standup at 10:00.".into(),
+        }
+    }
+
+    #[test]
+    fn memory_log_is_opt_in() {
+        assert!(!memory_log_enabled(None));
+        assert!(!memory_log_enabled(Some("0")));
+        assert!(memory_log_enabled(Some(" 1 ")));
+        assert!(memory_log_enabled(Some("true")));
+    }
+
+    #[tokio::test]
+    async fn record_is_sent_as_json_to_the_add_subcommand() {
+        let path = std::env::temp_dir().join(format!("acomm-memory-log-{}.json", std::process::id()));
+        let backend: Vec<String> =
+            ["sh", "-c", r#"test "$1" = add && cat > "$0""#, path.to_str().unwrap()].map(String::from).to_vec();
+        log_conversation(&backend, &record()).await.unwrap();
+        let logged: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(logged, serde_json::to_value(record()).unwrap());
+        assert_eq!(logged["prompt"], "今日の予定は？");
+    }
+
+    #[tokio::test]
+    async fn backend_failures_are_reported_not_raised() {
+        let failing: Vec<String> = ["sh", "-c", "echo index is locked >&2; exit 3"].map(String::from).to_vec();
+        let err = log_conversation(&failing, &record()).await.unwrap_err();
+        assert!(err.contains("index is locked"), "{err}");
+        let missing = vec!["acomm-no-such-memory-backend".to_string()];
+        assert!(log_conversation(&missing, &record()).await.unwrap_err().contains("failed to start"));
+    }
+}
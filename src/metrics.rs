@@ -0,0 +1,189 @@
+/**
+ * Prometheus metrics for the bridge.
+ *
+ * Enabled with `acomm --bridge --metrics <addr:port>`; counters are updated by the
+ * bridge's manager task as events flow through the broadcast channel and served as
+ * Prometheus text format on `GET /metrics`. Without the flag nothing is recorded.
+ */
+use crate::protocol::{AGENT_FAILURE_PREFIX, ProtocolEvent};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+
+#[derive(Default)]
+pub struct BridgeMetrics {
+    prompts_total: AtomicU64,
+    agent_errors_total: AtomicU64,
+    streamed_bytes_total: AtomicU64,
+    in_flight_prompts: AtomicU64,
+    lagged_events_total: AtomicU64,
+    channels: std::sync::Mutex<HashSet<String>>,
+}
+
+impl BridgeMetrics {
+    /// Update counters for one event seen on the broadcast channel.
+    pub fn observe(&self, event: &ProtocolEvent) {
+        match event {
+            ProtocolEvent::Prompt { channel, .. } => {
+                self.prompts_total.fetch_add(1, Ordering::Relaxed);
+                if let Some(channel) = channel {
+                    let scope = crate::bridge::channel_scope(channel);
+                    if let Ok(mut channels) = self.channels.lock() {
+                        channels.insert(scope);
+                    }
+                }
+            }
+            ProtocolEvent::AgentChunk { chunk, .. } => {
+                self.streamed_bytes_total.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            }
+            ProtocolEvent::SystemMessage { msg, .. } if msg.starts_with(AGENT_FAILURE_PREFIX) => {
+                self.agent_errors_total.fetch_add(1, Ordering::Relaxed);
+            }
+            ProtocolEvent::StatusUpdate { is_processing: true, .. } => {
+                self.in_flight_prompts.fetch_add(1, Ordering::Relaxed);
+            }
+            ProtocolEvent::StatusUpdate { is_processing: false, .. } => {
+                let _ = self.in_flight_prompts.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    Some(n.saturating_sub(1))
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Count events a slow subscriber skipped because the broadcast buffer overflowed.
+    pub fn record_lag(&self, skipped: u64) {
+        self.lagged_events_total.fetch_add(skipped, Ordering::Relaxed);
+    }
+
+    /// Render all metrics in Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let active_channels = self.channels.lock().map(|c| c.len()).unwrap_or(0) as u64;
+        let metrics = [
+            ("acomm_prompts_total", "counter", "Prompts dispatched to an agent.", self.prompts_total.load(Ordering::Relaxed)),
+            ("acomm_agent_errors_total", "counter", "Agent runs that failed.", self.agent_errors_total.load(Ordering::Relaxed)),
+            ("acomm_streamed_bytes_total", "counter", "Bytes of agent output streamed to clients.", self.streamed_bytes_total.load(Ordering::Relaxed)),
+            ("acomm_active_channels", "gauge", "Distinct channels that have sent prompts.", active_channels),
+            ("acomm_in_flight_prompts", "gauge", "Prompts currently being processed.", self.in_flight_prompts.load(Ordering::Relaxed)),
+            ("acomm_lagged_events_total", "counter", "Events dropped for subscribers that fell behind the broadcast buffer.", self.lagged_events_total.load(Ordering::Relaxed)),
+        ];
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            let _ = writeln!(out, "{name} {value}");
+        }
+        out
+    }
+}
+
+/// Serve `GET /metrics` on `addr` until the process exits.
+pub async fn serve_metrics(addr: &str, metrics: Arc<BridgeMetrics>) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("acomm metrics listening on http://{}/metrics", listener.local_addr()?);
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let metrics = Arc::clone(&metrics);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = match stream.read(&mut buf).await {
+                Ok(n) => n,
+                Err(_) => return,
+            };
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let response = metrics_http_response(&request, &metrics);
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        });
+    }
+}
+
+fn metrics_http_response(request: &str, metrics: &BridgeMetrics) -> String {
+    let path = request.lines().next().and_then(|line| {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("GET"), Some(path)) => Some(path),
+            _ => None,
+        }
+    });
+    let (status, content_type, body) = match path {
+        Some("/metrics") => ("200 OK", "text/plain; version=0.0.4", metrics.render()),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_string()),
+    };
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(is_processing: bool) -> ProtocolEvent {
+        ProtocolEvent::StatusUpdate { is_processing, channel: Some("tui".into()) }
+    }
+
+    #[test]
+    fn observe_counts_prompts_bytes_errors_and_channels() {
+        let metrics = BridgeMetrics::default();
+        for channel in ["discord:1:100", "discord:1:101", "tui"] {
+            metrics.observe(&ProtocolEvent::Prompt {
+                text: "hi".into(),
+                provider: None,
+                channel: Some(channel.into()),
+                reply_to: None,
+                priority: false,
+                author: None,
+            });
+        }
+        metrics.observe(&ProtocolEvent::AgentChunk { chunk: "héllo".into(), channel: None });
+        metrics.observe(&ProtocolEvent::SystemMessage {
+            msg: format!("{}: boom", AGENT_FAILURE_PREFIX),
+            channel: None,
+        });
+        metrics.observe(&ProtocolEvent::SystemMessage { msg: "Today:\n".into(), channel: None });
+
+        let text = metrics.render();
+        assert!(text.contains("acomm_prompts_total 3\n"));
+        assert!(text.contains("acomm_streamed_bytes_total 6\n"));
+        assert!(text.contains("acomm_agent_errors_total 1\n"));
+        assert!(text.contains("acomm_active_channels 2\n"));
+        assert!(text.contains("# TYPE acomm_in_flight_prompts gauge\n"));
+    }
+
+    #[test]
+    fn in_flight_gauge_tracks_status_updates_without_underflow() {
+        let metrics = BridgeMetrics::default();
+        metrics.observe(&status(true));
+        metrics.observe(&status(true));
+        metrics.observe(&status(false));
+        assert!(metrics.render().contains("acomm_in_flight_prompts 1\n"));
+        metrics.observe(&status(false));
+        metrics.observe(&status(false));
+        assert!(metrics.render().contains("acomm_in_flight_prompts 0\n"));
+    }
+
+    #[test]
+    fn record_lag_accumulates_skipped_events() {
+        let metrics = BridgeMetrics::default();
+        metrics.record_lag(3);
+        metrics.record_lag(4);
+        assert!(metrics.render().contains("acomm_lagged_events_total 7\n"));
+    }
+
+    #[test]
+    fn http_response_serves_metrics_path_only() {
+        let metrics = BridgeMetrics::default();
+        let ok = metrics_http_response("GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n", &metrics);
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(ok.contains("acomm_prompts_total 0"));
+        let missing = metrics_http_response("GET / HTTP/1.1\r\n\r\n", &metrics);
+        assert!(missing.starts_with("HTTP/1.1 404"));
+    }
+}
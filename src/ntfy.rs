@@ -1,4 +1,9 @@
-use crate::protocol::ProtocolEvent;
+use crate::protocol::{AGENT_FAILURE_PREFIX, ActiveAgent, ProtocolEvent, final_answer};
+use crate::quiet_hours;
+use crate::redact::redact_output;
+use crate::format_profile::{FormatProfile, OutboundMessage};
+use acomm::client::DEFAULT_SOCKET_PATH;
+use tracing::{info, warn};
 use std::error::Error;
 use tokio::net::UnixStream;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -6,7 +11,115 @@ use serde::{Deserialize, Serialize};
 use futures_util::StreamExt;
 use std::collections::HashMap;
 
-const SOCKET_PATH: &str = "/tmp/acomm.sock";
+const NTFY_THINKING_TEXT: &str = "… thinking";
+const NTFY_PROGRESS_PRIORITY: &str = "low";
+const NTFY_ERROR_PRIORITY: &str = "high";
+/// Used for proactive notices during `ACOMM_QUIET_HOURS`; ntfy delivers these without sound or popup.
+const NTFY_QUIET_PRIORITY: &str = "min";
+const DEFAULT_NTFY_SERVER_URL: &str = "https://ntfy.sh";
+/// Bodies above ntfy's ~4 KB message limit are turned into attachments, so long
+/// replies are split into several notifications instead.
+const NTFY_MAX_BODY_BYTES: usize = 4000;
+const NTFY_TITLE_MAX_CHARS: usize = 60;
+
+/// ntfy server connection settings.
+///
+/// Environment variables:
+///   NTFY_SERVER_URL — base URL of the ntfy server (default https://ntfy.sh)
+///   NTFY_TOKEN      — access token, sent as `Authorization: Bearer <token>`
+#[derive(Debug, Clone, PartialEq)]
+struct NtfyConfig {
+    base_url: String,
+    token: Option<String>,
+}
+
+impl NtfyConfig {
+    fn from_env() -> Result<Self, String> {
+        Self::parse(
+            std::env::var("NTFY_SERVER_URL").ok().as_deref(),
+            std::env::var("NTFY_TOKEN").ok().as_deref(),
+        )
+    }
+
+    /// Validate the server URL up front so a typo fails at startup instead of
+    /// surfacing later as repeated connection errors.
+    fn parse(server_url: Option<&str>, token: Option<&str>) -> Result<Self, String> {
+        let raw = server_url.map(str::trim).filter(|u| !u.is_empty()).unwrap_or(DEFAULT_NTFY_SERVER_URL);
+        let url = reqwest::Url::parse(raw).map_err(|e| format!("Invalid NTFY_SERVER_URL '{}': {}", raw, e))?;
+        if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+            return Err(format!("Invalid NTFY_SERVER_URL '{}': expected an http(s) URL", raw));
+        }
+        Ok(Self {
+            base_url: raw.trim_end_matches('/').to_string(),
+            token: token.map(str::trim).filter(|t| !t.is_empty()).map(str::to_string),
+        })
+    }
+
+    fn topic_url(&self, topic: &str) -> String {
+        format!("{}/{}", self.base_url, topic)
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+}
+
+/// Which ntfy topics the adapter subscribes to and publishes on.
+///
+/// Environment variables:
+///   NTFY_TOPIC_IN  — topic the adapter subscribes to for prompts
+///   NTFY_TOPIC_OUT — topic replies and notifications are published to
+/// Each falls back to NTFY_TOPIC. When both resolve to the same topic, outbound
+/// messages are prefixed with "[bot]" and inbound messages carrying that prefix are
+/// skipped so the adapter does not answer itself; with distinct topics neither is needed.
+#[derive(Debug, Clone, PartialEq)]
+struct NtfyTopics {
+    inbound: String,
+    outbound: String,
+}
+
+impl NtfyTopics {
+    fn from_env() -> Result<Self, String> {
+        Self::parse(
+            std::env::var("NTFY_TOPIC").ok().as_deref(),
+            std::env::var("NTFY_TOPIC_IN").ok().as_deref(),
+            std::env::var("NTFY_TOPIC_OUT").ok().as_deref(),
+        )
+    }
+
+    fn parse(topic: Option<&str>, topic_in: Option<&str>, topic_out: Option<&str>) -> Result<Self, String> {
+        let non_empty = |value: Option<&str>| value.map(str::trim).filter(|t| !t.is_empty()).map(str::to_string);
+        let pick = |specific: Option<&str>, name: &str| {
+            non_empty(specific)
+                .or_else(|| non_empty(topic))
+                .ok_or_else(|| format!("NTFY_TOPIC environment variable not set (or set {})", name))
+        };
+        Ok(Self {
+            inbound: pick(topic_in, "NTFY_TOPIC_IN")?,
+            outbound: pick(topic_out, "NTFY_TOPIC_OUT")?,
+        })
+    }
+
+    /// A single shared topic needs the "[bot]" marker to break the reply loop.
+    fn shared(&self) -> bool {
+        self.inbound == self.outbound
+    }
+
+    fn outbound_payload(&self, message: &str) -> String {
+        if self.shared() {
+            format!("[bot] {}", message)
+        } else {
+            message.to_string()
+        }
+    }
+
+    fn is_own_message(&self, text: &str) -> bool {
+        self.shared() && text.starts_with("[bot]")
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct NtfyMessage {
@@ -18,36 +131,71 @@ struct NtfyMessage {
     title: Option<String>,
 }
 
-/// Send a proactive agent notification to the ntfy topic.
+/// Send a proactive agent notification to the outbound ntfy topic.
 ///
 /// Required environment variables:
-///   NTFY_TOPIC — ntfy.sh topic name
+///   NTFY_TOPIC — ntfy topic name (or NTFY_TOPIC_OUT, see `NtfyTopics`)
+/// Optional: NTFY_SERVER_URL, NTFY_TOKEN (see `NtfyConfig`).
 ///
-/// The message is prefixed with "[bot]" to prevent the running ntfy adapter
-/// from forwarding it back to the bridge as a user message.
+/// On a shared topic the message is prefixed with "[bot]" to prevent the running
+/// ntfy adapter from forwarding it back to the bridge as a user message.
+///
+/// Bodies over ntfy's message size limit are split into numbered notifications.
+/// Fails when the server is unreachable or rejects the publish, so
+/// `acomm --agent "<text>" --ntfy` exits non-zero in scripts. During
+/// `ACOMM_QUIET_HOURS` the notifications are sent at minimum priority.
 pub async fn notify_ntfy(text: &str) -> Result<(), Box<dyn Error>> {
-    let topic = std::env::var("NTFY_TOPIC")
-        .map_err(|_| "NTFY_TOPIC environment variable not set")?;
-    send_to_ntfy(&topic, text).await
+    let topics = NtfyTopics::from_env()?;
+    let config = NtfyConfig::from_env()?;
+    let message = NtfyReply { content: text.to_string(), ..Default::default() };
+    let quiet = quiet_hours::quiet_now();
+    for notification in reply_notifications(&message) {
+        publish_ntfy(&config, &topics, &quieted(notification, quiet)).await?;
+    }
+    Ok(())
 }
 
 pub async fn start_ntfy_adapter() -> Result<(), Box<dyn Error>> {
-    let topic = std::env::var("NTFY_TOPIC").map_err(|_| "NTFY_TOPIC environment variable not set")?;
-    println!("ntfy adapter starting for topic: {}", topic);
+    let topics = NtfyTopics::from_env()?;
+    let config = NtfyConfig::from_env()?;
+    let topic = &topics.inbound;
+    if topics.shared() {
+        info!("ntfy adapter starting for topic: {} on {}", topic, config.base_url);
+    } else {
+        info!(
+            "ntfy adapter starting on {} (inbound: {}, outbound: {})",
+            config.base_url, topics.inbound, topics.outbound
+        );
+    }
 
-    let stream = UnixStream::connect(SOCKET_PATH).await.map_err(|e| {
+    let stream = UnixStream::connect(DEFAULT_SOCKET_PATH).await.map_err(|e| {
         format!("Bridge is not running. Please start it with 'acomm --bridge'. Error: {}", e)
     })?;
     let (reader, mut writer) = tokio::io::split(stream);
     let mut bridge_lines = BufReader::new(reader).lines();
 
-    let url = format!("https://ntfy.sh/{}/json", topic);
+    let url = format!("{}/json", config.topic_url(topic));
     let client = reqwest::Client::new();
-    let mut ntfy_stream = client.get(&url).send().await?.bytes_stream();
+    let response = config.authorize(client.get(&url)).send().await.map_err(|e| {
+        // TLS and DNS failures land here; name the server so misconfiguration is obvious.
+        format!("Could not connect to ntfy server {}: {}", config.base_url, e)
+    })?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "ntfy subscribe to {} failed with HTTP {} (check NTFY_TOKEN / topic permissions)",
+            url,
+            response.status()
+        )
+        .into());
+    }
+    let mut ntfy_stream = response.bytes_stream();
 
-    println!("Subscribed to ntfy.sh topic: {}", topic);
+    info!("Subscribed to ntfy topic: {}", topic);
 
-    let mut reply_buffers: HashMap<String, String> = HashMap::new();
+    let mut reply_buffers: HashMap<String, NtfyReply> = HashMap::new();
+    let mut active = ActiveAgent::default();
+    // Switches replayed during the bridge's initial sync are state, not news.
+    let mut bridge_sync_done = false;
 
     loop {
         tokio::select! {
@@ -58,7 +206,7 @@ pub async fn start_ntfy_adapter() -> Result<(), Box<dyn Error>> {
                     if let Ok(msg) = serde_json::from_str::<NtfyMessage>(json_line) {
                         if msg.event == "message" {
                             if let Some(text) = msg.message {
-                                if text.starts_with("[bot]") { continue; }
+                                if topics.is_own_message(&text) { continue; }
                                 let event = transform_ntfy_message(&text, &msg.id);
                                 let j = serde_json::to_string(&event)?;
                                 writer.write_all(format!("{}\n", j).as_bytes()).await?;
@@ -74,24 +222,37 @@ pub async fn start_ntfy_adapter() -> Result<(), Box<dyn Error>> {
                 };
                 if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
                     match event {
-                        ProtocolEvent::AgentChunk { ref chunk, channel: Some(ref ch) } if ch.starts_with("ntfy:") => {
-                            let msg_id = ch.replace("ntfy:", "");
-                            reply_buffers.entry(msg_id).or_default().push_str(chunk);
-                        }
-                        ProtocolEvent::Prompt { channel: Some(ref ch), .. } if ch.starts_with("ntfy:") => {
-                            let msg_id = ch.replace("ntfy:", "");
-                            reply_buffers.insert(msg_id, String::new());
-                        }
-                        ProtocolEvent::AgentDone { channel: Some(ref ch) } if ch.starts_with("ntfy:") => {
-                            let msg_id = ch.replace("ntfy:", "");
-                            if let Some(content) = reply_buffers.remove(&msg_id) {
-                                if !content.is_empty() {
-                                    send_to_ntfy(&topic, &content).await?;
+                        ProtocolEvent::BridgeSyncDone { .. } => bridge_sync_done = true,
+                        ProtocolEvent::ProviderSwitched { .. } | ProtocolEvent::ModelSwitched { .. } => {
+                            active.observe(&event);
+                            if let Some(notice) = switch_notice(&event, &active).filter(|_| bridge_sync_done) {
+                                let notice = quieted(notice, quiet_hours::quiet_now());
+                                if let Err(e) = publish_ntfy(&config, &topics, &notice).await {
+                                    warn!("ntfy switch notice failed: {}", e);
                                 }
                             }
                         }
+                        ProtocolEvent::StatusUpdate { is_processing: true, channel: Some(ref ch) } if ch.starts_with("ntfy:") => {
+                            // Best-effort progress ping; a failure here must not stop the adapter.
+                            let ping = OutboundMessage {
+                                body: NTFY_THINKING_TEXT.to_string(),
+                                priority: Some(NTFY_PROGRESS_PRIORITY),
+                                ..Default::default()
+                            };
+                            if let Err(e) = publish_ntfy(&config, &topics, &ping).await {
+                                warn!("ntfy progress ping failed: {}", e);
+                            }
+                        }
+                        ProtocolEvent::SystemMessage { ref msg, channel: Some(ref ch) } if ch.starts_with("ntfy:") => {
+                            publish_ntfy(&config, &topics, &system_notification(msg, &active)).await?;
+                        }
                         _ => {}
                     }
+                    if let Some(reply) = buffer_ntfy_reply(&mut reply_buffers, &active, &event) {
+                        for notification in reply_notifications(&reply) {
+                            publish_ntfy(&config, &topics, &notification).await?;
+                        }
+                    }
                 }
             }
         }
@@ -99,11 +260,224 @@ pub async fn start_ntfy_adapter() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-async fn send_to_ntfy(topic: &str, message: &str) -> Result<(), Box<dyn Error>> {
+/// A finished (or in-progress) reply to one ntfy prompt, with the agent that answered it.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct NtfyReply {
+    prompt: String,
+    content: String,
+    agent: ActiveAgent,
+}
+
+/// Track replies per ntfy conversation, keyed by the full bridge channel
+/// (`ntfy:<message id>`). Events for other channels are ignored. Returns the
+/// finished reply once its own channel reports `AgentDone` / `ChannelClosed`.
+fn buffer_ntfy_reply(
+    reply_buffers: &mut HashMap<String, NtfyReply>,
+    active: &ActiveAgent,
+    event: &ProtocolEvent,
+) -> Option<NtfyReply> {
+    match event {
+        ProtocolEvent::Prompt { text, provider, channel: Some(ch), .. } if ch.starts_with("ntfy:") => {
+            let reply = NtfyReply {
+                prompt: text.clone(),
+                content: String::new(),
+                agent: active.for_prompt(provider.as_ref()),
+            };
+            reply_buffers.insert(ch.clone(), reply);
+            None
+        }
+        ProtocolEvent::AgentChunk { chunk, channel: Some(ch) } if ch.starts_with("ntfy:") => {
+            reply_buffers.entry(ch.clone()).or_default().content.push_str(chunk);
+            None
+        }
+        ProtocolEvent::AgentDone { channel: Some(ch), .. }
+        | ProtocolEvent::ChannelClosed { channel: Some(ch), .. } if ch.starts_with("ntfy:") => {
+            reply_buffers.remove(ch).filter(|reply| !reply.content.is_empty()).map(|mut reply| {
+                reply.agent = reply.agent.answered(event);
+                reply
+            })
+        }
+        _ => None,
+    }
+}
+
+/// ntfy replies: titled after the prompt, tagged with the provider, ending with a
+/// `(provider · model)` trailer, and split into numbered parts when the body exceeds
+/// ntfy's limit. Each notification maps to one publish with its `Title` / `Priority`
+/// / `Tags` headers.
+struct NtfyProfile {
+    title: Option<String>,
+}
+
+impl NtfyProfile {
+    fn for_prompt(prompt: &str) -> Self {
+        Self { title: ntfy_title(prompt) }
+    }
+}
+
+impl FormatProfile for NtfyProfile {
+    fn format_reply(&self, content: &str, provider: &str, model: &str) -> Vec<OutboundMessage> {
+        let agent = ActiveAgent::new(provider, model);
+        let body = with_status_trailer(final_answer(content).unwrap_or(content), &agent);
+        let parts = split_ntfy_body(&body, NTFY_MAX_BODY_BYTES);
+        let total = parts.len();
+        parts
+            .into_iter()
+            .enumerate()
+            .map(|(i, body)| OutboundMessage {
+                body,
+                title: match (&self.title, total) {
+                    (title, 1) => title.clone(),
+                    (Some(title), _) => Some(format!("{} ({}/{})", title, i + 1, total)),
+                    (None, _) => Some(format!("({}/{})", i + 1, total)),
+                },
+                tags: ntfy_tags(&agent),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+/// Notifications for a finished reply, formatted by its prompt's `NtfyProfile`.
+fn reply_notifications(reply: &NtfyReply) -> Vec<OutboundMessage> {
+    NtfyProfile::for_prompt(&reply.prompt).format_reply(&reply.content, &reply.agent.provider, &reply.agent.model)
+}
+
+/// Bridge messages to an ntfy conversation; agent failures are sent at high priority.
+fn system_notification(msg: &str, agent: &ActiveAgent) -> OutboundMessage {
+    let is_error = msg.starts_with(AGENT_FAILURE_PREFIX);
+    OutboundMessage {
+        body: with_status_trailer(msg, agent),
+        title: is_error.then(|| AGENT_FAILURE_PREFIX.to_string()),
+        priority: is_error.then_some(NTFY_ERROR_PRIORITY),
+        tags: ntfy_tags(agent),
+        ..Default::default()
+    }
+}
+
+/// Downgrade a proactive notification to minimum priority during quiet hours.
+/// Replies to the user's own prompts are never passed through here.
+fn quieted(notification: OutboundMessage, quiet: bool) -> OutboundMessage {
+    if quiet {
+        OutboundMessage { priority: Some(NTFY_QUIET_PRIORITY), ..notification }
+    } else {
+        notification
+    }
+}
+
+/// Low-priority "now using provider:model" notice for a provider/model switch.
+/// A provider switch with a default model is followed by `ModelSwitched`, so only
+/// that second event is announced to avoid two notices per switch.
+fn switch_notice(event: &ProtocolEvent, active: &ActiveAgent) -> Option<OutboundMessage> {
+    let announce = match event {
+        ProtocolEvent::ProviderSwitched { .. } => active.model.is_empty(),
+        ProtocolEvent::ModelSwitched { .. } => true,
+        _ => false,
+    };
+    if !announce || active.provider.is_empty() {
+        return None;
+    }
+    let current = if active.model.is_empty() {
+        active.provider.clone()
+    } else {
+        format!("{}:{}", active.provider, active.model)
+    };
+    Some(OutboundMessage {
+        body: format!("now using {}", current),
+        priority: Some(NTFY_PROGRESS_PRIORITY),
+        tags: ntfy_tags(active),
+        ..Default::default()
+    })
+}
+
+fn ntfy_tags(agent: &ActiveAgent) -> Vec<String> {
+    let mut tags = vec!["robot".to_string()];
+    if !agent.provider.is_empty() {
+        tags.push(agent.provider.clone());
+    }
+    tags
+}
+
+/// `(gemini · auto-gemini-3)`, or `(opencode)` without a model; `None` before the
+/// bridge has reported a provider.
+fn ntfy_status_trailer(agent: &ActiveAgent) -> Option<String> {
+    match (agent.provider.trim(), agent.model.trim()) {
+        ("", _) => None,
+        (provider, "") => Some(format!("({})", provider)),
+        (provider, model) => Some(format!("({} · {})", provider, model)),
+    }
+}
+
+fn with_status_trailer(body: &str, agent: &ActiveAgent) -> String {
+    match ntfy_status_trailer(agent) {
+        Some(trailer) => format!("{}\n\n{}", body.trim_end(), trailer),
+        None => body.to_string(),
+    }
+}
+
+/// First non-empty line of the prompt, shortened to fit a notification title.
+fn ntfy_title(prompt: &str) -> Option<String> {
+    let line = prompt.lines().map(str::trim).find(|l| !l.is_empty())?;
+    if line.chars().count() <= NTFY_TITLE_MAX_CHARS {
+        return Some(line.to_string());
+    }
+    let truncated: String = line.chars().take(NTFY_TITLE_MAX_CHARS - 1).collect();
+    Some(format!("{}…", truncated.trim_end()))
+}
+
+/// Split `body` into pieces of at most `max_bytes`, preferring to break after a newline
+/// and never splitting a UTF-8 character.
+fn split_ntfy_body(body: &str, max_bytes: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = body;
+    while rest.len() > max_bytes {
+        let mut cut = max_bytes;
+        while !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if let Some(newline) = rest[..cut].rfind('\n') {
+            if newline > 0 {
+                cut = newline + 1;
+            }
+        }
+        parts.push(rest[..cut].to_string());
+        rest = &rest[cut..];
+    }
+    if !rest.is_empty() || parts.is_empty() {
+        parts.push(rest.to_string());
+    }
+    parts
+}
+
+async fn publish_ntfy(
+    config: &NtfyConfig,
+    topics: &NtfyTopics,
+    notification: &OutboundMessage,
+) -> Result<(), Box<dyn Error>> {
     let client = reqwest::Client::new();
-    let url = format!("https://ntfy.sh/{}", topic);
-    let payload = format!("[bot] {}", message);
-    client.post(&url).body(payload).send().await?;
+    let payload = topics.outbound_payload(&redact_output(&notification.body));
+    let mut request = config.authorize(client.post(config.topic_url(&topics.outbound)).body(payload));
+    if let Some(ref title) = notification.title {
+        request = request.header("Title", redact_output(title));
+    }
+    if let Some(priority) = notification.priority {
+        request = request.header("Priority", priority);
+    }
+    if !notification.tags.is_empty() {
+        request = request.header("Tags", notification.tags.join(","));
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("Could not reach ntfy server {}: {}", config.base_url, e))?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "ntfy publish to topic {} failed with HTTP {} (check NTFY_TOKEN / topic permissions)",
+            topics.outbound,
+            response.status()
+        )
+        .into());
+    }
     Ok(())
 }
 
@@ -112,6 +486,9 @@ pub fn transform_ntfy_message(text: &str, msg_id: &str) -> ProtocolEvent {
         text: text.to_string(),
         provider: None,
         channel: Some(format!("ntfy:{}", msg_id)),
+        reply_to: None,
+        priority: false,
+        author: None,
     }
 }
 
@@ -122,16 +499,246 @@ mod tests {
     #[tokio::test]
     async fn test_notify_ntfy_fails_without_topic_env() {
         let backup = std::env::var("NTFY_TOPIC").ok();
-        unsafe { std::env::remove_var("NTFY_TOPIC"); }
+        let backup_out = std::env::var("NTFY_TOPIC_OUT").ok();
+        unsafe {
+            std::env::remove_var("NTFY_TOPIC");
+            std::env::remove_var("NTFY_TOPIC_OUT");
+        }
         let result = notify_ntfy("test").await;
         assert!(result.is_err(), "should fail when NTFY_TOPIC is missing");
         let msg = format!("{}", result.unwrap_err());
         assert!(msg.contains("NTFY_TOPIC"), "error should mention missing var");
         unsafe {
             if let Some(v) = backup { std::env::set_var("NTFY_TOPIC", v); }
+            if let Some(v) = backup_out { std::env::set_var("NTFY_TOPIC_OUT", v); }
+        }
+    }
+
+    #[test]
+    fn test_ntfy_config_defaults_to_ntfy_sh_without_token() {
+        let config = NtfyConfig::parse(None, None).unwrap();
+        assert_eq!(config.base_url, "https://ntfy.sh");
+        assert_eq!(config.token, None);
+        assert_eq!(config.topic_url("alerts"), "https://ntfy.sh/alerts");
+    }
+
+    #[test]
+    fn test_ntfy_config_normalizes_self_hosted_url_and_token() {
+        let config = NtfyConfig::parse(Some("https://ntfy.example.com/base//"), Some(" tk_abc ")).unwrap();
+        assert_eq!(config.topic_url("alerts"), "https://ntfy.example.com/base/alerts");
+        assert_eq!(config.token.as_deref(), Some("tk_abc"));
+        assert_eq!(NtfyConfig::parse(Some("  "), Some("")).unwrap(), NtfyConfig::parse(None, None).unwrap());
+    }
+
+    #[test]
+    fn test_ntfy_config_rejects_malformed_urls() {
+        for bad in ["ntfy.example.com", "ftp://ntfy.example.com", "https://"] {
+            let err = NtfyConfig::parse(Some(bad), None).unwrap_err();
+            assert!(err.contains("NTFY_SERVER_URL"), "{bad}: {err}");
         }
     }
 
+    #[tokio::test]
+    async fn test_publish_to_unreachable_server_reports_the_server() {
+        let config = NtfyConfig::parse(Some("http://127.0.0.1:1"), None).unwrap();
+        let topics = NtfyTopics::parse(Some("acomm"), None, None).unwrap();
+        let notification = OutboundMessage { body: "hi".into(), ..Default::default() };
+        let err = publish_ntfy(&config, &topics, &notification).await.unwrap_err();
+        assert!(err.to_string().contains("Could not reach ntfy server http://127.0.0.1:1"), "{err}");
+    }
+
+    #[test]
+    fn test_proactive_notification_parts_are_numbered_without_prompt_title() {
+        let message = NtfyReply { content: "a".repeat(NTFY_MAX_BODY_BYTES + 1), ..Default::default() };
+        let titles: Vec<_> = reply_notifications(&message).into_iter().map(|n| n.title).collect();
+        assert_eq!(titles, vec![Some("(1/2)".to_string()), Some("(2/2)".to_string())]);
+
+        let short = NtfyReply { content: "Build finished".into(), ..Default::default() };
+        assert_eq!(reply_notifications(&short)[0].title, None);
+        assert_eq!(reply_notifications(&short)[0].body, "Build finished");
+    }
+
+    #[test]
+    fn test_single_ntfy_topic_keeps_bot_prefix_loop_guard() {
+        let topics = NtfyTopics::parse(Some("acomm"), None, None).unwrap();
+        assert_eq!(topics.inbound, "acomm");
+        assert_eq!(topics.outbound, "acomm");
+        assert_eq!(topics.outbound_payload("done"), "[bot] done");
+        assert!(topics.is_own_message("[bot] done"));
+        assert!(!topics.is_own_message("hello"));
+    }
+
+    #[test]
+    fn test_separate_ntfy_topics_drop_bot_prefix_and_filter() {
+        let topics = NtfyTopics::parse(Some("acomm"), Some("acomm-in"), Some("acomm-out")).unwrap();
+        assert_eq!(topics.inbound, "acomm-in");
+        assert_eq!(topics.outbound, "acomm-out");
+        assert_eq!(topics.outbound_payload("done"), "done");
+        assert!(!topics.is_own_message("[bot] is a legit prompt"));
+
+        // One side may fall back to NTFY_TOPIC.
+        let topics = NtfyTopics::parse(Some("acomm"), None, Some("acomm-out")).unwrap();
+        assert_eq!(topics.inbound, "acomm");
+        assert!(!topics.shared());
+    }
+
+    #[test]
+    fn test_ntfy_topics_require_a_topic_for_each_direction() {
+        let err = NtfyTopics::parse(None, Some("in"), None).unwrap_err();
+        assert!(err.contains("NTFY_TOPIC_OUT"));
+        assert!(NtfyTopics::parse(Some(" "), None, None).is_err());
+    }
+
+    fn chunk(text: &str, channel: &str) -> ProtocolEvent {
+        ProtocolEvent::AgentChunk { chunk: text.into(), channel: Some(channel.into()) }
+    }
+
+    fn done(channel: &str) -> ProtocolEvent {
+        ProtocolEvent::AgentDone { channel: Some(channel.into()), provider: None, model: None }
+    }
+
+    #[test]
+    fn test_interleaved_ntfy_conversations_flush_independently() {
+        let mut buffers = HashMap::new();
+        for ch in ["ntfy:a", "ntfy:b"] {
+            assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &transform_ntfy_message("q", &ch[5..])).is_none());
+        }
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &chunk("A1 ", "ntfy:a")).is_none());
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &chunk("B1 ", "ntfy:b")).is_none());
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &chunk("A2", "ntfy:a")).is_none());
+
+        assert_eq!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &done("ntfy:a")).map(|r| r.content), Some("A1 A2".to_string()));
+        assert!(buffers.contains_key("ntfy:b"));
+
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &chunk("B2", "ntfy:b")).is_none());
+        assert_eq!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &done("ntfy:b")).map(|r| r.content), Some("B1 B2".to_string()));
+        assert!(buffers.is_empty());
+    }
+
+    #[test]
+    fn test_ntfy_buffers_ignore_other_channels() {
+        let mut buffers = HashMap::new();
+        buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &transform_ntfy_message("q", "a"));
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &chunk("tui text", "tui")).is_none());
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &done("tui")).is_none());
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &chunk("", "ntfy:a")).is_none());
+        // An empty reply is dropped rather than sent.
+        assert!(buffer_ntfy_reply(&mut buffers, &ActiveAgent::default(), &done("ntfy:a")).is_none());
+        assert!(buffers.is_empty());
+    }
+
+    #[test]
+    fn test_reply_notification_is_titled_after_prompt_and_tagged_with_provider() {
+        let reply = NtfyReply {
+            prompt: "\n  Summarize the build log\nplease".into(),
+            content: "All green.\n".into(),
+            agent: ActiveAgent::new("gemini", "auto-gemini-3"),
+        };
+        let notifications = reply_notifications(&reply);
+        assert_eq!(notifications, vec![OutboundMessage {
+            body: "All green.\n\n(gemini · auto-gemini-3)".into(),
+            title: Some("Summarize the build log".into()),
+            priority: None,
+            tags: vec!["robot".into(), "gemini".into()],
+            ..Default::default()
+        }]);
+    }
+
+    #[test]
+    fn test_long_reply_is_split_with_part_counter_in_title() {
+        let line = format!("{}\n", "x".repeat(99));
+        let reply = NtfyReply { prompt: "q".into(), content: line.repeat(90), ..Default::default() };
+        let notifications = reply_notifications(&reply);
+        assert_eq!(notifications.len(), 3);
+        for (i, n) in notifications.iter().enumerate() {
+            assert!(n.body.len() <= NTFY_MAX_BODY_BYTES);
+            assert!(n.body.ends_with('\n'), "parts break on line boundaries");
+            assert_eq!(n.title, Some(format!("q ({}/3)", i + 1)));
+        }
+        let joined: String = notifications.iter().map(|n| n.body.as_str()).collect();
+        assert_eq!(joined, reply.content);
+    }
+
+    #[test]
+    fn test_ntfy_profile_without_prompt_numbers_parts_and_keeps_trailer_last() {
+        let line = format!("{}\n", "y".repeat(99));
+        let notifications = NtfyProfile { title: None }.format_reply(&line.repeat(90), "claude", "claude-haiku-4-5");
+        assert_eq!(notifications.len(), 3);
+        assert_eq!(notifications[0].title.as_deref(), Some("(1/3)"));
+        assert!(notifications.iter().all(|n| n.body.len() <= NTFY_MAX_BODY_BYTES));
+        assert!(notifications[2].body.ends_with("(claude · claude-haiku-4-5)"));
+        assert!(!notifications[0].body.contains("claude-haiku"));
+    }
+
+    #[test]
+    fn test_split_ntfy_body_respects_utf8_boundaries() {
+        let parts = split_ntfy_body(&"あ".repeat(5), 7);
+        assert_eq!(parts, vec!["ああ", "ああ", "あ"]);
+        assert_eq!(split_ntfy_body("", 7), vec![""]);
+    }
+
+    #[test]
+    fn test_ntfy_title_truncates_long_prompts() {
+        let title = ntfy_title(&"word ".repeat(30)).unwrap();
+        assert!(title.chars().count() <= NTFY_TITLE_MAX_CHARS);
+        assert!(title.ends_with('…'));
+        assert_eq!(ntfy_title("  \n "), None);
+    }
+
+    #[test]
+    fn test_agent_failures_are_sent_at_high_priority() {
+        let error = system_notification(&format!("{}: timeout", AGENT_FAILURE_PREFIX), &ActiveAgent::new("codex", ""));
+        assert_eq!(error.priority, Some("high"));
+        assert_eq!(error.tags, vec!["robot".to_string(), "codex".to_string()]);
+        assert!(error.body.ends_with("timeout\n\n(codex)"));
+        let info = system_notification("Switched to codex.", &ActiveAgent::default());
+        assert_eq!(info.priority, None);
+        assert_eq!(info.tags, vec!["robot".to_string()]);
+    }
+
+    #[test]
+    fn test_reply_records_the_prompt_provider_over_the_active_one() {
+        let mut buffers = HashMap::new();
+        let active = ActiveAgent::new("gemini", "gemini-2.5-pro");
+        let prompt = ProtocolEvent::Prompt {
+            text: "q".into(),
+            provider: Some(acore::AgentProvider::Claude),
+            channel: Some("ntfy:a".into()),
+            reply_to: None,
+            priority: false,
+            author: None,
+        };
+        buffer_ntfy_reply(&mut buffers, &active, &prompt);
+        buffer_ntfy_reply(&mut buffers, &active, &chunk("hi", "ntfy:a"));
+        let reply = buffer_ntfy_reply(&mut buffers, &active, &done("ntfy:a")).unwrap();
+        assert_eq!(reply.agent, ActiveAgent::new("claude", "claude-sonnet-4-6"));
+    }
+
+    #[test]
+    fn test_provider_switch_sends_one_low_priority_notice() {
+        let mut active = ActiveAgent::default();
+        let provider = ProtocolEvent::ProviderSwitched { provider: acore::AgentProvider::Claude };
+        active.observe(&provider);
+        assert_eq!(switch_notice(&provider, &active), None, "waits for the ModelSwitched that follows");
+
+        let model = ProtocolEvent::ModelSwitched { model: "claude-sonnet-4-6".into() };
+        active.observe(&model);
+        let notice = switch_notice(&model, &active).unwrap();
+        assert_eq!(notice.body, "now using claude:claude-sonnet-4-6");
+        assert_eq!(notice.priority, Some("low"));
+
+        let provider = ProtocolEvent::ProviderSwitched { provider: acore::AgentProvider::OpenCode };
+        active.observe(&provider);
+        assert_eq!(switch_notice(&provider, &active).unwrap().body, "now using opencode");
+    }
+
+    #[test]
+    fn test_quiet_hours_downgrade_proactive_notifications_to_min() {
+        let notification = OutboundMessage { body: "x".into(), priority: Some("high"), ..Default::default() };
+        assert_eq!(quieted(notification.clone(), false), notification);
+        assert_eq!(quieted(notification, true).priority, Some("min"));
+    }
+
     #[test]
     fn test_transform_ntfy_message() {
         let event = transform_ntfy_message("hello", "msg123");
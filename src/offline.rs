@@ -0,0 +1,79 @@
+/**
+ * Offline demo replies for the Dummy provider.
+ *
+ * With `ACOMM_OFFLINE=1` the bridge starts on `dummy:echo` and answers every prompt
+ * itself instead of calling an agent CLI, so acomm can be tried without API keys.
+ * The canned reply is streamed in several chunks and mixes prose, a list and a code
+ * block, which is enough to exercise TUI streaming, Discord answer extraction and
+ * Slack Block Kit rendering end to end.
+ */
+use std::time::Duration;
+
+/// Pause between streamed chunks so clients render the reply incrementally.
+pub const OFFLINE_CHUNK_DELAY: Duration = Duration::from_millis(40);
+const ECHO_PREVIEW_CHARS: usize = 200;
+
+/// Whether `ACOMM_OFFLINE` asks for the offline demo provider.
+pub fn offline_mode(raw: Option<&str>) -> bool {
+    matches!(raw.map(|v| v.trim().to_ascii_lowercase()).as_deref(), Some("1" | "true" | "yes"))
+}
+
+/// The demo reply to `prompt`, split into the chunks it is streamed as (one per line).
+pub fn offline_reply_chunks(prompt: &str) -> Vec<String> {
+    offline_reply(prompt).split_inclusive('\n').map(str::to_string).collect()
+}
+
+fn offline_reply(prompt: &str) -> String {
+    let prompt = prompt.trim();
+    let mut echoed: String = prompt.chars().take(ECHO_PREVIEW_CHARS).collect();
+    if prompt.chars().count() > ECHO_PREVIEW_CHARS {
+        echoed.push('…');
+    }
+    format!(
+        "**Offline demo reply** (dummy:echo)\n\
+         \n\
+         You said:\n\
+         \n\
+         > {echoed}\n\
+         \n\
+         No agent CLI was called because the bridge is running with `ACOMM_OFFLINE=1`. \
+         This reply is streamed in several chunks so you can check that:\n\
+         \n\
+         - the TUI renders text as it arrives,\n\
+         - Discord picks the final paragraph and appends the status line,\n\
+         - Slack threads the reply and renders code as a preformatted block.\n\
+         \n\
+         ```sh\n\
+         echo \"summarize this\" | acomm -p - --wait   # scripts work offline too\n\
+         ```\n\
+         \n\
+         Unset ACOMM_OFFLINE and restart the bridge (or run `/provider gemini`) to talk to a real agent.\n",
+        echoed = echoed.replace('\n', " "),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_mode_accepts_common_truthy_values() {
+        for raw in ["1", "true", " YES "] {
+            assert!(offline_mode(Some(raw)), "{raw}");
+        }
+        for raw in ["", "0", "false", "off"] {
+            assert!(!offline_mode(Some(raw)), "{raw}");
+        }
+        assert!(!offline_mode(None));
+    }
+
+    #[test]
+    fn reply_echoes_the_prompt_on_one_line_and_streams_in_many_chunks() {
+        let chunks = offline_reply_chunks("hello\nworld");
+        assert!(chunks.len() > 5);
+        let reply: String = chunks.concat();
+        assert!(reply.contains("> hello world\n"));
+        assert!(reply.contains("```sh\n"));
+        assert!(chunks.iter().all(|c| c.ends_with('\n')));
+    }
+}
@@ -0,0 +1,186 @@
+/**
+ * Per-session prompt queue for the bridge.
+ *
+ * Prompts that share an agent session (see `session_key` in the bridge) run one at a
+ * time: a prompt that arrives while its session is busy waits for the running one to
+ * finish instead of resuming the same session concurrently. Prompts flagged
+ * `priority` skip ahead of everything already waiting.
+ *
+ * `PromptDedup` drops a prompt that repeats one still queued or running in the same
+ * session, or one that finished only moments ago: a double-pressed Enter or a
+ * redelivered chat event would otherwise run the agent twice.
+ */
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// How long a finished prompt still counts as a duplicate (`ACOMM_DEDUP_WINDOW_SECS`).
+pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(5);
+
+/// What to do with a prompt handed to `PromptQueue::admit`.
+#[derive(Debug, PartialEq)]
+pub enum Admission<T> {
+    /// The session was idle: run the prompt now.
+    Start(T),
+    /// The session is busy: the prompt waits at this 1-based position.
+    Queued { position: usize },
+}
+
+#[derive(Debug)]
+pub struct PromptQueue<T> {
+    running: bool,
+    pending: VecDeque<T>,
+}
+
+impl<T> Default for PromptQueue<T> {
+    fn default() -> Self {
+        Self { running: false, pending: VecDeque::new() }
+    }
+}
+
+impl<T> PromptQueue<T> {
+    pub fn admit(&mut self, item: T, priority: bool) -> Admission<T> {
+        if !self.running {
+            self.running = true;
+            return Admission::Start(item);
+        }
+        if priority {
+            self.pending.push_front(item);
+            Admission::Queued { position: 1 }
+        } else {
+            self.pending.push_back(item);
+            Admission::Queued { position: self.pending.len() }
+        }
+    }
+
+    /// Called when the running prompt is done: the next one to run, or `None` once
+    /// nothing is waiting (the session is then idle again).
+    pub fn finish(&mut self) -> Option<T> {
+        let next = self.pending.pop_front();
+        self.running = next.is_some();
+        next
+    }
+
+    #[cfg(test)]
+    pub fn waiting(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Why `PromptDedup::admit` turned a prompt away.
+#[derive(Debug, PartialEq)]
+pub enum Duplicate {
+    /// The same prompt is still queued or running.
+    InFlight,
+    /// The same prompt finished this long ago, within the window.
+    Recent(Duration),
+}
+
+/// Prompts per session key and text: `None` while queued or running, then when they finished.
+#[derive(Debug)]
+pub struct PromptDedup {
+    window: Duration,
+    prompts: HashMap<(String, String), Option<Instant>>,
+}
+
+impl Default for PromptDedup {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+impl PromptDedup {
+    pub fn new(window: Duration) -> Self {
+        Self { window, prompts: HashMap::new() }
+    }
+
+    /// Register `text` for session `key`, or tell why it repeats an earlier prompt.
+    /// Every admitted prompt must be `finish`ed once it has run.
+    pub fn admit(&mut self, key: &str, text: &str, now: Instant) -> Result<(), Duplicate> {
+        let window = self.window;
+        self.prompts.retain(|_, done| done.is_none_or(|at| now.saturating_duration_since(at) < window));
+        let id = (key.to_string(), text.to_string());
+        match self.prompts.get(&id) {
+            Some(None) => Err(Duplicate::InFlight),
+            Some(Some(at)) => Err(Duplicate::Recent(now.saturating_duration_since(*at))),
+            None => {
+                self.prompts.insert(id, None);
+                Ok(())
+            }
+        }
+    }
+
+    /// Mark an admitted prompt as done at `now`; it stays a duplicate for the window.
+    pub fn finish(&mut self, key: &str, text: &str, now: Instant) {
+        if let Some(done) = self.prompts.get_mut(&(key.to_string(), text.to_string())) {
+            *done = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_session_starts_at_once_and_busy_one_queues_in_order() {
+        let mut queue = PromptQueue::default();
+        assert_eq!(queue.admit("a", false), Admission::Start("a"));
+        assert_eq!(queue.admit("b", false), Admission::Queued { position: 1 });
+        assert_eq!(queue.admit("c", false), Admission::Queued { position: 2 });
+        assert_eq!(queue.finish(), Some("b"));
+        assert_eq!(queue.finish(), Some("c"));
+        assert_eq!(queue.finish(), None);
+        assert_eq!(queue.admit("d", false), Admission::Start("d"));
+    }
+
+    #[test]
+    fn priority_prompt_is_dequeued_before_an_earlier_normal_one() {
+        let mut queue = PromptQueue::default();
+        assert_eq!(queue.admit("running", false), Admission::Start("running"));
+        assert_eq!(queue.admit("normal", false), Admission::Queued { position: 1 });
+        assert_eq!(queue.admit("urgent", true), Admission::Queued { position: 1 });
+        assert_eq!(queue.waiting(), 2);
+        assert_eq!(queue.finish(), Some("urgent"));
+        assert_eq!(queue.finish(), Some("normal"));
+        assert_eq!(queue.finish(), None);
+    }
+
+    #[test]
+    fn dedup_rejects_a_prompt_while_its_twin_is_queued_or_running() {
+        let mut dedup = PromptDedup::new(Duration::from_secs(5));
+        let t0 = Instant::now();
+        assert_eq!(dedup.admit("tui", "running", t0), Ok(()));
+        assert_eq!(dedup.admit("tui", "queued", t0), Ok(()));
+        assert_eq!(dedup.admit("tui", "running", t0), Err(Duplicate::InFlight));
+        // Long-running prompts stay in flight however long they take.
+        assert_eq!(dedup.admit("tui", "queued", t0 + Duration::from_secs(600)), Err(Duplicate::InFlight));
+        // The same text in another session is a different prompt.
+        assert_eq!(dedup.admit("slack:C1", "running", t0), Ok(()));
+    }
+
+    #[test]
+    fn dedup_rejects_a_recently_done_prompt_but_not_one_minutes_later() {
+        let mut dedup = PromptDedup::new(Duration::from_secs(5));
+        let t0 = Instant::now();
+        dedup.admit("tui", "hello", t0).unwrap();
+        dedup.finish("tui", "hello", t0 + Duration::from_secs(10));
+        assert_eq!(
+            dedup.admit("tui", "hello", t0 + Duration::from_secs(12)),
+            Err(Duplicate::Recent(Duration::from_secs(2)))
+        );
+        assert_eq!(dedup.admit("tui", "hello", t0 + Duration::from_secs(120)), Ok(()));
+
+        // A zero window only drops prompts that are still in flight.
+        let mut dedup = PromptDedup::new(Duration::ZERO);
+        dedup.admit("tui", "again", t0).unwrap();
+        dedup.finish("tui", "again", t0);
+        assert_eq!(dedup.admit("tui", "again", t0), Ok(()));
+    }
+
+    #[test]
+    fn priority_on_an_idle_session_just_starts() {
+        let mut queue = PromptQueue::default();
+        assert_eq!(queue.admit("urgent", true), Admission::Start("urgent"));
+        assert_eq!(queue.waiting(), 0);
+    }
+}
@@ -1,12 +1,51 @@
 use acore::AgentProvider;
 use serde::{Deserialize, Serialize};
 
+/// ブリッジとクライアントが `Hello` で交換するプロトコルバージョン。
+/// イベントの意味や形式を互換性なく変えたときに上げる。
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// エージェントの実行に失敗したときにブリッジが送る SystemMessage の接頭辞。
+pub const AGENT_FAILURE_PREFIX: &str = "Agent execution failed";
+
+const DEFAULT_GEMINI_MODEL: &str = "auto-gemini-3";
+const DEFAULT_CLAUDE_MODEL: &str = "claude-sonnet-4-6";
+const DEFAULT_CODEX_MODEL: &str = "gpt-5.3-codex";
+
+/// プロバイダーごとに選べる既知のモデル。先頭が切り替え時の既定モデル（OpenCode は既定なし）。
+pub fn models_for_provider(provider: &AgentProvider) -> &'static [&'static str] {
+    match provider {
+        AgentProvider::Gemini => &[DEFAULT_GEMINI_MODEL, "gemini-2.5-pro", "gemini-2.5-flash", "gemini-2.0-flash"],
+        AgentProvider::Claude => &[DEFAULT_CLAUDE_MODEL, "claude-opus-4-6", "claude-haiku-4-5"],
+        AgentProvider::Codex => &[DEFAULT_CODEX_MODEL, "gpt-4o", "gpt-4o-mini", "o1-mini"],
+        AgentProvider::Dummy => &["echo"],
+        AgentProvider::Mock => &["mock-model"],
+        AgentProvider::OpenCode => &[],
+    }
+}
+
+/// プロバイダーに切り替えたときに選ばれる既定モデル（OpenCode は既定なし）。
+pub fn default_model_for_provider(provider: &AgentProvider) -> Option<&'static str> {
+    models_for_provider(provider).first().copied()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum ProtocolEvent {
     Prompt { 
         text: String, 
         provider: Option<AgentProvider>,
         channel: Option<String>,
+        /// 返信先のメッセージ id（Discord の referenced_message.id、Slack の thread_ts）。
+        /// 同じスレッドのプロンプトはエージェントのセッションを共有し、返答も同じスレッドに返す。
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reply_to: Option<String>,
+        /// 緊急のプロンプト。チャンネルが実行中なら待ち行列の末尾ではなく先頭に入る。
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        priority: bool,
+        /// 送信者の id（Discord の author.id）。チャンネルに送信者が入らないアダプターが付け、
+        /// ブリッジはユーザーごとのクォータに使う。
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        author: Option<String>,
     },
     /// エージェントからの回答の断片（チャンク）。
     AgentChunk { 
@@ -15,6 +54,12 @@ pub enum ProtocolEvent {
     },
     AgentDone {
         channel: Option<String>,
+        /// 実際に回答したプロバイダーとモデル。フォールバックで別のプロバイダーが答えたときもこちらが正しい。
+        /// 古いブリッジは送らないので、無ければプロンプト時点のものを使う。
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        provider: Option<AgentProvider>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
     },
     SystemMessage { 
         msg: String,
@@ -24,6 +69,21 @@ pub enum ProtocolEvent {
         is_processing: bool,
         channel: Option<String>,
     },
+    /// ブリッジが Prompt を受け付けたことを、送信元の接続にだけ返す。
+    /// `seq` はその接続で受け付けた Prompt の通し番号（1 始まり）。
+    PromptAck {
+        channel: Option<String>,
+        seq: u64,
+    },
+    /// プロンプトが AgentDone に到達せずに終わった（エージェントタスクの panic 等）。
+    /// アダプターは AgentDone と同様にチャンネルごとの状態を片付ける。
+    ChannelClosed {
+        channel: Option<String>,
+        reason: String,
+    },
+    /// 接続直後にブリッジとクライアントが互いに送るプロトコルバージョン。
+    /// `Hello` を知らない古い相手は解釈できない行として読み飛ばす。
+    Hello { version: u32 },
     BridgeSyncDone {},
     SyncContext { context: String },
     ProviderSwitched { provider: AgentProvider },
@@ -38,7 +98,10 @@ impl ProtocolEvent {
             ProtocolEvent::AgentDone { channel, .. } => channel.clone(),
             ProtocolEvent::SystemMessage { channel, .. } => channel.clone(),
             ProtocolEvent::StatusUpdate { channel, .. } => channel.clone(),
-            ProtocolEvent::BridgeSyncDone { .. }
+            ProtocolEvent::PromptAck { channel, .. } => channel.clone(),
+            ProtocolEvent::ChannelClosed { channel, .. } => channel.clone(),
+            ProtocolEvent::Hello { .. }
+            | ProtocolEvent::BridgeSyncDone { .. }
             | ProtocolEvent::SyncContext { .. }
             | ProtocolEvent::ProviderSwitched { .. }
             | ProtocolEvent::ModelSwitched { .. } => None,
@@ -46,9 +109,111 @@ impl ProtocolEvent {
     }
 }
 
+/// 相手 (`peer`) が名乗ったバージョンが非互換なら、利用者向けの説明文を返す。
+pub fn version_mismatch_message(peer: &str, version: u32) -> Option<String> {
+    if version == PROTOCOL_VERSION {
+        return None;
+    }
+    Some(format!(
+        "Incompatible acomm protocol: the {} speaks version {} but this side speaks version {}. Upgrade acomm so both sides match.",
+        peer, version, PROTOCOL_VERSION
+    ))
+}
+
+/// プロバイダーのコマンド名（`/provider <name>` や `p-<name>` の `<name>`）を `AgentProvider` に変換する。
+/// 大文字小文字と前後の空白は無視する。未知の名前は `None`。
+pub fn provider_from_command_name(name: &str) -> Option<AgentProvider> {
+    match name.trim().to_ascii_lowercase().as_str() {
+        "gemini" => Some(AgentProvider::Gemini),
+        "claude" => Some(AgentProvider::Claude),
+        "codex" => Some(AgentProvider::Codex),
+        "opencode" => Some(AgentProvider::OpenCode),
+        "dummy" | "dummy-bot" | "dummybot" => Some(AgentProvider::Dummy),
+        "mock" => Some(AgentProvider::Mock),
+        _ => None,
+    }
+}
+
+/// `/compare` で各プロバイダーの出力を流すチャンネル（`tui#gemini`）。
+pub fn compare_channel(channel: &str, provider: &AgentProvider) -> String {
+    format!("{}#{}", channel, provider.command_name())
+}
+
+/// `compare_channel` の逆。元のチャンネルと比較中のプロバイダーに分ける。
+/// 末尾の `#` 以降がプロバイダー名でなければ `None`。
+pub fn split_compare_channel(channel: &str) -> Option<(&str, AgentProvider)> {
+    let (base, name) = channel.rsplit_once('#')?;
+    let provider = provider_from_command_name(name)?;
+    (!base.is_empty()).then_some((base, provider))
+}
+
+/// エグゼキューターが最終回答の直前に `AgentChunk` ストリームへ流せる区切り。
+/// ブリッジはそのまま中継し、アダプターは推測せずに区切り以降を回答として届ける。
+pub const FINAL_ANSWER_MARKER: &str = "<<<ACOMM_FINAL_ANSWER>>>";
+
+/// 返信本文のうち、最後の `FINAL_ANSWER_MARKER` より後ろ（前後の空白を除く）。
+/// 区切りがない、または区切りの後ろが空なら `None`。
+pub fn final_answer(content: &str) -> Option<&str> {
+    let (_, answer) = content.rsplit_once(FINAL_ANSWER_MARKER)?;
+    Some(answer.trim()).filter(|answer| !answer.is_empty())
+}
+
+/// アダプターが返信に添えるために追跡する、現在のプロバイダー名とモデル名。
+/// `ProviderSwitched` / `ModelSwitched` を `observe` に渡して更新する。空文字は未確定を表す。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ActiveAgent {
+    pub provider: String,
+    pub model: String,
+}
+
+impl ActiveAgent {
+    pub fn new(provider: &str, model: &str) -> Self {
+        Self { provider: provider.to_string(), model: model.to_string() }
+    }
+
+    /// プロバイダー切り替え時はそのプロバイダーの既定モデルに戻す（続く `ModelSwitched` で上書きされる）。
+    pub fn observe(&mut self, event: &ProtocolEvent) {
+        match event {
+            ProtocolEvent::ProviderSwitched { provider } => *self = Self::for_provider(provider),
+            ProtocolEvent::ModelSwitched { model } => self.model = model.clone(),
+            _ => {}
+        }
+    }
+
+    /// `AgentDone` が実際に回答したプロバイダーを運んでいれば、それに置き換えたもの。
+    /// フォールバックで別のプロバイダーが答えた返答にも正しい表示を付けるために使う。
+    pub fn answered(&self, event: &ProtocolEvent) -> Self {
+        match event {
+            ProtocolEvent::AgentDone { provider: Some(provider), model, .. } => match model {
+                Some(model) => Self::new(provider.command_name(), model),
+                None => Self::for_provider(provider),
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// プロンプトにプロバイダーが明示されていればそれを優先する。
+    pub fn for_prompt(&self, provider: Option<&AgentProvider>) -> Self {
+        match provider {
+            Some(provider) if provider.command_name() != self.provider => Self::for_provider(provider),
+            _ => self.clone(),
+        }
+    }
+
+    fn for_provider(provider: &AgentProvider) -> Self {
+        Self::new(
+            provider.command_name(),
+            default_model_for_provider(provider).unwrap_or_default(),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ProtocolEvent;
+    use super::{
+        ActiveAgent, FINAL_ANSWER_MARKER, PROTOCOL_VERSION, ProtocolEvent, compare_channel, default_model_for_provider,
+        final_answer, models_for_provider, provider_from_command_name, split_compare_channel, version_mismatch_message,
+    };
     use acore::AgentProvider;
 
     #[test]
@@ -63,6 +228,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn prompt_reply_to_is_optional_on_the_wire() {
+        let legacy = r#"{"Prompt":{"text":"hi","provider":null,"channel":"tui"}}"#;
+        match serde_json::from_str::<ProtocolEvent>(legacy).unwrap() {
+            ProtocolEvent::Prompt { reply_to, .. } => assert_eq!(reply_to, None),
+            _ => panic!("expected Prompt"),
+        }
+        let unthreaded = ProtocolEvent::Prompt { text: "hi".into(), provider: None, channel: None, reply_to: None, priority: false, author: None };
+        assert!(!serde_json::to_string(&unthreaded).unwrap().contains("reply_to"));
+
+        let threaded = ProtocolEvent::Prompt {
+            text: "hi".into(),
+            provider: None,
+            channel: Some("slack:U1:C1".into()),
+            reply_to: Some("1700000000.000100".into()),
+            priority: false,
+            author: None,
+        };
+        let json = serde_json::to_string(&threaded).unwrap();
+        assert!(json.contains(r#""reply_to":"1700000000.000100""#));
+        match serde_json::from_str::<ProtocolEvent>(&json).unwrap() {
+            ProtocolEvent::Prompt { reply_to, .. } => assert_eq!(reply_to.as_deref(), Some("1700000000.000100")),
+            _ => panic!("expected Prompt"),
+        }
+    }
+
     #[test]
     fn provider_switched_serializes_provider_field() {
         let event = ProtocolEvent::ProviderSwitched { provider: AgentProvider::Claude };
@@ -82,4 +273,103 @@ mod tests {
             _ => panic!("expected ProviderSwitched"),
         }
     }
+
+    #[test]
+    fn provider_from_command_name_covers_every_variant() {
+        for provider in [
+            AgentProvider::Gemini,
+            AgentProvider::Claude,
+            AgentProvider::Codex,
+            AgentProvider::OpenCode,
+            AgentProvider::Dummy,
+            AgentProvider::Mock,
+        ] {
+            assert_eq!(provider_from_command_name(provider.command_name()), Some(provider.clone()));
+        }
+    }
+
+    #[test]
+    fn default_model_is_the_first_known_model() {
+        assert_eq!(default_model_for_provider(&AgentProvider::Gemini), Some("auto-gemini-3"));
+        assert_eq!(default_model_for_provider(&AgentProvider::Codex), Some("gpt-5.3-codex"));
+        assert_eq!(default_model_for_provider(&AgentProvider::OpenCode), None);
+        assert!(models_for_provider(&AgentProvider::Claude).contains(&"claude-opus-4-6"));
+    }
+
+    #[test]
+    fn agent_done_overrides_the_agent_that_answered() {
+        let active = ActiveAgent::new("gemini", "auto-gemini-3");
+        let fallback = ProtocolEvent::AgentDone {
+            channel: Some("tui".into()),
+            provider: Some(AgentProvider::Claude),
+            model: Some("claude-opus-4-6".into()),
+        };
+        assert_eq!(active.answered(&fallback), ActiveAgent::new("claude", "claude-opus-4-6"));
+        let no_model = ProtocolEvent::AgentDone { channel: None, provider: Some(AgentProvider::Codex), model: None };
+        assert_eq!(active.answered(&no_model), ActiveAgent::new("codex", "gpt-5.3-codex"));
+        // 古いブリッジの AgentDone はそのまま
+        let legacy: ProtocolEvent = serde_json::from_str(r#"{"AgentDone":{"channel":"tui"}}"#).unwrap();
+        assert_eq!(active.answered(&legacy), active);
+    }
+
+    #[test]
+    fn provider_from_command_name_accepts_aliases_and_case() {
+        assert_eq!(provider_from_command_name("dummy-bot"), Some(AgentProvider::Dummy));
+        assert_eq!(provider_from_command_name("dummybot"), Some(AgentProvider::Dummy));
+        assert_eq!(provider_from_command_name(" Claude "), Some(AgentProvider::Claude));
+        assert_eq!(provider_from_command_name("OPENCODE"), Some(AgentProvider::OpenCode));
+        assert_eq!(provider_from_command_name("gpt"), None);
+        assert_eq!(provider_from_command_name(""), None);
+    }
+
+    #[test]
+    fn active_agent_follows_provider_and_model_switches() {
+        let mut active = ActiveAgent::default();
+        active.observe(&ProtocolEvent::ProviderSwitched { provider: AgentProvider::Codex });
+        assert_eq!(active, ActiveAgent::new("codex", "gpt-5.3-codex"));
+        active.observe(&ProtocolEvent::ModelSwitched { model: "o1-mini".into() });
+        assert_eq!(active.model, "o1-mini");
+        active.observe(&ProtocolEvent::ProviderSwitched { provider: AgentProvider::OpenCode });
+        assert_eq!(active, ActiveAgent::new("opencode", ""));
+        active.observe(&ProtocolEvent::AgentDone { channel: None, provider: None, model: None });
+        assert_eq!(active.provider, "opencode");
+    }
+
+    #[test]
+    fn active_agent_prefers_the_prompt_provider() {
+        let active = ActiveAgent::new("gemini", "gemini-2.5-pro");
+        assert_eq!(active.for_prompt(None), active);
+        assert_eq!(active.for_prompt(Some(&AgentProvider::Gemini)), active);
+        assert_eq!(
+            active.for_prompt(Some(&AgentProvider::Claude)),
+            ActiveAgent::new("claude", "claude-sonnet-4-6")
+        );
+    }
+
+    #[test]
+    fn hello_round_trips_and_mismatch_is_explained() {
+        let json = serde_json::to_string(&ProtocolEvent::Hello { version: PROTOCOL_VERSION }).unwrap();
+        assert_eq!(json, format!(r#"{{"Hello":{{"version":{}}}}}"#, PROTOCOL_VERSION));
+        assert_eq!(version_mismatch_message("client", PROTOCOL_VERSION), None);
+        let msg = version_mismatch_message("client", PROTOCOL_VERSION + 1).unwrap();
+        assert!(msg.contains("client") && msg.contains(&(PROTOCOL_VERSION + 1).to_string()));
+    }
+
+    #[test]
+    fn final_answer_is_the_text_after_the_last_marker() {
+        let content = format!("Reading files…\n{m}\ndraft\n{m}\n  The answer.\n\n", m = FINAL_ANSWER_MARKER);
+        assert_eq!(final_answer(&content), Some("The answer."));
+        assert_eq!(final_answer("no marker here"), None);
+        assert_eq!(final_answer(&format!("narration {}  \n", FINAL_ANSWER_MARKER)), None);
+    }
+
+    #[test]
+    fn compare_channels_round_trip() {
+        let channel = compare_channel("discord:1:2", &AgentProvider::Claude);
+        assert_eq!(channel, "discord:1:2#claude");
+        assert_eq!(split_compare_channel(&channel), Some(("discord:1:2", AgentProvider::Claude)));
+        assert_eq!(split_compare_channel("tui"), None);
+        assert_eq!(split_compare_channel("tui#general"), None);
+        assert_eq!(split_compare_channel("#gemini"), None);
+    }
 }
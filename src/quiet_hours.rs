@@ -0,0 +1,94 @@
+/**
+ * Quiet hours for proactive notifications.
+ *
+ * `ACOMM_QUIET_HOURS=22:00-07:00` (local time) marks a daily window during which
+ * agent-initiated notifications (`acomm --agent`, ntfy switch notices) are sent
+ * silently instead of pinging the phone. Replies to a user's own prompt are never
+ * affected. Ranges may span midnight; unset or invalid values disable the window.
+ */
+use chrono::{Local, NaiveTime};
+use std::sync::OnceLock;
+use tracing::warn;
+
+const QUIET_HOURS_ENV: &str = "ACOMM_QUIET_HOURS";
+
+/// A daily `[start, end)` window in local time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+/// Parse `HH:MM-HH:MM`.
+pub fn parse_quiet_hours(raw: &str) -> Result<QuietHours, String> {
+    let invalid = || format!("Invalid {} '{}': expected HH:MM-HH:MM", QUIET_HOURS_ENV, raw);
+    let (start, end) = raw.trim().split_once('-').ok_or_else(invalid)?;
+    let parse = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| invalid());
+    Ok(QuietHours { start: parse(start)?, end: parse(end)? })
+}
+
+/// Whether `now` falls inside `range`; a range whose end is before its start wraps past midnight.
+pub fn in_quiet_hours(now: NaiveTime, range: &QuietHours) -> bool {
+    if range.start <= range.end {
+        range.start <= now && now < range.end
+    } else {
+        now >= range.start || now < range.end
+    }
+}
+
+/// `ACOMM_QUIET_HOURS`, parsed once so an invalid value is reported only once.
+fn configured_quiet_hours() -> Option<QuietHours> {
+    static QUIET_HOURS: OnceLock<Option<QuietHours>> = OnceLock::new();
+    *QUIET_HOURS.get_or_init(|| {
+        let raw = std::env::var(QUIET_HOURS_ENV).ok().filter(|v| !v.trim().is_empty())?;
+        match parse_quiet_hours(&raw) {
+            Ok(range) => Some(range),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    })
+}
+
+/// Whether proactive notifications should be sent silently right now.
+pub fn quiet_now() -> bool {
+    configured_quiet_hours().is_some_and(|range| in_quiet_hours(Local::now().time(), &range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn midnight_spanning_range_covers_late_night_and_early_morning() {
+        let range = parse_quiet_hours("22:00-07:00").unwrap();
+        assert!(in_quiet_hours(at(22, 0), &range));
+        assert!(in_quiet_hours(at(23, 59), &range));
+        assert!(in_quiet_hours(at(0, 0), &range));
+        assert!(in_quiet_hours(at(3, 0), &range));
+        assert!(!in_quiet_hours(at(7, 0), &range));
+        assert!(!in_quiet_hours(at(12, 0), &range));
+        assert!(!in_quiet_hours(at(21, 59), &range));
+    }
+
+    #[test]
+    fn same_day_range_is_half_open() {
+        let range = parse_quiet_hours(" 13:00 - 14:30 ").unwrap();
+        assert!(!in_quiet_hours(at(12, 59), &range));
+        assert!(in_quiet_hours(at(13, 0), &range));
+        assert!(in_quiet_hours(at(14, 29), &range));
+        assert!(!in_quiet_hours(at(14, 30), &range));
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected_with_the_variable_name() {
+        for bad in ["22:00", "25:00-07:00", "late-early", ""] {
+            assert!(parse_quiet_hours(bad).unwrap_err().contains("ACOMM_QUIET_HOURS"), "{bad}");
+        }
+    }
+}
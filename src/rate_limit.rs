@@ -0,0 +1,364 @@
+/**
+ * Per-user rate limiting for the chat adapters.
+ *
+ * Every Discord author / Slack user gets a token bucket before their prompt is
+ * forwarded to the bridge: `ACOMM_RATE_LIMIT_BURST` prompts may arrive back to back
+ * (default 5) and one more becomes available every `ACOMM_RATE_LIMIT_REFILL_SECS`
+ * seconds (default 12, i.e. 5 per minute). A throttled user is told once when to
+ * retry; further prompts are dropped quietly until one is allowed again. A burst of
+ * 0 disables the limiter.
+ *
+ * The bridge keeps its own hourly quota on top (`Quota`): `ACOMM_QUOTA_PER_HOUR`
+ * prompts per user and hour (unset or 0 disables it), with bursts of up to
+ * `ACOMM_QUOTA_BURST` (default: the hourly rate). Channels or users listed in
+ * `ACOMM_QUOTA_EXEMPT` are never limited.
+ */
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const DEFAULT_BURST: u32 = 5;
+const DEFAULT_REFILL: Duration = Duration::from_secs(12);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitConfig {
+    /// Bucket size: prompts allowed in a burst.
+    pub burst: u32,
+    /// Time for one token to refill.
+    pub refill: Duration,
+}
+
+/// Parse `ACOMM_RATE_LIMIT_BURST` / `ACOMM_RATE_LIMIT_REFILL_SECS`. Returns `None` when
+/// the limiter is disabled, plus one warning per invalid value (which uses its default).
+pub fn parse_rate_limit_config(
+    burst_env: Option<&str>,
+    refill_env: Option<&str>,
+) -> (Option<RateLimitConfig>, Vec<String>) {
+    let mut warnings = Vec::new();
+    let burst = match burst_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => DEFAULT_BURST,
+        Some(raw) => raw.parse::<u32>().unwrap_or_else(|_| {
+            warnings.push(format!("Invalid ACOMM_RATE_LIMIT_BURST '{}'; using {}", raw, DEFAULT_BURST));
+            DEFAULT_BURST
+        }),
+    };
+    let refill = match refill_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => DEFAULT_REFILL,
+        Some(raw) => match raw.parse::<f64>() {
+            Ok(secs) if secs.is_finite() && secs > 0.0 => Duration::from_secs_f64(secs),
+            _ => {
+                warnings.push(format!(
+                    "Invalid ACOMM_RATE_LIMIT_REFILL_SECS '{}'; using {}",
+                    raw,
+                    DEFAULT_REFILL.as_secs()
+                ));
+                DEFAULT_REFILL
+            }
+        },
+    };
+    let config = (burst > 0).then_some(RateLimitConfig { burst, refill });
+    (config, warnings)
+}
+
+/// Outcome of a rate-limit check for one prompt.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateDecision {
+    Allow,
+    /// Over the limit. `notify` is set only for the first rejection in a row, so the
+    /// adapter replies once and then stays quiet.
+    Throttled { retry_after: Duration, notify: bool },
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    notified: bool,
+}
+
+/// Token buckets keyed by user id.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self { config, buckets: HashMap::new() }
+    }
+
+    /// The limiter configured by the environment, or `None` when disabled.
+    pub fn from_env() -> Option<Self> {
+        let (config, warnings) = parse_rate_limit_config(
+            std::env::var("ACOMM_RATE_LIMIT_BURST").ok().as_deref(),
+            std::env::var("ACOMM_RATE_LIMIT_REFILL_SECS").ok().as_deref(),
+        );
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        config.map(Self::new)
+    }
+
+    /// Take a token for `user` at `now`, refilling the bucket for the time elapsed.
+    pub fn check(&mut self, user: &str, now: Instant) -> RateDecision {
+        let capacity = self.config.burst as f64;
+        let refill_secs = self.config.refill.as_secs_f64();
+        let bucket = self
+            .buckets
+            .entry(user.to_string())
+            .or_insert(Bucket { tokens: capacity, updated: now, notified: false });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed / refill_secs).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            bucket.notified = false;
+            return RateDecision::Allow;
+        }
+        let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) * refill_secs);
+        let notify = !bucket.notified;
+        bucket.notified = true;
+        RateDecision::Throttled { retry_after, notify }
+    }
+
+    /// Give back the token `check` took for a prompt that was dropped before it ran.
+    pub fn refund(&mut self, user: &str) {
+        let capacity = self.config.burst as f64;
+        if let Some(bucket) = self.buckets.get_mut(user) {
+            bucket.tokens = (bucket.tokens + 1.0).min(capacity);
+        }
+    }
+
+    /// Forget `user`'s bucket so they start again with a full burst.
+    pub fn reset(&mut self, user: &str) -> bool {
+        self.buckets.remove(user).is_some()
+    }
+
+    /// Tokens each known user would have at `now`, sorted by user.
+    pub fn usage(&self, now: Instant) -> Vec<(String, f64)> {
+        let capacity = self.config.burst as f64;
+        let refill_secs = self.config.refill.as_secs_f64();
+        let mut usage: Vec<(String, f64)> = self
+            .buckets
+            .iter()
+            .map(|(user, bucket)| {
+                let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+                (user.clone(), (bucket.tokens + elapsed / refill_secs).min(capacity))
+            })
+            .collect();
+        usage.sort_by(|a, b| a.0.cmp(&b.0));
+        usage
+    }
+}
+
+/// Bridge-side hourly prompt quota: a `RateLimiter` refilling `per_hour` tokens an hour,
+/// plus the channels and users it never applies to.
+#[derive(Debug)]
+pub struct Quota {
+    per_hour: u32,
+    limiter: RateLimiter,
+    exempt: Vec<String>,
+}
+
+/// Parse `ACOMM_QUOTA_PER_HOUR` / `ACOMM_QUOTA_BURST` / `ACOMM_QUOTA_EXEMPT`. Returns
+/// `None` when the quota is disabled, plus one warning per invalid value.
+pub fn parse_quota_config(
+    per_hour_env: Option<&str>,
+    burst_env: Option<&str>,
+    exempt_env: Option<&str>,
+) -> (Option<Quota>, Vec<String>) {
+    let mut warnings = Vec::new();
+    let per_hour = match per_hour_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => 0,
+        Some(raw) => raw.parse::<u32>().unwrap_or_else(|_| {
+            warnings.push(format!("Invalid ACOMM_QUOTA_PER_HOUR '{}'; quota disabled", raw));
+            0
+        }),
+    };
+    let burst = match burst_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => per_hour,
+        Some(raw) => match raw.parse::<u32>() {
+            Ok(burst) if burst > 0 => burst,
+            _ => {
+                warnings.push(format!("Invalid ACOMM_QUOTA_BURST '{}'; using {}", raw, per_hour));
+                per_hour
+            }
+        },
+    };
+    let exempt = exempt_env
+        .unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect();
+    let quota = (per_hour > 0).then(|| Quota {
+        per_hour,
+        limiter: RateLimiter::new(RateLimitConfig { burst, refill: Duration::from_secs(3600) / per_hour }),
+        exempt,
+    });
+    (quota, warnings)
+}
+
+impl Quota {
+    /// The quota configured by the environment, or `None` when disabled.
+    pub fn from_env() -> Option<Self> {
+        let (quota, warnings) = parse_quota_config(
+            std::env::var("ACOMM_QUOTA_PER_HOUR").ok().as_deref(),
+            std::env::var("ACOMM_QUOTA_BURST").ok().as_deref(),
+            std::env::var("ACOMM_QUOTA_EXEMPT").ok().as_deref(),
+        );
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        quota
+    }
+
+    /// Take a prompt from `user`'s quota, unless `user` or the channel `scope` is exempt.
+    pub fn check(&mut self, user: &str, scope: &str, now: Instant) -> RateDecision {
+        if self.exempt.iter().any(|e| e == user || e == scope) {
+            return RateDecision::Allow;
+        }
+        self.limiter.check(user, now)
+    }
+
+    /// Undo a `check` that allowed a prompt which was then not run.
+    pub fn refund(&mut self, user: &str, scope: &str) {
+        if !self.exempt.iter().any(|e| e == user || e == scope) {
+            self.limiter.refund(user);
+        }
+    }
+
+    pub fn reset(&mut self, user: &str) -> bool {
+        self.limiter.reset(user)
+    }
+
+    /// One line for `/status`: the rate and what each user has left.
+    pub fn describe(&self, now: Instant) -> String {
+        let mut line = format!("Quota: {}/h, burst {}", self.per_hour, self.limiter.config.burst);
+        let usage = self.limiter.usage(now);
+        if !usage.is_empty() {
+            let users: Vec<String> = usage
+                .iter()
+                .map(|(user, tokens)| format!("{} {}/{} left", user, tokens.floor() as u64, self.limiter.config.burst))
+                .collect();
+            line.push_str(&format!(" — {}", users.join(", ")));
+        }
+        if !self.exempt.is_empty() {
+            line.push_str(&format!(" (exempt: {})", self.exempt.join(", ")));
+        }
+        line
+    }
+}
+
+/// The bridge's reply to a prompt over quota, rounding the wait up to whole minutes.
+pub fn quota_exceeded_message(retry_after: Duration) -> String {
+    format!("rate limit: try again in {} min", (retry_after.as_secs_f64() / 60.0).ceil().max(1.0) as u64)
+}
+
+/// The reply sent to a throttled user, rounding the wait up to whole seconds.
+pub fn rate_limited_message(retry_after: Duration) -> String {
+    format!("rate limited, try again in {}s", retry_after.as_secs_f64().ceil().max(1.0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(burst: u32, refill_secs: u64) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig { burst, refill: Duration::from_secs(refill_secs) })
+    }
+
+    #[test]
+    fn burst_is_allowed_then_throttled_with_a_single_notice() {
+        let mut limiter = limiter(2, 10);
+        let t0 = Instant::now();
+        assert_eq!(limiter.check("u1", t0), RateDecision::Allow);
+        assert_eq!(limiter.check("u1", t0), RateDecision::Allow);
+        assert_eq!(
+            limiter.check("u1", t0),
+            RateDecision::Throttled { retry_after: Duration::from_secs(10), notify: true }
+        );
+        assert!(matches!(limiter.check("u1", t0 + Duration::from_secs(4)), RateDecision::Throttled { notify: false, .. }));
+        // Other users have their own bucket.
+        assert_eq!(limiter.check("u2", t0), RateDecision::Allow);
+    }
+
+    #[test]
+    fn tokens_refill_over_time_up_to_the_burst() {
+        let mut limiter = limiter(2, 10);
+        let t0 = Instant::now();
+        limiter.check("u1", t0);
+        limiter.check("u1", t0);
+        match limiter.check("u1", t0 + Duration::from_secs(6)) {
+            RateDecision::Throttled { retry_after, .. } => assert!((retry_after.as_secs_f64() - 4.0).abs() < 1e-6),
+            other => panic!("expected throttle, got {other:?}"),
+        }
+        assert_eq!(limiter.check("u1", t0 + Duration::from_secs(11)), RateDecision::Allow);
+        // A long pause refills only up to the bucket size.
+        let later = t0 + Duration::from_secs(1000);
+        assert_eq!(limiter.check("u1", later), RateDecision::Allow);
+        assert_eq!(limiter.check("u1", later), RateDecision::Allow);
+        assert!(matches!(limiter.check("u1", later), RateDecision::Throttled { notify: true, .. }));
+    }
+
+    #[test]
+    fn config_defaults_disable_and_warnings() {
+        let (config, warnings) = parse_rate_limit_config(None, None);
+        assert_eq!(config, Some(RateLimitConfig { burst: DEFAULT_BURST, refill: DEFAULT_REFILL }));
+        assert!(warnings.is_empty());
+
+        assert_eq!(parse_rate_limit_config(Some("0"), None).0, None);
+
+        let (config, warnings) = parse_rate_limit_config(Some("lots"), Some("-1"));
+        assert_eq!(config, Some(RateLimitConfig { burst: DEFAULT_BURST, refill: DEFAULT_REFILL }));
+        assert_eq!(warnings.len(), 2);
+
+        let (config, _) = parse_rate_limit_config(Some(" 3 "), Some("1.5"));
+        assert_eq!(config, Some(RateLimitConfig { burst: 3, refill: Duration::from_millis(1500) }));
+    }
+
+    #[test]
+    fn quota_limits_users_exempts_listed_ones_and_resets() {
+        let (quota, warnings) = parse_quota_config(Some("6"), Some("2"), Some("tui, discord:42"));
+        let mut quota = quota.unwrap();
+        assert!(warnings.is_empty());
+        let t0 = Instant::now();
+        assert_eq!(quota.check("slack:U1", "slack:C1", t0), RateDecision::Allow);
+        assert_eq!(quota.check("slack:U1", "slack:C1", t0), RateDecision::Allow);
+        match quota.check("slack:U1", "slack:C1", t0) {
+            RateDecision::Throttled { retry_after, .. } => assert_eq!(retry_after, Duration::from_secs(600)),
+            other => panic!("expected throttle, got {other:?}"),
+        }
+        // Exempt scopes never run out.
+        for _ in 0..5 {
+            assert_eq!(quota.check("discord:42", "discord:42", t0), RateDecision::Allow);
+        }
+        assert_eq!(quota.describe(t0), "Quota: 6/h, burst 2 — slack:U1 0/2 left (exempt: tui, discord:42)");
+        quota.refund("slack:U1", "slack:C1");
+        assert_eq!(quota.describe(t0), "Quota: 6/h, burst 2 — slack:U1 1/2 left (exempt: tui, discord:42)");
+        assert!(quota.reset("slack:U1"));
+        assert!(!quota.reset("slack:U1"));
+        assert_eq!(quota.check("slack:U1", "slack:C1", t0), RateDecision::Allow);
+    }
+
+    #[test]
+    fn quota_config_is_off_by_default_and_burst_follows_the_rate() {
+        assert!(parse_quota_config(None, None, None).0.is_none());
+        assert!(parse_quota_config(Some("0"), Some("3"), None).0.is_none());
+        let (quota, warnings) = parse_quota_config(Some("10"), Some("none"), None);
+        assert_eq!(quota.unwrap().limiter.config, RateLimitConfig { burst: 10, refill: Duration::from_secs(360) });
+        assert_eq!(warnings.len(), 1);
+        let (quota, warnings) = parse_quota_config(Some("often"), None, None);
+        assert!(quota.is_none());
+        assert_eq!(warnings, vec!["Invalid ACOMM_QUOTA_PER_HOUR 'often'; quota disabled".to_string()]);
+        assert_eq!(quota_exceeded_message(Duration::from_secs(690)), "rate limit: try again in 12 min");
+    }
+
+    #[test]
+    fn rate_limited_message_rounds_up() {
+        assert_eq!(rate_limited_message(Duration::from_millis(3200)), "rate limited, try again in 4s");
+        assert_eq!(rate_limited_message(Duration::ZERO), "rate limited, try again in 1s");
+    }
+}
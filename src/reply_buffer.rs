@@ -0,0 +1,232 @@
+/**
+ * Bounded per-channel reply buffers for the chat adapters.
+ *
+ * The Discord and Slack adapters collect `AgentChunk`s per bridge channel until
+ * `AgentDone`. A runaway agent could grow one buffer without limit, and a buffer
+ * whose `AgentDone` never arrives (bridge crash, lost event) would stay forever.
+ * Each buffer therefore keeps at most `ACOMM_REPLY_BUFFER_MAX_BYTES` (default 1 MiB):
+ * past that the first quarter is kept, the middle is dropped behind a marker, and
+ * the rest is the latest output, since the final answer is usually at the end.
+ * Adapters also sweep out buffers older than `ACOMM_REPLY_BUFFER_TTL_SECS`
+ * (default 900, i.e. 15 minutes).
+ */
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+const DEFAULT_TTL: Duration = Duration::from_secs(15 * 60);
+/// How often adapters look for expired buffers.
+pub const REPLY_BUFFER_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReplyBufferLimits {
+    /// Bytes of agent output kept per buffer.
+    pub max_bytes: usize,
+    /// Age after which a buffer still waiting for `AgentDone` is dropped.
+    pub ttl: Duration,
+}
+
+impl Default for ReplyBufferLimits {
+    fn default() -> Self {
+        Self { max_bytes: DEFAULT_MAX_BYTES, ttl: DEFAULT_TTL }
+    }
+}
+
+/// Parse `ACOMM_REPLY_BUFFER_MAX_BYTES` / `ACOMM_REPLY_BUFFER_TTL_SECS`, plus one
+/// warning per invalid value (which uses its default).
+pub fn parse_reply_buffer_limits(max_env: Option<&str>, ttl_env: Option<&str>) -> (ReplyBufferLimits, Vec<String>) {
+    let mut warnings = Vec::new();
+    let max_bytes = match max_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => DEFAULT_MAX_BYTES,
+        Some(raw) => match raw.parse::<usize>() {
+            Ok(max) if max > 0 => max,
+            _ => {
+                warnings.push(format!("Invalid ACOMM_REPLY_BUFFER_MAX_BYTES '{}'; using {}", raw, DEFAULT_MAX_BYTES));
+                DEFAULT_MAX_BYTES
+            }
+        },
+    };
+    let ttl = match ttl_env.map(str::trim).filter(|v| !v.is_empty()) {
+        None => DEFAULT_TTL,
+        Some(raw) => match raw.parse::<u64>() {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs),
+            _ => {
+                warnings.push(format!(
+                    "Invalid ACOMM_REPLY_BUFFER_TTL_SECS '{}'; using {}",
+                    raw,
+                    DEFAULT_TTL.as_secs()
+                ));
+                DEFAULT_TTL
+            }
+        },
+    };
+    (ReplyBufferLimits { max_bytes, ttl }, warnings)
+}
+
+impl ReplyBufferLimits {
+    pub fn from_env() -> Self {
+        let (limits, warnings) = parse_reply_buffer_limits(
+            std::env::var("ACOMM_REPLY_BUFFER_MAX_BYTES").ok().as_deref(),
+            std::env::var("ACOMM_REPLY_BUFFER_TTL_SECS").ok().as_deref(),
+        );
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        limits
+    }
+}
+
+/// Agent output collected for one reply, capped at `max_bytes`.
+#[derive(Debug, Clone)]
+pub struct ReplyBuffer {
+    content: String,
+    max_bytes: usize,
+    /// Length of the kept head once something was dropped; the marker goes here.
+    head_len: usize,
+    omitted: usize,
+    started: Instant,
+}
+
+impl ReplyBuffer {
+    pub fn new(limits: &ReplyBufferLimits, now: Instant) -> Self {
+        Self { content: String::new(), max_bytes: limits.max_bytes, head_len: 0, omitted: 0, started: now }
+    }
+
+    /// Append a chunk. The buffer may run a quarter over the cap before it is trimmed
+    /// back, so the kept tail is not moved on every chunk.
+    pub fn push(&mut self, chunk: &str) {
+        self.content.push_str(chunk);
+        if self.content.len() <= self.max_bytes + self.max_bytes / 4 {
+            return;
+        }
+        if self.omitted == 0 {
+            self.head_len = floor_char_boundary(&self.content, self.max_bytes / 4);
+        }
+        let mut end = self.head_len + (self.content.len() - self.max_bytes);
+        while !self.content.is_char_boundary(end) {
+            end += 1;
+        }
+        self.omitted += end - self.head_len;
+        self.content.drain(self.head_len..end);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.content.is_empty()
+    }
+
+    /// The collected text, with a marker where output was dropped.
+    pub fn text(&self) -> Cow<'_, str> {
+        if self.omitted == 0 {
+            return Cow::Borrowed(&self.content);
+        }
+        let (head, tail) = self.content.split_at(self.head_len);
+        Cow::Owned(format!("{}\n\n[… {} bytes omitted …]\n\n{}", head, self.omitted, tail))
+    }
+
+    /// Whether the buffer has waited at least `ttl` since its prompt at `now`.
+    pub fn is_expired(&self, ttl: Duration, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= ttl
+    }
+}
+
+/// Keys of the buffers that have expired at `now`, sorted.
+pub fn expired_keys<'a>(
+    buffers: impl IntoIterator<Item = (&'a String, &'a ReplyBuffer)>,
+    ttl: Duration,
+    now: Instant,
+) -> Vec<String> {
+    let mut keys: Vec<String> =
+        buffers.into_iter().filter(|(_, buf)| buf.is_expired(ttl, now)).map(|(key, _)| key.clone()).collect();
+    keys.sort();
+    keys
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(max_bytes: usize) -> ReplyBuffer {
+        ReplyBuffer::new(&ReplyBufferLimits { max_bytes, ..ReplyBufferLimits::default() }, Instant::now())
+    }
+
+    #[test]
+    fn small_replies_are_kept_whole() {
+        let mut buf = buffer(100);
+        assert!(buf.is_empty());
+        buf.push("途中経過");
+        buf.push(" and the answer");
+        assert_eq!(buf.text(), "途中経過 and the answer");
+    }
+
+    #[test]
+    fn overflow_keeps_head_and_latest_tail_with_a_marker() {
+        let mut buf = buffer(400);
+        buf.push("HEAD: 調査を開始します。\n");
+        for i in 0..2000 {
+            buf.push(&format!("ログ行 {i}\n"));
+        }
+        buf.push("最終回答: 完了しました。");
+
+        let text = buf.text();
+        assert!(text.starts_with("HEAD: 調査を開始します。\n"));
+        assert!(text.ends_with("ログ行 1999\n最終回答: 完了しました。"));
+        assert!(text.contains(" bytes omitted …]"));
+        assert!(buf.content.len() <= 500, "{} bytes kept", buf.content.len());
+        // Every byte pushed is either kept or counted as omitted.
+        let pushed: usize = "HEAD: 調査を開始します。\n".len()
+            + (0..2000).map(|i| format!("ログ行 {i}\n").len()).sum::<usize>()
+            + "最終回答: 完了しました。".len();
+        assert_eq!(buf.content.len() + buf.omitted, pushed);
+    }
+
+    #[test]
+    fn a_single_huge_chunk_is_cut_at_char_boundaries() {
+        // 40 / 4 = 10 bytes of head, which falls inside the fourth "あ".
+        let mut buf = buffer(40);
+        buf.push(&"あ".repeat(100));
+        let text = buf.text();
+        assert!(text.starts_with("あああ\n\n[… "));
+        assert!(text.ends_with("あ"));
+        assert_eq!(buf.content.len() % 3, 0);
+    }
+
+    #[test]
+    fn expiry_follows_the_ttl() {
+        let now = Instant::now();
+        let buf = ReplyBuffer::new(&ReplyBufferLimits::default(), now);
+        assert!(!buf.is_expired(DEFAULT_TTL, now + Duration::from_secs(60)));
+        assert!(buf.is_expired(DEFAULT_TTL, now + DEFAULT_TTL));
+    }
+
+    #[test]
+    fn only_expired_buffers_are_swept() {
+        let now = Instant::now();
+        let limits = ReplyBufferLimits::default();
+        let buffers = std::collections::HashMap::from([
+            ("slack:C2:2.0".to_string(), ReplyBuffer::new(&limits, now)),
+            ("slack:C1:1.0".to_string(), ReplyBuffer::new(&limits, now)),
+            ("slack:C3:3.0".to_string(), ReplyBuffer::new(&limits, now + Duration::from_secs(600))),
+        ]);
+        assert!(expired_keys(&buffers, limits.ttl, now).is_empty());
+        assert_eq!(expired_keys(&buffers, limits.ttl, now + limits.ttl), vec!["slack:C1:1.0", "slack:C2:2.0"]);
+    }
+
+    #[test]
+    fn limits_are_parsed_with_warnings() {
+        assert_eq!(parse_reply_buffer_limits(None, None), (ReplyBufferLimits::default(), vec![]));
+        let (limits, warnings) = parse_reply_buffer_limits(Some(" 4096 "), Some("60"));
+        assert_eq!(limits, ReplyBufferLimits { max_bytes: 4096, ttl: Duration::from_secs(60) });
+        assert!(warnings.is_empty());
+        let (limits, warnings) = parse_reply_buffer_limits(Some("0"), Some("soon"));
+        assert_eq!(limits, ReplyBufferLimits::default());
+        assert_eq!(warnings.len(), 2);
+    }
+}
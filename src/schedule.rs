@@ -0,0 +1,460 @@
+/**
+ * Scheduled and delayed prompts.
+ *
+ * `/schedule add "30 7 * * *" <prompt>` runs a prompt at a five-field cron time (local
+ * time: minute, hour, day of month, month, day of week) and `/schedule add in:+30m
+ * <prompt>` runs it once after a delay. Each run uses the `schedule:<id>` channel, and a
+ * schedule may also forward its final reply to a notification target (`discord`, `slack`
+ * or `ntfy`, the same ones `acomm --agent` uses). Schedules are kept in
+ * `schedules.json` in the bridge state directory so they survive restarts; a run that
+ * was missed while the bridge was down is skipped unless the schedule was added with
+ * `--catch-up`, in which case it runs once, late.
+ */
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tracing::warn;
+
+pub const SCHEDULES_FILE: &str = "schedules.json";
+
+/// A run this late (in seconds) is still on time; later ones are misfires.
+const MISFIRE_GRACE_SECS: i64 = 60;
+
+/// How far ahead a cron expression is searched for its next time.
+const CRON_SEARCH_DAYS: i64 = 366 * 5;
+
+/// Bridge channel of a schedule's runs.
+pub fn schedule_channel(id: u64) -> String {
+    format!("schedule:{}", id)
+}
+
+/// Where a schedule's final reply is forwarded, besides its `schedule:<id>` channel.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Delivery {
+    Discord,
+    Slack,
+    Ntfy,
+}
+
+impl Delivery {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "discord" => Some(Self::Discord),
+            "slack" => Some(Self::Slack),
+            "ntfy" => Some(Self::Ntfy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Discord => "discord",
+            Self::Slack => "slack",
+            Self::Ntfy => "ntfy",
+        }
+    }
+}
+
+/// A parsed five-field cron expression. Each field is a bit set of the allowed values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether day of month / day of week were restricted (not `*`). When both are,
+    /// a day matching either one fires, as in standard cron.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let invalid = || format!("invalid cron field '{}' (allowed {}-{})", field, min, max);
+    let number = |s: &str| s.parse::<u32>().ok().filter(|n| (min..=max).contains(n)).ok_or_else(invalid);
+    let mut bits = 0u64;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().ok().filter(|s| *s > 0).ok_or_else(invalid)?),
+            None => (part, 1),
+        };
+        let (lo, hi) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((lo, hi)) => (number(lo)?, number(hi)?),
+                // `5/15` means every 15 starting at 5.
+                None if step > 1 => (number(range)?, max),
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if lo > hi {
+            return Err(invalid());
+        }
+        for value in (lo..=hi).step_by(step) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields.as_slice() else {
+            return Err(format!("expected 5 cron fields (minute hour day month weekday), got {}", fields.len()));
+        };
+        let mut weekdays = parse_cron_field(weekday, 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(Self {
+            minutes: parse_cron_field(minute, 0, 59)?,
+            hours: parse_cron_field(hour, 0, 23)?,
+            days: parse_cron_field(day, 1, 31)?,
+            months: parse_cron_field(month, 1, 12)?,
+            weekdays,
+            days_restricted: !day.starts_with('*'),
+            weekdays_restricted: !weekday.starts_with('*'),
+        })
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.days_restricted && self.weekdays_restricted { day || weekday } else { day && weekday }
+    }
+
+    /// The first matching minute strictly after `after`, or `None` if there is none
+    /// within `CRON_SEARCH_DAYS` (e.g. `0 0 30 2 *`).
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let limit = after + chrono::Duration::days(CRON_SEARCH_DAYS);
+        let mut t = after.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        while t <= limit {
+            if !self.matches_date(t.date()) {
+                t = t.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.with_minute(0)? + chrono::Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += chrono::Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+/// When a schedule runs: repeatedly on a cron expression, or once after a delay.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    Cron(Cron),
+    Once(chrono::Duration),
+}
+
+/// Parse `in:+30m` (also `in:30m`; units `s`, `m`, `h`, `d`) or a cron expression.
+pub fn parse_trigger(spec: &str) -> Result<Trigger, String> {
+    let spec = spec.trim();
+    let Some(delay) = spec.strip_prefix("in:") else {
+        return Cron::parse(spec).map(Trigger::Cron);
+    };
+    let invalid = || format!("invalid delay '{}': expected in:+<number><s|m|h|d>, e.g. in:+30m", spec);
+    let delay = delay.strip_prefix('+').unwrap_or(delay);
+    let unit_at = delay.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+    let amount: i64 = delay[..unit_at].parse().map_err(|_| invalid())?;
+    let duration = match &delay[unit_at..] {
+        "s" => chrono::Duration::try_seconds(amount),
+        "m" => chrono::Duration::try_minutes(amount),
+        "h" => chrono::Duration::try_hours(amount),
+        "d" => chrono::Duration::try_days(amount),
+        _ => None,
+    }
+    .filter(|d| *d > chrono::Duration::zero())
+    .ok_or_else(invalid)?;
+    Ok(Trigger::Once(duration))
+}
+
+/// Local wall-clock time of a unix timestamp.
+fn local_time(ts: i64) -> Option<NaiveDateTime> {
+    Local.timestamp_opt(ts, 0).single().map(|t| t.naive_local())
+}
+
+/// Unix timestamp of a local wall-clock time; for a time skipped by a DST change, the
+/// next minute that exists.
+fn local_timestamp(mut time: NaiveDateTime, cron: &Cron) -> Option<i64> {
+    loop {
+        if let Some(t) = Local.from_local_datetime(&time).earliest() {
+            return Some(t.timestamp());
+        }
+        time = cron.next_after(time)?;
+    }
+}
+
+/// Format a unix timestamp as local `YYYY-MM-DD HH:MM` for listings.
+pub fn format_local(ts: i64) -> String {
+    local_time(ts).map(|t| t.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_else(|| ts.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: u64,
+    /// The trigger as given: a cron expression or `in:+30m`.
+    pub spec: String,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deliver: Option<Delivery>,
+    /// Run once, late, when the bridge was down at the scheduled time (otherwise skip).
+    #[serde(default)]
+    pub catch_up: bool,
+    /// Unix time of the next run.
+    pub next_run: i64,
+}
+
+impl Schedule {
+    fn is_repeating(&self) -> bool {
+        !self.spec.trim_start().starts_with("in:")
+    }
+
+    /// The next cron time after `now`, or `None` for one-off schedules.
+    fn next_after(&self, now: i64) -> Option<i64> {
+        let Ok(Trigger::Cron(cron)) = parse_trigger(&self.spec) else { return None };
+        let next = cron.next_after(local_time(now)?)?;
+        local_timestamp(next, &cron)
+    }
+
+    /// One line for `/schedule list`.
+    pub fn describe(&self) -> String {
+        let mut line = format!("#{} [{}] next {}", self.id, self.spec, format_local(self.next_run));
+        if let Some(deliver) = self.deliver {
+            line.push_str(&format!(" → {}", deliver.name()));
+        }
+        if self.catch_up {
+            line.push_str(" (catch-up)");
+        }
+        line.push_str(&format!(": {}", self.text));
+        line
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ScheduleFile {
+    next_id: u64,
+    schedules: Vec<Schedule>,
+}
+
+/// The bridge's schedules and where they are persisted (`None` keeps them in memory).
+#[derive(Debug, Default)]
+pub struct ScheduleStore {
+    path: Option<PathBuf>,
+    next_id: u64,
+    schedules: Vec<Schedule>,
+}
+
+impl ScheduleStore {
+    /// Load the schedules at `path`; a missing or unreadable file means no schedules.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let content = path.as_deref().and_then(|p| std::fs::read_to_string(p).ok());
+        let file = match content.map(|c| serde_json::from_str::<ScheduleFile>(&c)) {
+            Some(Ok(file)) => file,
+            Some(Err(e)) => {
+                warn!("Ignoring unreadable {}: {}", SCHEDULES_FILE, e);
+                ScheduleFile::default()
+            }
+            None => ScheduleFile::default(),
+        };
+        Self { path, next_id: file.next_id, schedules: file.schedules }
+    }
+
+    pub fn list(&self) -> &[Schedule] {
+        &self.schedules
+    }
+
+    /// Add a schedule whose first run is computed from `now` (unix seconds).
+    pub fn add(
+        &mut self,
+        spec: &str,
+        text: &str,
+        deliver: Option<Delivery>,
+        catch_up: bool,
+        now: i64,
+    ) -> Result<&Schedule, String> {
+        let next_run = match parse_trigger(spec)? {
+            Trigger::Once(delay) => now + delay.num_seconds(),
+            Trigger::Cron(cron) => local_time(now)
+                .and_then(|t| cron.next_after(t))
+                .and_then(|t| local_timestamp(t, &cron))
+                .ok_or_else(|| format!("'{}' never fires", spec))?,
+        };
+        self.next_id += 1;
+        self.schedules.push(Schedule {
+            id: self.next_id,
+            spec: spec.trim().to_string(),
+            text: text.to_string(),
+            deliver,
+            catch_up,
+            next_run,
+        });
+        self.persist();
+        Ok(self.schedules.last().expect("just pushed"))
+    }
+
+    /// Remove a schedule; `false` if there was none with that id.
+    pub fn remove(&mut self, id: u64) -> bool {
+        let before = self.schedules.len();
+        self.schedules.retain(|s| s.id != id);
+        let removed = self.schedules.len() != before;
+        if removed {
+            self.persist();
+        }
+        removed
+    }
+
+    /// Schedules to run at `now`. Each due schedule runs at most once however many of
+    /// its times have passed; a time missed by more than `MISFIRE_GRACE_SECS` is skipped
+    /// unless the schedule has `catch_up`. Repeating schedules move on to their next
+    /// time after `now`, one-off schedules are removed.
+    pub fn take_due(&mut self, now: i64) -> Vec<Schedule> {
+        let mut fire = Vec::new();
+        let mut changed = false;
+        self.schedules.retain_mut(|schedule| {
+            if schedule.next_run > now {
+                return true;
+            }
+            changed = true;
+            if schedule.catch_up || now - schedule.next_run <= MISFIRE_GRACE_SECS {
+                fire.push(schedule.clone());
+            } else {
+                warn!("Skipping missed run of schedule #{} (was due {})", schedule.id, format_local(schedule.next_run));
+            }
+            if !schedule.is_repeating() {
+                return false;
+            }
+            match schedule.next_after(now) {
+                Some(next) => {
+                    schedule.next_run = next;
+                    true
+                }
+                None => false,
+            }
+        });
+        if changed {
+            self.persist();
+        }
+        fire
+    }
+
+    fn persist(&self) {
+        let Some(path) = self.path.as_deref() else { return };
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let file = ScheduleFile { next_id: self.next_id, schedules: self.schedules.clone() };
+        let written = serde_json::to_string_pretty(&file)
+            .map_err(std::io::Error::other)
+            .and_then(|content| std::fs::write(path, content));
+        if let Err(e) = written {
+            warn!("Could not write {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn next(expr: &str, after: &str) -> Option<NaiveDateTime> {
+        Cron::parse(expr).unwrap().next_after(at(after))
+    }
+
+    #[test]
+    fn cron_finds_the_next_matching_minute() {
+        assert_eq!(next("30 7 * * *", "2026-10-18 07:29"), Some(at("2026-10-18 07:30")));
+        assert_eq!(next("30 7 * * *", "2026-10-18 07:30"), Some(at("2026-10-19 07:30")));
+        assert_eq!(next("*/15 * * * *", "2026-10-18 23:50"), Some(at("2026-10-19 00:00")));
+        assert_eq!(next("0 9 * * 1-5", "2026-10-17 12:00"), Some(at("2026-10-19 09:00")));
+        assert_eq!(next("0 0 1 */3 *", "2026-10-18 00:00"), Some(at("2027-01-01 00:00")));
+        assert_eq!(next("0 0 29 2 *", "2026-03-01 00:00"), Some(at("2028-02-29 00:00")));
+        assert_eq!(next("0 0 30 2 *", "2026-03-01 00:00"), None);
+    }
+
+    #[test]
+    fn cron_day_of_month_and_weekday_match_either_when_both_are_set() {
+        // 2026-10-18 is a Sunday; the 20th is a Tuesday.
+        assert_eq!(next("0 12 20 * 0", "2026-10-18 13:00"), Some(at("2026-10-20 12:00")));
+        assert_eq!(next("0 12 * * 7", "2026-10-18 13:00"), Some(at("2026-10-25 12:00")));
+    }
+
+    #[test]
+    fn bad_triggers_are_rejected() {
+        assert!(Cron::parse("30 7 * *").unwrap_err().contains("expected 5 cron fields"));
+        assert!(Cron::parse("60 7 * * *").unwrap_err().contains("'60'"));
+        assert!(Cron::parse("0 9 * * 5-1").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+        assert_eq!(parse_trigger("in:+30m"), Ok(Trigger::Once(chrono::Duration::minutes(30))));
+        assert_eq!(parse_trigger("in:2h"), Ok(Trigger::Once(chrono::Duration::hours(2))));
+        assert!(parse_trigger("in:+30").is_err());
+        assert!(parse_trigger("in:0m").is_err());
+        assert!(parse_trigger("in:+1w").is_err());
+    }
+
+    #[test]
+    fn one_off_schedules_fire_once_and_are_removed() {
+        let mut store = ScheduleStore::default();
+        let id = store.add("in:+30m", "stretch", Some(Delivery::Ntfy), false, 1_000).unwrap().id;
+        assert_eq!(store.list()[0].next_run, 1_000 + 30 * 60);
+        assert!(store.take_due(1_000 + 30 * 60 - 1).is_empty());
+        let due = store.take_due(1_000 + 30 * 60 + 5);
+        assert_eq!(due.len(), 1);
+        assert_eq!((due[0].id, due[0].deliver), (id, Some(Delivery::Ntfy)));
+        assert!(store.list().is_empty());
+    }
+
+    #[test]
+    fn misfires_are_skipped_unless_catch_up_is_set() {
+        let mut store = ScheduleStore::default();
+        store.add("in:+1m", "skip me", None, false, 0).unwrap();
+        store.add("in:+1m", "run me late", None, true, 0).unwrap();
+        let now = 60 + 3 * 3600;
+        let due = store.take_due(now);
+        assert_eq!(due.iter().map(|s| s.text.as_str()).collect::<Vec<_>>(), vec!["run me late"]);
+        assert!(store.list().is_empty());
+    }
+
+    #[test]
+    fn repeating_schedules_run_once_and_move_past_now() {
+        let mut store = ScheduleStore::default();
+        let start = Local::now().timestamp();
+        store.add("*/5 * * * *", "tick", None, true, start).unwrap();
+        let first = store.list()[0].next_run;
+        // Several times have passed; it still runs only once and the next time is in the future.
+        let late = first + 3 * 3600;
+        assert_eq!(store.take_due(late).len(), 1);
+        assert!(store.list()[0].next_run > late);
+        assert!(store.take_due(late).is_empty());
+        assert!(store.remove(1));
+        assert!(!store.remove(1));
+    }
+
+    #[test]
+    fn schedules_survive_a_reload() {
+        let dir = std::env::temp_dir().join(format!("acomm-schedules-{}", std::process::id()));
+        let path = dir.join(SCHEDULES_FILE);
+        let mut store = ScheduleStore::load(Some(path.clone()));
+        store.add("30 7 * * *", "/today", Some(Delivery::Discord), false, Local::now().timestamp()).unwrap();
+        store.add("in:+1h", "later", None, false, Local::now().timestamp()).unwrap();
+        assert!(store.remove(2));
+
+        let mut reloaded = ScheduleStore::load(Some(path));
+        assert_eq!(reloaded.list(), store.list());
+        assert_eq!(reloaded.add("in:+1m", "next id", None, false, 0).unwrap().id, 3);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
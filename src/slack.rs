@@ -9,26 +9,79 @@
  *   SLACK_APP_TOKEN  — xapp-... App-Level Token with connections:write scope
  *   SLACK_BOT_TOKEN  — xoxb-... Bot Token with chat:write scope
  *
- * Required bot scopes: app_mentions:read, channels:history, chat:write
- * Required event subscriptions: message.channels (or app_mention)
+ * Optional environment variables:
+ *   SLACK_SNIPPET_THRESHOLD — replies longer than this many chars (default 3000)
+ *   are uploaded as a markdown snippet instead of a plain message.
+ *   SLACK_REQUIRE_MENTION — set to 1 to forward channel messages only when they
+ *   mention the bot. DMs are always forwarded.
+ *   SLACK_ALLOWED_USER_IDS — comma-separated Slack user IDs to allow.
+ *   SLACK_ALLOWED_CHANNEL_IDS — comma-separated Slack channel IDs to allow.
+ *   If set, messages from other users / channels are ignored.
+ *   SLACK_NOTIFY_CHANNEL_ID — channel for `acomm --agent <text> --slack` notifications.
+ *   SLACK_STREAM_REPLIES — set to 1 to post a threaded placeholder and grow it
+ *   with chat.update while the agent streams, instead of replying once at the end.
+ *   SLACK_MAX_CHARS — max characters per Block Kit section (200-3000, default 3000).
+ *   ACOMM_REPLY_BUFFER_MAX_BYTES / ACOMM_REPLY_BUFFER_TTL_SECS — cap and lifetime
+ *   of the per-channel reply buffers (see `reply_buffer`).
+ *
+ * Required bot scopes: app_mentions:read, channels:history, chat:write, files:write
+ * (plus im:history for direct messages)
+ * Required event subscriptions: message.channels and/or app_mention (message.im for DMs)
+ * Direct messages bypass the mention requirement and channel allowlist and are
+ * bridged as `slack:dm:<user>:<channel>`.
+ * Optional: a `/acomm` slash command (provider / model / status), delivered over Socket Mode.
+ * (tokens_revoked / app_uninstalled are always delivered and stop the adapter).
  */
 
-use crate::protocol::ProtocolEvent;
-use std::collections::HashMap;
+use crate::format_profile::{FormatProfile, OutboundMessage, mark_partial_reply, parse_max_chars, reply_footer, reply_footer_from_env};
+use crate::protocol::{ActiveAgent, ProtocolEvent, final_answer};
+use crate::rate_limit::{RateDecision, RateLimiter, rate_limited_message};
+use crate::redact::redact_output;
+use crate::reply_buffer::{REPLY_BUFFER_SWEEP_INTERVAL, ReplyBuffer, ReplyBufferLimits, expired_keys};
+use acomm::client::DEFAULT_SOCKET_PATH;
+use regex::Regex;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
+use std::sync::OnceLock;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
 use futures_util::{SinkExt, StreamExt};
 use serde::Deserialize;
 use serde_json::{json, Value};
-use tokio_tungstenite::connect_async;
 use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
 use std::time::Duration;
+use tracing::{debug, info, warn};
 
-const SOCKET_PATH: &str = "/tmp/acomm.sock";
 const SLACK_API_BASE: &str = "https://slack.com/api";
 const SLACK_OPEN_SOCKET_MODE_MAX_ATTEMPTS: usize = 3;
 const SLACK_OPEN_SOCKET_MODE_RETRY_DELAY_MS: u64 = 750;
+const SLACK_RECONNECT_MAX_ATTEMPTS: u32 = 6;
+const SLACK_RECONNECT_BASE_DELAY_MS: u64 = 1_000;
+const SLACK_RECONNECT_MAX_DELAY_MS: u64 = 30_000;
+
+const SLACK_API_MAX_ATTEMPTS: usize = 3;
+const SLACK_API_DEFAULT_RETRY_AFTER_SECS: u64 = 1;
+const SLACK_RECENT_MESSAGE_KEYS: usize = 256;
+const SLACK_THINKING_TEXT: &str = "… thinking";
+const SLACK_STREAM_PLACEHOLDER_TEXT: &str = "⏳ …";
+/// Slack asks for roughly one message write per second per channel.
+const SLACK_STREAM_MIN_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+const SLACK_STREAM_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+const SLACK_STREAM_UPDATE_CHARS: usize = 400;
+
+const DEFAULT_SLACK_SNIPPET_THRESHOLD: usize = 3000;
+const SLACK_SNIPPET_CODE_BLOCK_LINES: usize = 40;
+const SLACK_SNIPPET_SUMMARY_CHARS: usize = 300;
+const SLACK_SNIPPET_NOTE: &str = "_(full reply attached as a snippet)_";
+/// Block Kit limits: blocks per message and characters per section text.
+const SLACK_MAX_BLOCKS: usize = 50;
+const SLACK_SECTION_TEXT_LIMIT: usize = 3000;
+/// Accepted `SLACK_MAX_CHARS` range; the default is `SLACK_SECTION_TEXT_LIMIT`.
+const SLACK_MAX_CHARS_MIN: usize = 200;
+const SLACK_MAX_CHARS_MAX: usize = SLACK_SECTION_TEXT_LIMIT;
+
+type SlackWebSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
 
 // ─── Slack Socket Mode payload types ──────────────────────────────────────────
 
@@ -40,16 +93,153 @@ struct SocketModeEnvelope {
     envelope_id: String,
     #[serde(default)]
     payload: Option<Value>,
+    /// Set when Slack redelivers an envelope it considers unacknowledged.
+    #[serde(default)]
+    retry_attempt: Option<u32>,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SlackMessageEvent {
+    /// `message` or `app_mention`
+    #[serde(rename = "type", default)]
+    pub event_type: String,
     pub channel: String,
     pub user: Option<String>,
     pub text: Option<String>,
     /// Present when the message is from a bot
     pub bot_id: Option<String>,
     pub subtype: Option<String>,
+    /// Message timestamp; shared by the `message` and `app_mention` events for one post.
+    #[serde(default)]
+    pub ts: Option<String>,
+    /// `im` for direct messages, `channel` / `group` / `mpim` otherwise.
+    #[serde(default)]
+    pub channel_type: Option<String>,
+    /// Parent message ts when the message was posted in a thread.
+    #[serde(default)]
+    pub thread_ts: Option<String>,
+}
+
+impl SlackMessageEvent {
+    fn is_direct_message(&self) -> bool {
+        self.channel_type.as_deref() == Some("im") || is_slack_dm_channel(&self.channel)
+    }
+}
+
+/// A reply being streamed into a placeholder message via chat.update.
+struct SlackStreamingReply {
+    channel_id: String,
+    ts: String,
+    thread_ts: Option<String>,
+    last_update: tokio::time::Instant,
+    last_update_chars: usize,
+}
+
+/// Throttle for streamed updates: never faster than once a second, otherwise update
+/// every couple of seconds or as soon as enough new text has arrived.
+fn should_stream_slack_update(since_last_update: Duration, pending_chars: usize) -> bool {
+    since_last_update >= SLACK_STREAM_MIN_UPDATE_INTERVAL
+        && (since_last_update >= SLACK_STREAM_UPDATE_INTERVAL
+            || pending_chars >= SLACK_STREAM_UPDATE_CHARS)
+}
+
+/// Render an in-progress streamed reply, truncated so partial updates stay readable.
+fn render_slack_stream_preview(content: &str, limit: usize) -> String {
+    let mut preview: String = content.chars().take(limit).collect();
+    if content.chars().count() > limit {
+        preview.push('…');
+    }
+    markdown_to_mrkdwn(&preview)
+}
+
+/// Decides which Slack events are forwarded to the bridge.
+struct SlackEventFilter {
+    bot_user_id: Option<String>,
+    require_mention: bool,
+    /// `channel:ts` of recently forwarded messages, so a post delivered both as
+    /// `message` and `app_mention` only reaches the agent once.
+    recent_messages: VecDeque<String>,
+    allowed_user_ids: Option<HashSet<String>>,
+    allowed_channel_ids: Option<HashSet<String>>,
+    /// Users already reported as rejected by the allowlist (logged once each).
+    rejected_users: HashSet<String>,
+    /// Events API `event_id`s already handled, so redelivered events are dropped.
+    recent_event_ids: VecDeque<String>,
+    rate_limiter: Option<RateLimiter>,
+    /// "rate limited" replies for throttled users, posted by the adapter loop.
+    rate_limit_notices: Vec<SlackRateLimitNotice>,
+}
+
+/// A reply to post in the thread of a throttled user's message.
+#[derive(Debug, Clone, PartialEq)]
+struct SlackRateLimitNotice {
+    channel_id: String,
+    thread_ts: Option<String>,
+    text: String,
+}
+
+impl SlackEventFilter {
+    fn new(bot_user_id: Option<String>, require_mention: bool) -> Self {
+        Self {
+            bot_user_id,
+            require_mention,
+            recent_messages: VecDeque::new(),
+            allowed_user_ids: None,
+            allowed_channel_ids: None,
+            rejected_users: HashSet::new(),
+            recent_event_ids: VecDeque::new(),
+            rate_limiter: None,
+            rate_limit_notices: Vec::new(),
+        }
+    }
+
+    /// Record an Events API `event_id`; returns `false` if it was already seen.
+    fn first_delivery(&mut self, event_id: &str) -> bool {
+        if self.recent_event_ids.iter().any(|id| id == event_id) {
+            return false;
+        }
+        self.recent_event_ids.push_back(event_id.to_string());
+        if self.recent_event_ids.len() > SLACK_RECENT_MESSAGE_KEYS {
+            self.recent_event_ids.pop_front();
+        }
+        true
+    }
+
+    /// Return the prompt text to forward, or `None` if the event should be ignored.
+    fn prompt_text(&mut self, event: &SlackMessageEvent) -> Option<String> {
+        // Skip bot messages, subtypes (edits, joins, etc.), and empty messages
+        if event.bot_id.is_some() || event.subtype.is_some() {
+            return None;
+        }
+        if event.user.is_some() && event.user.as_deref() == self.bot_user_id.as_deref() {
+            return None;
+        }
+        let text = event.text.as_deref().filter(|t| !t.is_empty())?;
+
+        let is_mention = event.event_type == "app_mention"
+            || mentions_slack_user(text, self.bot_user_id.as_deref());
+        if self.require_mention && !is_mention && !event.is_direct_message() {
+            return None;
+        }
+
+        if let Some(ts) = &event.ts {
+            let key = format!("{}:{}", event.channel, ts);
+            if self.recent_messages.contains(&key) {
+                return None;
+            }
+            self.recent_messages.push_back(key);
+            if self.recent_messages.len() > SLACK_RECENT_MESSAGE_KEYS {
+                self.recent_messages.pop_front();
+            }
+        }
+
+        let stripped = if is_mention {
+            strip_slack_mention(text, self.bot_user_id.as_deref())
+        } else {
+            text.to_string()
+        };
+        if stripped.is_empty() { None } else { Some(stripped) }
+    }
 }
 
 // ─── Public adapter entry point ───────────────────────────────────────────────
@@ -64,7 +254,13 @@ pub async fn notify_slack(text: &str) -> Result<(), Box<dyn Error>> {
         .map_err(|_| "SLACK_BOT_TOKEN environment variable not set")?;
     let channel_id = std::env::var("SLACK_NOTIFY_CHANNEL_ID")
         .map_err(|_| "SLACK_NOTIFY_CHANNEL_ID environment variable not set")?;
-    send_slack_message(&bot_token, &channel_id, text).await
+    // Same formatting and snippet fallback as replies to prompts.
+    let profile = SlackProfile {
+        snippet_threshold: load_slack_snippet_threshold_from_env(),
+        max_chars: load_slack_max_chars_from_env(),
+        show_status: false,
+    };
+    deliver_slack_reply(&bot_token, &channel_id, &profile.format_reply(text, "", ""), None).await
 }
 
 pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
@@ -73,10 +269,10 @@ pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
     let bot_token = std::env::var("SLACK_BOT_TOKEN")
         .map_err(|_| "SLACK_BOT_TOKEN environment variable not set (xoxb-...)")?;
 
-    println!("Slack Socket Mode adapter starting...");
+    info!("Slack Socket Mode adapter starting...");
 
     // Connect to acomm bridge
-    let bridge_stream = UnixStream::connect(SOCKET_PATH).await.map_err(|e| {
+    let bridge_stream = UnixStream::connect(DEFAULT_SOCKET_PATH).await.map_err(|e| {
         format!(
             "Bridge is not running. Please start it with 'acomm --bridge'. Error: {}",
             e
@@ -85,16 +281,56 @@ pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
     let (bridge_reader, mut bridge_writer) = tokio::io::split(bridge_stream);
     let mut bridge_lines = BufReader::new(bridge_reader).lines();
 
-    // Obtain WebSocket URL from Slack
-    let ws_url = open_socket_mode_connection(&app_token).await?;
-    println!("Connecting to Slack Socket Mode WebSocket...");
+    let (mut ws_sink, mut ws_stream) = connect_socket_mode(&app_token).await?.split();
+    info!("Connected to Slack Socket Mode.");
 
-    let (ws_stream, _) = connect_async(&ws_url).await?;
-    let (mut ws_sink, mut ws_stream) = ws_stream.split();
-
-    println!("Connected to Slack Socket Mode.");
-
-    let mut reply_buffers: HashMap<String, String> = HashMap::new();
+    let bot_user_id = match fetch_bot_user_id(&bot_token).await {
+        Ok(id) => {
+            info!("Slack bot user id: {}", id);
+            Some(id)
+        }
+        Err(e) => {
+            warn!("Slack auth.test failed; mention detection limited to app_mention events: {}", e);
+            None
+        }
+    };
+    let require_mention = std::env::var("SLACK_REQUIRE_MENTION")
+        .map(|v| matches!(v.trim(), "1" | "true"))
+        .unwrap_or(false);
+    if require_mention {
+        info!("Slack mention requirement enabled (DMs are exempt).");
+    }
+    let mut event_filter = SlackEventFilter::new(bot_user_id, require_mention);
+    event_filter.allowed_user_ids = load_allowed_slack_ids_from_env("SLACK_ALLOWED_USER_IDS");
+    event_filter.allowed_channel_ids = load_allowed_slack_ids_from_env("SLACK_ALLOWED_CHANNEL_IDS");
+    event_filter.rate_limiter = RateLimiter::from_env();
+    if let Some(ids) = &event_filter.allowed_user_ids {
+        info!("Slack user allowlist enabled: {} user id(s)", ids.len());
+    }
+    if let Some(ids) = &event_filter.allowed_channel_ids {
+        info!("Slack channel allowlist enabled: {} channel id(s)", ids.len());
+    }
+    let snippet_threshold = load_slack_snippet_threshold_from_env();
+    let max_chars = load_slack_max_chars_from_env();
+    let profile = SlackProfile { snippet_threshold, max_chars, show_status: reply_footer_from_env() };
+    let stream_replies = std::env::var("SLACK_STREAM_REPLIES")
+        .map(|v| matches!(v.trim(), "1" | "true"))
+        .unwrap_or(false);
+    if stream_replies {
+        info!("Slack streaming replies enabled.");
+    }
+    // ts of the last forwarded user message per bridge channel, used as thread_ts.
+    let mut last_message_ts: HashMap<String, String> = HashMap::new();
+    // thread_ts of prompts posted inside a thread (`reply_to`), so the reply lands in that thread.
+    let mut reply_threads: HashMap<String, String> = HashMap::new();
+    let mut streaming_replies: HashMap<String, SlackStreamingReply> = HashMap::new();
+    let mut reply_buffers: HashMap<String, ReplyBuffer> = HashMap::new();
+    let buffer_limits = ReplyBufferLimits::from_env();
+    let mut buffer_sweep = tokio::time::interval(REPLY_BUFFER_SWEEP_INTERVAL);
+    // Mirrors the bridge's active provider/model for the reply status line.
+    let mut active = ActiveAgent::default();
+    // Transient "thinking" placeholder ts per bridge channel.
+    let mut thinking_messages: HashMap<String, String> = HashMap::new();
 
     loop {
         tokio::select! {
@@ -102,8 +338,16 @@ pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
             ws_msg = ws_stream.next() => {
                 let msg = match ws_msg {
                     Some(Ok(m)) => m,
-                    Some(Err(e)) => return Err(format!("WebSocket error: {}", e).into()),
-                    None => return Err("Slack Socket Mode disconnected".into()),
+                    Some(Err(e)) => {
+                        let reason = format!("WebSocket error: {}", e);
+                        (ws_sink, ws_stream) = reconnect_socket_mode(&app_token, &reason).await?.split();
+                        continue;
+                    }
+                    None => {
+                        (ws_sink, ws_stream) =
+                            reconnect_socket_mode(&app_token, "WebSocket stream ended").await?.split();
+                        continue;
+                    }
                 };
 
                 let text = match msg {
@@ -112,7 +356,11 @@ pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
                         ws_sink.send(Message::Pong(data)).await?;
                         continue;
                     }
-                    Message::Close(_) => return Err("Slack closed the WebSocket connection".into()),
+                    Message::Close(_) => {
+                        (ws_sink, ws_stream) =
+                            reconnect_socket_mode(&app_token, "Slack closed the WebSocket connection").await?.split();
+                        continue;
+                    }
                     _ => continue,
                 };
 
@@ -123,7 +371,7 @@ pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
 
                 match envelope.envelope_type.as_str() {
                     "hello" => {
-                        println!("Slack Socket Mode hello received.");
+                        info!("Slack Socket Mode hello received.");
                     }
                     "events_api" => {
                         // Acknowledge the event immediately to avoid retries
@@ -132,49 +380,207 @@ pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
                             ws_sink.send(Message::Text(serde_json::to_string(&ack)?.into())).await?;
                         }
 
+                        if let Some(attempt) = envelope.retry_attempt.filter(|n| *n > 0) {
+                            info!("Slack redelivered envelope (retry attempt {}).", attempt);
+                        }
                         if let Some(payload) = envelope.payload {
-                            if let Ok(event) = serde_json::from_value::<SlackMessageEvent>(
-                                payload["event"].clone(),
-                            ) {
-                                handle_slack_event(event, &mut bridge_writer).await?;
+                            if let Some((bridge_channel, Some(ts))) =
+                                handle_events_api_payload(&payload, &mut event_filter, &mut bridge_writer).await?
+                            {
+                                last_message_ts.insert(bridge_channel, ts);
+                            }
+                            for notice in std::mem::take(&mut event_filter.rate_limit_notices) {
+                                if let Err(e) = post_slack_message(
+                                    &bot_token,
+                                    &notice.channel_id,
+                                    &notice.text,
+                                    None,
+                                    notice.thread_ts.as_deref(),
+                                )
+                                .await
+                                {
+                                    warn!("Slack rate limit notice failed: {}", e);
+                                }
                             }
                         }
                     }
+                    "slash_commands" => {
+                        let payload = envelope.payload.unwrap_or_default();
+                        let user_id = payload["user_id"].as_str().unwrap_or("unknown");
+                        let channel_id = payload["channel_id"].as_str().unwrap_or_default();
+                        let (bridge_command, reply) = admit_slash_command(
+                            &mut event_filter,
+                            payload["text"].as_str().unwrap_or_default(),
+                            user_id,
+                            channel_id,
+                            std::time::Instant::now(),
+                        );
+                        if !envelope.envelope_id.is_empty() {
+                            let ack = json!({
+                                "envelope_id": envelope.envelope_id,
+                                "payload": { "response_type": "ephemeral", "text": reply },
+                            });
+                            ws_sink.send(Message::Text(serde_json::to_string(&ack)?.into())).await?;
+                        }
+                        if let Some(command) = bridge_command {
+                            let event = transform_slack_message(&command, user_id, channel_id, None);
+                            let j = serde_json::to_string(&event)?;
+                            bridge_writer.write_all(format!("{}\n", j).as_bytes()).await?;
+                        }
+                    }
                     "disconnect" => {
-                        return Err("Slack requested disconnect".into());
+                        // Slack recycles Socket Mode connections periodically; open a
+                        // fresh one and keep the bridge connection and reply buffers.
+                        (ws_sink, ws_stream) =
+                            reconnect_socket_mode(&app_token, "Slack requested disconnect").await?.split();
                     }
                     _ => {}
                 }
             }
 
+            // Drop reply buffers whose AgentDone never arrived.
+            _ = buffer_sweep.tick() => {
+                for ch in expired_keys(&reply_buffers, buffer_limits.ttl, std::time::Instant::now()) {
+                    warn!(
+                        "Dropping the Slack reply buffer for {} after {}s without AgentDone",
+                        ch,
+                        buffer_limits.ttl.as_secs()
+                    );
+                    reply_buffers.remove(&ch);
+                    streaming_replies.remove(&ch);
+                    reply_threads.remove(&ch);
+                    clear_slack_thinking_message(&bot_token, &mut thinking_messages, &ch).await;
+                }
+            }
+
             // Bridge protocol events
             line_res = bridge_lines.next_line() => {
                 let line = match line_res? {
                     Some(l) => l,
-                    None => break,
+                    None => {
+                        for (ch, messages) in partial_reply_flushes(&reply_buffers, &profile, &active) {
+                            clear_slack_thinking_message(&bot_token, &mut thinking_messages, &ch).await;
+                            let delivered = match streaming_replies.remove(&ch) {
+                                Some(stream) => finish_slack_stream(&bot_token, &stream, &messages).await,
+                                None => {
+                                    let slack_channel = slack_channel_id_from_bridge_channel(&ch).unwrap_or_default();
+                                    deliver_slack_reply(&bot_token, slack_channel, &messages, reply_threads.get(&ch).map(String::as_str)).await
+                                }
+                            };
+                            if let Err(e) = delivered {
+                                warn!("Slack partial reply delivery failed: {}", e);
+                            }
+                        }
+                        break;
+                    }
                 };
                 if let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) {
                     match event {
-                        ProtocolEvent::Prompt { channel: Some(ref ch), .. }
+                        ProtocolEvent::ProviderSwitched { .. } | ProtocolEvent::ModelSwitched { .. } => {
+                            active.observe(&event);
+                        }
+                        ProtocolEvent::Prompt { channel: Some(ref ch), ref reply_to, .. }
                             if ch.starts_with("slack:") =>
                         {
-                            reply_buffers.insert(ch.clone(), String::new());
+                            reply_buffers.insert(ch.clone(), ReplyBuffer::new(&buffer_limits, std::time::Instant::now()));
+                            match reply_to {
+                                Some(thread_ts) => reply_threads.insert(ch.clone(), thread_ts.clone()),
+                                None => reply_threads.remove(ch),
+                            };
+                            if stream_replies {
+                                if let Some(slack_channel) = slack_channel_id_from_bridge_channel(ch) {
+                                    let forwarded_ts = last_message_ts.remove(ch);
+                                    let thread_ts = reply_to.clone().or(forwarded_ts);
+                                    match post_slack_message(&bot_token, slack_channel, SLACK_STREAM_PLACEHOLDER_TEXT, None, thread_ts.as_deref()).await {
+                                        Ok(Some(ts)) => {
+                                            streaming_replies.insert(ch.clone(), SlackStreamingReply {
+                                                channel_id: slack_channel.to_string(),
+                                                ts,
+                                                thread_ts,
+                                                last_update: tokio::time::Instant::now(),
+                                                last_update_chars: 0,
+                                            });
+                                        }
+                                        Ok(None) => {}
+                                        Err(e) => warn!("Slack streaming placeholder failed: {}", e),
+                                    }
+                                }
+                            }
                         }
                         ProtocolEvent::AgentChunk { ref chunk, channel: Some(ref ch) }
                             if ch.starts_with("slack:") =>
                         {
-                            reply_buffers.entry(ch.clone()).or_default().push_str(chunk);
+                            let buffer = reply_buffers
+                                .entry(ch.clone())
+                                .or_insert_with(|| ReplyBuffer::new(&buffer_limits, std::time::Instant::now()));
+                            buffer.push(chunk);
+                            if let Some(stream) = streaming_replies.get_mut(ch) {
+                                let content = buffer.text();
+                                let chars = content.chars().count();
+                                let pending = chars.saturating_sub(stream.last_update_chars);
+                                if should_stream_slack_update(stream.last_update.elapsed(), pending) {
+                                    let preview = render_slack_stream_preview(&redact_output(&content), snippet_threshold);
+                                    if let Err(e) = update_slack_message(&bot_token, &stream.channel_id, &stream.ts, &preview, None).await {
+                                        warn!("Slack streaming update failed: {}", e);
+                                    }
+                                    stream.last_update = tokio::time::Instant::now();
+                                    stream.last_update_chars = chars;
+                                }
+                            }
+                        }
+                        ProtocolEvent::StatusUpdate { is_processing: true, channel: Some(ref ch) }
+                            if ch.starts_with("slack:") && !streaming_replies.contains_key(ch) =>
+                        {
+                            if let Some(slack_channel) = slack_channel_id_from_bridge_channel(ch) {
+                                match post_slack_message(&bot_token, slack_channel, SLACK_THINKING_TEXT, None, None).await {
+                                    Ok(Some(ts)) => {
+                                        thinking_messages.insert(ch.clone(), ts);
+                                    }
+                                    Ok(None) => {}
+                                    Err(e) => warn!("Slack thinking indicator failed: {}", e),
+                                }
+                            }
+                        }
+                        ProtocolEvent::StatusUpdate { is_processing: false, channel: Some(ref ch) }
+                            if ch.starts_with("slack:") =>
+                        {
+                            clear_slack_thinking_message(&bot_token, &mut thinking_messages, ch).await;
+                        }
+                        ProtocolEvent::SystemMessage { ref msg, channel: Some(ref ch) }
+                            if ch.starts_with("slack:") =>
+                        {
+                            if let Some(slack_channel) = slack_channel_id_from_bridge_channel(ch) {
+                                if let Err(e) = send_slack_message(&bot_token, slack_channel, &markdown_to_mrkdwn(msg)).await {
+                                    warn!("Slack system message failed: {}", e);
+                                }
+                            }
                         }
-                        ProtocolEvent::AgentDone { channel: Some(ref ch) }
+                        ProtocolEvent::AgentDone { channel: Some(ref ch), .. }
+                        | ProtocolEvent::ChannelClosed { channel: Some(ref ch), .. }
                             if ch.starts_with("slack:") =>
                         {
-                            // Channel format: "slack:<user_id>:<channel_id>"
-                            let parts: Vec<&str> = ch.splitn(3, ':').collect();
-                            let slack_channel = parts.get(2).unwrap_or(&"");
+                            clear_slack_thinking_message(&bot_token, &mut thinking_messages, ch).await;
+                            let slack_channel = slack_channel_id_from_bridge_channel(ch).unwrap_or_default();
                             let key = ch.to_string();
-                            if let Some(content) = reply_buffers.remove(&key) {
-                                if !content.is_empty() {
-                                    send_slack_message(&bot_token, slack_channel, &content).await?;
+                            let stream = streaming_replies.remove(&key);
+                            let reply_thread = reply_threads.remove(&key);
+                            if let Some(buffer) = reply_buffers.remove(&key) {
+                                if !buffer.is_empty() || stream.is_some() {
+                                    let agent = active.answered(&event);
+                                    let messages = profile.format_reply(&buffer.text(), &agent.provider, &agent.model);
+                                    let delivered = match stream {
+                                        Some(stream) => finish_slack_stream(&bot_token, &stream, &messages).await,
+                                        None => deliver_slack_reply(&bot_token, slack_channel, &messages, reply_thread.as_deref()).await,
+                                    };
+                                    if let Err(e) = delivered {
+                                        warn!("Slack reply delivery failed: {}", e);
+                                        let notice = ProtocolEvent::SystemMessage {
+                                            msg: format!("Slack reply delivery failed: {}", e),
+                                            channel: Some(ch.clone()),
+                                        };
+                                        let j = serde_json::to_string(&notice)?;
+                                        bridge_writer.write_all(format!("{}\n", j).as_bytes()).await?;
+                                    }
                                 }
                             }
                         }
@@ -190,6 +596,75 @@ pub async fn start_slack_adapter() -> Result<(), Box<dyn Error>> {
 
 // ─── Helpers ──────────────────────────────────────────────────────────────────
 
+/// Replies still being collected when the bridge disconnected, marked as partial,
+/// as `(bridge channel, messages)` in channel order.
+fn partial_reply_flushes(
+    reply_buffers: &HashMap<String, ReplyBuffer>,
+    profile: &SlackProfile,
+    active: &ActiveAgent,
+) -> Vec<(String, Vec<OutboundMessage>)> {
+    let mut flushes: Vec<(String, Vec<OutboundMessage>)> = reply_buffers
+        .iter()
+        .map(|(ch, buffer)| (ch, buffer.text()))
+        .filter(|(_, content)| !content.trim().is_empty())
+        .map(|(ch, content)| {
+            (ch.clone(), profile.format_reply(&mark_partial_reply(&content), &active.provider, &active.model))
+        })
+        .collect();
+    flushes.sort_by(|a, b| a.0.cmp(&b.0));
+    flushes
+}
+
+/// Open a Socket Mode session and connect to its WebSocket URL.
+async fn connect_socket_mode(app_token: &str) -> Result<SlackWebSocket, Box<dyn Error>> {
+    let ws_url = open_socket_mode_connection(app_token).await?;
+    info!("Connecting to Slack Socket Mode WebSocket...");
+    let (ws_stream, _) = connect_async(&ws_url).await?;
+    Ok(ws_stream)
+}
+
+/// Re-establish the Socket Mode WebSocket with exponential backoff.
+///
+/// Returns the last connection error once `SLACK_RECONNECT_MAX_ATTEMPTS` is exhausted.
+async fn reconnect_socket_mode(
+    app_token: &str,
+    reason: &str,
+) -> Result<SlackWebSocket, Box<dyn Error>> {
+    warn!("Slack Socket Mode connection lost ({}); reconnecting...", reason);
+    let mut attempt = 1;
+    loop {
+        match connect_socket_mode(app_token).await {
+            Ok(ws_stream) => {
+                info!("Reconnected to Slack Socket Mode (attempt {}).", attempt);
+                return Ok(ws_stream);
+            }
+            Err(e) if attempt >= SLACK_RECONNECT_MAX_ATTEMPTS => return Err(e),
+            Err(e) => {
+                let delay = slack_reconnect_delay(attempt);
+                warn!(
+                    "Slack Socket Mode reconnect failed (attempt {}/{}), retrying in {}ms: {}",
+                    attempt,
+                    SLACK_RECONNECT_MAX_ATTEMPTS,
+                    delay.as_millis(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Backoff before the next reconnect: 1s, 2s, 4s, ... capped at 30s.
+fn slack_reconnect_delay(attempt: u32) -> Duration {
+    let factor = 1u64 << attempt.saturating_sub(1).min(16);
+    Duration::from_millis(
+        SLACK_RECONNECT_BASE_DELAY_MS
+            .saturating_mul(factor)
+            .min(SLACK_RECONNECT_MAX_DELAY_MS),
+    )
+}
+
 /// Call apps.connections.open to get a fresh WebSocket URL.
 async fn open_socket_mode_connection(app_token: &str) -> Result<String, Box<dyn Error>> {
     let client = reqwest::Client::new();
@@ -201,6 +676,27 @@ async fn open_socket_mode_connection(app_token: &str) -> Result<String, Box<dyn
             .send()
             .await
         {
+            Ok(res) if attempt < SLACK_OPEN_SOCKET_MODE_MAX_ATTEMPTS
+                && (res.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+                    || res.status().is_server_error()) =>
+            {
+                let delay = res
+                    .headers()
+                    .get("Retry-After")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse::<u64>().ok())
+                    .map(Duration::from_secs)
+                    .unwrap_or(Duration::from_millis(SLACK_OPEN_SOCKET_MODE_RETRY_DELAY_MS));
+                warn!(
+                    "Slack apps.connections.open returned HTTP {} (attempt {}/{}), retrying in {}ms",
+                    res.status(),
+                    attempt,
+                    SLACK_OPEN_SOCKET_MODE_MAX_ATTEMPTS,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
             Ok(res) => match res.json().await {
                 Ok(v) => v,
                 Err(e) => {
@@ -208,7 +704,7 @@ async fn open_socket_mode_connection(app_token: &str) -> Result<String, Box<dyn
                     if attempt < SLACK_OPEN_SOCKET_MODE_MAX_ATTEMPTS
                         && should_retry_open_socket_mode_reqwest_error(&debug_msg)
                     {
-                        eprintln!(
+                        warn!(
                             "Slack apps.connections.open body decode timed out (attempt {}/{}), retrying in {}ms: {}",
                             attempt,
                             SLACK_OPEN_SOCKET_MODE_MAX_ATTEMPTS,
@@ -227,7 +723,7 @@ async fn open_socket_mode_connection(app_token: &str) -> Result<String, Box<dyn
                 if attempt < SLACK_OPEN_SOCKET_MODE_MAX_ATTEMPTS
                     && should_retry_open_socket_mode_reqwest_error(&debug_msg)
                 {
-                    eprintln!(
+                    warn!(
                         "Slack apps.connections.open request timed out (attempt {}/{}), retrying in {}ms: {}",
                         attempt,
                         SLACK_OPEN_SOCKET_MODE_MAX_ATTEMPTS,
@@ -262,26 +758,221 @@ fn should_retry_open_socket_mode_reqwest_error(message: &str) -> bool {
     message.contains("TimedOut") || message.to_ascii_lowercase().contains("timed out")
 }
 
+const ACOMM_SLASH_COMMAND_USAGE: &str =
+    "Usage: `/acomm provider <name>`, `/acomm model <name>`, `/acomm status`";
+
+/// Translate the text of a `/acomm` slash command into a bridge command.
+/// Unknown or incomplete subcommands return the usage text instead.
+fn parse_acomm_slash_command(text: &str) -> Result<String, &'static str> {
+    let mut words = text.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("provider"), Some(name), None) => Ok(format!("/provider {}", name)),
+        (Some("model"), Some(name), None) => Ok(format!("/model {}", name)),
+        (Some("status"), None, None) => Ok("/status".to_string()),
+        _ => Err(ACOMM_SLASH_COMMAND_USAGE),
+    }
+}
+
+const SLACK_SLASH_COMMAND_REJECTED: &str = "You are not allowed to use acomm here.";
+
+/// Decide what to do with a `/acomm` slash command: the bridge command to forward, if
+/// any, and the ephemeral reply for the user. Commands go through the same allowlists
+/// and per-user rate limit as messages.
+fn admit_slash_command(
+    filter: &mut SlackEventFilter,
+    text: &str,
+    user_id: &str,
+    channel_id: &str,
+    now: std::time::Instant,
+) -> (Option<String>, String) {
+    let allowed = is_slack_id_allowed(Some(user_id), filter.allowed_user_ids.as_ref())
+        && is_slack_id_allowed(Some(channel_id), filter.allowed_channel_ids.as_ref());
+    if !allowed {
+        debug!("Rejecting /acomm from user {} in channel {}", user_id, channel_id);
+        return (None, SLACK_SLASH_COMMAND_REJECTED.to_string());
+    }
+    let command = match parse_acomm_slash_command(text) {
+        Ok(command) => command,
+        Err(usage) => return (None, usage.to_string()),
+    };
+    if let Some(limiter) = filter.rate_limiter.as_mut() {
+        // The reply is only shown to the user, so it goes out on every throttled command.
+        if let RateDecision::Throttled { retry_after, .. } = limiter.check(user_id, now) {
+            info!("Rate limited Slack user {}", user_id);
+            return (None, rate_limited_message(retry_after));
+        }
+    }
+    let reply = format!("Sent `{}` to acomm.", command);
+    (Some(command), reply)
+}
+
+/// Handle an `events_api` envelope payload, skipping events whose `event_id` was
+/// already processed (Slack retries slow or lost acks with the same id).
+async fn handle_events_api_payload<W>(
+    payload: &Value,
+    filter: &mut SlackEventFilter,
+    bridge_writer: &mut W,
+) -> Result<Option<(String, Option<String>)>, Box<dyn Error>>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    if let Some(event_id) = payload["event_id"].as_str() {
+        if !filter.first_delivery(event_id) {
+            debug!("Skipping duplicate Slack event {}", event_id);
+            return Ok(None);
+        }
+    }
+    match payload["event"]["type"].as_str().unwrap_or_default() {
+        "message" | "app_mention" => {}
+        // The bot token is dead from here on; stop instead of looping on 401s.
+        lifecycle @ ("tokens_revoked" | "app_uninstalled") => {
+            return Err(format!(
+                "Slack app access revoked ({}); reinstall the app and update SLACK_BOT_TOKEN / SLACK_APP_TOKEN",
+                lifecycle
+            )
+            .into());
+        }
+        _ => return Ok(None),
+    }
+    match serde_json::from_value::<SlackMessageEvent>(payload["event"].clone()) {
+        Ok(event) => handle_slack_event(event, filter, bridge_writer).await,
+        Err(_) => Ok(None),
+    }
+}
+
 /// Process a Slack message event and forward it to the bridge if appropriate.
+///
+/// Returns the bridge channel and Slack `ts` of a forwarded message so replies can
+/// be threaded under it.
 async fn handle_slack_event<W>(
     event: SlackMessageEvent,
+    filter: &mut SlackEventFilter,
     bridge_writer: &mut W,
-) -> Result<(), Box<dyn Error>>
+) -> Result<Option<(String, Option<String>)>, Box<dyn Error>>
 where
     W: AsyncWriteExt + Unpin,
 {
-    // Skip bot messages, subtypes (edits, joins, etc.), and empty messages
-    if event.bot_id.is_some() { return Ok(()); }
-    if event.subtype.is_some() { return Ok(()); }
-    let text = match event.text {
-        Some(ref t) if !t.is_empty() => t.clone(),
-        _ => return Ok(()),
+    if !should_forward_slack_message(
+        &event,
+        filter.allowed_user_ids.as_ref(),
+        filter.allowed_channel_ids.as_ref(),
+    ) {
+        let user_id = event.user.as_deref().unwrap_or("unknown");
+        let user_rejected = !is_slack_id_allowed(Some(user_id), filter.allowed_user_ids.as_ref());
+        if user_rejected && event.bot_id.is_none() && filter.rejected_users.insert(user_id.to_string()) {
+            debug!("Ignoring Slack messages from non-allowed user: {}", user_id);
+        }
+        return Ok(None);
+    }
+    let Some(text) = filter.prompt_text(&event) else {
+        return Ok(None);
     };
     let user_id = event.user.as_deref().unwrap_or("unknown");
-    let protocol_event = transform_slack_message(&text, user_id, &event.channel);
+    if let Some(limiter) = filter.rate_limiter.as_mut() {
+        if let RateDecision::Throttled { retry_after, notify } = limiter.check(user_id, std::time::Instant::now()) {
+            info!("Rate limited Slack user {}", user_id);
+            if notify {
+                filter.rate_limit_notices.push(SlackRateLimitNotice {
+                    channel_id: event.channel.clone(),
+                    thread_ts: event.ts.clone(),
+                    text: rate_limited_message(retry_after),
+                });
+            }
+            return Ok(None);
+        }
+    }
+    let thread_ts = event.thread_ts.as_deref();
+    let protocol_event = if event.is_direct_message() {
+        transform_slack_direct_message(&text, user_id, &event.channel, thread_ts)
+    } else {
+        transform_slack_message(&text, user_id, &event.channel, thread_ts)
+    };
+    let bridge_channel = protocol_event.clone_channel();
     let j = serde_json::to_string(&protocol_event)?;
     bridge_writer.write_all(format!("{}\n", j).as_bytes()).await?;
-    Ok(())
+    Ok(bridge_channel.map(|ch| (ch, event.ts)))
+}
+
+fn parse_allowed_slack_ids(raw: &str) -> HashSet<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
+fn load_allowed_slack_ids_from_env(var: &str) -> Option<HashSet<String>> {
+    let raw = std::env::var(var).ok()?;
+    let ids = parse_allowed_slack_ids(&raw);
+    if ids.is_empty() { None } else { Some(ids) }
+}
+
+fn is_slack_id_allowed(id: Option<&str>, allowed: Option<&HashSet<String>>) -> bool {
+    match allowed {
+        Some(ids) => id.is_some_and(|id| ids.contains(id)),
+        None => true,
+    }
+}
+
+fn should_forward_slack_message(
+    event: &SlackMessageEvent,
+    allowed_user_ids: Option<&HashSet<String>>,
+    allowed_channel_ids: Option<&HashSet<String>>,
+) -> bool {
+    // DMs are never subject to the channel allowlist; the user allowlist still applies.
+    is_slack_id_allowed(event.user.as_deref(), allowed_user_ids)
+        && (event.is_direct_message() || is_slack_id_allowed(Some(&event.channel), allowed_channel_ids))
+}
+
+/// Call auth.test to learn the bot's own user id.
+async fn fetch_bot_user_id(bot_token: &str) -> Result<String, Box<dyn Error>> {
+    let res = call_slack_api(bot_token, "auth.test", &json!({})).await?;
+    res["user_id"]
+        .as_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| "Missing user_id in auth.test response".into())
+}
+
+/// DM channel ids start with `D`.
+fn is_slack_dm_channel(channel: &str) -> bool {
+    channel.starts_with('D')
+}
+
+fn mentions_slack_user(text: &str, user_id: Option<&str>) -> bool {
+    let Some(id) = user_id else { return false };
+    text.contains(&format!("<@{}>", id)) || text.contains(&format!("<@{}|", id))
+}
+
+/// Remove the bot's `<@U…>` / `<@U…|name>` mention tokens and trim the result.
+///
+/// When the bot id is unknown, only a leading mention token is removed.
+fn strip_slack_mention(text: &str, bot_user_id: Option<&str>) -> String {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("<@") {
+        let Some(len) = rest[start..].find('>') else { break };
+        let token = &rest[start + 2..start + len];
+        let token_id = token.split('|').next().unwrap_or_default();
+        let is_bot = match bot_user_id {
+            Some(id) => token_id == id,
+            None => out.trim().is_empty() && rest[..start].trim().is_empty(),
+        };
+        out.push_str(&rest[..start]);
+        rest = if is_bot {
+            let after = &rest[start + len + 1..];
+            // Drop the space that separated the mention so no double space remains.
+            if out.is_empty() || out.ends_with(char::is_whitespace) {
+                after.strip_prefix(' ').unwrap_or(after)
+            } else {
+                after
+            }
+        } else {
+            out.push_str(&rest[start..=start + len]);
+            &rest[start + len + 1..]
+        };
+    }
+    out.push_str(rest);
+    out.trim().to_string()
 }
 
 /// Send a message to a Slack channel via chat.postMessage.
@@ -289,27 +980,588 @@ async fn send_slack_message(
     bot_token: &str,
     channel: &str,
     text: &str,
+) -> Result<(), Box<dyn Error>> {
+    post_slack_message(bot_token, channel, &redact_output(text), None, None).await?;
+    Ok(())
+}
+
+/// Post via chat.postMessage and return the new message's `ts` when Slack reports one.
+///
+/// With `blocks`, `text` is only the notification / accessibility fallback.
+async fn post_slack_message(
+    bot_token: &str,
+    channel: &str,
+    text: &str,
+    blocks: Option<&[Value]>,
+    thread_ts: Option<&str>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let mut payload = json!({ "channel": channel, "text": text });
+    if let Some(blocks) = blocks {
+        payload["blocks"] = json!(blocks);
+    }
+    if let Some(thread_ts) = thread_ts {
+        payload["thread_ts"] = json!(thread_ts);
+    }
+    let res = call_slack_api(bot_token, "chat.postMessage", &payload).await?;
+    Ok(res["ts"].as_str().map(|s| s.to_string()))
+}
+
+/// Replace a message's text (and blocks, when given) via chat.update.
+async fn update_slack_message(
+    bot_token: &str,
+    channel: &str,
+    ts: &str,
+    text: &str,
+    blocks: Option<&[Value]>,
+) -> Result<(), Box<dyn Error>> {
+    let mut payload = json!({ "channel": channel, "ts": ts, "text": text });
+    if let Some(blocks) = blocks {
+        payload["blocks"] = json!(blocks);
+    }
+    call_slack_api(bot_token, "chat.update", &payload).await?;
+    Ok(())
+}
+
+/// Final update of a streamed reply; oversized replies become a snippet in the thread.
+async fn finish_slack_stream(
+    bot_token: &str,
+    stream: &SlackStreamingReply,
+    messages: &[OutboundMessage],
+) -> Result<(), Box<dyn Error>> {
+    let Some(message) = messages.first() else {
+        return delete_slack_message(bot_token, &stream.channel_id, &stream.ts).await;
+    };
+    update_slack_message(bot_token, &stream.channel_id, &stream.ts, &message.body, message.blocks.as_deref()).await?;
+    if let Some(full) = &message.attachment {
+        let thread_ts = stream.thread_ts.as_deref().unwrap_or(&stream.ts);
+        upload_slack_snippet(bot_token, &stream.channel_id, full, SLACK_SNIPPET_NOTE, Some(thread_ts)).await?;
+    }
+    Ok(())
+}
+
+/// Delete a message via chat.delete.
+async fn delete_slack_message(
+    bot_token: &str,
+    channel: &str,
+    ts: &str,
+) -> Result<(), Box<dyn Error>> {
+    call_slack_api(bot_token, "chat.delete", &json!({ "channel": channel, "ts": ts })).await?;
+    Ok(())
+}
+
+/// Best-effort removal of the "thinking" placeholder for a bridge channel.
+async fn clear_slack_thinking_message(
+    bot_token: &str,
+    thinking_messages: &mut HashMap<String, String>,
+    bridge_channel: &str,
+) {
+    let Some(ts) = thinking_messages.remove(bridge_channel) else { return };
+    let Some(slack_channel) = slack_channel_id_from_bridge_channel(bridge_channel) else { return };
+    if let Err(e) = delete_slack_message(bot_token, slack_channel, &ts).await {
+        warn!("Slack thinking indicator cleanup failed: {}", e);
+    }
+}
+
+/// Channel format: `slack:<user_id>:<channel_id>`
+fn slack_channel_id_from_bridge_channel(channel: &str) -> Option<&str> {
+    let rest = channel.strip_prefix("slack:")?;
+    let rest = rest.strip_prefix("dm:").unwrap_or(rest);
+    match rest.split_once(':') {
+        Some((_, channel_id)) if !channel_id.is_empty() => Some(channel_id),
+        _ => None,
+    }
+}
+
+fn load_slack_snippet_threshold_from_env() -> usize {
+    std::env::var("SLACK_SNIPPET_THRESHOLD")
+        .ok()
+        .and_then(|v| v.trim().parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_SLACK_SNIPPET_THRESHOLD)
+}
+
+/// Section length from `SLACK_MAX_CHARS`, defaulting to `SLACK_SECTION_TEXT_LIMIT`.
+fn load_slack_max_chars_from_env() -> usize {
+    let (max_chars, warning) = parse_max_chars(
+        "SLACK_MAX_CHARS",
+        std::env::var("SLACK_MAX_CHARS").ok().as_deref(),
+        SLACK_SECTION_TEXT_LIMIT,
+        SLACK_MAX_CHARS_MIN,
+        SLACK_MAX_CHARS_MAX,
+    );
+    if let Some(warning) = warning {
+        warn!("{}", warning);
+    }
+    max_chars
+}
+
+/// Post formatted reply messages: Block Kit posts, or a summary uploaded with the
+/// full reply as a snippet. With `thread_ts` they are posted in that thread.
+async fn deliver_slack_reply(
+    bot_token: &str,
+    channel: &str,
+    messages: &[OutboundMessage],
+    thread_ts: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    for message in messages {
+        match &message.attachment {
+            Some(full) => {
+                let comment = format!("{}\n{}", message.body, SLACK_SNIPPET_NOTE);
+                upload_slack_snippet(bot_token, channel, full, &comment, thread_ts).await?;
+            }
+            None => {
+                post_slack_message(bot_token, channel, &message.body, message.blocks.as_deref(), thread_ts).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Upload `content` as a markdown file via the external upload flow
+/// (files.getUploadURLExternal → upload → files.completeUploadExternal).
+async fn upload_slack_snippet(
+    bot_token: &str,
+    channel: &str,
+    content: &str,
+    initial_comment: &str,
+    thread_ts: Option<&str>,
 ) -> Result<(), Box<dyn Error>> {
     let client = reqwest::Client::new();
-    client
-        .post(format!("{}/chat.postMessage", SLACK_API_BASE))
+    let res: Value = client
+        .post(format!("{}/files.getUploadURLExternal", SLACK_API_BASE))
         .header("Authorization", format!("Bearer {}", bot_token))
-        .json(&json!({ "channel": channel, "text": text }))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(format!("filename=reply.md&length={}", content.len()))
         .send()
+        .await?
+        .json()
         .await?;
+    ensure_slack_ok(&res, "files.getUploadURLExternal")?;
+    let upload_url = res["upload_url"]
+        .as_str()
+        .ok_or("Missing upload_url in Slack response")?;
+    let file_id = res["file_id"]
+        .as_str()
+        .ok_or("Missing file_id in Slack response")?;
+
+    client
+        .post(upload_url)
+        .body(content.to_string())
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let mut payload = json!({
+        "files": [{ "id": file_id, "title": "Agent reply" }],
+        "channel_id": channel,
+        "initial_comment": initial_comment,
+    });
+    if let Some(thread_ts) = thread_ts {
+        payload["thread_ts"] = json!(thread_ts);
+    }
+    call_slack_api(bot_token, "files.completeUploadExternal", &payload).await?;
     Ok(())
 }
 
+#[derive(Debug)]
+enum SlackApiOutcome {
+    Success(Value),
+    RetryAfter(Duration),
+    Failed(String),
+}
+
+/// Classify a Web API response: success, rate-limited (with the delay to wait), or failure.
+fn classify_slack_api_response(
+    method: &str,
+    status: reqwest::StatusCode,
+    retry_after: Option<&str>,
+    body: &str,
+) -> SlackApiOutcome {
+    let retry_delay = || {
+        Duration::from_secs(
+            retry_after
+                .and_then(|v| v.trim().parse::<u64>().ok())
+                .unwrap_or(SLACK_API_DEFAULT_RETRY_AFTER_SECS),
+        )
+    };
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return SlackApiOutcome::RetryAfter(retry_delay());
+    }
+    let res: Value = match serde_json::from_str(body) {
+        Ok(v) => v,
+        Err(_) => {
+            return SlackApiOutcome::Failed(format!(
+                "{} failed with HTTP {}: {}",
+                method,
+                status,
+                body.trim()
+            ));
+        }
+    };
+    if res["ok"].as_bool() == Some(true) {
+        return SlackApiOutcome::Success(res);
+    }
+    match res["error"].as_str() {
+        Some("ratelimited") => SlackApiOutcome::RetryAfter(retry_delay()),
+        Some(error) => SlackApiOutcome::Failed(format!("{} failed: {}", method, error)),
+        None => SlackApiOutcome::Failed(format!("{} failed: {}", method, res)),
+    }
+}
+
+/// POST a JSON payload to a Slack Web API method, retrying rate-limited calls
+/// after `Retry-After` up to `SLACK_API_MAX_ATTEMPTS` times.
+async fn call_slack_api(
+    bot_token: &str,
+    method: &str,
+    payload: &Value,
+) -> Result<Value, Box<dyn Error>> {
+    let client = reqwest::Client::new();
+    for attempt in 1..=SLACK_API_MAX_ATTEMPTS {
+        let response = client
+            .post(format!("{}/{}", SLACK_API_BASE, method))
+            .header("Authorization", format!("Bearer {}", bot_token))
+            .json(payload)
+            .send()
+            .await?;
+        let status = response.status();
+        let retry_after = response
+            .headers()
+            .get("Retry-After")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let body = response.text().await.unwrap_or_default();
+        match classify_slack_api_response(method, status, retry_after.as_deref(), &body) {
+            SlackApiOutcome::Success(res) => return Ok(res),
+            SlackApiOutcome::RetryAfter(delay) if attempt < SLACK_API_MAX_ATTEMPTS => {
+                warn!(
+                    "Slack {} rate limited (attempt {}/{}), retrying in {}s",
+                    method,
+                    attempt,
+                    SLACK_API_MAX_ATTEMPTS,
+                    delay.as_secs()
+                );
+                tokio::time::sleep(delay).await;
+            }
+            SlackApiOutcome::RetryAfter(_) => {
+                return Err(format!(
+                    "{} still rate limited after {} attempts",
+                    method, SLACK_API_MAX_ATTEMPTS
+                )
+                .into());
+            }
+            SlackApiOutcome::Failed(msg) => return Err(msg.into()),
+        }
+    }
+    Err(format!("{} failed after retries", method).into())
+}
+
+fn ensure_slack_ok(res: &Value, method: &str) -> Result<(), String> {
+    if res["ok"].as_bool() == Some(true) {
+        Ok(())
+    } else {
+        Err(format!("{} failed: {}", method, res))
+    }
+}
+
+/// Decide whether a reply should be uploaded as a snippet rather than posted inline.
+fn should_upload_slack_snippet(content: &str, threshold: usize) -> bool {
+    content.chars().count() > threshold
+        || largest_code_block_lines(content) >= SLACK_SNIPPET_CODE_BLOCK_LINES
+}
+
+fn largest_code_block_lines(content: &str) -> usize {
+    let mut largest = 0;
+    let mut current: Option<usize> = None;
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            current = match current {
+                Some(n) => {
+                    largest = largest.max(n);
+                    None
+                }
+                None => Some(0),
+            };
+        } else if let Some(n) = current.as_mut() {
+            *n += 1;
+        }
+    }
+    // An unterminated fence still counts as code.
+    largest.max(current.unwrap_or(0))
+}
+
+/// First paragraph of the reply, truncated for the snippet's accompanying message.
+fn summarize_for_slack(content: &str) -> String {
+    let first = content
+        .trim()
+        .split("\n\n")
+        .next()
+        .unwrap_or_default()
+        .trim();
+    if first.chars().count() <= SLACK_SNIPPET_SUMMARY_CHARS {
+        return first.to_string();
+    }
+    let mut out: String = first
+        .chars()
+        .take(SLACK_SNIPPET_SUMMARY_CHARS.saturating_sub(1))
+        .collect();
+    out.push('…');
+    out
+}
+
+/// `reply_footer` for the context block under a reply.
+fn slack_reply_status(provider: &str, model: &str) -> String {
+    match reply_footer(provider, model) {
+        footer if footer.is_empty() => "unknown".to_string(),
+        footer => footer,
+    }
+}
+
+/// Slack replies: Block Kit with the `provider:model` context block (`ACOMM_REPLY_FOOTER`),
+/// or a summary plus snippet when the reply is long, code-heavy, or exceeds the block limit.
+struct SlackProfile {
+    snippet_threshold: usize,
+    /// Max characters per section / preformatted block.
+    max_chars: usize,
+    /// Only with `ACOMM_REPLY_FOOTER`; proactive notifications never carry one.
+    show_status: bool,
+}
+
+impl FormatProfile for SlackProfile {
+    fn format_reply(&self, content: &str, provider: &str, model: &str) -> Vec<OutboundMessage> {
+        let content = redact_output(final_answer(content).unwrap_or(content));
+        if content.trim().is_empty() {
+            return Vec::new();
+        }
+        let status = self.show_status.then(|| slack_reply_status(provider, model));
+        let blocks = if should_upload_slack_snippet(&content, self.snippet_threshold) {
+            None
+        } else {
+            markdown_to_slack_blocks(&content, status.as_deref(), self.max_chars)
+        };
+        let message = match blocks {
+            Some(blocks) => OutboundMessage {
+                body: markdown_to_mrkdwn(&content),
+                blocks: Some(blocks),
+                ..Default::default()
+            },
+            None => OutboundMessage {
+                body: markdown_to_mrkdwn(&summarize_for_slack(&content)),
+                attachment: Some(content),
+                ..Default::default()
+            },
+        };
+        vec![message]
+    }
+}
+
+/// Split a reply into prose and fenced code segments (`true` marks code).
+fn split_markdown_fences(content: &str) -> Vec<(bool, String)> {
+    let mut segments: Vec<(bool, String)> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut in_fence = false;
+    for line in content.split('\n') {
+        if line.trim_start().starts_with("```") {
+            segments.push((in_fence, current.join("\n")));
+            current.clear();
+            in_fence = !in_fence;
+        } else {
+            current.push(line);
+        }
+    }
+    segments.push((in_fence, current.join("\n")));
+    segments.retain(|(_, text)| !text.trim().is_empty());
+    segments
+}
+
+/// Split `text` into pieces of at most `limit` characters, preferring line boundaries.
+fn chunk_by_lines(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for line in text.split('\n') {
+        let mut line_chars: Vec<char> = line.chars().collect();
+        // Hard-split lines that alone exceed the limit.
+        while line_chars.len() > limit {
+            if !current.is_empty() {
+                chunks.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            chunks.push(line_chars.drain(..limit).collect());
+        }
+        let len = line_chars.len();
+        let needed = if current.is_empty() { len } else { len + 1 };
+        if current_len + needed > limit && !current.is_empty() {
+            chunks.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+            current_len += 1;
+        }
+        current.extend(line_chars);
+        current_len += len;
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+    chunks
+}
+
+/// Convert a Markdown reply to Block Kit: mrkdwn sections for prose, preformatted
+/// rich text for fenced code, and the `provider:model` status as a trailing context
+/// block, each at most `max_chars` long. Returns `None` when the result would exceed Slack's block limit, in which
+/// case the caller should fall back to a snippet upload.
+fn markdown_to_slack_blocks(content: &str, status: Option<&str>, max_chars: usize) -> Option<Vec<Value>> {
+    let mut blocks = Vec::new();
+    for (is_code, text) in split_markdown_fences(content) {
+        if is_code {
+            for chunk in chunk_by_lines(&text, max_chars) {
+                blocks.push(json!({
+                    "type": "rich_text",
+                    "elements": [{
+                        "type": "rich_text_preformatted",
+                        "elements": [{ "type": "text", "text": chunk }],
+                    }],
+                }));
+            }
+        } else {
+            let mrkdwn = markdown_to_mrkdwn(text.trim_matches('\n'));
+            for chunk in chunk_by_lines(&mrkdwn, max_chars) {
+                if chunk.trim().is_empty() {
+                    continue;
+                }
+                blocks.push(json!({
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": chunk },
+                }));
+            }
+        }
+    }
+    if let Some(status) = status {
+        blocks.push(json!({
+            "type": "context",
+            "elements": [{ "type": "mrkdwn", "text": status }],
+        }));
+    }
+    if blocks.len() > SLACK_MAX_BLOCKS { None } else { Some(blocks) }
+}
+
+/// Convert common Markdown to Slack mrkdwn.
+///
+/// Bold (`**x**` / `__x__`) becomes `*x*`, strikethrough becomes `~x~`, links become
+/// `<url|text>`, headings become bold lines and `-`/`*` bullets become `•`. Fenced code
+/// blocks and inline code spans are left untouched apart from dropping the fence
+/// language tag, which Slack would otherwise render as code.
+fn markdown_to_mrkdwn(text: &str) -> String {
+    let mut out = Vec::new();
+    let mut in_fence = false;
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_fence = !in_fence;
+            out.push(if in_fence {
+                let indent = &line[..line.len() - trimmed.len()];
+                format!("{indent}```")
+            } else {
+                line.to_string()
+            });
+            continue;
+        }
+        if in_fence {
+            out.push(line.to_string());
+        } else {
+            out.push(convert_markdown_line(line));
+        }
+    }
+    out.join("\n")
+}
+
+fn convert_markdown_line(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+
+    let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+        let heading = convert_markdown_inline(trimmed[hashes..].trim())
+            .replace('*', "");
+        return format!("{indent}*{heading}*");
+    }
+    if let Some(rest) = trimmed
+        .strip_prefix("- ")
+        .or_else(|| trimmed.strip_prefix("* "))
+    {
+        return format!("{indent}• {}", convert_markdown_inline(rest));
+    }
+    format!("{indent}{}", convert_markdown_inline(trimmed))
+}
+
+fn convert_markdown_inline(text: &str) -> String {
+    // Odd segments are inside inline code spans and must stay verbatim.
+    text.split('`')
+        .enumerate()
+        .map(|(idx, segment)| {
+            if idx % 2 == 1 {
+                return segment.to_string();
+            }
+            let s = replace_paired(segment, "**", "*");
+            let s = replace_paired(&s, "__", "*");
+            let s = replace_paired(&s, "~~", "~");
+            convert_markdown_links(&s)
+        })
+        .collect::<Vec<_>>()
+        .join("`")
+}
+
+/// Replace matched pairs of `delim` with `repl`; an unmatched trailing delimiter is kept.
+fn replace_paired(s: &str, delim: &str, repl: &str) -> String {
+    let parts: Vec<&str> = s.split(delim).collect();
+    let paired = (parts.len() - 1) / 2 * 2;
+    let mut out = String::new();
+    for (idx, part) in parts.iter().enumerate() {
+        if idx > 0 {
+            out.push_str(if idx <= paired { repl } else { delim });
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+/// `[label](url)` → `<url|label>`. The label may not contain brackets, so a stray
+/// `[` earlier on the line is left alone rather than swallowed into the link.
+fn convert_markdown_links(s: &str) -> String {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link = LINK.get_or_init(|| Regex::new(r"\[([^\]\[]+)\]\(([^)\s]+)\)").expect("built-in link pattern must compile"));
+    link.replace_all(s, "<$2|$1>").into_owned()
+}
+
 // ─── Public transformation helpers ────────────────────────────────────────────
 
 /// Convert a Slack message event to a ProtocolEvent::Prompt for the bridge.
 ///
-/// Channel format: `slack:<user_id>:<slack_channel_id>`
-pub fn transform_slack_message(text: &str, user_id: &str, slack_channel: &str) -> ProtocolEvent {
+/// Channel format: `slack:<user_id>:<slack_channel_id>`; `thread_ts` becomes `reply_to`
+/// for messages posted in a thread.
+pub fn transform_slack_message(text: &str, user_id: &str, slack_channel: &str, thread_ts: Option<&str>) -> ProtocolEvent {
     ProtocolEvent::Prompt {
         text: text.to_string(),
         provider: None,
         channel: Some(format!("slack:{}:{}", user_id, slack_channel)),
+        reply_to: thread_ts.map(str::to_string),
+        priority: false,
+        author: None,
+    }
+}
+
+/// Convert a Slack direct message to a ProtocolEvent::Prompt for the bridge.
+///
+/// Channel format: `slack:dm:<user_id>:<im_channel_id>`
+pub fn transform_slack_direct_message(text: &str, user_id: &str, im_channel: &str, thread_ts: Option<&str>) -> ProtocolEvent {
+    ProtocolEvent::Prompt {
+        text: text.to_string(),
+        provider: None,
+        channel: Some(format!("slack:dm:{}:{}", user_id, im_channel)),
+        reply_to: thread_ts.map(str::to_string),
+        priority: false,
+        author: None,
     }
 }
 
@@ -361,8 +1613,8 @@ mod tests {
 
     #[test]
     fn test_transform_slack_message() {
-        let event = transform_slack_message("hello執事", "U12345", "C98765");
-        if let ProtocolEvent::Prompt { text, channel, provider } = event {
+        let event = transform_slack_message("hello執事", "U12345", "C98765", None);
+        if let ProtocolEvent::Prompt { text, channel, provider, .. } = event {
             assert_eq!(text, "hello執事");
             assert_eq!(channel, Some("slack:U12345:C98765".to_string()));
             assert!(provider.is_none());
@@ -373,7 +1625,7 @@ mod tests {
 
     #[test]
     fn test_transform_slack_message_channel_prefix() {
-        let event = transform_slack_message("test", "Uabc", "Cdef");
+        let event = transform_slack_message("test", "Uabc", "Cdef", None);
         if let ProtocolEvent::Prompt { channel, .. } = event {
             let ch = channel.unwrap();
             assert!(ch.starts_with("slack:"), "Channel must start with 'slack:'");
@@ -388,7 +1640,7 @@ mod tests {
 
     #[test]
     fn test_transform_slack_message_unknown_user() {
-        let event = transform_slack_message("hi", "unknown", "C001");
+        let event = transform_slack_message("hi", "unknown", "C001", None);
         if let ProtocolEvent::Prompt { channel, .. } = event {
             assert_eq!(channel, Some("slack:unknown:C001".to_string()));
         } else {
@@ -398,7 +1650,7 @@ mod tests {
 
     #[test]
     fn test_transform_slack_message_preserves_cjk() {
-        let event = transform_slack_message("おはようございます！", "U999", "C888");
+        let event = transform_slack_message("おはようございます！", "U999", "C888", None);
         if let ProtocolEvent::Prompt { text, .. } = event {
             assert_eq!(text, "おはようございます！");
         } else {
@@ -443,6 +1695,546 @@ mod tests {
         assert!(should_retry_open_socket_mode_reqwest_error("request timed out while reading body"));
     }
 
+    fn slack_event(event_type: &str, channel: &str, text: &str, ts: &str) -> SlackMessageEvent {
+        SlackMessageEvent {
+            event_type: event_type.to_string(),
+            channel: channel.to_string(),
+            user: Some("U1".to_string()),
+            text: Some(text.to_string()),
+            bot_id: None,
+            subtype: None,
+            ts: Some(ts.to_string()),
+            channel_type: None,
+            thread_ts: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redelivered_event_is_forwarded_once() {
+        let envelope: SocketModeEnvelope = serde_json::from_value(json!({
+            "type": "events_api",
+            "envelope_id": "env-1",
+            "retry_attempt": 1,
+            "payload": {
+                "event_id": "Ev123",
+                "event": { "type": "message", "channel": "C1", "user": "U1", "text": "hi" }
+            }
+        }))
+        .unwrap();
+        let payload = envelope.payload.unwrap();
+        let mut filter = SlackEventFilter::new(None, false);
+        let mut writer: Vec<u8> = Vec::new();
+        handle_events_api_payload(&payload, &mut filter, &mut writer).await.unwrap();
+        // No ts on the event, so only the event_id check can catch the redelivery.
+        handle_events_api_payload(&payload, &mut filter, &mut writer).await.unwrap();
+
+        let written = String::from_utf8(writer).unwrap();
+        let prompts: Vec<&str> = written.lines().filter(|l| l.contains("\"Prompt\"")).collect();
+        assert_eq!(prompts.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_throttled_user_is_told_once_and_not_forwarded() {
+        let mut filter = SlackEventFilter::new(None, false);
+        filter.rate_limiter = Some(RateLimiter::new(crate::rate_limit::RateLimitConfig {
+            burst: 1,
+            refill: Duration::from_secs(60),
+        }));
+        let mut writer: Vec<u8> = Vec::new();
+
+        assert!(handle_slack_event(slack_event("message", "C1", "a", "1.0"), &mut filter, &mut writer).await.unwrap().is_some());
+        assert!(handle_slack_event(slack_event("message", "C1", "b", "2.0"), &mut filter, &mut writer).await.unwrap().is_none());
+        assert!(handle_slack_event(slack_event("message", "C1", "c", "3.0"), &mut filter, &mut writer).await.unwrap().is_none());
+
+        let written = String::from_utf8(writer).unwrap();
+        assert_eq!(written.lines().filter(|l| l.contains("\"Prompt\"")).count(), 1);
+        assert_eq!(filter.rate_limit_notices.len(), 1);
+        let notice = &filter.rate_limit_notices[0];
+        assert_eq!(notice.channel_id, "C1");
+        assert_eq!(notice.thread_ts.as_deref(), Some("2.0"));
+        assert!(notice.text.starts_with("rate limited, try again in "));
+    }
+
+    #[tokio::test]
+    async fn test_direct_message_bypasses_mention_and_channel_filters() {
+        let mut filter = SlackEventFilter::new(Some("UBOT".into()), true);
+        filter.allowed_channel_ids = Some(HashSet::from(["C1".to_string()]));
+        let mut event = slack_event("message", "G777", "hello", "5.0");
+        event.channel_type = Some("im".into());
+        let mut writer: Vec<u8> = Vec::new();
+
+        let forwarded = handle_slack_event(event, &mut filter, &mut writer).await.unwrap();
+
+        assert_eq!(forwarded, Some(("slack:dm:U1:G777".to_string(), Some("5.0".to_string()))));
+    }
+
+    #[tokio::test]
+    async fn test_direct_message_still_honors_user_allowlist_and_subtypes() {
+        let mut filter = SlackEventFilter::new(None, false);
+        filter.allowed_user_ids = Some(HashSet::from(["U2".to_string()]));
+        let mut writer: Vec<u8> = Vec::new();
+        let mut dm = slack_event("message", "D1", "hi", "1.0");
+        dm.channel_type = Some("im".into());
+        assert!(handle_slack_event(dm, &mut filter, &mut writer).await.unwrap().is_none());
+
+        let mut filter = SlackEventFilter::new(None, false);
+        for subtype in ["message_changed", "message_deleted"] {
+            let mut edited = slack_event("message", "D1", "hi", "2.0");
+            edited.channel_type = Some("im".into());
+            edited.subtype = Some(subtype.into());
+            assert!(handle_slack_event(edited, &mut filter, &mut writer).await.unwrap().is_none());
+        }
+        assert!(writer.is_empty());
+    }
+
+    #[test]
+    fn test_markdown_to_slack_blocks_splits_prose_and_code() {
+        let reply = "# Title\nSome **bold** text\n\n```rust\nfn main() {}\n```\nDone.";
+        let blocks = markdown_to_slack_blocks(reply, Some("claude:claude-sonnet-4-6"), SLACK_SECTION_TEXT_LIMIT).unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0]["type"], "section");
+        assert_eq!(blocks[0]["text"]["text"], "*Title*\nSome *bold* text");
+        assert_eq!(blocks[1]["type"], "rich_text");
+        assert_eq!(blocks[1]["elements"][0]["type"], "rich_text_preformatted");
+        assert_eq!(blocks[1]["elements"][0]["elements"][0]["text"], "fn main() {}");
+        assert_eq!(blocks[2]["text"]["text"], "Done.");
+        assert_eq!(blocks[3]["type"], "context");
+        assert_eq!(blocks[3]["elements"][0]["text"], "claude:claude-sonnet-4-6");
+    }
+
+    #[test]
+    fn test_markdown_to_slack_blocks_caps_section_length() {
+        let long_line = "a".repeat(SLACK_SECTION_TEXT_LIMIT + 10);
+        let reply = format!("{}\nshort", long_line);
+        let blocks = markdown_to_slack_blocks(&reply, None, SLACK_SECTION_TEXT_LIMIT).unwrap();
+        assert!(blocks.len() >= 2);
+        for block in &blocks {
+            assert!(block["text"]["text"].as_str().unwrap().chars().count() <= SLACK_SECTION_TEXT_LIMIT);
+        }
+    }
+
+    #[test]
+    fn test_slack_replies_respect_a_custom_lower_limit() {
+        let profile = SlackProfile { snippet_threshold: 3000, max_chars: 500, show_status: false };
+        let reply = (0..40).map(|i| format!("line {} of a long answer", i)).collect::<Vec<_>>().join("\n");
+        let messages = profile.format_reply(&reply, "claude", "");
+        let blocks = messages[0].blocks.as_ref().unwrap();
+        assert!(blocks.len() >= 2);
+        for block in blocks {
+            assert!(block["text"]["text"].as_str().unwrap().chars().count() <= 500);
+        }
+    }
+
+    #[test]
+    fn test_slack_max_chars_from_env_value() {
+        let parse = |raw| parse_max_chars("SLACK_MAX_CHARS", raw, SLACK_SECTION_TEXT_LIMIT, SLACK_MAX_CHARS_MIN, SLACK_MAX_CHARS_MAX);
+        assert_eq!(parse(None), (SLACK_SECTION_TEXT_LIMIT, None));
+        assert_eq!(parse(Some(" 1200 ")), (1200, None));
+        let (limit, warning) = parse(Some("4000"));
+        assert_eq!(limit, SLACK_SECTION_TEXT_LIMIT);
+        assert!(warning.unwrap().contains("SLACK_MAX_CHARS"));
+        assert!(parse(Some("50")).1.is_some());
+    }
+
+    #[test]
+    fn test_markdown_to_slack_blocks_gives_up_beyond_block_limit() {
+        let reply = (0..SLACK_MAX_BLOCKS)
+            .map(|i| format!("para {}\n```\ncode {}\n```", i, i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert!(markdown_to_slack_blocks(&reply, None, SLACK_SECTION_TEXT_LIMIT).is_none());
+    }
+
+    #[test]
+    fn test_chunk_by_lines_prefers_line_boundaries() {
+        assert_eq!(chunk_by_lines("ab\ncd\nef", 5), vec!["ab\ncd", "ef"]);
+        assert_eq!(chunk_by_lines("abcdefg", 3), vec!["abc", "def", "g"]);
+    }
+
+    #[test]
+    fn test_slack_reply_status() {
+        assert_eq!(slack_reply_status("gemini", "auto-gemini-3"), "gemini:auto-gemini-3");
+        assert_eq!(slack_reply_status("opencode", ""), "opencode");
+        assert_eq!(slack_reply_status("", ""), "unknown");
+    }
+
+    #[test]
+    fn test_slack_profile_posts_blocks_with_status_context() {
+        let profile = SlackProfile { snippet_threshold: 3000, max_chars: SLACK_SECTION_TEXT_LIMIT, show_status: true };
+        let messages = profile.format_reply("**Done.**", "codex", "gpt-4o");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body, "*Done.*");
+        assert!(messages[0].attachment.is_none());
+        let blocks = messages[0].blocks.as_ref().unwrap();
+        assert_eq!(blocks.last().unwrap()["elements"][0]["text"], "codex:gpt-4o");
+
+        let quiet = SlackProfile { snippet_threshold: 3000, max_chars: SLACK_SECTION_TEXT_LIMIT, show_status: false };
+        let blocks = quiet.format_reply("Done.", "codex", "gpt-4o")[0].blocks.clone().unwrap();
+        assert!(blocks.iter().all(|b| b["type"] != "context"));
+    }
+
+    #[test]
+    fn test_shutdown_flushes_pending_reply_buffers_as_partial() {
+        let profile = SlackProfile { snippet_threshold: 3000, max_chars: SLACK_SECTION_TEXT_LIMIT, show_status: false };
+        let active = ActiveAgent::new("codex", "gpt-4o");
+        let buffer = |content: &str| {
+            let mut buf = ReplyBuffer::new(&ReplyBufferLimits::default(), std::time::Instant::now());
+            buf.push(content);
+            buf
+        };
+        let mut reply_buffers = HashMap::new();
+        reply_buffers.insert("slack:C2:2.0".to_string(), buffer("Second so far"));
+        reply_buffers.insert("slack:C1:1.0".to_string(), buffer("First so far"));
+        reply_buffers.insert("slack:C3:3.0".to_string(), buffer(""));
+
+        let flushes = partial_reply_flushes(&reply_buffers, &profile, &active);
+        assert_eq!(flushes.len(), 2);
+        assert_eq!(flushes[0].0, "slack:C1:1.0");
+        assert_eq!(flushes[0].1.len(), 1);
+        assert_eq!(flushes[0].1[0].body, "(partial — bridge disconnected)\n\nFirst so far");
+        assert_eq!(flushes[1].0, "slack:C2:2.0");
+    }
+
+    #[test]
+    fn test_slack_profile_falls_back_to_snippet_over_threshold() {
+        let profile = SlackProfile { snippet_threshold: 100, max_chars: SLACK_SECTION_TEXT_LIMIT, show_status: true };
+        let reply = format!("Summary line.\n\n{}", "detail ".repeat(50));
+        let messages = profile.format_reply(&reply, "gemini", "m");
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].body, "Summary line.");
+        assert_eq!(messages[0].attachment.as_deref(), Some(reply.as_str()));
+        assert!(messages[0].blocks.is_none());
+        assert!(profile.format_reply("  \n", "gemini", "m").is_empty());
+    }
+
+    #[test]
+    fn test_parse_acomm_slash_command() {
+        assert_eq!(parse_acomm_slash_command("provider claude"), Ok("/provider claude".to_string()));
+        assert_eq!(
+            parse_acomm_slash_command(" model  gpt-5.3-codex "),
+            Ok("/model gpt-5.3-codex".to_string())
+        );
+        assert_eq!(parse_acomm_slash_command("status"), Ok("/status".to_string()));
+        assert_eq!(parse_acomm_slash_command(""), Err(ACOMM_SLASH_COMMAND_USAGE));
+        assert_eq!(parse_acomm_slash_command("provider"), Err(ACOMM_SLASH_COMMAND_USAGE));
+        assert_eq!(parse_acomm_slash_command("clear"), Err(ACOMM_SLASH_COMMAND_USAGE));
+    }
+
+    #[test]
+    fn test_slash_command_is_checked_against_allowlists_and_rate_limit() {
+        let mut filter = SlackEventFilter::new(None, false);
+        filter.allowed_user_ids = Some(HashSet::from(["U1".to_string()]));
+        filter.rate_limiter = Some(RateLimiter::new(crate::rate_limit::RateLimitConfig {
+            burst: 1,
+            refill: Duration::from_secs(60),
+        }));
+        let now = std::time::Instant::now();
+
+        assert_eq!(
+            admit_slash_command(&mut filter, "status", "U2", "C1", now),
+            (None, SLACK_SLASH_COMMAND_REJECTED.to_string())
+        );
+        assert_eq!(
+            admit_slash_command(&mut filter, "status", "U1", "C1", now),
+            (Some("/status".to_string()), "Sent `/status` to acomm.".to_string())
+        );
+        let (command, reply) = admit_slash_command(&mut filter, "status", "U1", "C1", now);
+        assert_eq!(command, None);
+        assert!(reply.starts_with("rate limited"), "{reply}");
+    }
+
+    #[tokio::test]
+    async fn test_non_message_event_is_not_forwarded() {
+        let payload = json!({
+            "event_id": "Ev1",
+            "event": { "type": "channel_rename", "channel": "C1", "user": "U1", "text": "hi", "ts": "1.0" }
+        });
+        let mut filter = SlackEventFilter::new(None, false);
+        let mut writer: Vec<u8> = Vec::new();
+        let forwarded = handle_events_api_payload(&payload, &mut filter, &mut writer).await.unwrap();
+        assert!(forwarded.is_none());
+        assert!(writer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tokens_revoked_stops_the_adapter() {
+        let payload = json!({
+            "event_id": "Ev2",
+            "event": { "type": "tokens_revoked", "tokens": { "bot": ["UBOT"] } }
+        });
+        let mut filter = SlackEventFilter::new(None, false);
+        let mut writer: Vec<u8> = Vec::new();
+        let err = handle_events_api_payload(&payload, &mut filter, &mut writer).await.unwrap_err();
+        assert!(err.to_string().contains("tokens_revoked"));
+        assert!(writer.is_empty());
+    }
+
+    #[test]
+    fn test_first_delivery_is_bounded() {
+        let mut filter = SlackEventFilter::new(None, false);
+        assert!(filter.first_delivery("Ev0"));
+        assert!(!filter.first_delivery("Ev0"));
+        for i in 1..=SLACK_RECENT_MESSAGE_KEYS {
+            assert!(filter.first_delivery(&format!("Ev{}", i)));
+        }
+        assert_eq!(filter.recent_event_ids.len(), SLACK_RECENT_MESSAGE_KEYS);
+        assert!(filter.first_delivery("Ev0"));
+    }
+
+    #[test]
+    fn test_should_stream_slack_update_throttles() {
+        assert!(!should_stream_slack_update(Duration::from_millis(500), 10_000));
+        assert!(!should_stream_slack_update(Duration::from_millis(1500), 10));
+        assert!(should_stream_slack_update(Duration::from_millis(1500), 400));
+        assert!(should_stream_slack_update(Duration::from_secs(2), 1));
+    }
+
+    #[test]
+    fn test_render_slack_stream_preview_truncates_and_converts() {
+        assert_eq!(render_slack_stream_preview("**hi**", 100), "*hi*");
+        assert_eq!(render_slack_stream_preview("abcdef", 3), "abc…");
+    }
+
+    #[tokio::test]
+    async fn test_handle_slack_event_returns_thread_ts_of_forwarded_message() {
+        let mut filter = SlackEventFilter::new(None, false);
+        let mut writer: Vec<u8> = Vec::new();
+        let forwarded = handle_slack_event(slack_event("message", "C1", "hi", "9.1"), &mut filter, &mut writer)
+            .await
+            .unwrap();
+        assert_eq!(forwarded, Some(("slack:U1:C1".to_string(), Some("9.1".to_string()))));
+    }
+
+    #[tokio::test]
+    async fn test_handle_slack_event_forwards_thread_ts_as_reply_to() {
+        let mut filter = SlackEventFilter::new(None, false);
+        let mut writer: Vec<u8> = Vec::new();
+        let mut event = slack_event("message", "C1", "and then?", "9.5");
+        event.thread_ts = Some("9.1".to_string());
+        handle_slack_event(event, &mut filter, &mut writer).await.unwrap();
+        let forwarded: ProtocolEvent = serde_json::from_slice(writer.trim_ascii_end()).unwrap();
+        assert!(matches!(forwarded, ProtocolEvent::Prompt { reply_to: Some(ts), .. } if ts == "9.1"));
+    }
+
+    #[test]
+    fn test_classify_slack_api_response_success() {
+        let outcome = classify_slack_api_response(
+            "chat.postMessage",
+            reqwest::StatusCode::OK,
+            None,
+            r#"{"ok":true,"ts":"1.0"}"#,
+        );
+        assert!(matches!(outcome, SlackApiOutcome::Success(v) if v["ts"] == "1.0"));
+    }
+
+    #[test]
+    fn test_classify_slack_api_response_retries_429_with_retry_after() {
+        let outcome = classify_slack_api_response(
+            "chat.postMessage",
+            reqwest::StatusCode::TOO_MANY_REQUESTS,
+            Some("7"),
+            "",
+        );
+        assert!(matches!(outcome, SlackApiOutcome::RetryAfter(d) if d == Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn test_classify_slack_api_response_reports_ok_false_error() {
+        let outcome = classify_slack_api_response(
+            "chat.postMessage",
+            reqwest::StatusCode::OK,
+            None,
+            r#"{"ok":false,"error":"not_in_channel"}"#,
+        );
+        assert!(matches!(outcome, SlackApiOutcome::Failed(msg) if msg.contains("not_in_channel")));
+    }
+
+    #[test]
+    fn test_classify_slack_api_response_reports_non_json_body() {
+        let outcome = classify_slack_api_response(
+            "chat.postMessage",
+            reqwest::StatusCode::BAD_GATEWAY,
+            None,
+            "upstream error",
+        );
+        assert!(matches!(outcome, SlackApiOutcome::Failed(msg) if msg.contains("HTTP 502")));
+    }
+
+    #[test]
+    fn test_parse_allowed_slack_ids_trims_and_dedups() {
+        let ids = parse_allowed_slack_ids(" U1 , , U2,U1 ");
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains("U1"));
+        assert!(ids.contains("U2"));
+    }
+
+    #[test]
+    fn test_should_forward_slack_message_applies_user_allowlist() {
+        let event = slack_event("message", "C1", "hello", "1.0");
+        let allowed = parse_allowed_slack_ids("U1,U9");
+        let other = parse_allowed_slack_ids("U2");
+        assert!(should_forward_slack_message(&event, Some(&allowed), None));
+        assert!(!should_forward_slack_message(&event, Some(&other), None));
+        assert!(should_forward_slack_message(&event, None, None));
+    }
+
+    #[test]
+    fn test_should_forward_slack_message_applies_channel_allowlist() {
+        let event = slack_event("message", "C1", "hello", "1.0");
+        let allowed = parse_allowed_slack_ids("C1");
+        let other = parse_allowed_slack_ids("C2");
+        assert!(should_forward_slack_message(&event, None, Some(&allowed)));
+        assert!(!should_forward_slack_message(&event, None, Some(&other)));
+    }
+
+    #[tokio::test]
+    async fn test_handle_slack_event_logs_rejected_user_once_and_forwards_nothing() {
+        let mut filter = SlackEventFilter::new(None, false);
+        filter.allowed_user_ids = Some(parse_allowed_slack_ids("U2"));
+        let mut writer: Vec<u8> = Vec::new();
+
+        handle_slack_event(slack_event("message", "C1", "a", "1.0"), &mut filter, &mut writer).await.unwrap();
+        handle_slack_event(slack_event("message", "C1", "b", "2.0"), &mut filter, &mut writer).await.unwrap();
+
+        assert!(writer.is_empty());
+        assert_eq!(filter.rejected_users.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_handle_slack_event_forwards_listed_user_when_allowlist_enabled() {
+        let mut filter = SlackEventFilter::new(None, false);
+        filter.allowed_user_ids = Some(parse_allowed_slack_ids("U1,U2"));
+        let mut writer: Vec<u8> = Vec::new();
+
+        let forwarded = handle_slack_event(slack_event("message", "C1", "hello", "1.0"), &mut filter, &mut writer)
+            .await
+            .unwrap();
+
+        assert_eq!(forwarded, Some(("slack:U1:C1".to_string(), Some("1.0".to_string()))));
+        assert!(String::from_utf8(writer).unwrap().contains("hello"));
+        assert!(filter.rejected_users.is_empty());
+    }
+
+    #[test]
+    fn test_slack_channel_id_from_bridge_channel() {
+        assert_eq!(slack_channel_id_from_bridge_channel("slack:U1:C1"), Some("C1"));
+        assert_eq!(slack_channel_id_from_bridge_channel("slack:dm:U1:D1"), Some("D1"));
+        assert_eq!(slack_channel_id_from_bridge_channel("slack:U1:"), None);
+        assert_eq!(slack_channel_id_from_bridge_channel("discord:1:2"), None);
+    }
+
+    #[test]
+    fn test_strip_slack_mention_removes_only_bot_token() {
+        assert_eq!(strip_slack_mention("<@UBOT> hello", Some("UBOT")), "hello");
+        assert_eq!(strip_slack_mention("hi <@UBOT|yui> there", Some("UBOT")), "hi there");
+        assert_eq!(strip_slack_mention("<@UBOT> ping <@U2>", Some("UBOT")), "ping <@U2>");
+        assert_eq!(strip_slack_mention("<@UBOT> hello", None), "hello");
+        assert_eq!(strip_slack_mention("ask <@U2>", None), "ask <@U2>");
+    }
+
+    #[test]
+    fn test_filter_require_mention_drops_unmentioned_channel_messages() {
+        let mut filter = SlackEventFilter::new(Some("UBOT".into()), true);
+        assert_eq!(filter.prompt_text(&slack_event("message", "C1", "hello", "1.0")), None);
+        assert_eq!(
+            filter.prompt_text(&slack_event("message", "C1", "<@UBOT> hello", "2.0")),
+            Some("hello".to_string())
+        );
+    }
+
+    #[test]
+    fn test_filter_dm_bypasses_mention_requirement() {
+        let mut filter = SlackEventFilter::new(Some("UBOT".into()), true);
+        assert_eq!(
+            filter.prompt_text(&slack_event("message", "D123", "hello", "1.0")),
+            Some("hello".to_string())
+        );
+    }
+
+    #[test]
+    fn test_filter_app_mention_is_stripped_and_deduplicated_with_message() {
+        let mut filter = SlackEventFilter::new(Some("UBOT".into()), false);
+        assert_eq!(
+            filter.prompt_text(&slack_event("app_mention", "C1", "<@UBOT> status?", "5.0")),
+            Some("status?".to_string())
+        );
+        assert_eq!(
+            filter.prompt_text(&slack_event("message", "C1", "<@UBOT> status?", "5.0")),
+            None,
+            "the same post delivered as a message event must not be forwarded twice"
+        );
+    }
+
+    #[test]
+    fn test_filter_skips_own_messages() {
+        let mut filter = SlackEventFilter::new(Some("U1".into()), false);
+        assert_eq!(filter.prompt_text(&slack_event("message", "C1", "echo", "1.0")), None);
+    }
+
+    #[test]
+    fn test_markdown_to_mrkdwn_converts_bold_links_and_headings() {
+        let md = "## Result\nThis is **important** and ~~old~~.\nSee [docs](https://example.com).\n- item";
+        let out = markdown_to_mrkdwn(md);
+        assert_eq!(
+            out,
+            "*Result*\nThis is *important* and ~old~.\nSee <https://example.com|docs>.\n• item"
+        );
+    }
+
+    #[test]
+    fn test_markdown_link_label_stops_at_brackets() {
+        assert_eq!(markdown_to_mrkdwn("see [a] and [b](https://x)"), "see [a] and <https://x|b>");
+        assert_eq!(markdown_to_mrkdwn("[a](https://x) [b](https://y)"), "<https://x|a> <https://y|b>");
+    }
+
+    #[test]
+    fn test_markdown_to_mrkdwn_preserves_code() {
+        let md = "Use `**raw**` here\n```rust\nlet x = **y**;\n```";
+        let out = markdown_to_mrkdwn(md);
+        assert_eq!(out, "Use `**raw**` here\n```\nlet x = **y**;\n```");
+    }
+
+    #[test]
+    fn test_markdown_to_mrkdwn_keeps_unmatched_delimiters() {
+        assert_eq!(markdown_to_mrkdwn("a ** b"), "a ** b");
+        assert_eq!(markdown_to_mrkdwn("[not a link]"), "[not a link]");
+    }
+
+    #[test]
+    fn test_should_upload_slack_snippet_uses_char_threshold() {
+        let short = "あ".repeat(3000);
+        let long = "あ".repeat(3001);
+        assert!(!should_upload_slack_snippet(&short, 3000));
+        assert!(should_upload_slack_snippet(&long, 3000));
+    }
+
+    #[test]
+    fn test_should_upload_slack_snippet_detects_large_code_blocks() {
+        let small = format!("intro\n```\n{}\n```", ["x"; 5].join("\n"));
+        let large = format!("intro\n```\n{}\n```", vec!["x"; 40].join("\n"));
+        assert!(!should_upload_slack_snippet(&small, 3000));
+        assert!(should_upload_slack_snippet(&large, 3000));
+    }
+
+    #[test]
+    fn test_summarize_for_slack_takes_first_paragraph() {
+        assert_eq!(summarize_for_slack("first part\n\nsecond part"), "first part");
+        let long = "a".repeat(500);
+        let summary = summarize_for_slack(&long);
+        assert_eq!(summary.chars().count(), 300);
+        assert!(summary.ends_with('…'));
+    }
+
+    #[test]
+    fn test_slack_reconnect_delay_doubles_and_caps() {
+        assert_eq!(slack_reconnect_delay(1), Duration::from_secs(1));
+        assert_eq!(slack_reconnect_delay(2), Duration::from_secs(2));
+        assert_eq!(slack_reconnect_delay(3), Duration::from_secs(4));
+        assert_eq!(slack_reconnect_delay(5), Duration::from_secs(16));
+        assert_eq!(slack_reconnect_delay(6), Duration::from_secs(30));
+        assert_eq!(slack_reconnect_delay(40), Duration::from_secs(30));
+    }
+
     #[test]
     fn test_should_retry_open_socket_mode_reqwest_error_ignores_non_timeout() {
         let msg = r#"reqwest::Error { kind: Decode, source: serde_json::Error(\"expected value\") }"#;
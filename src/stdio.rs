@@ -0,0 +1,333 @@
+/**
+ * stdin/stdout JSON-RPC adapter for editor integrations.
+ *
+ * `acomm adapter stdio` lets a Neovim / VS Code plugin drive acomm as a child
+ * process: one JSON-RPC 2.0 message per line on stdin, responses and notifications
+ * one per line on stdout (logs go to stderr).
+ *
+ * Methods:
+ *   prompt {text, channel?, provider?} → {requestId, channel}
+ *   switchProvider {provider}          → {provider}
+ *   status {}                          → {status, provider, model}
+ *
+ * Notifications for a prompt carry its `requestId`: `agentChunk {requestId, chunk}`,
+ * `systemMessage {requestId, message}`, `error {requestId, message}` and finally
+ * `agentDone {requestId}` (with `footer: "provider:model"` when ACOMM_REPLY_FOOTER=1
+ * and the bridge named the agent that answered). Every call runs on its own bridge channel
+ * (`<channel or "stdio">:<requestId>`), which is how concurrent prompts are told
+ * apart. The adapter exits when stdin closes.
+ */
+use crate::format_profile::{reply_footer, reply_footer_from_env};
+use crate::protocol::{AGENT_FAILURE_PREFIX, ActiveAgent, ProtocolEvent, provider_from_command_name};
+use acomm::client::DEFAULT_SOCKET_PATH;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::error::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tracing::info;
+
+const DEFAULT_CHANNEL_PREFIX: &str = "stdio";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// What a bridge-bound call is waiting for before it can be answered.
+#[derive(Debug, Clone, PartialEq)]
+enum PendingKind {
+    Prompt,
+    SwitchProvider(String),
+    Status,
+}
+
+#[derive(Debug, Clone)]
+struct Pending {
+    rpc_id: Value,
+    request_id: String,
+    kind: PendingKind,
+}
+
+/// Correlates JSON-RPC calls with bridge events by channel.
+#[derive(Debug, Default)]
+struct StdioSession {
+    next_id: u64,
+    /// Calls waiting for their `PromptAck` (or `/status` reply), keyed by channel.
+    pending: HashMap<String, Pending>,
+    /// Prompts whose output may still arrive: channel → requestId.
+    streams: HashMap<String, String>,
+    active: ActiveAgent,
+    /// Name the answering agent in `agentDone` (`ACOMM_REPLY_FOOTER`).
+    footer: bool,
+}
+
+impl StdioSession {
+    /// Handle one line from stdin. Returns messages for stdout and, for calls that need
+    /// the bridge, the event to send to it.
+    fn handle_request(&mut self, line: &str) -> (Vec<Value>, Option<ProtocolEvent>) {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return (vec![rpc_error(Value::Null, PARSE_ERROR, &format!("parse error: {}", e))], None),
+        };
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let Some(method) = request["method"].as_str() else {
+            return (vec![rpc_error(id, INVALID_REQUEST, "missing method")], None);
+        };
+        let params = &request["params"];
+        let (prefix, text, provider, kind) = match method {
+            "prompt" => {
+                let Some(text) = params["text"].as_str().filter(|t| !t.trim().is_empty()) else {
+                    return (vec![rpc_error(id, INVALID_PARAMS, "params.text is required")], None);
+                };
+                let provider = match params["provider"].as_str() {
+                    None => None,
+                    Some(name) => match provider_from_command_name(name) {
+                        Some(provider) => Some(provider),
+                        None => return (vec![rpc_error(id, INVALID_PARAMS, &format!("unknown provider '{}'", name))], None),
+                    },
+                };
+                (params["channel"].as_str(), text.to_string(), provider, PendingKind::Prompt)
+            }
+            "switchProvider" => {
+                let Some(provider) = params["provider"].as_str().and_then(provider_from_command_name) else {
+                    return (vec![rpc_error(id, INVALID_PARAMS, "params.provider must name a known provider")], None);
+                };
+                let name = provider.command_name().to_string();
+                (None, format!("/provider {}", name), None, PendingKind::SwitchProvider(name))
+            }
+            "status" => (None, "/status".to_string(), None, PendingKind::Status),
+            other => return (vec![rpc_error(id, METHOD_NOT_FOUND, &format!("unknown method '{}'", other))], None),
+        };
+        self.next_id += 1;
+        let request_id = self.next_id.to_string();
+        let prefix = prefix.map(str::trim).filter(|p| !p.is_empty()).unwrap_or(DEFAULT_CHANNEL_PREFIX);
+        let channel = format!("{}:{}", prefix, request_id);
+        if kind == PendingKind::Prompt {
+            // Stream from the start: output can arrive before the bridge's ack.
+            self.streams.insert(channel.clone(), request_id.clone());
+        }
+        self.pending.insert(channel.clone(), Pending { rpc_id: id, request_id, kind });
+        (Vec::new(), Some(ProtocolEvent::Prompt { text, provider, channel: Some(channel), reply_to: None, priority: false, author: None }))
+    }
+
+    /// Turn one bridge event into responses / notifications for stdout.
+    fn handle_event(&mut self, event: &ProtocolEvent) -> Vec<Value> {
+        self.active.observe(event);
+        let Some(channel) = event.clone_channel() else { return Vec::new() };
+        match event {
+            ProtocolEvent::PromptAck { .. } => {
+                let Some(pending) = self.pending.get(&channel).cloned() else { return Vec::new() };
+                match pending.kind {
+                    PendingKind::Prompt => {
+                        self.pending.remove(&channel);
+                        vec![rpc_result(pending.rpc_id, json!({ "requestId": pending.request_id, "channel": channel }))]
+                    }
+                    PendingKind::SwitchProvider(name) => {
+                        self.pending.remove(&channel);
+                        vec![rpc_result(pending.rpc_id, json!({ "provider": name }))]
+                    }
+                    // Answered by the bridge's `/status` reply.
+                    PendingKind::Status => Vec::new(),
+                }
+            }
+            ProtocolEvent::SystemMessage { msg, .. } => {
+                if self.pending.get(&channel).is_some_and(|p| p.kind == PendingKind::Status) {
+                    let pending = self.pending.remove(&channel).expect("checked above");
+                    return vec![rpc_result(
+                        pending.rpc_id,
+                        json!({ "status": msg, "provider": self.active.provider, "model": self.active.model }),
+                    )];
+                }
+                let Some(request_id) = self.streams.get(&channel) else { return Vec::new() };
+                let method = if msg.starts_with(AGENT_FAILURE_PREFIX) { "error" } else { "systemMessage" };
+                vec![notification(method, json!({ "requestId": request_id, "message": msg }))]
+            }
+            ProtocolEvent::AgentChunk { chunk, .. } => match self.streams.get(&channel) {
+                Some(request_id) => vec![notification("agentChunk", json!({ "requestId": request_id, "chunk": chunk }))],
+                None => Vec::new(),
+            },
+            ProtocolEvent::AgentDone { .. } => match self.streams.remove(&channel) {
+                Some(request_id) => {
+                    let answered = self.active.answered(event);
+                    let footer = reply_footer(&answered.provider, &answered.model);
+                    let params = if self.footer && !footer.is_empty() {
+                        json!({ "requestId": request_id, "footer": footer })
+                    } else {
+                        json!({ "requestId": request_id })
+                    };
+                    vec![notification("agentDone", params)]
+                }
+                None => Vec::new(),
+            },
+            ProtocolEvent::ChannelClosed { reason, .. } => match self.streams.remove(&channel) {
+                Some(request_id) => vec![
+                    notification("error", json!({ "requestId": request_id, "message": reason })),
+                    notification("agentDone", json!({ "requestId": request_id })),
+                ],
+                None => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn rpc_result(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+fn rpc_error(id: Value, code: i64, message: &str) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+pub async fn start_stdio_adapter() -> Result<(), Box<dyn Error>> {
+    let stream = UnixStream::connect(DEFAULT_SOCKET_PATH).await.map_err(|e| {
+        format!("Bridge is not running. Please start it with 'acomm --bridge'. Error: {}", e)
+    })?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut bridge_lines = BufReader::new(reader).lines();
+    let mut stdin_lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    let mut session = StdioSession { footer: reply_footer_from_env(), ..Default::default() };
+    // Events replayed during the initial sync belong to earlier clients.
+    let mut synced = false;
+    info!("stdio adapter ready");
+
+    loop {
+        let messages = tokio::select! {
+            line = stdin_lines.next_line() => {
+                let Some(line) = line? else { break };
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let (messages, event) = session.handle_request(&line);
+                if let Some(event) = event {
+                    writer.write_all(format!("{}\n", serde_json::to_string(&event)?).as_bytes()).await?;
+                }
+                messages
+            }
+            line = bridge_lines.next_line() => {
+                let Some(line) = line? else {
+                    return Err("Bridge closed the connection".into());
+                };
+                let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) else { continue };
+                if matches!(event, ProtocolEvent::BridgeSyncDone {}) {
+                    synced = true;
+                }
+                let messages = session.handle_event(&event);
+                if synced { messages } else { Vec::new() }
+            }
+        };
+        for message in messages {
+            stdout.write_all(format!("{}\n", message).as_bytes()).await?;
+        }
+        stdout.flush().await?;
+    }
+    info!("stdin closed; stdio adapter exiting");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use acore::AgentProvider;
+
+    fn sent_channel(event: &Option<ProtocolEvent>) -> String {
+        event.as_ref().and_then(ProtocolEvent::clone_channel).expect("a bridge event")
+    }
+
+    fn ack(channel: &str) -> ProtocolEvent {
+        ProtocolEvent::PromptAck { channel: Some(channel.into()), seq: 1 }
+    }
+
+    #[test]
+    fn concurrent_prompts_are_correlated_by_request_id() {
+        let mut session = StdioSession::default();
+        let (out, first) = session.handle_request(r#"{"jsonrpc":"2.0","id":1,"method":"prompt","params":{"text":"a"}}"#);
+        assert!(out.is_empty());
+        let (_, second) = session.handle_request(
+            r#"{"jsonrpc":"2.0","id":"b","method":"prompt","params":{"text":"b","channel":"nvim","provider":"claude"}}"#,
+        );
+        let (first, second_event) = (sent_channel(&first), second.clone());
+        let second = sent_channel(&second);
+        assert_eq!(first, "stdio:1");
+        assert_eq!(second, "nvim:2");
+        assert!(matches!(second_event, Some(ProtocolEvent::Prompt { provider: Some(AgentProvider::Claude), .. })));
+
+        assert_eq!(
+            session.handle_event(&ack(&second)),
+            vec![json!({"jsonrpc":"2.0","id":"b","result":{"requestId":"2","channel":"nvim:2"}})]
+        );
+        assert_eq!(session.handle_event(&ack(&first))[0]["result"]["requestId"], "1");
+
+        let chunk = ProtocolEvent::AgentChunk { chunk: "x".into(), channel: Some(first.clone()) };
+        assert_eq!(
+            session.handle_event(&chunk),
+            vec![json!({"jsonrpc":"2.0","method":"agentChunk","params":{"requestId":"1","chunk":"x"}})]
+        );
+        let failure = ProtocolEvent::SystemMessage { msg: format!("{}: boom", AGENT_FAILURE_PREFIX), channel: Some(second.clone()) };
+        assert_eq!(session.handle_event(&failure)[0]["method"], "error");
+        let done = session.handle_event(&ProtocolEvent::AgentDone { channel: Some(second.clone()), provider: None, model: None });
+        assert_eq!(done, vec![json!({"jsonrpc":"2.0","method":"agentDone","params":{"requestId":"2"}})]);
+        // Other clients' channels are not ours.
+        assert!(session.handle_event(&ProtocolEvent::AgentDone { channel: Some("tui".into()), provider: None, model: None }).is_empty());
+    }
+
+    #[test]
+    fn agent_done_names_the_answering_agent_when_the_footer_is_on() {
+        let mut session = StdioSession { footer: true, ..Default::default() };
+        let (_, event) = session.handle_request(r#"{"jsonrpc":"2.0","id":1,"method":"prompt","params":{"text":"a"}}"#);
+        let channel = sent_channel(&event);
+        session.handle_event(&ack(&channel));
+        let done = ProtocolEvent::AgentDone {
+            channel: Some(channel),
+            provider: Some(AgentProvider::Claude),
+            model: Some("claude-opus-4-6".into()),
+        };
+        assert_eq!(
+            session.handle_event(&done),
+            vec![json!({"jsonrpc":"2.0","method":"agentDone","params":{"requestId":"1","footer":"claude:claude-opus-4-6"}})]
+        );
+    }
+
+    #[test]
+    fn switch_provider_and_status_map_to_bridge_commands() {
+        let mut session = StdioSession::default();
+        let (_, event) = session.handle_request(r#"{"jsonrpc":"2.0","id":7,"method":"switchProvider","params":{"provider":"Codex"}}"#);
+        assert!(matches!(&event, Some(ProtocolEvent::Prompt { text, .. }) if text == "/provider codex"));
+        let result = session.handle_event(&ack(&sent_channel(&event)));
+        assert_eq!(result, vec![json!({"jsonrpc":"2.0","id":7,"result":{"provider":"codex"}})]);
+
+        session.handle_event(&ProtocolEvent::ProviderSwitched { provider: AgentProvider::Codex });
+        let (_, event) = session.handle_request(r#"{"jsonrpc":"2.0","id":8,"method":"status"}"#);
+        let channel = sent_channel(&event);
+        assert!(session.handle_event(&ack(&channel)).is_empty());
+        let reply = ProtocolEvent::SystemMessage { msg: "Provider: codex / Model: gpt-5.3-codex".into(), channel: Some(channel) };
+        let result = session.handle_event(&reply);
+        assert_eq!(result[0]["id"], 8);
+        assert_eq!(result[0]["result"]["provider"], "codex");
+        assert_eq!(result[0]["result"]["status"], "Provider: codex / Model: gpt-5.3-codex");
+    }
+
+    #[test]
+    fn malformed_requests_get_json_rpc_errors() {
+        let mut session = StdioSession::default();
+        let code = |session: &mut StdioSession, line: &str| {
+            let (out, event) = session.handle_request(line);
+            assert!(event.is_none());
+            out[0]["error"]["code"].as_i64().unwrap()
+        };
+        assert_eq!(code(&mut session, "{not json"), PARSE_ERROR);
+        assert_eq!(code(&mut session, r#"{"jsonrpc":"2.0","id":1}"#), INVALID_REQUEST);
+        assert_eq!(code(&mut session, r#"{"jsonrpc":"2.0","id":1,"method":"nope"}"#), METHOD_NOT_FOUND);
+        assert_eq!(code(&mut session, r#"{"jsonrpc":"2.0","id":1,"method":"prompt","params":{}}"#), INVALID_PARAMS);
+        assert_eq!(
+            code(&mut session, r#"{"jsonrpc":"2.0","id":1,"method":"switchProvider","params":{"provider":"gpt"}}"#),
+            INVALID_PARAMS
+        );
+    }
+}
@@ -0,0 +1,245 @@
+/**
+ * Prompt templates kept by the bridge.
+ *
+ * `/template save <name> <text>` stores a reusable prompt prefix, and a prompt written
+ * as `@name rest of the question` is expanded to the template text followed by the
+ * question before it runs. Templates live in `~/.config/acomm/templates.toml`, one
+ * `name = "text"` entry per line. Only that flat subset of TOML is read (basic and
+ * literal single-line strings, `#` comments), which is also all the bridge writes, so
+ * the file stays hand-editable without pulling in a TOML parser.
+ */
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+const TEMPLATES_FILE: &str = "templates.toml";
+const TEMPLATES_HEADER: &str = "# acomm prompt templates; use `@name` at the start of a prompt.\n";
+
+/// Location of the templates file (`~/.config/acomm/templates.toml`).
+pub fn templates_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|p| p.join("acomm").join(TEMPLATES_FILE))
+}
+
+/// Template names are TOML bare keys: ASCII letters, digits, `-` and `_`.
+pub fn is_template_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Parse the templates file, plus one warning per line that was skipped.
+pub fn parse_templates(content: &str) -> (BTreeMap<String, String>, Vec<String>) {
+    let mut templates = BTreeMap::new();
+    let mut warnings = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line.split_once('=').and_then(|(key, value)| {
+            let key = key.trim();
+            is_template_name(key).then_some(key).zip(parse_string_value(value.trim()))
+        });
+        match entry {
+            Some((name, text)) => {
+                templates.insert(name.to_string(), text);
+            }
+            None => warnings.push(format!("{}: line {}: expected name = \"text\"", TEMPLATES_FILE, index + 1)),
+        }
+    }
+    (templates, warnings)
+}
+
+/// A single-line basic (`"…"`, with escapes) or literal (`'…'`) string, optionally
+/// followed by a comment.
+fn parse_string_value(raw: &str) -> Option<String> {
+    let (text, rest) = if let Some(body) = raw.strip_prefix('\'') {
+        let end = body.find('\'')?;
+        (body[..end].to_string(), &body[end + 1..])
+    } else {
+        let body = raw.strip_prefix('"')?;
+        let mut text = String::new();
+        let mut chars = body.char_indices();
+        let end = loop {
+            let (i, c) = chars.next()?;
+            match c {
+                '"' => break i,
+                '\\' => match chars.next()?.1 {
+                    'n' => text.push('\n'),
+                    't' => text.push('\t'),
+                    'r' => text.push('\r'),
+                    '"' => text.push('"'),
+                    '\\' => text.push('\\'),
+                    'u' => {
+                        let hex: String = (0..4).filter_map(|_| chars.next().map(|(_, c)| c)).collect();
+                        text.push(u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)?);
+                    }
+                    _ => return None,
+                },
+                c => text.push(c),
+            }
+        };
+        (text, &body[end + 1..])
+    };
+    let rest = rest.trim();
+    (rest.is_empty() || rest.starts_with('#')).then_some(text)
+}
+
+/// Render templates in the format `parse_templates` reads.
+pub fn render_templates(templates: &BTreeMap<String, String>) -> String {
+    let mut out = String::from(TEMPLATES_HEADER);
+    for (name, text) in templates {
+        out.push_str(name);
+        out.push_str(" = \"");
+        for c in text.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\t' => out.push_str("\\t"),
+                '\r' => out.push_str("\\r"),
+                c if c.is_control() => out.push_str(&format!("\\u{:04X}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push_str("\"\n");
+    }
+    out
+}
+
+/// The bridge's templates and where they are persisted (`None` keeps them in memory).
+#[derive(Debug, Default)]
+pub struct TemplateStore {
+    path: Option<PathBuf>,
+    templates: BTreeMap<String, String>,
+}
+
+impl TemplateStore {
+    /// Load the templates at `path`; a missing file means no templates yet.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let content = path.as_deref().and_then(|p| std::fs::read_to_string(p).ok()).unwrap_or_default();
+        let (templates, warnings) = parse_templates(&content);
+        for warning in warnings {
+            warn!("{}", warning);
+        }
+        Self { path, templates }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.templates.keys().map(String::as_str).collect()
+    }
+
+    /// Add or replace a template and write the file.
+    pub fn save(&mut self, name: &str, text: &str) -> std::io::Result<()> {
+        self.templates.insert(name.to_string(), text.to_string());
+        self.persist()
+    }
+
+    /// Remove a template; `Ok(false)` if there was none by that name.
+    pub fn delete(&mut self, name: &str) -> std::io::Result<bool> {
+        if self.templates.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.persist().map(|()| true)
+    }
+
+    /// Expand a leading `@name` in `prompt`: `Ok(None)` when the prompt does not start
+    /// with a template reference, `Err` (listing the defined names) for an unknown one.
+    pub fn expand(&self, prompt: &str) -> Result<Option<String>, String> {
+        let Some(rest) = prompt.strip_prefix('@') else { return Ok(None) };
+        let (name, question) = rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+        if !is_template_name(name) {
+            return Ok(None);
+        }
+        let Some(template) = self.templates.get(name) else {
+            return Err(if self.templates.is_empty() {
+                format!("Unknown template '{}'. No templates are defined; use /template save <name> <text>.", name)
+            } else {
+                format!("Unknown template '{}'. Available templates: {}", name, self.names().join(", "))
+            });
+        };
+        let question = question.trim();
+        Ok(Some(if question.is_empty() { template.clone() } else { format!("{}\n\n{}", template, question) }))
+    }
+
+    fn persist(&self) -> std::io::Result<()> {
+        let Some(path) = self.path.as_deref() else { return Ok(()) };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_file(path, &render_templates(&self.templates))
+    }
+}
+
+fn write_file(path: &Path, content: &str) -> std::io::Result<()> {
+    let tmp = path.with_extension("toml.tmp");
+    std::fs::write(&tmp, content)?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(entries: &[(&str, &str)]) -> TemplateStore {
+        TemplateStore {
+            path: None,
+            templates: entries.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn templates_round_trip_through_the_file_format() {
+        let templates = BTreeMap::from([
+            ("ja".to_string(), "日本語で簡潔に答えて".to_string()),
+            ("review".to_string(), "You are reviewing a PR.\n\t- output a \"bullet\" list \\ nothing else".to_string()),
+            ("bell".to_string(), "ding\u{7}".to_string()),
+        ]);
+        let rendered = render_templates(&templates);
+        assert!(rendered.contains("ja = \"日本語で簡潔に答えて\"\n"));
+        assert_eq!(parse_templates(&rendered), (templates, vec![]));
+    }
+
+    #[test]
+    fn hand_written_files_are_read_and_bad_lines_reported() {
+        let content = "# mine\n\nshort = 'be brief'  # literal\nbad key = \"x\"\nunterminated = \"oops\nemoji = \"\\u2728 go\"\n";
+        let (templates, warnings) = parse_templates(content);
+        assert_eq!(templates.get("short").map(String::as_str), Some("be brief"));
+        assert_eq!(templates.get("emoji").map(String::as_str), Some("✨ go"));
+        assert_eq!(templates.len(), 2);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("line 4"));
+    }
+
+    #[test]
+    fn expansion_prepends_the_template_to_the_question() {
+        let store = store(&[("ja", "Answer in Japanese, concisely.")]);
+        assert_eq!(
+            store.expand("@ja what is a monad?"),
+            Ok(Some("Answer in Japanese, concisely.\n\nwhat is a monad?".to_string()))
+        );
+        assert_eq!(store.expand("@ja"), Ok(Some("Answer in Japanese, concisely.".to_string())));
+        assert_eq!(store.expand("plain prompt @ja"), Ok(None));
+        assert_eq!(store.expand("@ alone"), Ok(None));
+        assert_eq!(store.expand("@user@example.com hi"), Ok(None));
+    }
+
+    #[test]
+    fn unknown_template_lists_the_available_ones() {
+        let err = store(&[("ja", "x"), ("review", "y")]).expand("@jp hello").unwrap_err();
+        assert_eq!(err, "Unknown template 'jp'. Available templates: ja, review");
+        assert!(store(&[]).expand("@jp hello").unwrap_err().contains("No templates are defined"));
+    }
+
+    #[test]
+    fn saved_templates_are_written_to_disk() {
+        let dir = std::env::temp_dir().join(format!("acomm-templates-{}", std::process::id()));
+        let path = dir.join("acomm").join(TEMPLATES_FILE);
+        let mut store = TemplateStore::load(Some(path.clone()));
+        assert!(store.names().is_empty());
+        store.save("ja", "日本語で").unwrap();
+        store.save("en", "In English").unwrap();
+        assert!(store.delete("en").unwrap());
+        assert!(!store.delete("en").unwrap());
+        assert_eq!(TemplateStore::load(Some(path)).names(), vec!["ja"]);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
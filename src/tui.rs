@@ -1,17 +1,32 @@
-use crate::protocol::ProtocolEvent;
+use crate::format_profile::reply_footer;
+use crate::protocol::{FINAL_ANSWER_MARKER, ProtocolEvent, split_compare_channel};
+use acomm::client::EventWriter;
 use acore::AgentProvider;
 use crossterm::event::{self, KeyCode, KeyEventKind, KeyModifiers};
 use ratatui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Style},
-    widgets::{Block, Borders, Paragraph, Wrap},
+    text::Span,
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
-use std::{error::Error, fs, path::PathBuf};
+use std::{collections::{HashMap, HashSet}, error::Error, fs, path::PathBuf, time::{Duration, Instant}};
 use tokio::sync::mpsc;
-use tokio::io::AsyncWriteExt;
-use unicode_width::UnicodeWidthStr;
+use tokio::io::{AsyncRead, AsyncWrite};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// ブリッジが受け付けた自分のプロンプトの末尾に付ける印。
+const SENT_MARK: &str = "  sent ✓\n";
+
+/// 処理中の Ctrl+C から、この時間内にもう一度押されたら終了する。
+const QUIT_CONFIRM_WINDOW: Duration = Duration::from_secs(2);
+
+/// 入力欄の undo 履歴に残すスナップショットの上限。
+const UNDO_LIMIT: usize = 100;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EditKind { Insert, Delete, Other }
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum InputMode { Normal, Editing }
@@ -22,6 +37,9 @@ pub struct InputState {
     pub history: Vec<String>,
     pub history_index: Option<usize>,
     pub kill_buffer: String,
+    undo_stack: Vec<(String, usize)>,
+    redo_stack: Vec<(String, usize)>,
+    last_edit: Option<EditKind>,
 }
 
 impl InputState {
@@ -40,10 +58,52 @@ impl InputState {
             history,
             history_index: None,
             kill_buffer: String::new(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_edit: None,
+        }
+    }
+
+    /// 編集の直前に呼び、現在の (text, cursor_position) を undo 履歴に積む。
+    /// 連続した1文字入力・削除は1ステップにまとめ、空白で区切りを入れる。
+    fn snapshot(&mut self, kind: EditKind, word_break: bool) {
+        let coalesce = kind != EditKind::Other && self.last_edit == Some(kind) && !word_break;
+        if !coalesce {
+            self.undo_stack.push((self.text.clone(), self.cursor_position));
+            if self.undo_stack.len() > UNDO_LIMIT {
+                self.undo_stack.remove(0);
+            }
         }
+        self.redo_stack.clear();
+        self.last_edit = Some(kind);
     }
 
-    fn history_path() -> Option<PathBuf> {
+    pub fn undo(&mut self) {
+        if let Some((text, cursor)) = self.undo_stack.pop() {
+            let current = (std::mem::replace(&mut self.text, text), self.cursor_position);
+            self.redo_stack.push(current);
+            self.cursor_position = cursor;
+        }
+        self.last_edit = None;
+    }
+
+    pub fn redo(&mut self) {
+        if let Some((text, cursor)) = self.redo_stack.pop() {
+            let current = (std::mem::replace(&mut self.text, text), self.cursor_position);
+            self.undo_stack.push(current);
+            self.cursor_position = cursor;
+        }
+        self.last_edit = None;
+    }
+
+    /// 入力内容を丸ごと置き換えたとき (送信・履歴移動) は undo 履歴を捨てる。
+    fn clear_undo(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.last_edit = None;
+    }
+
+    pub(crate) fn history_path() -> Option<PathBuf> {
         dirs::cache_dir().map(|mut p| {
             p.push("acomm");
             p.push("history.txt");
@@ -62,10 +122,12 @@ impl InputState {
     }
 
     pub fn move_cursor_left(&mut self) {
+        self.last_edit = None;
         self.cursor_position = self.cursor_position.saturating_sub(1);
     }
 
     pub fn move_cursor_right(&mut self) {
+        self.last_edit = None;
         let count = self.text.chars().count();
         if self.cursor_position < count {
             self.cursor_position += 1;
@@ -103,6 +165,7 @@ impl InputState {
     }
 
     pub fn enter_char(&mut self, new_char: char) {
+        self.snapshot(EditKind::Insert, new_char.is_whitespace());
         let idx = self.byte_index();
         self.text.insert(idx, new_char);
         self.cursor_position += 1;
@@ -118,19 +181,24 @@ impl InputState {
 
     pub fn delete_char(&mut self) {
         if self.cursor_position != 0 {
-            self.move_cursor_left();
+            let prev = self.text.chars().nth(self.cursor_position - 1).unwrap_or(' ');
+            self.snapshot(EditKind::Delete, prev.is_whitespace());
+            self.cursor_position -= 1;
             let idx = self.byte_index();
             self.text.remove(idx);
         }
     }
 
     pub fn kill_line(&mut self) {
+        self.snapshot(EditKind::Other, true);
         let idx = self.byte_index();
         self.kill_buffer = self.text.split_off(idx);
     }
 
     pub fn yank(&mut self) {
         let yank_text = self.kill_buffer.clone();
+        if yank_text.is_empty() { return; }
+        self.snapshot(EditKind::Other, true);
         let idx = self.byte_index();
         self.text.insert_str(idx, &yank_text);
         self.cursor_position += yank_text.chars().count();
@@ -147,6 +215,7 @@ impl InputState {
         self.text.clear();
         self.cursor_position = 0;
         self.history_index = None;
+        self.clear_undo();
         res
     }
 
@@ -159,6 +228,7 @@ impl InputState {
         self.history_index = Some(new_idx);
         self.text = self.history[new_idx].clone();
         self.cursor_position = self.text.chars().count();
+        self.clear_undo();
     }
 
     pub fn history_down(&mut self) {
@@ -172,6 +242,7 @@ impl InputState {
             self.text.clear();
         }
         self.cursor_position = self.text.chars().count();
+        self.clear_undo();
     }
 
     pub fn get_lines(&self) -> Vec<String> {
@@ -197,6 +268,105 @@ pub struct App {
     pub auto_scroll: bool,
     pub channel: String,
     pub spinner_idx: usize,
+    /// Tab 補完の候補。次のキー入力で消える一時表示。
+    pub completions: Vec<String>,
+    /// 直近の描画時のチャット欄の内側のサイズ（折り返し後の行数計算に使う）。
+    pub chat_width: u16,
+    pub chat_height: u16,
+    /// Normal モードで `g` を1回押した状態（続けて `g` で先頭へ）。
+    pub pending_g: bool,
+    /// エコーされた Prompt より先に届いた PromptAck の数。
+    pub pending_acks: usize,
+    /// 入力がこの文字数を超えたら Input 欄の文字数表示を警告色にする（ACOMM_TUI_INPUT_WARN_CHARS）。
+    pub input_warn_chars: Option<usize>,
+    /// Ctrl+P のプロバイダー / モデル選択ポップアップ。開いている間はキー入力をここで処理する。
+    pub picker: Option<Picker>,
+    /// チャンネルごとの最新のエージェント出力行（messages の添字）。複数チャンネルのチャンクが
+    /// 交互に届いても、書きかけの行には同じチャンネルのチャンクだけを続ける。
+    pub chunk_lines: HashMap<String, usize>,
+    /// 返答の後に答えたプロバイダーとモデル（`reply_footer`）を1行表示する（ACOMM_REPLY_FOOTER）。
+    pub reply_footer: bool,
+    /// 処理中に Ctrl+C で実行中のプロンプトを止めた時刻（二度押しで終了する判定に使う）。
+    pub ctrl_c_at: Option<Instant>,
+    /// このチャンネル（と `/compare` の `<channel>#<provider>`）で実行中のもの。
+    /// `is_processing` は他のチャンネルの StatusUpdate でも変わるので、Ctrl+C はこちらを見る。
+    pub running_channels: HashSet<String>,
+}
+
+/// Ctrl+C を押したときにすること。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CtrlC {
+    /// TUI を終了する。
+    Quit,
+    /// 実行中のプロンプトを `/cancel` で止める。
+    Cancel,
+}
+
+/// Ctrl+P ポップアップの段階。プロバイダーを選ぶと、そのプロバイダーのモデル一覧に進む。
+#[derive(Debug, Clone, PartialEq)]
+pub enum PickerStage {
+    Provider,
+    Model(AgentProvider),
+}
+
+/// プロバイダー / モデル選択ポップアップの状態。一覧は `PROVIDER_NAMES` と `models_for_provider` から作る。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Picker {
+    pub stage: PickerStage,
+    pub selected: usize,
+}
+
+/// ポップアップで Enter を押した結果。
+#[derive(Debug, PartialEq)]
+pub enum PickerOutcome {
+    /// モデル一覧に進んだ（または何も選べなかった）ので開いたまま。
+    Stay,
+    /// 閉じて、このブリッジコマンドを順に送る。
+    Send(Vec<String>),
+}
+
+impl Picker {
+    /// いま使っているプロバイダーを選んだ状態で開く。
+    pub fn open(active: &AgentProvider) -> Self {
+        let selected = crate::bridge::PROVIDER_NAMES.iter().position(|name| *name == active.command_name()).unwrap_or(0);
+        Self { stage: PickerStage::Provider, selected }
+    }
+
+    pub fn items(&self) -> Vec<&'static str> {
+        match &self.stage {
+            PickerStage::Provider => crate::bridge::PROVIDER_NAMES.to_vec(),
+            PickerStage::Model(provider) => crate::protocol::models_for_provider(provider).to_vec(),
+        }
+    }
+
+    /// 選択を `delta` 行動かす。端で止まる。
+    pub fn move_by(&mut self, delta: isize) {
+        let last = self.items().len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// プロバイダーを選んだらモデル一覧へ進み、モデルを選んだら `/provider` と `/model` を送る。
+    /// 既知のモデルがないプロバイダー（OpenCode）は `/provider` だけで閉じる。
+    pub fn confirm(&mut self) -> PickerOutcome {
+        let Some(&choice) = self.items().get(self.selected) else { return PickerOutcome::Stay };
+        match &self.stage {
+            PickerStage::Provider => {
+                let Some(provider) = crate::protocol::provider_from_command_name(choice) else {
+                    return PickerOutcome::Stay;
+                };
+                if crate::protocol::models_for_provider(&provider).is_empty() {
+                    return PickerOutcome::Send(vec![format!("/provider {}", choice)]);
+                }
+                self.stage = PickerStage::Model(provider);
+                self.selected = 0;
+                PickerOutcome::Stay
+            }
+            PickerStage::Model(provider) => PickerOutcome::Send(vec![
+                format!("/provider {}", provider.command_name()),
+                format!("/model {}", choice),
+            ]),
+        }
+    }
 }
 
 impl App {
@@ -210,39 +380,48 @@ impl App {
             }
             ProtocolEvent::Prompt { text, channel, .. } => {
                 let channel_name = channel.unwrap_or_else(|| "unknown".into());
+                // `/compare` は各プロバイダーのチャンネル（`tui#gemini` など）でエコーされるので、元のチャンネルの 1 件として見出し付きで出す。
+                let (channel_name, header) = match split_compare_channel(&channel_name) {
+                    Some((base, _)) => (base.to_string(), "--- (Compare) ---\n"),
+                    None => (channel_name, "--- (Start) ---\n"),
+                };
                 let msg = format!("[user][{}] {}\n", channel_name, text);
                 if self.messages.last() != Some(&msg) {
-                    self.messages.push("--- (Start) ---\n".into());
+                    self.messages.push(header.into());
                     self.messages.push(msg);
                 }
+                if channel_name == self.channel && self.pending_acks > 0 && self.mark_prompt_sent() {
+                    self.pending_acks -= 1;
+                }
                 if self.auto_scroll { self.scroll_to_bottom(); }
             }
-            ProtocolEvent::AgentChunk { chunk, .. } => {
+            ProtocolEvent::AgentChunk { chunk, channel } => {
                 if chunk.is_empty() { return; }
-                let provider_prefix = format!("[{}] ", self.active_cli.command_name());
-                
+                // 比較中のストリームは、現在のプロバイダーではなくそのストリームのプロバイダー名を付ける。
+                let provider = match channel.as_deref().and_then(split_compare_channel) {
+                    Some((_, provider)) => provider,
+                    None => self.active_cli.clone(),
+                };
+                let provider_prefix = format!("[{}] ", provider.command_name());
+                let key = channel.unwrap_or_else(|| "unknown".into());
+
                 for line in chunk.split_inclusive('\n') {
-                    let mut pushed = false;
-                    if let Some(last) = self.messages.last_mut() {
-                        if last.starts_with(&provider_prefix) && !last.ends_with('\n') {
-                            last.push_str(line);
-                            pushed = true;
-                        }
-                    }
-                    if !pushed {
-                        let is_just_nl = line == "\n";
-                        let prev_is_just_prefix = self.messages.last().map_or(false, |m| m == &format!("{provider_prefix}\n"));
-                        if is_just_nl && prev_is_just_prefix {
-                            // Skip redundant
-                        } else {
+                    let last = self.chunk_lines.get(&key).copied();
+                    match last {
+                        Some(i) if !self.messages[i].ends_with('\n') => self.messages[i].push_str(line),
+                        // 空行だけのプレフィックス行が続くのは冗長なので飛ばす。
+                        Some(i) if line == "\n" && self.messages[i] == format!("{provider_prefix}\n") => {}
+                        _ => {
                             self.messages.push(format!("{provider_prefix}{line}"));
+                            self.chunk_lines.insert(key.clone(), self.messages.len() - 1);
                         }
                     }
                 }
                 if self.auto_scroll { self.scroll_to_bottom(); }
             }
-            ProtocolEvent::StatusUpdate { is_processing, .. } => { 
+            ProtocolEvent::StatusUpdate { is_processing, channel } => { 
                 self.is_processing = is_processing; 
+                self.track_running(channel, is_processing);
             }
             ProtocolEvent::ProviderSwitched { provider } => { 
                 self.active_cli = provider; 
@@ -251,16 +430,36 @@ impl App {
                 self.messages.push(format!("[System]: {}\n", msg)); 
                 if self.auto_scroll { self.scroll_to_bottom(); }
             }
-            ProtocolEvent::AgentDone { .. } => {
+            ProtocolEvent::AgentDone { channel, provider, model } => {
                 self.is_processing = false;
-                if let Some(last) = self.messages.last_mut() {
-                    if !last.ends_with('\n') { last.push('\n'); }
+                self.track_running(channel.clone(), false);
+                self.finish_chunk_line(channel);
+                if let Some(provider) = provider.filter(|_| self.reply_footer) {
+                    self.messages.push(format!("  -- {}\n", reply_footer(provider.command_name(), model.as_deref().unwrap_or(""))));
                 }
                 if self.auto_scroll { self.scroll_to_bottom(); }
             }
+            ProtocolEvent::ChannelClosed { channel, reason } => {
+                self.is_processing = false;
+                self.track_running(channel.clone(), false);
+                self.finish_chunk_line(channel);
+                self.messages.push(format!("[System]: {}\n", reason));
+                if self.auto_scroll { self.scroll_to_bottom(); }
+            }
+            ProtocolEvent::PromptAck { channel, .. } => {
+                // 自分の送信がブリッジに届いた印。エコーがまだなら届いた時に付ける。
+                if channel.as_deref() == Some(self.channel.as_str()) && !self.mark_prompt_sent() {
+                    self.pending_acks += 1;
+                }
+            }
             ProtocolEvent::BridgeSyncDone { .. } => {
                 // Internal bridge sync marker; no UI output.
             }
+            ProtocolEvent::Hello { version } => {
+                if let Some(msg) = crate::protocol::version_mismatch_message("bridge", version) {
+                    self.messages.push(format!("[System]: {}\n", msg));
+                }
+            }
             ProtocolEvent::ModelSwitched { model } => {
                 self.messages.push(format!("[Model switched → {}]\n", model));
                 if self.auto_scroll { self.scroll_to_bottom(); }
@@ -268,9 +467,113 @@ impl App {
         }
     }
 
+    /// このチャンネルの実行状態だけを `running_channels` に記録する。
+    fn track_running(&mut self, channel: Option<String>, running: bool) {
+        let Some(channel) = channel else { return };
+        let base = split_compare_channel(&channel).map_or(channel.as_str(), |(base, _)| base);
+        if base != self.channel {
+            return;
+        }
+        if running {
+            self.running_channels.insert(channel);
+        } else {
+            self.running_channels.remove(&channel);
+        }
+    }
+
+    /// そのチャンネルの書きかけのエージェント出力行を改行で閉じる。
+    fn finish_chunk_line(&mut self, channel: Option<String>) {
+        let key = channel.unwrap_or_else(|| "unknown".into());
+        if let Some(line) = self.chunk_lines.remove(&key).and_then(|i| self.messages.get_mut(i)) {
+            if !line.ends_with('\n') { line.push('\n'); }
+        }
+    }
+
+    /// チャット欄の本文。書きかけの行の後ろに別のメッセージが来ても同じ行に繋がらないよう、
+    /// 途中のメッセージは改行で区切る。最終回答の区切り（`FINAL_ANSWER_MARKER`）は表示しない。
+    pub fn chat_text(&self) -> String {
+        let mut text = String::new();
+        for (i, message) in self.messages.iter().enumerate() {
+            text.push_str(message);
+            if i + 1 < self.messages.len() && !message.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+        text.replace(FINAL_ANSWER_MARKER, "")
+    }
+
+    /// Ctrl+C: このチャンネルが待機中ならすぐ終了。処理中の最初の1回はプロンプトを止めるだけにして、
+    /// `QUIT_CONFIRM_WINDOW` 以内にもう一度押されたら終了する。
+    pub fn ctrl_c(&mut self, now: Instant) -> CtrlC {
+        if self.running_channels.is_empty() {
+            return CtrlC::Quit;
+        }
+        match self.ctrl_c_at.replace(now) {
+            Some(pressed) if now.duration_since(pressed) <= QUIT_CONFIRM_WINDOW => CtrlC::Quit,
+            _ => {
+                self.messages.push("[System]: Cancelling the running prompt; press Ctrl+C again to quit\n".into());
+                if self.auto_scroll { self.scroll_to_bottom(); }
+                CtrlC::Cancel
+            }
+        }
+    }
+
+    /// このチャンネルの最新の送信プロンプトに「sent ✓」を付ける。付けられなければ false。
+    fn mark_prompt_sent(&mut self) -> bool {
+        let prefix = format!("[user][{}] ", self.channel);
+        let Some(last) = self.messages.iter_mut().rev().find(|m| m.starts_with(&prefix)) else {
+            return false;
+        };
+        if last.ends_with(SENT_MARK) {
+            return false;
+        }
+        let text = last.trim_end_matches('\n').to_string();
+        *last = format!("{}{}", text, SENT_MARK);
+        true
+    }
+
+    /// Tab キー: 候補が1つなら確定し、複数なら共通接頭辞まで進めて候補を表示する。
+    pub fn complete_input(&mut self) {
+        let candidates = complete_command(&self.input.text);
+        let completed = match candidates.as_slice() {
+            [] => return,
+            [only] if !only.contains(' ') => format!("{only} "),
+            [only] => only.clone(),
+            _ => common_prefix(&candidates),
+        };
+        if completed.chars().count() > self.input.text.chars().count() {
+            self.input.snapshot(EditKind::Other, true);
+            self.input.text = completed;
+            self.input.cursor_position = self.input.text.chars().count();
+        }
+        if candidates.len() > 1 {
+            self.completions = candidates
+                .iter()
+                .map(|c| c.rsplit(' ').next().unwrap_or(c).to_string())
+                .collect();
+        }
+    }
+
+    /// 最下部を表示するときの scroll 値。折り返し後の総行数からチャット欄の高さを引いたもの。
+    pub fn max_scroll(&self) -> u16 {
+        let total = wrapped_line_count(&self.chat_text(), self.chat_width);
+        total.saturating_sub(self.chat_height as usize).min(u16::MAX as usize) as u16
+    }
+
     pub fn scroll_to_bottom(&mut self) {
-        let total_lines = self.messages.iter().map(|m| m.chars().filter(|&c| c == '\n').count()).sum::<usize>();
-        self.scroll = total_lines as u16;
+        self.scroll = self.max_scroll();
+    }
+
+    /// `G`: 最下部へ移動し、自動スクロールを再開する。
+    pub fn jump_to_bottom(&mut self) {
+        self.scroll_to_bottom();
+        self.auto_scroll = true;
+    }
+
+    /// `gg`: 先頭へ移動し、自動スクロールを止める。
+    pub fn jump_to_top(&mut self) {
+        self.scroll = 0;
+        self.auto_scroll = false;
     }
 }
 
@@ -281,10 +584,22 @@ pub enum AppEvent {
     Tick,
 }
 
-pub async fn run_tui_app<B: Backend, W: AsyncWriteExt + Unpin>(
+/// Ctrl+C を処理し、終了するなら true を返す。処理中ならこのチャンネルへ `/cancel` を送る。
+async fn handle_ctrl_c<S: AsyncRead + AsyncWrite + Unpin>(app: &mut App, writer: &mut EventWriter<S>) -> bool {
+    match app.ctrl_c(Instant::now()) {
+        CtrlC::Quit => true,
+        CtrlC::Cancel => {
+            let event = ProtocolEvent::Prompt { text: "/cancel".into(), provider: None, channel: Some(app.channel.clone()), reply_to: None, priority: false, author: None };
+            let _ = writer.send(&event).await;
+            false
+        }
+    }
+}
+
+pub async fn run_tui_app<B: Backend, S: AsyncRead + AsyncWrite + Unpin>(
     terminal: &mut Terminal<B>,
     mut app: App,
-    writer: &mut W,
+    writer: &mut EventWriter<S>,
     mut rx: mpsc::Receiver<AppEvent>,
 ) -> Result<(), Box<dyn Error>> 
 where <B as Backend>::Error: 'static {
@@ -307,60 +622,92 @@ where <B as Backend>::Error: 'static {
                     if key.kind != KeyEventKind::Press {
                         continue;
                     }
+                    app.completions.clear();
+                    if let Some(picker) = app.picker.as_mut() {
+                        match key.code {
+                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                if handle_ctrl_c(&mut app, writer).await { return Ok(()); }
+                            }
+                            KeyCode::Esc => app.picker = None,
+                            KeyCode::Down | KeyCode::Char('j') => picker.move_by(1),
+                            KeyCode::Up | KeyCode::Char('k') => picker.move_by(-1),
+                            KeyCode::Enter => {
+                                if let PickerOutcome::Send(commands) = picker.confirm() {
+                                    app.picker = None;
+                                    for text in commands {
+                                        let event = ProtocolEvent::Prompt { text, provider: None, channel: None, reply_to: None, priority: false, author: None };
+                                        let _ = writer.send(&event).await;
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                        continue;
+                    }
                     if key.modifiers.contains(KeyModifiers::CONTROL) {
                         match key.code {
-                            KeyCode::Char('c') => return Ok(()),
+                            KeyCode::Char('c') => {
+                                if handle_ctrl_c(&mut app, writer).await { return Ok(()); }
+                            }
+                            // Normal モードの Ctrl+P は選択ポップアップ、入力中は履歴をさかのぼる
+                            KeyCode::Char('p') if app.input_mode == InputMode::Normal => {
+                                app.picker = Some(Picker::open(&app.active_cli));
+                            }
                             KeyCode::Char('p') => app.input.history_up(),
                             KeyCode::Char('n') => app.input.history_down(),
                             KeyCode::Char('k') => app.input.kill_line(),
                             KeyCode::Char('y') => app.input.yank(),
+                            // Ctrl+_ は端末によって Ctrl+/ や Ctrl+7 として届く
+                            KeyCode::Char('_' | '/' | '7') => app.input.undo(),
+                            KeyCode::Char('?') => app.input.redo(),
                             KeyCode::Char('a') => app.input.cursor_position = 0,
                             KeyCode::Char('e') => app.input.cursor_position = app.input.text.chars().count(),
                             _ => {}
                         }
                     }
 
+                    let after_g = std::mem::take(&mut app.pending_g);
                     match app.input_mode {
                         InputMode::Normal => match key.code {
+                            KeyCode::Char('g') if after_g => app.jump_to_top(),
+                            KeyCode::Char('g') => app.pending_g = true,
+                            KeyCode::Char('G') => app.jump_to_bottom(),
                             KeyCode::Char('i') => app.input_mode = InputMode::Editing,
                             KeyCode::Char('q') => return Ok(()),
-                            KeyCode::Char('1') | KeyCode::Char('2') | KeyCode::Char('3') | KeyCode::Char('4') => {
-                                let provider_name = match key.code {
-                                    KeyCode::Char('1') => "gemini",
-                                    KeyCode::Char('2') => "claude",
-                                    KeyCode::Char('3') => "codex",
-                                    _ => "opencode",
-                                };
-                                let event = ProtocolEvent::Prompt { text: format!("/provider {provider_name}"), provider: None, channel: None };
-                                if let Ok(j) = serde_json::to_string(&event) { let _ = writer.write_all(format!("{}\n", j).as_bytes()).await; }
+                            KeyCode::Char(c @ '1'..='4') => {
+                                if let Some(text) = number_key_command(c) {
+                                    let event = ProtocolEvent::Prompt { text, provider: None, channel: None, reply_to: None, priority: false, author: None };
+                                    let _ = writer.send(&event).await;
+                                }
                             }
                             KeyCode::Up | KeyCode::Char('k') => {
                                 app.scroll = app.scroll.saturating_sub(1);
                                 app.auto_scroll = false;
                             }
                             KeyCode::Down | KeyCode::Char('j') => {
-                                app.scroll = app.scroll.saturating_add(1);
+                                let max_scroll = app.max_scroll();
+                                app.scroll = app.scroll.saturating_add(1).min(max_scroll);
                                 // 最下部に達したら自動スクロール復帰
-                                let total_lines = app.messages.iter().map(|m| m.chars().filter(|&c| c == '\n').count()).sum::<usize>() as u16;
-                                if app.scroll >= total_lines { app.auto_scroll = true; }
+                                if app.scroll >= max_scroll { app.auto_scroll = true; }
                             }
                             KeyCode::PageUp => {
                                 app.scroll = app.scroll.saturating_sub(10);
                                 app.auto_scroll = false;
                             }
                             KeyCode::PageDown => {
-                                app.scroll = app.scroll.saturating_add(10);
-                                let total_lines = app.messages.iter().map(|m| m.chars().filter(|&c| c == '\n').count()).sum::<usize>() as u16;
-                                if app.scroll >= total_lines { app.auto_scroll = true; }
+                                let max_scroll = app.max_scroll();
+                                app.scroll = app.scroll.saturating_add(10).min(max_scroll);
+                                if app.scroll >= max_scroll { app.auto_scroll = true; }
                             }
                             _ => {}
                         }
                         InputMode::Editing => match key.code {
+                            KeyCode::Tab => app.complete_input(),
                             KeyCode::Enter => {
                                 if key.modifiers.contains(KeyModifiers::SHIFT) || key.modifiers.contains(KeyModifiers::ALT) {
                                     app.input.enter_char('\n');
                                 } else {
-                                    let msg = app.input.reset();
+                                    let (msg, priority) = split_priority_prefix(&app.input.reset());
                                     if !msg.is_empty() {
                                         app.messages.push("--- (Start) ---\n".into());
                                         app.messages.push(format!("[user][{}] {}\n", app.channel, msg));
@@ -368,11 +715,12 @@ where <B as Backend>::Error: 'static {
                                         app.auto_scroll = true; // 自身の入力時は最下部へ
                                         app.scroll_to_bottom();
                                         
-                                        let event = ProtocolEvent::Prompt { text: msg, provider: None, channel: Some(app.channel.clone()) };
-                                        if let Ok(j) = serde_json::to_string(&event) { let _ = writer.write_all(format!("{}\n", j).as_bytes()).await; }
+                                        let event = ProtocolEvent::Prompt { text: msg, provider: None, channel: Some(app.channel.clone()), reply_to: None, priority, author: None };
+                                        let _ = writer.send(&event).await;
                                     }
                                 }
                             }
+                            KeyCode::Char('_') if key.modifiers.contains(KeyModifiers::ALT) => app.input.redo(),
                             KeyCode::Char(c) => if !key.modifiers.contains(KeyModifiers::CONTROL) { app.input.enter_char(c); }
                             KeyCode::Backspace => app.input.delete_char(),
                             KeyCode::Left => app.input.move_cursor_left(),
@@ -389,43 +737,306 @@ where <B as Backend>::Error: 'static {
     }
 }
 
+/// Normal モードの数字キー (1〜4) に割り当てたプロバイダー。`PROVIDER_NAMES` の並び順に従う。
+fn number_key_provider(key: char) -> Option<AgentProvider> {
+    let index = key.to_digit(10)?.checked_sub(1)? as usize;
+    if index >= 4 {
+        return None;
+    }
+    crate::bridge::PROVIDER_NAMES.get(index).and_then(|name| crate::protocol::provider_from_command_name(name))
+}
+
+/// 数字キーで送るブリッジコマンド。ブリッジが `ProviderSwitched` を返すとヘッダーの CLI 表示が更新される。
+fn number_key_command(key: char) -> Option<String> {
+    number_key_provider(key).map(|provider| format!("/provider {}", provider.command_name()))
+}
+
+/// `/` で始まる入力に対する補完候補を、置き換え後の入力全体として返す。
+/// 1語目はブリッジのコマンド名、`/provider` と `/model` の引数はブリッジの既知の値から補完する。
+pub fn complete_command(input: &str) -> Vec<String> {
+    let Some(rest) = input.strip_prefix('/') else { return Vec::new() };
+    if rest.contains('\n') {
+        return Vec::new();
+    }
+    match rest.split_once(' ') {
+        None => crate::bridge::BRIDGE_COMMANDS
+            .iter()
+            .filter(|c| c.starts_with(rest))
+            .map(|c| format!("/{c}"))
+            .collect(),
+        Some((command, arg)) => {
+            let arg = arg.trim_start();
+            if arg.contains(' ') {
+                return Vec::new();
+            }
+            let candidates: Vec<&str> = match command {
+                "provider" => crate::bridge::PROVIDER_NAMES.to_vec(),
+                "model" => crate::bridge::known_models(),
+                _ => Vec::new(),
+            };
+            candidates
+                .into_iter()
+                .filter(|c| c.starts_with(arg))
+                .map(|c| format!("/{command} {c}"))
+                .collect()
+        }
+    }
+}
+
+/// 候補の共通接頭辞（文字単位）。
+fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else { return String::new() };
+    let mut prefix: Vec<char> = first.chars().collect();
+    for candidate in &candidates[1..] {
+        let len = prefix.iter().zip(candidate.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(len);
+    }
+    prefix.into_iter().collect()
+}
+
 /// 入力テキストの行数に応じて入力エリアの高さを計算する（borders 込み、最小 5）
 pub fn compute_input_height(text: &str) -> u16 {
     let line_count = text.split('\n').count() as u16;
     (line_count + 2).max(5)
 }
 
+/// 入力欄の折り返し規則: 表示幅 `width` を超える文字は次の行へ送る（全角は幅 2）。
+/// 描画（`wrap_input_text`）とカーソル位置（`visual_cursor_pos`）で同じ規則を使う。
+fn next_visual_pos(row: u16, col: u16, c: char, width: u16) -> (u16, u16) {
+    if c == '\n' {
+        return (row + 1, 0);
+    }
+    let w = c.width().unwrap_or(0) as u16;
+    if width > 0 && col > 0 && col + w > width {
+        (row + 1, w)
+    } else {
+        (row, col + w)
+    }
+}
+
+/// テキストを表示幅 `width` で折り返したときの表示行数（`next_visual_pos` と同じ規則）。
+pub fn wrapped_line_count(text: &str, width: u16) -> usize {
+    text.lines()
+        .map(|line| {
+            let (mut row, mut col) = (0u16, 0u16);
+            for c in line.chars() {
+                (row, col) = next_visual_pos(row, col, c, width);
+            }
+            row as usize + 1
+        })
+        .sum()
+}
+
+/// 入力テキストを入力欄の幅で折り返し、表示行ごとに改行を入れたテキストを返す。
+pub fn wrap_input_text(text: &str, width: u16) -> String {
+    let mut out = String::with_capacity(text.len());
+    let (mut row, mut col) = (0u16, 0u16);
+    for c in text.chars() {
+        let (next_row, next_col) = next_visual_pos(row, col, c, width);
+        if c != '\n' && next_row > row {
+            out.push('\n');
+        }
+        out.push(c);
+        (row, col) = (next_row, next_col);
+    }
+    out
+}
+
+/// カーソル（文字インデックス）の折り返し後の表示位置を (row, col) で返す。
+/// 行末ちょうどで幅を使い切った場合は次の表示行の先頭に置く。
+pub fn visual_cursor_pos(text: &str, cursor_char_idx: usize, width: u16) -> (u16, u16) {
+    let (mut row, mut col) = (0u16, 0u16);
+    for c in text.chars().take(cursor_char_idx) {
+        (row, col) = next_visual_pos(row, col, c, width);
+    }
+    if width > 0 && col >= width {
+        (row + 1, 0)
+    } else {
+        (row, col)
+    }
+}
+
+/// 先頭の `!` は優先プロンプトの指定。`!` を外した本文と優先フラグを返す。
+/// `!` だけの入力は通常のテキストとして扱う。
+pub fn split_priority_prefix(msg: &str) -> (String, bool) {
+    match msg.strip_prefix('!').map(str::trim_start) {
+        Some(rest) if !rest.is_empty() => (rest.to_string(), true),
+        _ => (msg.to_string(), false),
+    }
+}
+
+/// 入力の文字数（バイト数ではなく Unicode の文字単位）と行数。
+pub fn input_stats(text: &str) -> (usize, usize) {
+    (text.chars().count(), text.split('\n').count())
+}
+
+/// ACOMM_TUI_INPUT_WARN_CHARS を解釈する。0 や数値でない値は警告なし。
+pub fn parse_input_warn_chars(value: Option<&str>) -> Option<usize> {
+    value.and_then(|v| v.trim().parse::<usize>().ok()).filter(|&n| n > 0)
+}
+
+/// Input 欄のタイトル。文字数・行数と、あれば Tab 補完の候補を並べる。
+fn input_title(app: &App) -> Span<'static> {
+    let (chars, lines) = input_stats(&app.input.text);
+    let counter = format!("{} chars, {} {}", chars, lines, if lines == 1 { "line" } else { "lines" });
+    let title = if app.completions.is_empty() {
+        format!(" Input | {} ", counter)
+    } else {
+        format!(" Input | {} | {} ", counter, app.completions.join("  "))
+    };
+    if app.input_warn_chars.is_some_and(|limit| chars > limit) {
+        Span::styled(title, Style::default().fg(Color::Red))
+    } else {
+        Span::raw(title)
+    }
+}
+
 fn render_ui(f: &mut Frame, app: &mut App) {
-    let input_height = compute_input_height(&app.input.text);
+    let input_width = f.area().width.saturating_sub(2);
+    let wrapped_input = wrap_input_text(&app.input.text, input_width);
+    let input_height = compute_input_height(&wrapped_input);
     let chunks = Layout::default().direction(Direction::Vertical).constraints([Constraint::Length(3), Constraint::Min(1), Constraint::Length(input_height)]).split(f.area());
     let spinner_chars = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
     let mode_str = if app.is_processing { format!("THINKING {}", spinner_chars[app.spinner_idx]) } else { match app.input_mode { InputMode::Normal => "NORMAL".into(), InputMode::Editing => "INSERT".into() } };
     let header = Paragraph::new(format!(" Mode: {} | CLI: {} | Channel: {} | AutoScroll: {}", mode_str, app.active_cli.command_name(), app.channel, app.auto_scroll)).block(Block::default().title(" Status ").borders(Borders::ALL));
     f.render_widget(header, chunks[0]);
     
-    let chat_height = chunks[1].height.saturating_sub(2);
-    let chat_content = app.messages.join("");
-    let total_lines = chat_content.chars().filter(|&c| c == '\n').count();
-    let current_scroll = app.scroll.min(total_lines.saturating_sub(chat_height as usize) as u16);
+    app.chat_width = chunks[1].width.saturating_sub(2);
+    app.chat_height = chunks[1].height.saturating_sub(2);
+    let chat_content = app.chat_text();
+    let current_scroll = app.scroll.min(app.max_scroll());
     
     let chat = Paragraph::new(chat_content).wrap(Wrap { trim: false }).scroll((current_scroll, 0)).block(Block::default().title(" Chat history ").borders(Borders::ALL));
     f.render_widget(chat, chunks[1]);
     
-    let input = Paragraph::new(app.input.text.as_str()).style(if let InputMode::Editing = app.input_mode { Style::default().fg(Color::Yellow) } else { Style::default() }).block(Block::default().title(" Input ").borders(Borders::ALL));
+    let input = Paragraph::new(wrapped_input.as_str()).style(if let InputMode::Editing = app.input_mode { Style::default().fg(Color::Yellow) } else { Style::default() }).block(Block::default().title(input_title(app)).borders(Borders::ALL));
     f.render_widget(input, chunks[2]);
     
-    if let (InputMode::Editing, false) = (app.input_mode, app.is_processing) {
-        let (row, _col) = app.input.get_cursor_coords();
-        let text_before_cursor: String = app.input.text.chars().take(app.input.cursor_position).collect();
-        let cursor_x: u16 = text_before_cursor.split('\n').last().unwrap_or("").width() as u16;
-        f.set_cursor_position((chunks[2].x + cursor_x + 1, chunks[2].y + row as u16 + 1));
+    if let (InputMode::Editing, false, None) = (app.input_mode, app.is_processing, &app.picker) {
+        let (row, col) = visual_cursor_pos(&app.input.text, app.input.cursor_position, input_width);
+        f.set_cursor_position((chunks[2].x + col + 1, chunks[2].y + row + 1));
+    }
+
+    if let Some(picker) = &app.picker {
+        render_picker(f, picker);
     }
 }
 
+/// 画面中央に重ねて描く選択ポップアップ。
+fn render_picker(f: &mut Frame, picker: &Picker) {
+    let items = picker.items();
+    let title = match &picker.stage {
+        PickerStage::Provider => " Provider (j/k, Enter, Esc) ".to_string(),
+        PickerStage::Model(provider) => format!(" Model for {} (j/k, Enter, Esc) ", provider.command_name()),
+    };
+    // 枠線 2 と選択マーク "> " の 2 の分を足す。
+    let width = items.iter().map(|item| item.width()).max().unwrap_or(0).max(title.width()) as u16 + 4;
+    let area = centered_rect(width, items.len() as u16 + 2, f.area());
+    let list = List::new(items.into_iter().map(ListItem::new))
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::Yellow))
+        .highlight_symbol("> ");
+    let mut state = ListState::default().with_selected(Some(picker.selected));
+    f.render_widget(Clear, area);
+    f.render_stateful_widget(list, area, &mut state);
+}
+
+/// `area` の中央に置いた幅 `width`・高さ `height` の矩形。`area` より大きければ `area` に収める。
+fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect::new(area.x + (area.width - width) / 2, area.y + (area.height - height) / 2, width, height)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// テスト用の App（`tui` チャンネル、Normal モード、待機中）。テストごとに必要なフィールドだけ上書きする。
+    fn test_app() -> App {
+        App {
+            input: InputState::new(),
+            input_mode: InputMode::Normal,
+            messages: Vec::new(),
+            active_cli: AgentProvider::Gemini,
+            is_processing: false,
+            scroll: 0,
+            auto_scroll: true,
+            channel: "tui".into(),
+            spinner_idx: 0,
+            completions: Vec::new(),
+            chat_width: 0,
+            chat_height: 0,
+            pending_g: false,
+            pending_acks: 0,
+            input_warn_chars: None,
+            picker: None,
+            chunk_lines: HashMap::new(),
+            reply_footer: false,
+            ctrl_c_at: None,
+            running_channels: HashSet::new(),
+        }
+    }
+
+    #[test]
+    fn number_keys_map_to_providers_the_bridge_accepts() {
+        assert_eq!(number_key_provider('1'), Some(AgentProvider::Gemini));
+        assert_eq!(number_key_provider('2'), Some(AgentProvider::Claude));
+        assert_eq!(number_key_provider('3'), Some(AgentProvider::Codex));
+        assert_eq!(number_key_provider('4'), Some(AgentProvider::OpenCode));
+        assert_eq!(number_key_provider('0'), None);
+        assert_eq!(number_key_provider('5'), None);
+    }
+
+    #[test]
+    fn number_key_sends_provider_command_and_header_follows_echo() {
+        assert_eq!(number_key_command('2').as_deref(), Some("/provider claude"));
+        assert_eq!(number_key_command('9'), None);
+
+        let mut app = test_app();
+        app.handle_bus_event(ProtocolEvent::ProviderSwitched { provider: AgentProvider::Claude });
+        assert_eq!(app.active_cli, AgentProvider::Claude);
+    }
+
+    #[test]
+    fn ctrl_c_cancels_while_processing_and_quits_on_a_quick_second_press() {
+        let mut app = App { input_mode: InputMode::Editing, ..test_app() };
+        let t0 = Instant::now();
+        // 待機中はすぐ終了する。
+        assert_eq!(app.ctrl_c(t0), CtrlC::Quit);
+
+        // 処理中の1回目は止めるだけで、猶予内の2回目で終了する。
+        app.handle_bus_event(ProtocolEvent::StatusUpdate { is_processing: true, channel: Some("tui".into()) });
+        assert_eq!(app.ctrl_c(t0), CtrlC::Cancel);
+        assert_eq!(app.messages.last().unwrap(), "[System]: Cancelling the running prompt; press Ctrl+C again to quit\n");
+        assert_eq!(app.ctrl_c(t0 + Duration::from_millis(1500)), CtrlC::Quit);
+
+        // 猶予を過ぎた2回目は、もう一度止めるだけ。
+        app.ctrl_c_at = None;
+        assert_eq!(app.ctrl_c(t0), CtrlC::Cancel);
+        assert_eq!(app.ctrl_c(t0 + QUIT_CONFIRM_WINDOW + Duration::from_millis(1)), CtrlC::Cancel);
+        assert_eq!(app.ctrl_c(t0 + QUIT_CONFIRM_WINDOW * 2), CtrlC::Quit);
+    }
+
+    #[test]
+    fn ctrl_c_quits_while_only_another_channel_is_processing() {
+        let mut app = test_app();
+        let t0 = Instant::now();
+        // 他のチャンネルの処理中は、このチャンネルの Ctrl+C を止めない。
+        app.handle_bus_event(ProtocolEvent::StatusUpdate { is_processing: true, channel: Some("discord:1:10".into()) });
+        assert!(app.is_processing);
+        assert_eq!(app.ctrl_c(t0), CtrlC::Quit);
+
+        // `/compare` の各プロバイダーのチャンネルはこのチャンネルの実行として扱い、全部終わるまで止める対象。
+        app.handle_bus_event(ProtocolEvent::StatusUpdate { is_processing: true, channel: Some("tui#gemini".into()) });
+        app.handle_bus_event(ProtocolEvent::StatusUpdate { is_processing: true, channel: Some("tui#claude".into()) });
+        app.handle_bus_event(ProtocolEvent::AgentDone { channel: Some("tui#gemini".into()), provider: None, model: None });
+        assert_eq!(app.ctrl_c(t0), CtrlC::Cancel);
+        app.handle_bus_event(ProtocolEvent::StatusUpdate { is_processing: false, channel: Some("tui#claude".into()) });
+        app.ctrl_c_at = None;
+        assert_eq!(app.ctrl_c(t0), CtrlC::Quit);
+    }
+
     #[test]
     fn test_compute_input_height_single_line() {
         assert_eq!(compute_input_height(""), 5);
@@ -484,26 +1095,161 @@ mod tests {
         assert_eq!(input.text, "acb");
     }
 
+    fn typed(text: &str) -> InputState {
+        let mut input = InputState::new();
+        input.history.clear();
+        for c in text.chars() {
+            input.enter_char(c);
+        }
+        input
+    }
+
+    #[test]
+    fn test_undo_after_kill_restores_text_and_cursor() {
+        let mut input = typed("hello world");
+        input.cursor_position = 5;
+        input.kill_line();
+        assert_eq!(input.text, "hello");
+        input.undo();
+        assert_eq!(input.text, "hello world");
+        assert_eq!(input.cursor_position, 5);
+        input.redo();
+        assert_eq!(input.text, "hello");
+        assert_eq!(input.cursor_position, 5);
+    }
+
+    #[test]
+    fn test_undo_coalesces_typed_words() {
+        let mut input = typed("hello world");
+        input.undo();
+        assert_eq!(input.text, "hello");
+        assert_eq!(input.cursor_position, 5);
+        input.undo();
+        assert_eq!(input.text, "");
+        input.undo();
+        assert_eq!(input.text, "");
+        input.redo();
+        input.redo();
+        assert_eq!(input.text, "hello world");
+        assert_eq!(input.cursor_position, 11);
+    }
+
+    #[test]
+    fn test_new_edit_clears_redo() {
+        let mut input = typed("ab");
+        input.delete_char();
+        input.undo();
+        assert_eq!(input.text, "ab");
+        input.enter_char('c');
+        input.redo();
+        assert_eq!(input.text, "abc");
+    }
+
+    #[test]
+    fn test_undo_stack_is_bounded() {
+        let mut input = typed("");
+        for _ in 0..UNDO_LIMIT + 20 {
+            input.enter_char(' ');
+        }
+        assert_eq!(input.undo_stack.len(), UNDO_LIMIT);
+    }
+
+    #[test]
+    fn test_visual_cursor_pos_wraps_ascii_lines() {
+        assert_eq!(visual_cursor_pos("abcdef", 0, 4), (0, 0));
+        assert_eq!(visual_cursor_pos("abcdef", 3, 4), (0, 3));
+        assert_eq!(visual_cursor_pos("abcdef", 5, 4), (1, 1));
+        // 幅ちょうどで埋まった行の末尾は次の行の先頭
+        assert_eq!(visual_cursor_pos("abcd", 4, 4), (1, 0));
+        assert_eq!(visual_cursor_pos("ab\ncdefg", 8, 4), (2, 1));
+    }
+
+    #[test]
+    fn test_visual_cursor_pos_counts_wide_chars() {
+        assert_eq!(visual_cursor_pos("あいう", 2, 10), (0, 4));
+        // 幅 5 では「う」が入りきらず次の行へ
+        assert_eq!(visual_cursor_pos("あいう", 3, 5), (1, 2));
+        assert_eq!(visual_cursor_pos("aあ", 1, 2), (0, 1));
+        assert_eq!(visual_cursor_pos("aあ", 2, 2), (2, 0));
+        assert_eq!(visual_cursor_pos("漢字\nx", 4, 3), (2, 1));
+    }
+
+    #[test]
+    fn test_wrap_input_text_matches_cursor_rule() {
+        assert_eq!(wrap_input_text("abcdef", 4), "abcd\nef");
+        assert_eq!(wrap_input_text("あいう", 5), "あい\nう");
+        assert_eq!(wrap_input_text("ab\ncd", 4), "ab\ncd");
+        assert_eq!(wrap_input_text("abc", 0), "abc");
+    }
+
+    #[test]
+    fn picker_walks_from_provider_to_model_and_sends_both_commands() {
+        let mut picker = Picker::open(&AgentProvider::Claude);
+        assert_eq!(picker.items()[picker.selected], "claude");
+        picker.move_by(-10);
+        assert_eq!(picker.selected, 0);
+        picker.move_by(2);
+        assert_eq!(picker.items()[picker.selected], "codex");
+        assert_eq!(picker.confirm(), PickerOutcome::Stay);
+        assert_eq!(picker.stage, PickerStage::Model(AgentProvider::Codex));
+        assert_eq!(picker.items()[0], "gpt-5.3-codex");
+        picker.move_by(100);
+        assert_eq!(picker.items()[picker.selected], "o1-mini");
+        assert_eq!(
+            picker.confirm(),
+            PickerOutcome::Send(vec!["/provider codex".to_string(), "/model o1-mini".to_string()])
+        );
+    }
+
+    #[test]
+    fn picker_sends_only_provider_when_it_has_no_known_models() {
+        let mut picker = Picker::open(&AgentProvider::OpenCode);
+        assert_eq!(picker.confirm(), PickerOutcome::Send(vec!["/provider opencode".to_string()]));
+    }
+
+    #[test]
+    fn centered_rect_is_clamped_to_the_screen() {
+        assert_eq!(centered_rect(20, 6, Rect::new(0, 0, 80, 24)), Rect::new(30, 9, 20, 6));
+        assert_eq!(centered_rect(100, 40, Rect::new(0, 0, 80, 24)), Rect::new(0, 0, 80, 24));
+    }
+
+    #[test]
+    fn test_complete_command_names_and_arguments() {
+        assert_eq!(complete_command("/pro"), vec!["/provider".to_string()]);
+        assert_eq!(complete_command("/hi"), vec!["/history".to_string()]);
+        assert_eq!(complete_command("/provider cla"), vec!["/provider claude".to_string()]);
+        let models = complete_command("/model ");
+        assert!(models.contains(&"/model auto-gemini-3".to_string()));
+        assert!(models.contains(&"/model gpt-5.3-codex".to_string()));
+        assert!(complete_command("hello /pro").is_empty());
+        assert!(complete_command("/search foo").is_empty());
+    }
+
+    #[test]
+    fn test_complete_input_extends_common_prefix_and_lists_candidates() {
+        let mut app = App { input_mode: InputMode::Editing, ..test_app() };
+        app.input.text = "/provider c".into();
+        app.input.cursor_position = app.input.text.chars().count();
+        app.complete_input();
+        assert_eq!(app.input.text, "/provider c");
+        assert_eq!(app.completions, vec!["claude".to_string(), "codex".to_string()]);
+
+        app.input.text = "/cl".into();
+        app.complete_input();
+        assert_eq!(app.input.text, "/clear ");
+        assert_eq!(app.input.cursor_position, 7);
+    }
+
     #[test]
     fn test_app_message_handling_clean_output() {
-        let mut app = App {
-            input: InputState::new(),
-            input_mode: InputMode::Normal,
-            messages: Vec::new(),
-            active_cli: AgentProvider::Gemini,
-            is_processing: false,
-            scroll: 0,
-            auto_scroll: true,
-            channel: "tui".into(),
-            spinner_idx: 0,
-        };
+        let mut app = test_app();
 
-        app.handle_bus_event(ProtocolEvent::Prompt { text: "test".into(), provider: None, channel: Some("tui".into()) });
+        app.handle_bus_event(ProtocolEvent::Prompt { text: "test".into(), provider: None, channel: Some("tui".into()), reply_to: None, priority: false, author: None });
         app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "Line 1\n".into(), channel: Some("tui".into()) });
         app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "\n".into(), channel: Some("tui".into()) });
         app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "\n".into(), channel: Some("tui".into()) });
         app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "Line 3".into(), channel: Some("tui".into()) });
-        app.handle_bus_event(ProtocolEvent::AgentDone { channel: Some("tui".into()) });
+        app.handle_bus_event(ProtocolEvent::AgentDone { channel: Some("tui".into()), provider: None, model: None });
 
         for (i, m) in app.messages.iter().enumerate() {
             println!("msg[{}]: {:?}", i, m);
@@ -512,4 +1258,168 @@ mod tests {
         let empty_gemini_lines = app.messages.iter().filter(|m| m.as_str() == "[gemini] \n" || m.as_str() == "[gemini] ").count();
         assert!(empty_gemini_lines <= 1, "Too many redundant empty gemini lines found");
     }
+
+    #[test]
+    fn interleaved_chunks_from_two_channels_do_not_merge() {
+        let mut app = test_app();
+        let chunk = |text: &str, channel: &str| ProtocolEvent::AgentChunk { chunk: text.into(), channel: Some(channel.into()) };
+
+        app.handle_bus_event(chunk("Hello from ", "tui"));
+        app.handle_bus_event(chunk("Bonjour de ", "discord:1:2"));
+        app.handle_bus_event(chunk("the TUI\nsecond ", "tui"));
+        app.handle_bus_event(chunk("Discord", "discord:1:2"));
+        app.handle_bus_event(ProtocolEvent::AgentDone { channel: Some("discord:1:2".into()), provider: None, model: None });
+        app.handle_bus_event(chunk("line", "tui"));
+        app.handle_bus_event(ProtocolEvent::AgentDone { channel: Some("tui".into()), provider: None, model: None });
+
+        assert_eq!(
+            app.messages,
+            vec!["[gemini] Hello from the TUI\n", "[gemini] Bonjour de Discord\n", "[gemini] second line\n"]
+        );
+        assert!(app.chunk_lines.is_empty());
+    }
+
+    #[test]
+    fn compare_streams_are_labelled_with_their_provider() {
+        let mut app = test_app();
+        for provider in ["gemini", "claude"] {
+            app.handle_bus_event(ProtocolEvent::Prompt {
+                text: "どっちが速い?".into(),
+                provider: None,
+                channel: Some(format!("tui#{provider}")),
+                reply_to: None,
+                priority: false,
+                author: None,
+            });
+        }
+        app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "Gemini says".into(), channel: Some("tui#gemini".into()) });
+        app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "Claude says\n".into(), channel: Some("tui#claude".into()) });
+        app.handle_bus_event(ProtocolEvent::AgentDone { channel: Some("tui#gemini".into()), provider: None, model: None });
+
+        assert_eq!(
+            app.messages,
+            vec!["--- (Compare) ---\n", "[user][tui] どっちが速い?\n", "[gemini] Gemini says\n", "[claude] Claude says\n"]
+        );
+    }
+
+    #[test]
+    fn chat_text_keeps_an_unfinished_line_apart_from_later_messages() {
+        let mut app = test_app();
+        app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "thinking".into(), channel: Some("tui".into()) });
+        app.handle_bus_event(ProtocolEvent::SystemMessage { msg: "other channel".into(), channel: Some("slack:C1".into()) });
+        assert_eq!(app.chat_text(), "[gemini] thinking\n[System]: other channel\n");
+        // チャンクの境目で分かれた区切りも、繋がった行からは消える
+        let (head, tail) = FINAL_ANSWER_MARKER.split_at(5);
+        app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: format!(" {}", head), channel: Some("tui".into()) });
+        app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: format!("{}done", tail), channel: Some("tui".into()) });
+        assert_eq!(app.chat_text(), "[gemini] thinking done\n[System]: other channel\n");
+    }
+
+    #[test]
+    fn reply_footer_line_follows_the_answer_when_enabled() {
+        let mut app = App { reply_footer: true, ..test_app() };
+        let done = || ProtocolEvent::AgentDone {
+            channel: Some("tui".into()),
+            provider: Some(AgentProvider::Claude),
+            model: Some("claude-sonnet-4-6".into()),
+        };
+        app.handle_bus_event(ProtocolEvent::AgentChunk { chunk: "answer".into(), channel: Some("tui".into()) });
+        app.handle_bus_event(done());
+        assert_eq!(app.messages, vec!["[gemini] answer\n", "  -- claude:claude-sonnet-4-6\n"]);
+        // 既定では表示しない
+        app.reply_footer = false;
+        app.handle_bus_event(done());
+        assert_eq!(app.messages.len(), 2);
+    }
+
+    #[test]
+    fn prompt_ack_marks_own_prompt_as_sent_in_either_order() {
+        let mut app = test_app();
+        let prompt = |text: &str, ch: &str| ProtocolEvent::Prompt { text: text.into(), provider: None, channel: Some(ch.into()), reply_to: None, priority: false, author: None };
+        let ack = |ch: &str, seq| ProtocolEvent::PromptAck { channel: Some(ch.into()), seq };
+
+        app.handle_bus_event(prompt("first", "tui"));
+        app.handle_bus_event(ack("tui", 1));
+        assert_eq!(app.messages.last().unwrap(), "[user][tui] first  sent ✓\n");
+
+        // ack がエコーより先に届いた場合
+        app.handle_bus_event(ack("tui", 2));
+        app.handle_bus_event(prompt("second", "tui"));
+        assert_eq!(app.messages.last().unwrap(), "[user][tui] second  sent ✓\n");
+        assert_eq!(app.pending_acks, 0);
+
+        // 他チャンネルのプロンプトには付けない
+        app.handle_bus_event(prompt("other", "discord:1:2"));
+        app.handle_bus_event(ack("discord:1:2", 1));
+        assert_eq!(app.messages.last().unwrap(), "[user][discord:1:2] other\n");
+    }
+
+    #[test]
+    fn wrapped_line_count_counts_display_rows() {
+        assert_eq!(wrapped_line_count("abc\n", 0), 1);
+        assert_eq!(wrapped_line_count("abcdefghij\nxy\n", 4), 3 + 1);
+        // 全角は幅 2
+        assert_eq!(wrapped_line_count("あいう\n", 4), 2);
+    }
+
+    #[test]
+    fn jump_keys_target_top_and_wrapped_bottom() {
+        let mut app = App {
+            messages: vec!["short\n".into(), "0123456789012345678901234\n".into(), "end\n".into()],
+            scroll: 2,
+            chat_width: 10,
+            chat_height: 2,
+            ..test_app()
+        };
+        // 1 + 3 (25 文字を幅 10 で折り返し) + 1 = 5 行、高さ 2 なので最下部は 3
+        assert_eq!(app.max_scroll(), 3);
+
+        app.jump_to_top();
+        assert_eq!((app.scroll, app.auto_scroll), (0, false));
+
+        app.jump_to_bottom();
+        assert_eq!((app.scroll, app.auto_scroll), (3, true));
+
+        app.chat_height = 10;
+        app.jump_to_bottom();
+        assert_eq!(app.scroll, 0, "everything fits, so the bottom is the top");
+    }
+
+    #[test]
+    fn bang_prefix_marks_a_priority_prompt() {
+        assert_eq!(split_priority_prefix("!至急 ビルドを直して"), ("至急 ビルドを直して".to_string(), true));
+        assert_eq!(split_priority_prefix("! fix it"), ("fix it".to_string(), true));
+        assert_eq!(split_priority_prefix("fix it!"), ("fix it!".to_string(), false));
+        assert_eq!(split_priority_prefix("!"), ("!".to_string(), false));
+    }
+
+    #[test]
+    fn input_counter_counts_unicode_chars_and_lines() {
+        assert_eq!(input_stats(""), (0, 1));
+        assert_eq!(input_stats("こんにちは"), (5, 1));
+        assert_eq!(input_stats("a\nb\n"), (4, 3));
+        assert_eq!(parse_input_warn_chars(Some("2000")), Some(2000));
+        assert_eq!(parse_input_warn_chars(Some("0")), None);
+        assert_eq!(parse_input_warn_chars(Some("lots")), None);
+        assert_eq!(parse_input_warn_chars(None), None);
+    }
+
+    #[test]
+    fn input_title_turns_red_past_the_warn_threshold() {
+        let mut app = App {
+            input_mode: InputMode::Editing,
+            input_warn_chars: Some(3),
+            ..test_app()
+        };
+        app.input.text = "日本語".into();
+        let title = input_title(&app);
+        assert_eq!(title.content, " Input | 3 chars, 1 line ");
+        assert_eq!(title.style.fg, None);
+
+        app.input.text = "日本語\n".into();
+        app.completions = vec!["/model".into()];
+        let title = input_title(&app);
+        assert_eq!(title.content, " Input | 4 chars, 2 lines | /model ");
+        assert_eq!(title.style.fg, Some(Color::Red));
+    }
 }
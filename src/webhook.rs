@@ -0,0 +1,429 @@
+/**
+ * Outbound webhook adapter: bridge → HTTP fan-out.
+ *
+ * `acomm adapter webhook` subscribes to the bridge and POSTs matching
+ * ProtocolEvents as JSON to the configured URLs, for automation tools such as
+ * n8n or Home Assistant. Targets come from `ACOMM_WEBHOOKS` (a JSON array) or the
+ * file named by `ACOMM_WEBHOOKS_FILE`:
+ *
+ *   [{"prefix": "discord:", "url": "https://hooks.example.org/acomm",
+ *     "events": ["Reply"], "secret": "optional, else ACOMM_WEBHOOK_SECRET"}]
+ *
+ * `prefix` matches the event channel ("" matches everything, including
+ * channel-less events); `events` lists ProtocolEvent kinds (`AgentDone`,
+ * `Prompt`, ...) plus `Reply`, a synthetic event with the concatenated reply sent
+ * when a channel finishes. Omitting `events` sends every kind except `Reply`.
+ *
+ * With a secret each request carries `X-Acomm-Signature: sha256=<hex HMAC of the
+ * body>`. 5xx responses and network errors are retried with backoff; deliveries
+ * that still fail are appended to `~/.cache/acomm/webhook_dead_letter.jsonl`.
+ * Events replayed during the bridge's initial sync are not delivered. Bodies go
+ * through `redact_output` before they are signed, like every other surface.
+ */
+use crate::protocol::{ActiveAgent, ProtocolEvent};
+use crate::redact::redact_output;
+use acomm::client::DEFAULT_SOCKET_PATH;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+
+const WEBHOOK_MAX_ATTEMPTS: u32 = 4;
+const WEBHOOK_BASE_DELAY: Duration = Duration::from_secs(1);
+const WEBHOOK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBHOOK_QUEUE_CAP: usize = 256;
+const DEAD_LETTER_FILE: &str = "webhook_dead_letter.jsonl";
+pub const SIGNATURE_HEADER: &str = "X-Acomm-Signature";
+const EVENT_HEADER: &str = "X-Acomm-Event";
+/// Synthetic event kind carrying a channel's finished reply.
+const REPLY_KIND: &str = "Reply";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct WebhookEntry {
+    #[serde(default)]
+    prefix: String,
+    url: String,
+    #[serde(default)]
+    events: Option<Vec<String>>,
+    #[serde(default)]
+    secret: Option<String>,
+}
+
+/// One configured destination.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookTarget {
+    pub prefix: String,
+    pub url: String,
+    /// Event kinds to deliver; `None` delivers every ProtocolEvent kind.
+    pub events: Option<Vec<String>>,
+    pub secret: Option<String>,
+}
+
+impl WebhookTarget {
+    fn wants(&self, kind: &str, channel: Option<&str>) -> bool {
+        let kind_ok = match &self.events {
+            Some(events) => events.iter().any(|e| e.eq_ignore_ascii_case(kind)),
+            None => kind != REPLY_KIND,
+        };
+        let channel_ok = self.prefix.is_empty() || channel.is_some_and(|ch| ch.starts_with(&self.prefix));
+        kind_ok && channel_ok
+    }
+}
+
+/// Parse the webhook JSON config. `default_secret` (`ACOMM_WEBHOOK_SECRET`) applies to
+/// entries without their own secret.
+pub fn parse_webhooks(raw: &str, default_secret: Option<&str>) -> Result<Vec<WebhookTarget>, String> {
+    let entries: Vec<WebhookEntry> =
+        serde_json::from_str(raw).map_err(|e| format!("Invalid webhook config: {}", e))?;
+    let default_secret = default_secret.map(str::trim).filter(|s| !s.is_empty());
+    entries
+        .into_iter()
+        .map(|entry| {
+            let url = entry.url.trim().to_string();
+            if !(url.starts_with("https://") || url.starts_with("http://")) {
+                return Err(format!("Invalid webhook URL '{}': must be http(s)", url));
+            }
+            let secret = entry
+                .secret
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .or_else(|| default_secret.map(str::to_string));
+            Ok(WebhookTarget { prefix: entry.prefix, url, events: entry.events, secret })
+        })
+        .collect()
+}
+
+fn load_webhooks_from_env() -> Result<Vec<WebhookTarget>, Box<dyn Error>> {
+    let secret = std::env::var("ACOMM_WEBHOOK_SECRET").ok();
+    let raw = match (std::env::var("ACOMM_WEBHOOKS").ok(), std::env::var("ACOMM_WEBHOOKS_FILE").ok()) {
+        (Some(raw), _) if !raw.trim().is_empty() => raw,
+        (_, Some(path)) if !path.trim().is_empty() => std::fs::read_to_string(path.trim())
+            .map_err(|e| format!("Cannot read ACOMM_WEBHOOKS_FILE {}: {}", path.trim(), e))?,
+        _ => return Err("ACOMM_WEBHOOKS or ACOMM_WEBHOOKS_FILE must be set".into()),
+    };
+    let targets = parse_webhooks(&raw, secret.as_deref())?;
+    if targets.is_empty() {
+        return Err("The webhook config has no entries".into());
+    }
+    Ok(targets)
+}
+
+/// The kind name of an event, as used in `events` and the `X-Acomm-Event` header.
+fn event_kind(event: &ProtocolEvent) -> String {
+    match serde_json::to_value(event) {
+        Ok(Value::Object(map)) => map.keys().next().cloned().unwrap_or_default(),
+        Ok(Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+/// Collects replies per channel so `Reply` can be sent when a channel finishes.
+#[derive(Debug, Default)]
+struct ReplyCollector {
+    active: ActiveAgent,
+    replies: HashMap<String, (String, ActiveAgent, String)>,
+}
+
+impl ReplyCollector {
+    /// Returns the `Reply` payload when `event` finishes a channel with a non-empty reply.
+    fn observe(&mut self, event: &ProtocolEvent) -> Option<Value> {
+        self.active.observe(event);
+        match event {
+            ProtocolEvent::Prompt { text, provider, channel: Some(ch), .. } => {
+                let agent = self.active.for_prompt(provider.as_ref());
+                self.replies.insert(ch.clone(), (text.clone(), agent, String::new()));
+                None
+            }
+            ProtocolEvent::AgentChunk { chunk, channel: Some(ch) } => {
+                if let Some((_, _, content)) = self.replies.get_mut(ch) {
+                    content.push_str(chunk);
+                }
+                None
+            }
+            ProtocolEvent::ChannelClosed { channel: Some(ch), .. } => {
+                self.replies.remove(ch);
+                None
+            }
+            ProtocolEvent::AgentDone { channel: Some(ch), .. } => {
+                let (prompt, agent, content) = self.replies.remove(ch)?;
+                let agent = agent.answered(event);
+                (!content.trim().is_empty()).then(|| {
+                    json!({ REPLY_KIND: {
+                        "channel": ch,
+                        "prompt": prompt,
+                        "text": content,
+                        "provider": agent.provider,
+                        "model": agent.model,
+                    } })
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A request body queued for one target.
+#[derive(Debug)]
+struct Delivery {
+    kind: String,
+    body: String,
+}
+
+pub async fn start_webhook_adapter() -> Result<(), Box<dyn Error>> {
+    let targets = load_webhooks_from_env()?;
+    let stream = UnixStream::connect(DEFAULT_SOCKET_PATH).await.map_err(|e| {
+        format!("Bridge is not running. Please start it with 'acomm --bridge'. Error: {}", e)
+    })?;
+    info!("Webhook adapter delivering to {} target(s)", targets.len());
+
+    let client = reqwest::Client::builder().timeout(WEBHOOK_REQUEST_TIMEOUT).build()?;
+    let dead_letter = crate::bridge::persisted_state_dir().map(|dir| dir.join(DEAD_LETTER_FILE));
+    // One ordered queue per target, so a slow endpoint only delays its own events.
+    let queues: Vec<(Arc<WebhookTarget>, mpsc::Sender<Delivery>)> = targets
+        .into_iter()
+        .map(|target| {
+            let target = Arc::new(target);
+            let (tx, rx) = mpsc::channel(WEBHOOK_QUEUE_CAP);
+            tokio::spawn(deliver_queue(client.clone(), Arc::clone(&target), rx, dead_letter.clone()));
+            (target, tx)
+        })
+        .collect();
+
+    let mut lines = BufReader::new(stream).lines();
+    let mut replies = ReplyCollector::default();
+    let mut synced = false;
+    while let Some(line) = lines.next_line().await? {
+        let Ok(event) = serde_json::from_str::<ProtocolEvent>(&line) else { continue };
+        if matches!(event, ProtocolEvent::BridgeSyncDone {}) {
+            synced = true;
+            continue;
+        }
+        let reply = replies.observe(&event);
+        if !synced {
+            continue;
+        }
+        let channel = event.clone_channel();
+        for (kind, body) in outgoing_bodies(&event, line, reply) {
+            for (target, queue) in &queues {
+                if !target.wants(&kind, channel.as_deref()) {
+                    continue;
+                }
+                if queue.try_send(Delivery { kind: kind.clone(), body: body.clone() }).is_err() {
+                    warn!("Webhook queue for {} is full; dropping a {} event", target.url, kind);
+                }
+            }
+        }
+    }
+    info!("Bridge closed the connection; webhook adapter stopping");
+    Ok(())
+}
+
+/// The `(kind, body)` pairs to send for one bridge event: the event itself, then its
+/// `Reply` if it finished a channel. Secrets are masked before anything is queued.
+fn outgoing_bodies(event: &ProtocolEvent, line: String, reply: Option<Value>) -> Vec<(String, String)> {
+    let mut outgoing = vec![(event_kind(event), line)];
+    if let Some(reply) = reply {
+        outgoing.push((REPLY_KIND.to_string(), reply.to_string()));
+    }
+    outgoing.into_iter().map(|(kind, body)| (kind, redact_output(&body))).collect()
+}
+
+async fn deliver_queue(
+    client: reqwest::Client,
+    target: Arc<WebhookTarget>,
+    mut rx: mpsc::Receiver<Delivery>,
+    dead_letter: Option<PathBuf>,
+) {
+    while let Some(delivery) = rx.recv().await {
+        if let Err(reason) = deliver(&client, &target, &delivery).await {
+            error!("Webhook delivery to {} failed: {}", target.url, reason);
+            if let Some(path) = dead_letter.as_deref() {
+                record_dead_letter(path, &target.url, &delivery, &reason);
+            }
+        }
+    }
+}
+
+/// POST one event, retrying 5xx responses and network errors with exponential backoff.
+async fn deliver(client: &reqwest::Client, target: &WebhookTarget, delivery: &Delivery) -> Result<(), String> {
+    let mut attempt = 1;
+    loop {
+        let mut request = client
+            .post(&target.url)
+            .header("Content-Type", "application/json")
+            .header(EVENT_HEADER, &delivery.kind)
+            .body(delivery.body.clone());
+        if let Some(secret) = target.secret.as_deref() {
+            request = request.header(SIGNATURE_HEADER, signature_header(secret, delivery.body.as_bytes()));
+        }
+        let failure = match request.send().await {
+            Ok(res) if res.status().is_success() => {
+                debug!("Delivered {} to {}", delivery.kind, target.url);
+                return Ok(());
+            }
+            Ok(res) if !res.status().is_server_error() => return Err(format!("HTTP {}", res.status())),
+            Ok(res) => format!("HTTP {}", res.status()),
+            Err(e) => e.to_string(),
+        };
+        if attempt >= WEBHOOK_MAX_ATTEMPTS {
+            return Err(format!("{} (after {} attempts)", failure, attempt));
+        }
+        let delay = webhook_retry_delay(attempt);
+        warn!("Webhook delivery to {} failed ({}); retrying in {}ms", target.url, failure, delay.as_millis());
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// Backoff before retry `attempt + 1`: 1s, 2s, 4s, ...
+fn webhook_retry_delay(attempt: u32) -> Duration {
+    WEBHOOK_BASE_DELAY * 2u32.saturating_pow(attempt.saturating_sub(1))
+}
+
+fn record_dead_letter(path: &Path, url: &str, delivery: &Delivery, reason: &str) {
+    use std::io::Write;
+    let entry = json!({
+        "time": chrono::Utc::now().to_rfc3339(),
+        "url": url,
+        "kind": delivery.kind,
+        "error": reason,
+        "body": delivery.body,
+    });
+    let result = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::OpenOptions::new().create(true).append(true).open(path))
+        .and_then(|mut file| writeln!(file, "{}", entry));
+    if let Err(e) = result {
+        error!("Cannot write webhook dead letter to {}: {}", path.display(), e);
+    }
+}
+
+/// `sha256=<hex>` HMAC of the request body.
+pub fn signature_header(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    format!("sha256={}", digest.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use acore::AgentProvider;
+
+    #[test]
+    fn signature_matches_the_rfc_4231_vector() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            signature_header("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn parses_config_with_default_secret() {
+        let raw = r#"[
+            {"prefix": "discord:", "url": "https://a.example/hook", "events": ["AgentDone", "Reply"]},
+            {"url": "http://ha.local:8123/api/webhook/x", "secret": "own"}
+        ]"#;
+        let targets = parse_webhooks(raw, Some("shared")).unwrap();
+        assert_eq!(targets[0].secret.as_deref(), Some("shared"));
+        assert_eq!(targets[1].secret.as_deref(), Some("own"));
+        assert_eq!(targets[1].prefix, "");
+        assert!(parse_webhooks(r#"[{"url": "ftp://x"}]"#, None).is_err());
+        assert!(parse_webhooks("{", None).is_err());
+    }
+
+    #[test]
+    fn targets_filter_by_prefix_and_kind() {
+        let targets = parse_webhooks(
+            r#"[{"prefix": "discord:", "url": "https://a", "events": ["agentdone", "Reply"]}, {"url": "https://b"}]"#,
+            None,
+        )
+        .unwrap();
+        assert!(targets[0].wants("AgentDone", Some("discord:1:2")));
+        assert!(targets[0].wants("Reply", Some("discord:1:2")));
+        assert!(!targets[0].wants("AgentChunk", Some("discord:1:2")));
+        assert!(!targets[0].wants("AgentDone", Some("slack:U:C")));
+        assert!(!targets[0].wants("ProviderSwitched", None));
+        assert!(targets[1].wants("ProviderSwitched", None));
+        assert!(!targets[1].wants("Reply", Some("tui")));
+    }
+
+    #[test]
+    fn event_kind_names_the_variant() {
+        assert_eq!(event_kind(&ProtocolEvent::AgentDone { channel: None, provider: None, model: None }), "AgentDone");
+        assert_eq!(event_kind(&ProtocolEvent::BridgeSyncDone {}), "BridgeSyncDone");
+    }
+
+    #[test]
+    fn reply_collector_emits_the_concatenated_reply_on_done() {
+        let mut collector = ReplyCollector::default();
+        let ch = Some("discord:1:2".to_string());
+        collector.observe(&ProtocolEvent::ProviderSwitched { provider: AgentProvider::Claude });
+        collector.observe(&ProtocolEvent::ModelSwitched { model: "opus".into() });
+        collector.observe(&ProtocolEvent::Prompt { text: "hi".into(), provider: None, channel: ch.clone(), reply_to: None, priority: false, author: None });
+        collector.observe(&ProtocolEvent::AgentChunk { chunk: "Hel".into(), channel: ch.clone() });
+        collector.observe(&ProtocolEvent::AgentChunk { chunk: "lo".into(), channel: ch.clone() });
+        let reply = collector.observe(&ProtocolEvent::AgentDone { channel: ch.clone(), provider: None, model: None }).unwrap();
+        assert_eq!(
+            reply,
+            json!({ "Reply": { "channel": "discord:1:2", "prompt": "hi", "text": "Hello", "provider": "claude", "model": "opus" } })
+        );
+
+        collector.observe(&ProtocolEvent::Prompt { text: "again".into(), provider: None, channel: ch.clone(), reply_to: None, priority: false, author: None });
+        collector.observe(&ProtocolEvent::AgentChunk { chunk: "partial".into(), channel: ch.clone() });
+        collector.observe(&ProtocolEvent::ChannelClosed { channel: ch.clone(), reason: "panic".into() });
+        assert!(collector.observe(&ProtocolEvent::AgentDone { channel: ch, provider: None, model: None }).is_none());
+    }
+
+    #[test]
+    fn outgoing_bodies_are_redacted() {
+        let key = "sk-ant-REDACTED";
+        let ch = Some("discord:1:2".to_string());
+        let chunk = ProtocolEvent::AgentChunk { chunk: format!("key is {key}"), channel: ch };
+        let reply = json!({ "Reply": { "channel": "discord:1:2", "text": format!("key is {key}") } });
+
+        let bodies = outgoing_bodies(&chunk, serde_json::to_string(&chunk).unwrap(), Some(reply));
+        assert_eq!(bodies.iter().map(|(kind, _)| kind.as_str()).collect::<Vec<_>>(), ["AgentChunk", "Reply"]);
+        for (_, body) in &bodies {
+            assert!(!body.contains(key), "{body}");
+            assert!(body.contains(crate::redact::REDACTED), "{body}");
+            serde_json::from_str::<Value>(body).unwrap();
+        }
+    }
+
+    #[test]
+    fn retry_delay_doubles() {
+        assert_eq!(webhook_retry_delay(1), Duration::from_secs(1));
+        assert_eq!(webhook_retry_delay(3), Duration::from_secs(4));
+    }
+
+    #[test]
+    fn dead_letters_are_appended_as_jsonl() {
+        let dir = std::env::temp_dir().join(format!("acomm-webhook-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join(DEAD_LETTER_FILE);
+        let delivery = Delivery { kind: "AgentDone".into(), body: "{}".into() };
+        record_dead_letter(&path, "https://a", &delivery, "HTTP 500");
+        record_dead_letter(&path, "https://a", &delivery, "HTTP 404");
+        let lines: Vec<Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["error"], "HTTP 404");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,64 @@
+//! Exit codes of the `acomm` binary for the failures scripts need to tell apart.
+//!
+//! The bridge tests leave /tmp/acomm.sock behind without a listener, so the
+//! "bridge unreachable" cases are skipped when a real bridge is running.
+
+use std::os::unix::net::UnixStream;
+use std::process::{Command, Output};
+
+const SOCKET_PATH: &str = "/tmp/acomm.sock";
+
+fn acomm(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_acomm"))
+        .args(args)
+        .output()
+        .expect("failed to run acomm")
+}
+
+fn stderr_line(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).trim_end().to_string()
+}
+
+fn bridge_running() -> bool {
+    UnixStream::connect(SOCKET_PATH).is_ok()
+}
+
+#[test]
+fn unknown_flag_exits_5_with_prefixed_error() {
+    let output = acomm(&["--bogus"]);
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = stderr_line(&output);
+    assert!(stderr.starts_with("acomm: error: "), "{stderr}");
+    assert_eq!(stderr.lines().count(), 1, "{stderr}");
+}
+
+#[test]
+fn bad_channel_exits_5() {
+    let output = acomm(&["--publish", "hi", "--channel", "two words"]);
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr_line(&output).starts_with("acomm: error: invalid channel"));
+}
+
+#[test]
+fn help_still_exits_0_on_stdout() {
+    let output = acomm(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!output.stdout.is_empty());
+}
+
+#[test]
+fn publish_without_bridge_exits_2() {
+    if bridge_running() {
+        eprintln!("skipping: a bridge is listening on {SOCKET_PATH}");
+        return;
+    }
+    for args in [&["--publish", "hi"][..], &["--dump"], &["--subscribe"]] {
+        let output = acomm(args);
+        assert_eq!(output.status.code(), Some(2), "{args:?}");
+        assert!(
+            stderr_line(&output).starts_with(&format!("acomm: error: bridge unreachable at {SOCKET_PATH}")),
+            "{args:?}"
+        );
+        assert!(output.stdout.is_empty(), "{args:?}");
+    }
+}
@@ -17,7 +17,7 @@ import { homedir } from 'node:os';
 import { join } from 'node:path';
 import type { Bridge } from './bridge.js';
 import type { AgentProvider, ProtocolEvent } from './protocol.js';
-import { providerCommandName, AGENT_PROVIDERS, normalizeProvider, getModelsForProvider } from './protocol.js';
+import { providerCommandName, AGENT_PROVIDERS, normalizeProvider, getModelsForProvider, PROTOCOL_VERSION } from './protocol.js';
 import MultilineInput from './MultilineInput.js';
 import SelectionMenu from './SelectionMenu.js';
 import SlashAutocomplete from './SlashAutocomplete.js';
@@ -234,10 +234,23 @@ export default function App({ bridge, channel, initialProvider = 'Gemini', subsc
         }
         currentTurnRef.current = null;
       }
+    } else if ('ChannelClosed' in event) {
+      // The prompt ended without AgentDone (agent task panicked or was aborted).
+      setAwaitingFirstChunk(false);
+      setIsProcessing(false);
+      markLastComplete();
+      currentTurnRef.current = null;
+      push(chalk.yellow(`[System] ${event.ChannelClosed.reason}`));
     } else if ('SystemMessage' in event) {
       push(chalk.yellow(`[System] ${event.SystemMessage.msg}`));
     } else if ('StatusUpdate' in event) {
       setIsProcessing(event.StatusUpdate.is_processing);
+    } else if ('Hello' in event) {
+      if (event.Hello.version !== PROTOCOL_VERSION) {
+        push(chalk.red(
+          `[System] Incompatible acomm protocol: the bridge speaks version ${event.Hello.version} but this TUI speaks version ${PROTOCOL_VERSION}. Upgrade acomm so both sides match.`,
+        ));
+      }
     } else if ('BridgeSyncDone' in event) {
       hasCompletedInitialSyncRef.current = true;
       if (!initialProviderSyncSentRef.current) {
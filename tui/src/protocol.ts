@@ -9,6 +9,9 @@
  *   {"ProviderSwitched":{"provider":"Gemini"}}
  */
 
+/** Protocol version exchanged in the `Hello` handshake; must match the bridge's. */
+export const PROTOCOL_VERSION = 1;
+
 export type AgentProvider = 'Gemini' | 'Claude' | 'Codex' | 'OpenCode' | 'Dummy' | 'Mock';
 
 export const AGENT_PROVIDERS: AgentProvider[] = ['Gemini', 'Claude', 'Codex', 'OpenCode', 'Dummy'];
@@ -39,6 +42,9 @@ export type ProtocolEvent =
   | { AgentDone: { channel: string | null } }
   | { SystemMessage: { msg: string; channel: string | null } }
   | { StatusUpdate: { is_processing: boolean; channel: string | null } }
+  | { PromptAck: { channel: string | null; seq: number } }
+  | { ChannelClosed: { channel: string | null; reason: string } }
+  | { Hello: { version: number } }
   | { BridgeSyncDone: {} }
   | { SyncContext: { context: string } }
   | { ProviderSwitched: { provider: AgentProvider } }